@@ -1,12 +1,45 @@
 // eslint-disable-next-line n/no-missing-import
 import { afterEach, beforeEach, describe, expect, test } from 'bun:test';
-import { mkdir, realpath, rename, rm, symlink, unlink, writeFile } from 'node:fs/promises';
+import { execFileSync } from 'node:child_process';
+import { appendFile, chmod, link, mkdir, readdir, readFile, realpath, rename, rm, stat, symlink, unlink, utimes, writeFile } from 'node:fs/promises';
 import { tmpdir } from 'node:os';
 import path from 'node:path';
 import { setTimeout as sleep } from 'node:timers/promises';
+import { Worker } from 'node:worker_threads';
 
 // eslint-disable-next-line n/no-missing-import, n/no-unpublished-import
-import { subscribe, type Subscription, type WatchEvent } from '../index.js';
+import {
+  capabilities,
+  clearFaults,
+  diffTrees,
+  dispatchThreadCount,
+  getEventsSince,
+  injectFaults,
+  isIgnored,
+  listSubscriptions,
+  once,
+  type ParcelEvent,
+  type RawEvent,
+  scan,
+  type ScanEntry,
+  subscribe,
+  subscribeAsync,
+  subscribeRouted,
+  subscribeSince,
+  type Subscription,
+  unsubscribeAll,
+  validateOptions,
+  waitForFile,
+  watch,
+  type WatchCallbackJson,
+  type WatchCallbackResult,
+  type WatchEvent,
+  type WatchOptions,
+  type WatchWarning,
+  writeSnapshot,
+} from '../index.js';
+// eslint-disable-next-line n/no-missing-import, n/no-unpublished-import
+import { createEventRing, EventRingReader } from '../ring.js';
 
 /** Counter for generating unique filenames */
 let fileCounter = 0;
@@ -14,9 +47,18 @@ let fileCounter = 0;
 /** Whether we're running on Windows (paths are case-insensitive and events may report parent directories) */
 const isWindows = process.platform === 'win32';
 
+/** Whether permission checks are enforced for the current user (not on Windows, and never for root) */
+const canDenyPermissions = !isWindows && process.getuid?.() !== 0;
+
 /** Delay after subscribing before performing file operations */
 const subscribeDelay = 100;
 
+/** Whether the addon was built with the `faults` feature, which the fault injection tests need */
+const hasFaults = typeof injectFaults === 'function';
+
+/** Whether the addon was built with the `mock` feature, which the tests injecting raw events need */
+const hasMock = !validateOptions(tmpdir(), { backend: 'mock' }).problems.some(({ code }) => code === 'INVALID_BACKEND');
+
 /** Compare two paths for equality (case-insensitive on Windows) */
 const pathsEqual = (path1: string, path2: string) => {
   const norm1 = path.normalize(path1);
@@ -74,6 +116,11 @@ const expectEventType = (match: EventMatch, expectedType: 'create' | 'update' |
   }
 };
 
+/** Check that no dispatch thread is left running, which only builds with the `faults` feature can count */
+const expectNoDispatchThreads = () => {
+  if (hasFaults) expect(dispatchThreadCount()).toBe(0);
+};
+
 /** Check if any event matches the path */
 const hasEventWithPath = (events: WatchEvent[], targetPath: string) => events.some((event) => pathMatches(event.path, targetPath));
 
@@ -111,7 +158,7 @@ describe('watcher', () => {
 
   const createCollector = () => ({ errors: [] as Error[], events: [] as WatchEvent[] });
 
-  const subscribeWithCollector = (directory: string, options?: { ignore?: string[] }) => {
+  const subscribeWithCollector = (directory: string, options?: WatchOptions) => {
     collector = createCollector();
     subscription = subscribe(
       directory,
@@ -190,6 +237,111 @@ describe('watcher', () => {
       expect([hasEventWithPath(events, sourcePath), hasEventWithPath(events, destinationPath)]).toContain(true);
     });
 
+    test.skipIf(process.platform !== 'darwin' && process.platform !== 'win32')('should emit one update with the new spelling for a case-only rename', async () => {
+      const sourcePath = path.join(testDirectory, 'Readme.md');
+      const destinationPath = path.join(testDirectory, 'README.md');
+      await writeFile(sourcePath, 'hello world');
+      await sleep(100);
+
+      subscribeWithCollector(testDirectory);
+      await sleep(subscribeDelay);
+
+      await rename(sourcePath, destinationPath);
+      await waitForEvents(collector);
+      await sleep(200);
+
+      const renamed = collector.events.filter((event) => event.path.toLowerCase() === destinationPath.toLowerCase());
+      expect(renamed.map(({ path: eventPath, type }) => [eventPath, type])).toEqual([[destinationPath, 'update']]);
+    });
+
+    test.skipIf(process.platform !== 'linux')('should emit events for both spellings of a case-only rename on a case-sensitive file system', async () => {
+      const sourcePath = path.join(testDirectory, 'Readme.md');
+      const destinationPath = path.join(testDirectory, 'README.md');
+      await writeFile(sourcePath, 'hello world');
+      await sleep(100);
+
+      subscribeWithCollector(testDirectory);
+      await sleep(subscribeDelay);
+
+      await rename(sourcePath, destinationPath);
+
+      const events = await waitForEvents(collector);
+      expect([hasEventWithPath(events, sourcePath), hasEventWithPath(events, destinationPath)]).toEqual([true, true]);
+    });
+
+    for (const [name, options] of [
+      ['', {}],
+      [' without pairing renames by file id', { cacheLimit: 0 }],
+    ] as const) {
+      test(`should emit a delete when a file is moved out of the directory${name}`, async () => {
+        const outsideDirectory = `${testDirectory}-outside`;
+        await mkdir(outsideDirectory);
+        const filePath = getFilename(testDirectory);
+        await writeFile(filePath, 'hello world');
+        await sleep(100);
+
+        try {
+          subscribeWithCollector(testDirectory, options);
+          await sleep(subscribeDelay);
+
+          await rename(filePath, path.join(outsideDirectory, path.basename(filePath)));
+
+          expectEventType(findEventByPath(await waitForEvents(collector), filePath), 'delete');
+        } finally {
+          await rm(outsideDirectory, { recursive: true, force: true });
+        }
+      });
+
+      test(`should emit a create when a file is moved into the directory${name}`, async () => {
+        const outsideDirectory = `${testDirectory}-outside`;
+        await mkdir(outsideDirectory);
+        const filePath = getFilename(testDirectory);
+        const outsidePath = path.join(outsideDirectory, path.basename(filePath));
+        await writeFile(outsidePath, 'hello world');
+        await sleep(100);
+
+        try {
+          subscribeWithCollector(testDirectory, options);
+          await sleep(subscribeDelay);
+
+          await rename(outsidePath, filePath);
+
+          expectEventType(findEventByPath(await waitForEvents(collector), filePath), 'create');
+        } finally {
+          await rm(outsideDirectory, { recursive: true, force: true });
+        }
+      });
+    }
+
+    test('should emit a delete and a create when a file moves between two subscriptions', async () => {
+      const sourceDirectory = path.join(testDirectory, 'source');
+      const destinationDirectory = path.join(testDirectory, 'destination');
+      await mkdir(sourceDirectory);
+      await mkdir(destinationDirectory);
+      const sourcePath = getFilename(sourceDirectory);
+      const destinationPath = path.join(destinationDirectory, path.basename(sourcePath));
+      await writeFile(sourcePath, 'hello world');
+
+      // The parent's watch is shared, so the OS watcher pairs the two halves of the rename
+      const parent = subscribe(testDirectory, null, { pull: true });
+      const sourceEvents: WatchEvent[] = [];
+      const destinationEvents: WatchEvent[] = [];
+      const source = subscribe(sourceDirectory, (batch) => sourceEvents.push(...batch.events));
+      const destination = subscribe(destinationDirectory, (batch) => destinationEvents.push(...batch.events));
+      try {
+        await Promise.all([parent.ready, source.ready, destination.ready]);
+        await rename(sourcePath, destinationPath);
+        await sleep(500);
+
+        expect(sourceEvents.map(({ path: eventPath, type }) => [eventPath, type])).toEqual([[sourcePath, 'delete']]);
+        expect(destinationEvents.map(({ path: eventPath, type }) => [eventPath, type])).toEqual([[destinationPath, 'create']]);
+      } finally {
+        parent.unsubscribe();
+        source.unsubscribe();
+        destination.unsubscribe();
+      }
+    });
+
     test('should emit when a file is deleted', async () => {
       const filePath = getFilename(testDirectory);
       await writeFile(filePath, 'hello world');
@@ -428,6 +580,21 @@ describe('watcher', () => {
       }
     });
 
+    test.skipIf(process.platform !== 'linux')('should warn about a symlink to a directory containing it', async () => {
+      await mkdir(path.join(testDirectory, 'nested'));
+      const linkPath = path.join(testDirectory, 'nested', 'loop');
+      await symlink('..', linkPath);
+
+      const warnings: WatchWarning[] = [];
+      const started = Date.now();
+      subscription = subscribe(testDirectory, (batch) => warnings.push(...(batch.warnings ?? [])));
+      await subscription.ready;
+      await sleep(100);
+
+      expect(Date.now() - started).toBeLessThan(2000);
+      expect(warnings.filter((warning) => warning.kind === 'symlink-cycle').map((warning) => warning.path)).toEqual([linkPath]);
+    });
+
     test('should emit when a symlink is deleted', async () => {
       const targetPath = getFilename(testDirectory);
       await writeFile(targetPath, 'target content');
@@ -570,6 +737,91 @@ describe('watcher', () => {
       // Backup file should NOT have an event
       expect(findEventByPath(events, backupFile)).toBeUndefined();
     });
+
+    test('should ignore every level below a directory pattern and nothing next to it', async () => {
+      await mkdir(path.join(testDirectory, 'build', 'nested', 'deeper'), { recursive: true });
+      await mkdir(path.join(testDirectory, 'builder'));
+
+      subscribeWithCollector(testDirectory, { ignore: ['build/**', '**/*.log'] });
+      await sleep(subscribeDelay);
+
+      // Several files per directory, so later ones find the directory's verdict remembered
+      const ignoredFiles: string[] = [];
+      const normalFiles: string[] = [];
+      for (let index = 0; index < 3; index++) {
+        ignoredFiles.push(getFilename(testDirectory, 'build'), getFilename(testDirectory, 'build', 'nested', 'deeper'), path.join(testDirectory, 'builder', `debug${index.toString()}.log`));
+        normalFiles.push(getFilename(testDirectory, 'builder'), getFilename(testDirectory));
+      }
+      for (const file of [...ignoredFiles, ...normalFiles]) {
+        await writeFile(file, 'content');
+      }
+
+      const events = await waitForEvents(collector, { minEvents: normalFiles.length });
+      for (const file of normalFiles) {
+        expect(findEventByPath(events, file)).toBeDefined();
+      }
+      for (const file of ignoredFiles) {
+        expect(findEventByPath(events, file)).toBeUndefined();
+      }
+    });
+
+    test.skipIf(process.platform !== 'linux')('should not register watches below ignored directories', async () => {
+      for (let index = 0; index < 10; index++) {
+        await mkdir(path.join(testDirectory, 'node_modules', `package${index.toString()}`, 'lib'), { recursive: true });
+      }
+      await mkdir(path.join(testDirectory, 'src'));
+
+      const unfiltered = subscribe(testDirectory, () => {}, { isolated: true });
+      const unfilteredCount = unfiltered.watchCount;
+      unfiltered.unsubscribe();
+
+      subscribeWithCollector(testDirectory, { ignore: ['node_modules/**'] });
+      expect(subscription?.watchCount).toBeLessThan(unfilteredCount);
+      await sleep(subscribeDelay);
+
+      // New directories are registered unless they are ignored
+      await mkdir(path.join(testDirectory, 'node_modules', 'added'));
+      await mkdir(path.join(testDirectory, 'src', 'added'));
+      const filePath = getFilename(testDirectory, 'src', 'added');
+      await writeFile(filePath, 'content');
+
+      const events = await waitForEvents(collector, { minEvents: 2 });
+      expect(findEventByPath(events, filePath)).toBeDefined();
+      expect(subscription?.watchCount).toBe(3);
+    });
+
+    test.skipIf(process.platform !== 'linux')('should not register watches below ignored directories named without a wildcard', async () => {
+      await mkdir(path.join(testDirectory, 'src'));
+
+      subscribeWithCollector(testDirectory, { ignore: ['node_modules'] });
+      const watchCount = subscription?.watchCount;
+      await sleep(subscribeDelay);
+
+      await mkdir(path.join(testDirectory, 'node_modules', 'a', 'b'), { recursive: true });
+      const filePath = getFilename(testDirectory, 'src');
+      await writeFile(filePath, 'content');
+
+      const events = await waitForEvents(collector);
+      expect(findEventByPath(events, filePath)).toBeDefined();
+      expect(subscription?.watchCount).toBe(watchCount);
+    });
+
+    test.skipIf(process.platform !== 'linux')('should unwatch ignored directories created below a recursively watched directory', async () => {
+      await mkdir(path.join(testDirectory, 'src', 'lib'), { recursive: true });
+
+      subscribeWithCollector(testDirectory, { ignore: ['**/.cache/**'] });
+      expect(subscription?.watchCount).toBe(3);
+      await sleep(subscribeDelay);
+
+      await mkdir(path.join(testDirectory, 'src', 'lib', '.cache', 'nested'), { recursive: true });
+      await mkdir(path.join(testDirectory, 'src', 'package', '.cache'), { recursive: true });
+      const filePath = getFilename(testDirectory, 'src', 'package');
+      await writeFile(filePath, 'content');
+
+      const events = await waitForEvents(collector, { minEvents: 3 });
+      expect(findEventByPath(events, filePath)).toBeDefined();
+      expect(subscription?.watchCount).toBe(4);
+    });
   });
 
   describe('multiple subscriptions', () => {
@@ -606,6 +858,193 @@ describe('watcher', () => {
       }
     });
 
+    test('should apply ignore patterns per subscription on a shared watcher', async () => {
+      const collector1 = createCollector();
+      const collector2 = createCollector();
+
+      const sub1 = subscribe(testDirectory, ({ error, events }) => {
+        if (error) collector1.errors.push(error);
+        else collector1.events.push(...events);
+      });
+      const sub2 = subscribe(
+        testDirectory,
+        ({ error, events }) => {
+          if (error) collector2.errors.push(error);
+          else collector2.events.push(...events);
+        },
+        { ignore: ['*.log'] },
+      );
+
+      try {
+        await sleep(subscribeDelay);
+
+        const logFile = path.join(testDirectory, 'debug.log');
+        const textFile = path.join(testDirectory, 'notes.txt');
+        await writeFile(logFile, 'log');
+        await writeFile(textFile, 'text');
+
+        await waitForEvents(collector1, { minEvents: 2 });
+        await waitForEvents(collector2);
+
+        expect(findEventByPath(collector1.events, logFile)).toBeDefined();
+        expect(findEventByPath(collector2.events, logFile)).toBeUndefined();
+        expect(findEventByPath(collector2.events, textFile)).toBeDefined();
+      } finally {
+        sub1.unsubscribe();
+        sub2.unsubscribe();
+      }
+    });
+
+    test('should keep delivering to other subscriptions after one unsubscribes', async () => {
+      const collector1 = createCollector();
+      const collector2 = createCollector();
+
+      const sub1 = subscribe(testDirectory, ({ error, events }) => {
+        if (error) collector1.errors.push(error);
+        else collector1.events.push(...events);
+      });
+      const sub2 = subscribe(testDirectory, ({ error, events }) => {
+        if (error) collector2.errors.push(error);
+        else collector2.events.push(...events);
+      });
+
+      try {
+        await sleep(subscribeDelay);
+        sub1.unsubscribe();
+
+        const filePath = getFilename(testDirectory);
+        await writeFile(filePath, 'content');
+
+        await waitForEvents(collector2);
+
+        expect(findEventByPath(collector2.events, filePath)).toBeDefined();
+        expect(collector1.events).toHaveLength(0);
+      } finally {
+        sub2.unsubscribe();
+      }
+    });
+
+    test('should support isolated subscriptions to the same directory', async () => {
+      const collector1 = createCollector();
+      const collector2 = createCollector();
+
+      const sub1 = subscribe(testDirectory, ({ error, events }) => {
+        if (error) collector1.errors.push(error);
+        else collector1.events.push(...events);
+      });
+      const sub2 = subscribe(
+        testDirectory,
+        ({ error, events }) => {
+          if (error) collector2.errors.push(error);
+          else collector2.events.push(...events);
+        },
+        { isolated: true },
+      );
+
+      try {
+        await sleep(subscribeDelay);
+
+        const filePath = getFilename(testDirectory);
+        await writeFile(filePath, 'content');
+
+        await waitForEvents(collector1);
+        await waitForEvents(collector2);
+
+        expect(findEventByPath(collector1.events, filePath)).toBeDefined();
+        expect(findEventByPath(collector2.events, filePath)).toBeDefined();
+      } finally {
+        sub1.unsubscribe();
+        sub2.unsubscribe();
+      }
+    });
+
+    test('should attach a subscription to a subdirectory of a watched directory', async () => {
+      const subDirectory = path.join(testDirectory, 'packages', 'app');
+      await mkdir(subDirectory, { recursive: true });
+
+      const outer = subscribeWithCollector(testDirectory);
+      const innerCollector = createCollector();
+      const inner = subscribe(subDirectory, ({ error, events }) => {
+        if (error) innerCollector.errors.push(error);
+        else innerCollector.events.push(...events);
+      });
+
+      try {
+        expect(outer.attached).toBe(false);
+        expect(inner.attached).toBe(true);
+
+        await sleep(subscribeDelay);
+
+        const innerFile = getFilename(subDirectory);
+        const outerFile = getFilename(testDirectory);
+        await writeFile(innerFile, 'inner');
+        await writeFile(outerFile, 'outer');
+
+        await waitForEvents(collector, { minEvents: 2 });
+        await waitForEvents(innerCollector);
+
+        expect(findEventByPath(collector.events, innerFile)).toBeDefined();
+        expect(findEventByPath(collector.events, outerFile)).toBeDefined();
+        expect(findEventByPath(innerCollector.events, innerFile)).toBeDefined();
+        expect(findEventByPath(innerCollector.events, outerFile)).toBeUndefined();
+      } finally {
+        inner.unsubscribe();
+      }
+    });
+
+    test('should promote an attached subscription when the parent unsubscribes', async () => {
+      const subDirectory = path.join(testDirectory, 'src');
+      await mkdir(subDirectory, { recursive: true });
+
+      const outer = subscribe(testDirectory, () => {});
+      const inner = subscribeWithCollector(subDirectory);
+
+      expect(inner.attached).toBe(true);
+      outer.unsubscribe();
+      expect(inner.attached).toBe(false);
+
+      await sleep(subscribeDelay);
+
+      const filePath = getFilename(subDirectory);
+      await writeFile(filePath, 'content');
+
+      const events = await waitForEvents(collector);
+      expect(findEventByPath(events, filePath)).toBeDefined();
+    });
+
+    test.skipIf(!hasFaults)('should dispatch every subscription from a single thread', async () => {
+      const countTasks = async () => (process.platform === 'linux' ? (await readdir('/proc/self/task')).length : 0);
+      const subscriptions: Subscription[] = [];
+
+      try {
+        for (let index = 0; index < 2; index++) {
+          const directory = path.join(testDirectory, `first${index.toString()}`);
+          await mkdir(directory);
+          subscriptions.push(subscribe(directory, () => {}));
+        }
+
+        const threads = dispatchThreadCount();
+        const tasks = await countTasks();
+        expect(threads).toBe(1);
+
+        for (let index = 0; index < 20; index++) {
+          const directory = path.join(testDirectory, `more${index.toString()}`);
+          await mkdir(directory);
+          subscriptions.push(subscribe(directory, () => {}, { strict: index % 2 === 0 }));
+        }
+
+        expect(dispatchThreadCount()).toBe(threads);
+        expect(await countTasks()).toBe(tasks);
+
+        subscriptions.push(subscribe(testDirectory, () => {}, { isolated: true }));
+        expect(dispatchThreadCount()).toBe(threads + 1);
+      } finally {
+        for (const sub of subscriptions) sub.unsubscribe();
+      }
+
+      expect(dispatchThreadCount()).toBe(0);
+    });
+
     test('should support subscriptions to different directories', async () => {
       const directory1 = path.join(await realpath(tmpdir()), `watcher-test-1-${Date.now().toString()}`);
       const directory2 = path.join(await realpath(tmpdir()), `watcher-test-2-${Date.now().toString()}`);
@@ -684,6 +1123,97 @@ describe('watcher', () => {
       expect(findEventByPath(collector.events, file2)).toBeUndefined();
     });
 
+    test('should release the callback after unsubscribe', async () => {
+      let callbackReference: WeakRef<() => void> | undefined;
+
+      (() => {
+        const retained = Array.from({ length: 100_000 }, (_, index) => index);
+        const callback = () => retained.length;
+        callbackReference = new WeakRef(callback);
+        subscribe(testDirectory, callback).unsubscribe();
+      })();
+
+      for (let attempt = 0; attempt < 10 && callbackReference?.deref(); attempt++) {
+        await sleep(50);
+        Bun.gc(true);
+      }
+
+      expect(callbackReference?.deref()).toBeUndefined();
+    });
+
+    test('should deliver pending events before unsubscribeAsync resolves', async () => {
+      subscribeWithCollector(testDirectory);
+      await sleep(subscribeDelay);
+
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'pending');
+
+      await subscription?.unsubscribeAsync();
+      subscription = undefined;
+
+      expectEventType(findEventByPath(collector.events, filePath), 'create');
+    });
+
+    test('should close every subscription with unsubscribeAll', async () => {
+      const leaked = subscribe(testDirectory, () => {
+        /* empty */
+      });
+      subscribeWithCollector(testDirectory);
+      subscribe(testDirectory, null).unsubscribe();
+      await sleep(subscribeDelay);
+
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'pending');
+
+      expect(await unsubscribeAll()).toBe(2);
+      expect(listSubscriptions()).toEqual([]);
+      expect(leaked.attached).toBe(false);
+      expectNoDispatchThreads();
+      expectEventType(findEventByPath(collector.events, filePath), 'create');
+      expect(await unsubscribeAll()).toBe(0);
+      subscription = undefined;
+    });
+
+    test('should clean up a subscription that is garbage collected without unsubscribe', async () => {
+      let callbackReference: WeakRef<() => void> | undefined;
+
+      (() => {
+        const callback = () => {
+          /* empty */
+        };
+        callbackReference = new WeakRef(callback);
+        subscribe(testDirectory, callback);
+      })();
+
+      for (let attempt = 0; attempt < 10 && callbackReference?.deref(); attempt++) {
+        await sleep(50);
+        Bun.gc(true);
+      }
+
+      expect(callbackReference?.deref()).toBeUndefined();
+    });
+
+    test('should warn through onLog about a subscription that is garbage collected without unsubscribe', async () => {
+      const lines: string[] = [];
+
+      (() => {
+        subscribe(
+          testDirectory,
+          () => {
+            /* empty */
+          },
+          { warnIfLeaked: true, onLog: (level, message) => lines.push(`${level} ${message}`) },
+        );
+      })();
+
+      for (let attempt = 0; attempt < 20 && !lines.some((line) => line.startsWith('warn')); attempt++) {
+        await sleep(50);
+        Bun.gc(true);
+      }
+
+      expect(lines).toContain(`warn Subscription for ${testDirectory} was garbage collected without unsubscribe`);
+    });
+
     test('should allow re-subscribing after unsubscribe', async () => {
       subscribeWithCollector(testDirectory);
       await sleep(subscribeDelay);
@@ -745,31 +1275,372 @@ describe('watcher', () => {
     });
   });
 
-  describe('rapid changes', () => {
-    test('should handle rapid file creations', async () => {
-      subscribeWithCollector(testDirectory);
-      await sleep(subscribeDelay);
-
-      const files: string[] = [];
-      for (let index = 0; index < 10; index++) {
-        const filePath = getFilename(testDirectory);
-        files.push(filePath);
-        await writeFile(filePath, `content ${index.toString()}`);
-      }
+  describe('retry', () => {
+    test('should report the number of attempts when the directory never appears', () => {
+      const missingDirectory = path.join(testDirectory, 'does-not-exist-' + Date.now().toString());
 
-      const events = await waitForEvents(collector, { minEvents: 10, timeout: 5000 });
-      for (const filePath of files) {
-        expect(findEventByPath(events, filePath)).toBeDefined();
-      }
+      expect(() =>
+        subscribe(
+          missingDirectory,
+          () => {
+            /* empty */
+          },
+          { retry: { attempts: 3, delayMs: 10 } },
+        ),
+      ).toThrow('after 3 attempts');
     });
 
-    test('should handle rapid file updates', async () => {
-      const filePath = getFilename(testDirectory);
-      await writeFile(filePath, 'initial');
-      await sleep(100);
+    test('should not retry when the path is not a directory', async () => {
+      const filePath = path.join(testDirectory, 'not-a-dir.txt');
+      await writeFile(filePath, 'content');
 
-      subscribeWithCollector(testDirectory);
-      await sleep(subscribeDelay);
+      const start = Date.now();
+      expect(() =>
+        subscribe(
+          filePath,
+          () => {
+            /* empty */
+          },
+          { retry: { attempts: 5, delayMs: 500 } },
+        ),
+      ).toThrow('Path is not a directory');
+      expect(Date.now() - start).toBeLessThan(500);
+    });
+
+    test('should not retry invalid glob patterns', () => {
+      const start = Date.now();
+      expect(() =>
+        subscribe(
+          path.join(testDirectory, 'does-not-exist-' + Date.now().toString()),
+          () => {
+            /* empty */
+          },
+          { ignore: ['{unclosed'], retry: { attempts: 5, delayMs: 500 } },
+        ),
+      ).toThrow('Invalid glob pattern');
+      expect(Date.now() - start).toBeLessThan(500);
+    });
+
+    test('should cap the delay however large the backoff makes it', () => {
+      const missingDirectory = path.join(testDirectory, 'does-not-exist-' + Date.now().toString());
+
+      expect(() =>
+        subscribe(
+          missingDirectory,
+          () => {
+            /* empty */
+          },
+          { retry: { attempts: 2, delayMs: 1, backoff: 1e300 } },
+        ),
+      ).toThrow('after 2 attempts');
+    });
+
+    test('should reject a retry policy without attempts', () => {
+      expect(() =>
+        subscribe(
+          testDirectory,
+          () => {
+            /* empty */
+          },
+          { retry: { attempts: 0, delayMs: 10 } },
+        ),
+      ).toThrow();
+    });
+
+    test('should watch normally when a retry policy is given', async () => {
+      subscribeWithCollector(testDirectory, { retry: { attempts: 3, backoff: 2, delayMs: 10 } });
+      await sleep(subscribeDelay);
+
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+
+      expectEventType(findEventByPath(await waitForEvents(collector), filePath), 'create');
+    });
+  });
+
+  describe('permissions', () => {
+    test.skipIf(!canDenyPermissions)('should skip unreadable subdirectories and warn once', async () => {
+      const lockedDirectory = path.join(testDirectory, 'locked');
+      const openDirectory = path.join(testDirectory, 'open');
+      await mkdir(path.join(lockedDirectory, 'inner'), { recursive: true });
+      await mkdir(openDirectory);
+      await chmod(lockedDirectory, 0o000);
+
+      const warnings: WatchWarning[] = [];
+      collector = createCollector();
+      subscription = subscribe(testDirectory, ({ error, events, warnings: batchWarnings }) => {
+        if (error) collector.errors.push(error);
+        else collector.events.push(...events);
+        if (batchWarnings) warnings.push(...batchWarnings);
+      });
+
+      try {
+        await sleep(subscribeDelay);
+
+        const filePath = path.join(openDirectory, 'file.txt');
+        await writeFile(filePath, 'content');
+
+        expectEventType(findEventByPath(await waitForEvents(collector), filePath), 'create');
+        expect(warnings).toHaveLength(1);
+        expect(warnings[0]?.kind).toBe('permission-denied');
+        expect(pathsEqual(warnings[0]?.path ?? '', lockedDirectory)).toBe(true);
+      } finally {
+        await chmod(lockedDirectory, 0o755);
+      }
+    });
+
+    test.skipIf(!canDenyPermissions)('should pick up a skipped directory once it becomes readable', async () => {
+      const lockedDirectory = path.join(testDirectory, 'locked');
+      await mkdir(lockedDirectory);
+      await chmod(lockedDirectory, 0o000);
+
+      subscribeWithCollector(testDirectory);
+      await sleep(subscribeDelay);
+
+      await chmod(lockedDirectory, 0o755);
+      await sleep(300);
+
+      const filePath = path.join(lockedDirectory, 'file.txt');
+      await writeFile(filePath, 'content');
+
+      await waitForEvents(collector, { minEvents: 2 });
+      expectEventType(findEventByPath(collector.events, filePath), 'create');
+    });
+
+    test.skipIf(!canDenyPermissions)('should fail fast in strict mode', async () => {
+      const lockedDirectory = path.join(testDirectory, 'locked');
+      await mkdir(lockedDirectory);
+      await chmod(lockedDirectory, 0o000);
+
+      try {
+        expect(() =>
+          subscribe(
+            testDirectory,
+            () => {
+              /* empty */
+            },
+            { strict: true },
+          ),
+        ).toThrow();
+      } finally {
+        await chmod(lockedDirectory, 0o755);
+      }
+    });
+
+    test.skipIf(!canDenyPermissions || isWindows)('should warn when access to the directory is lost and regained', async () => {
+      const warnings: WatchWarning[] = [];
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => {
+        warnings.push(...(batch.warnings ?? []));
+        events.push(...batch.events);
+      });
+      await subscription.ready;
+
+      try {
+        await chmod(testDirectory, 0o000);
+        await sleep(300);
+        expect(warnings.map(({ kind, path: warningPath }) => [kind, warningPath])).toEqual([['access-lost', testDirectory]]);
+      } finally {
+        await chmod(testDirectory, 0o755);
+      }
+      await sleep(300);
+      expect(warnings.map(({ kind }) => kind)).toEqual(['access-lost', 'access-regained']);
+      expect(warnings[1]?.message).toContain('watched again');
+
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await sleep(300);
+      expect(hasEventWithPath(events, filePath)).toBe(true);
+
+    });
+
+    test.skipIf(!canDenyPermissions || isWindows)('should not warn about metadata changes that leave access alone', async () => {
+      const warnings: WatchWarning[] = [];
+      subscription = subscribe(testDirectory, (batch) => warnings.push(...(batch.warnings ?? [])));
+      await subscription.ready;
+
+      await chmod(testDirectory, 0o700);
+      await sleep(300);
+      expect(warnings).toEqual([]);
+    });
+  });
+
+  describe('callback exceptions', () => {
+    test('should rethrow callback exceptions with the batch seq and keep delivering', async () => {
+      const uncaught: Error[] = [];
+      const onUncaught = (error: Error) => uncaught.push(error);
+      process.on('uncaughtException', onUncaught);
+
+      const seqs: number[] = [];
+      subscription = subscribe(testDirectory, ({ seq }) => {
+        seqs.push(seq);
+        if (seqs.length === 1) throw new Error('callback failure');
+      });
+
+      try {
+        await sleep(subscribeDelay);
+
+        await writeFile(getFilename(testDirectory), 'first');
+        await sleep(300);
+        await writeFile(getFilename(testDirectory), 'second');
+        await sleep(300);
+
+        expect(seqs.length).toBeGreaterThanOrEqual(2);
+        expect(uncaught).toHaveLength(1);
+        expect(uncaught[0]?.message).toContain(`batch ${String(seqs[0])}`);
+        expect(uncaught[0]?.message).toContain('callback failure');
+      } finally {
+        process.off('uncaughtException', onUncaught);
+      }
+    });
+  });
+
+  describe.skipIf(!hasFaults)('panics', () => {
+    afterEach(() => {
+      clearFaults();
+    });
+
+    test('should surface a panic on the watcher thread as an error and stop watching', async () => {
+      subscribeWithCollector(testDirectory);
+      await sleep(subscribeDelay);
+
+      injectFaults({ panics: 1 });
+      await writeFile(path.join(testDirectory, 'panic.txt'), 'boom');
+      await sleep(500);
+
+      expect(collector.errors).toHaveLength(1);
+      expect(collector.errors[0]?.message).toContain('panicked');
+
+      // The failed subscription no longer delivers events
+      await writeFile(getFilename(testDirectory), 'after panic');
+      await sleep(500);
+      expect(collector.events).toHaveLength(0);
+    });
+
+    test('should reject ready once the watcher thread has failed', async () => {
+      subscribeWithCollector(testDirectory);
+      await subscription?.ready;
+
+      injectFaults({ panics: 1 });
+      await writeFile(path.join(testDirectory, 'panic.txt'), 'boom');
+      await sleep(500);
+
+      await expect(subscription?.ready).rejects.toThrow('panicked');
+    });
+
+    test('should stop only the subscription whose batch panicked', async () => {
+      const results: [WatchCallbackResult[], WatchCallbackResult[]] = [[], []];
+      const subscriptions = results.map((batches) => subscribe(testDirectory, (result) => batches.push(result)));
+      try {
+        await Promise.all(subscriptions.map((each) => each.ready));
+
+        injectFaults({ panics: 1 });
+        await writeFile(path.join(testDirectory, 'panic.txt'), 'boom');
+        await sleep(500);
+
+        const failed = results.findIndex((batches) => batches.some((batch) => batch.error?.message.includes('panicked')));
+        expect(failed).not.toBe(-1);
+        const survivor = results[1 - failed] ?? [];
+        expect(survivor.some((batch) => batch.error)).toBe(false);
+
+        // The other subscription on the same watcher keeps receiving events
+        const filePath = getFilename(testDirectory);
+        await writeFile(filePath, 'after panic');
+        await sleep(500);
+        expect(findEventByPath(survivor.flatMap((batch) => batch.events), filePath)?.type).toBe('create');
+        expect(findEventByPath(results[failed]?.flatMap((batch) => batch.events) ?? [], filePath)).toBeUndefined();
+      } finally {
+        subscriptions.forEach((each) => each.unsubscribe());
+      }
+    });
+  });
+
+  describe('process exit', () => {
+    test('should exit cleanly with live subscriptions', async () => {
+      const scriptPath = path.join(testDirectory, 'exit.mjs');
+      const indexPath = path.join(import.meta.dir, '..', 'index.js');
+      await writeFile(
+        scriptPath,
+        [
+          `import { subscribe } from ${JSON.stringify(indexPath)};`,
+          `import { writeFileSync } from 'node:fs';`,
+          `for (let index = 0; index < 3; index++) subscribe(${JSON.stringify(testDirectory)}, () => {});`,
+          `writeFileSync(${JSON.stringify(path.join(testDirectory, 'churn.txt'))}, String(Math.random()));`,
+          `setTimeout(() => process.exit(0), Number(process.argv[2]));`,
+        ].join('\n'),
+      );
+
+      for (let iteration = 0; iteration < 10; iteration++) {
+        const child = Bun.spawnSync([process.execPath, scriptPath, String((iteration % 3) * 50)]);
+        expect(child.exitCode).toBe(0);
+      }
+    });
+  });
+
+  describe('worker threads', () => {
+    test('should survive terminating workers with live subscriptions while files churn', async () => {
+      const workerPath = path.join(testDirectory, 'worker.mjs');
+      const indexPath = path.join(import.meta.dir, '..', 'index.js');
+      await writeFile(
+        workerPath,
+        [
+          `import { subscribe } from ${JSON.stringify(indexPath)};`,
+          `import { parentPort, workerData } from 'node:worker_threads';`,
+          `subscribe(workerData, () => {});`,
+          `parentPort.postMessage('ready');`,
+        ].join('\n'),
+      );
+
+      const directories = [path.join(testDirectory, 'a'), path.join(testDirectory, 'b')];
+      for (const directory of directories) await mkdir(directory);
+
+      let churning = true;
+      const churn = (async () => {
+        while (churning) {
+          for (const directory of directories) await writeFile(path.join(directory, `file${String(fileCounter++ % 10)}`), 'churn');
+          await sleep(1);
+        }
+      })();
+
+      try {
+        for (let iteration = 0; iteration < 10; iteration++) {
+          const workers = directories.map((directory) => new Worker(workerPath, { workerData: directory }));
+          await Promise.all(workers.map((worker) => new Promise((resolve) => worker.once('message', resolve))));
+          await sleep(Math.random() * 100);
+          await Promise.all(workers.map((worker) => worker.terminate()));
+        }
+      } finally {
+        churning = false;
+        await churn;
+      }
+    });
+  });
+
+  describe('rapid changes', () => {
+    test('should handle rapid file creations', async () => {
+      subscribeWithCollector(testDirectory);
+      await sleep(subscribeDelay);
+
+      const files: string[] = [];
+      for (let index = 0; index < 10; index++) {
+        const filePath = getFilename(testDirectory);
+        files.push(filePath);
+        await writeFile(filePath, `content ${index.toString()}`);
+      }
+
+      const events = await waitForEvents(collector, { minEvents: 10, timeout: 5000 });
+      for (const filePath of files) {
+        expect(findEventByPath(events, filePath)).toBeDefined();
+      }
+    });
+
+    test('should handle rapid file updates', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'initial');
+      await sleep(100);
+
+      subscribeWithCollector(testDirectory);
+      await sleep(subscribeDelay);
 
       for (let index = 0; index < 5; index++) {
         await writeFile(filePath, `update ${index.toString()}`);
@@ -779,6 +1650,44 @@ describe('watcher', () => {
       expectEventType(findEventByPath(await waitForEvents(collector), filePath), 'update');
     });
 
+    test('should order the delete of a replaced file before its create', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'initial');
+      await sleep(100);
+
+      subscribeWithCollector(testDirectory);
+      await sleep(subscribeDelay);
+
+      await unlink(filePath);
+      await writeFile(filePath, 'replaced');
+
+      await waitForEvents(collector);
+      await sleep(300);
+
+      const types = collector.events.filter((event) => event.path === filePath).map(({ type }) => type);
+      expect(types.at(-1)).not.toBe('delete');
+      if (types.includes('delete') && types.includes('create')) {
+        expect(types.indexOf('delete')).toBeLessThan(types.indexOf('create'));
+      }
+    });
+
+    test('should keep a lone delete or create as it is', async () => {
+      const deletedPath = getFilename(testDirectory);
+      await writeFile(deletedPath, 'initial');
+      await sleep(100);
+
+      subscribeWithCollector(testDirectory);
+      await sleep(subscribeDelay);
+
+      const createdPath = getFilename(testDirectory);
+      await unlink(deletedPath);
+      await writeFile(createdPath, 'content');
+
+      const events = await waitForEvents(collector, { minEvents: 2 });
+      expect(events.filter((event) => event.path === deletedPath).map(({ type }) => type)).toEqual(['delete']);
+      expectEventType(findEventByPath(events, createdPath), 'create');
+    });
+
     test('should handle create and immediate delete', async () => {
       subscribeWithCollector(testDirectory);
       await sleep(subscribeDelay);
@@ -801,14 +1710,2757 @@ describe('watcher', () => {
     });
   });
 
-  describe('subscription object', () => {
-    test('should return a subscription object with unsubscribe method', () => {
+  describe('initial scan', () => {
+    test('should emit create events for existing content', async () => {
+      await mkdir(path.join(testDirectory, 'src'));
+      await mkdir(path.join(testDirectory, 'node_modules', 'package'), { recursive: true });
+      const filePath = getFilename(testDirectory, 'src');
+      await writeFile(filePath, 'content');
+      await writeFile(path.join(testDirectory, 'node_modules', 'package', 'index.js'), 'content');
+
+      const batches: { events: WatchEvent[]; initial?: string }[] = [];
+      subscription = subscribe(testDirectory, ({ events, initial }) => batches.push({ events, initial }), { emitInitial: true, ignore: ['node_modules/**'] });
+      await subscription.ready;
+
+      expect(batches.map(({ initial }) => initial)).toEqual(['complete']);
+      const paths = batches[0]?.events.map((event) => event.path).sort();
+      expect(paths).toEqual([path.join(testDirectory, 'node_modules'), path.join(testDirectory, 'src'), filePath]);
+      expect(batches[0]?.events.every((event) => event.type === 'create' && event.isInitial)).toBe(true);
+    });
+
+    test('should deliver live events after the initial scan', async () => {
+      for (let index = 0; index < 1500; index++) {
+        await writeFile(path.join(testDirectory, `existing${index.toString()}`), 'content');
+      }
+
+      const batches: { events: WatchEvent[]; initial?: string }[] = [];
+      subscription = subscribe(testDirectory, ({ events, initial }) => batches.push({ events, initial }), { emitInitial: true });
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await subscription.ready;
+      await sleep(500);
+
+      expect(batches.map(({ initial }) => initial).slice(0, 2)).toEqual(['partial', 'complete']);
+
+      const scanned = batches.slice(0, 2).flatMap(({ events }) => events);
+      expect(scanned.length).toBeGreaterThanOrEqual(1500);
+      expect(scanned.every((event) => event.isInitial)).toBe(true);
+
+      const live = batches.slice(2);
+      const liveEvents = live.flatMap(({ events }) => events);
+      expect(live.every(({ initial }) => initial === undefined)).toBe(true);
+      expect(liveEvents.every((event) => !event.isInitial)).toBe(true);
+      expect(findEventByPath(liveEvents, filePath)).toBeDefined();
+    });
+  });
+
+  describe('rescan', () => {
+    test('should deliver synthetic events for changes not delivered yet', async () => {
+      const changedPath = getFilename(testDirectory);
+      const removedPath = getFilename(testDirectory);
+      const addedPath = getFilename(testDirectory);
+      await writeFile(changedPath, 'content');
+      await writeFile(removedPath, 'content');
+
+      const batches: { events: WatchEvent[]; synthetic?: boolean }[] = [];
+      subscription = subscribe(testDirectory, ({ events, synthetic }) => batches.push({ events, synthetic }), { emitInitial: true, ignore: ['*.log'] });
+      await subscription.ready;
+
+      // Rescanning before the debouncer hands over the live events finds the changes first
+      await writeFile(changedPath, 'changed content');
+      await unlink(removedPath);
+      await writeFile(addedPath, 'content');
+      await writeFile(path.join(testDirectory, 'ignored.log'), 'content');
+      expect(await subscription.triggerRescan()).toEqual({ created: 1, updated: 1, deleted: 1 });
+
+      const synthetic = batches.filter((batch) => batch.synthetic).flatMap(({ events }) => events);
+      expect(synthetic).toHaveLength(3);
+      expectEventType(findEventByPath(synthetic, addedPath), 'create');
+      expectEventType(findEventByPath(synthetic, changedPath), 'update');
+      expectEventType(findEventByPath(synthetic, removedPath), 'delete');
+
+      // Delivered live events are taken into account by later rescans
+      await sleep(300);
+      expect(await subscription.triggerRescan()).toEqual({ created: 0, updated: 0, deleted: 0 });
+    });
+
+    test('should reject paths outside the watched directory', () => {
       subscription = subscribe(testDirectory, () => {
         /* empty */
       });
 
-      expect(subscription).toBeDefined();
-      expect(typeof subscription.unsubscribe).toBe('function');
+      expect(() => subscription.triggerRescan('../elsewhere')).toThrow('Path is not inside the watched directory');
+    });
+  });
+
+  describe('getTree', () => {
+    test('should list what the subscription knows of the tree', async () => {
+      await mkdir(path.join(testDirectory, 'nested'));
+      const nestedPath = getFilename(testDirectory, 'nested');
+      await writeFile(nestedPath, 'content');
+      await writeFile(path.join(testDirectory, 'ignored.log'), 'content');
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      }, { emitInitial: true, ignore: ['*.log'] });
+      await subscription.ready;
+
+      const addedPath = getFilename(testDirectory);
+      await writeFile(addedPath, 'content');
+      await sleep(300);
+
+      const tree = subscription.getTree();
+      expect(tree.map((entry) => entry.path).sort()).toEqual([addedPath, path.join(testDirectory, 'nested'), nestedPath].sort());
+      expect(tree.find((entry) => entry.path === path.join(testDirectory, 'nested'))?.fileType).toBe('directory');
+      expect(subscription.getTree('nested').map((entry) => entry.path)).toEqual([path.join(testDirectory, 'nested'), nestedPath]);
+
+      const chunks: ScanEntry[][] = [];
+      expect(subscription.getTree(undefined, (entries) => chunks.push(entries))).toEqual([]);
+      expect(chunks.flat()).toHaveLength(3);
+    });
+
+    test('should throw without a recorded state', () => {
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      });
+
+      expect(() => subscription?.getTree()).toThrow('getTree needs the state of the tree');
+      expect(() => subscription?.getTree('../elsewhere')).toThrow('Path is not inside the watched directory');
+    });
+  });
+
+  describe('injectRaw', () => {
+    describe.skipIf(!hasMock)('with the mock backend', () => {
+      /** A subscription with the mock backend, collecting its batches */
+      const subscribeMock = (options: WatchOptions = {}) => {
+        const batches: WatchCallbackResult[] = [];
+        subscription = subscribe(testDirectory, (result) => batches.push(result), { ...options, backend: 'mock' });
+        return batches;
+      };
+
+      test('should pair the halves of a rename by their tracker', async () => {
+        const batches = subscribeMock();
+        expect(subscription?.backend).toBe('mock');
+
+        const from = path.join(testDirectory, 'from.txt');
+        const to = path.join(testDirectory, 'to.txt');
+        // The disk is left as the rename would have left it, which the pairing looks at
+        await writeFile(to, 'content');
+        const events: RawEvent[] = [
+          { kind: 'Modify(Name(From))', paths: [from], tracker: 7 },
+          { kind: 'Modify(Name(To))', paths: [to], tracker: 7 },
+        ];
+        subscription?.injectRaw(events);
+        await sleep(300);
+
+        const delivered = batches.flatMap((batch) => batch.events ?? []).map(({ path, type }) => ({ path, type }));
+        expect(delivered).toContainEqual({ path: from, type: 'delete' });
+        expect(delivered).toContainEqual({ path: to, type: 'create' });
+      });
+
+      test('should leave out ignored paths', async () => {
+        const batches = subscribeMock({ ignore: ['*.log'] });
+
+        const kept = path.join(testDirectory, 'kept.txt');
+        subscription?.injectRaw([
+          { kind: 'Create(File)', paths: [path.join(testDirectory, 'ignored.log')] },
+          { kind: 'Create(File)', paths: [kept] },
+        ]);
+        await sleep(300);
+
+        expect(batches.flatMap((batch) => batch.events ?? []).map((event) => event.path)).toEqual([kept]);
+      });
+
+      test('should reject unknown kinds', () => {
+        subscribeMock();
+        expect(() => subscription?.injectRaw([{ kind: 'Create(Symlink)', paths: [testDirectory] }])).toThrow("Unknown event kind 'Create(Symlink)'");
+      });
+    });
+
+    test('should reject other backends', () => {
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      });
+      expect(() => subscription?.injectRaw([])).toThrow("injectRaw is only available on subscriptions with backend: 'mock'");
+    });
+  });
+
+  describe.skipIf(!hasFaults)('injectFaults', () => {
+    afterEach(() => {
+      clearFaults();
+    });
+
+    test('should deliver backend errors ahead of the batch they came with', async () => {
+      const results: WatchCallbackResult[] = [];
+      subscription = subscribe(testDirectory, (result) => results.push(result));
+      await subscription.ready;
+
+      injectFaults({ backendErrors: 1 });
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await sleep(300);
+
+      const failed = results.findIndex((result) => result.error);
+      expect(results[failed]?.error?.message).toContain('backend error injected by injectFaults');
+      expect(results[failed]?.events).toEqual([]);
+      expect(findEventByPath(results.slice(failed + 1).flatMap((result) => result.events), filePath)?.type).toBe('create');
+    });
+
+    /** The errors delivered for `count` batches that each come with a backend error, and their `repeated` */
+    const repeatedErrors = async (count: number, options: WatchOptions) => {
+      const results: WatchCallbackResult[] = [];
+      subscription = subscribe(testDirectory, (result) => results.push(result), options);
+      await subscription.ready;
+
+      for (let i = 0; i < count; i++) {
+        injectFaults({ backendErrors: 1 });
+        await writeFile(getFilename(testDirectory), 'content');
+        await sleep(150);
+      }
+      await sleep(1000);
+      return results.filter((result) => result.error).map((result) => [result.error?.message, result.repeated]);
+    };
+
+    test('should deliver a repeated backend error once and then summarize the repeats', async () => {
+      expect(await repeatedErrors(4, { repeatedErrorWindowMs: 1000 })).toEqual([
+        ['backend error injected by injectFaults', undefined],
+        ['backend error injected by injectFaults (repeated 3 times in the last 1s)', 3],
+      ]);
+    });
+
+    test('should deliver every repeat with a window of 0', async () => {
+      const errors = await repeatedErrors(3, { repeatedErrorWindowMs: 0 });
+      expect(errors).toEqual(Array.from({ length: 3 }, () => ['backend error injected by injectFaults', undefined]));
+    });
+
+    test('should pass backend errors beside the JSON of the batch', async () => {
+      const results: WatchCallbackJson[] = [];
+      subscription = subscribe(testDirectory, (result) => results.push(result), { serialization: 'json' });
+      await subscription.ready;
+
+      injectFaults({ backendErrors: 1 });
+      await writeFile(getFilename(testDirectory), 'content');
+      await sleep(300);
+
+      const failed = results.find((result) => result.error);
+      expect(failed?.error?.message).toContain('backend error injected by injectFaults');
+      expect(JSON.parse(failed?.json ?? '{}')).toMatchObject({ events: [] });
+    });
+
+    test('should count batches the callback could not be called with', async () => {
+      const events: WatchEvent[] = [];
+      const lines: string[] = [];
+      subscription = subscribe(testDirectory, ({ events: batchEvents }) => events.push(...batchEvents), {
+        logLevel: 'warn',
+        onLog: (level, message) => lines.push(`${level} ${message}`),
+      });
+      await subscription.ready;
+
+      injectFaults({ queueFull: 1 });
+      const droppedPath = getFilename(testDirectory);
+      await writeFile(droppedPath, 'content');
+      await sleep(300);
+      const deliveredPath = getFilename(testDirectory);
+      await writeFile(deliveredPath, 'content');
+      await sleep(300);
+
+      expect(subscription.stats().droppedBatches).toBe(1);
+      expect(hasEventWithPath(events, droppedPath)).toBe(false);
+      expect(hasEventWithPath(events, deliveredPath)).toBe(true);
+      expect(lines.some((line) => line.startsWith('warn dropped batch'))).toBe(true);
+
+      subscription.resetStats();
+      expect(subscription.stats().droppedBatches).toBe(0);
+    });
+
+    test('should leave paths whose metadata cannot be read out of the tree', async () => {
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      }, { emitInitial: true });
+      await subscription.ready;
+
+      injectFaults({ statFailures: ['**/unreadable.txt'] });
+      const unreadablePath = path.join(testDirectory, 'unreadable.txt');
+      const readablePath = path.join(testDirectory, 'readable.txt');
+      await writeFile(unreadablePath, 'content');
+      await writeFile(readablePath, 'content');
+      await sleep(300);
+
+      expect(subscription.getTree().map((entry) => entry.path)).toEqual([readablePath]);
+    });
+
+    test('should fall back to polling when the native watcher fails to start', async () => {
+      injectFaults({ nativeFailures: 1 });
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, ({ events: batchEvents }) => events.push(...batchEvents), { pollIntervalMs: 100 });
+      expect(subscription.backend).toBe('polling');
+      expect(subscription.backendReason).toContain('failed to start');
+
+      await subscription.ready;
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await sleep(600);
+      expect(findEventByPath(events, filePath)?.type).toBe('create');
+      subscription.unsubscribe();
+
+      injectFaults({ nativeFailures: 1 });
+      expect(() =>
+        subscribe(testDirectory, () => {
+          /* empty */
+        }, { backend: 'native' }),
+      ).toThrow('native watcher failure injected by injectFaults');
+    });
+  });
+
+  describe('reconcileIntervalMs', () => {
+    afterEach(() => {
+      if (hasFaults) {
+        clearFaults();
+      }
+    });
+
+    test.skipIf(!hasFaults)('should deliver changes the watcher missed as synthetic events', async () => {
+      injectFaults({ missedPaths: ['**/missed.txt'] });
+      const batches: { events: WatchEvent[]; synthetic?: boolean }[] = [];
+      subscription = subscribe(testDirectory, ({ events, synthetic }) => batches.push({ events, synthetic }), { reconcileIntervalMs: 500 });
+      await subscription.ready;
+      // The first walk records the state once the tree has been quiet for a second
+      await sleep(1300);
+
+      const missedPath = path.join(testDirectory, 'missed.txt');
+      const seenPath = getFilename(testDirectory);
+      await writeFile(missedPath, 'content');
+      await writeFile(seenPath, 'content');
+      await sleep(2500);
+
+      const synthetic = batches.filter((batch) => batch.synthetic).flatMap(({ events }) => events);
+      expect(synthetic.map((event) => event.path)).toEqual([missedPath]);
+      expectEventType(findEventByPath(synthetic, missedPath), 'create');
+      expect(findEventByPath(batches.filter((batch) => !batch.synthetic).flatMap(({ events }) => events), seenPath)).toBeDefined();
+    });
+
+    test('should reject an interval of zero', () => {
+      expect(validateOptions(testDirectory, { reconcileIntervalMs: 0 }).problems.map(({ code }) => code)).toEqual(['INVALID_RECONCILE_INTERVAL']);
+    });
+  });
+
+  describe('heartbeatMs', () => {
+    test('should deliver heartbeats while nothing else is delivered', async () => {
+      const batches: WatchCallbackResult[] = [];
+      subscription = subscribe(testDirectory, (batch) => batches.push(batch), { heartbeatMs: 200 });
+      await subscription.ready;
+      await sleep(700);
+
+      const heartbeats = batches.filter((batch) => batch.heartbeat);
+      expect(heartbeats.length).toBeGreaterThanOrEqual(2);
+      expect(heartbeats.every((batch) => batch.events.length === 0)).toBe(true);
+    });
+
+    test('should stop delivering heartbeats after unsubscribe', async () => {
+      const batches: WatchCallbackResult[] = [];
+      subscription = subscribe(testDirectory, (batch) => batches.push(batch), { heartbeatMs: 100 });
+      await subscription.ready;
+      await sleep(300);
+      subscription.unsubscribe();
+
+      const delivered = batches.length;
+      await sleep(400);
+      expect(batches.length).toBe(delivered);
+    });
+
+    test('should reject an interval of zero', () => {
+      expect(validateOptions(testDirectory, { heartbeatMs: 0 }).problems.map(({ code }) => code)).toEqual(['INVALID_HEARTBEAT_INTERVAL']);
+    });
+  });
+
+  describe('debounceMs', () => {
+    /** Milliseconds from writing a file until the batch with it is delivered */
+    const deliveryLatency = async (file: string) => {
+      const start = Date.now();
+      await writeFile(file, 'content');
+      const deadline = start + 3000;
+      while (!hasEventWithPath(collector.events, file) && Date.now() < deadline) {
+        await sleep(10);
+      }
+      return Date.now() - start;
+    };
+
+    test('should deliver after the interval given and change it with setDebounce', async () => {
+      subscribeWithCollector(testDirectory, { debounceMs: 600 });
+      await sleep(subscribeDelay);
+
+      expect(await deliveryLatency(path.join(testDirectory, 'slow.txt'))).toBeGreaterThanOrEqual(500);
+      subscription?.setDebounce(50);
+      expect(await deliveryLatency(path.join(testDirectory, 'fast.txt'))).toBeLessThan(400);
+    });
+
+    test('should deliver events received before setDebounce exactly once', async () => {
+      subscribeWithCollector(testDirectory, { debounceMs: 300 });
+      await sleep(subscribeDelay);
+
+      const file = path.join(testDirectory, 'handover.txt');
+      await writeFile(file, 'content');
+      subscription?.setDebounce(50);
+      await sleep(800);
+
+      expect(collector.events.filter((event) => pathMatches(event.path, file))).toHaveLength(1);
+    });
+
+    test('should only change the interval of subscriptions with a watcher of their own', () => {
+      subscription = subscribe(testDirectory, () => {});
+      expect(() => subscription?.setDebounce(50)).toThrow(/setDebounce/);
+    });
+
+    test('should reject an interval of zero', () => {
+      expect(validateOptions(testDirectory, { debounceMs: 0 }).problems.map(({ code }) => code)).toEqual(['INVALID_DEBOUNCE']);
+      subscription = subscribe(testDirectory, () => {}, { isolated: true });
+      expect(() => subscription?.setDebounce(0)).toThrow('debounceMs must be greater than 0');
+    });
+  });
+
+  describe('getPendingEvents', () => {
+    test('should list the events the debouncer holds without taking them', async () => {
+      subscribeWithCollector(testDirectory, { debounceMs: 600, ignore: ['*.log'] });
+      await sleep(subscribeDelay);
+
+      const file = path.join(testDirectory, 'pending.txt');
+      await writeFile(file, 'content');
+      await writeFile(path.join(testDirectory, 'ignored.log'), 'content');
+      await sleep(100);
+
+      const pending = subscription?.getPendingEvents() ?? [];
+      expect(pending).toHaveLength(1);
+      expectEventType(findEventByPath(pending, file), 'create');
+      expect(hasEventWithPath(collector.events, file)).toBe(false);
+
+      await waitForEvents(collector);
+      expect(hasEventWithPath(collector.events, file)).toBe(true);
+      expect(subscription?.getPendingEvents()).toEqual([]);
+    });
+
+    test('should be empty after unsubscribe', async () => {
+      subscribeWithCollector(testDirectory, { debounceMs: 600 });
+      await sleep(subscribeDelay);
+      await writeFile(path.join(testDirectory, 'pending.txt'), 'content');
+      await sleep(100);
+
+      subscription?.unsubscribe();
+      expect(subscription?.getPendingEvents()).toEqual([]);
+    });
+  });
+
+  describe('sinceEventId', () => {
+    test.skipIf(process.platform === 'darwin')('should be rejected where no history of events is kept', () => {
+      expect(validateOptions(testDirectory, { sinceEventId: 1 }).problems.map(({ code }) => code)).toEqual(['INVALID_SINCE_EVENT_ID']);
+      expect(() => subscribe(testDirectory, () => {}, { sinceEventId: 1 })).toThrow('sinceEventId');
+      subscribeWithCollector(testDirectory);
+      expect(subscription?.eventId).toBeNull();
+    });
+
+    test.skipIf(process.platform !== 'darwin')('should replay the changes made since the event id of the last batch', async () => {
+      const eventIds: number[] = [];
+      subscription = subscribe(testDirectory, ({ eventId }) => eventId !== undefined && eventIds.push(eventId));
+      await sleep(subscribeDelay);
+      await writeFile(path.join(testDirectory, 'before.txt'), 'content');
+      await sleep(1000);
+      subscription.unsubscribe();
+      expect(eventIds.length).toBeGreaterThan(0);
+
+      const file = path.join(testDirectory, 'offline.txt');
+      await writeFile(file, 'content');
+      await sleep(1500);
+
+      const batches: { events: WatchEvent[]; historical?: boolean }[] = [];
+      subscription = subscribe(testDirectory, ({ events, historical }) => batches.push({ events, historical }), { sinceEventId: eventIds.at(-1) });
+      await sleep(1000);
+      expect(batches[0]?.historical).toBe(true);
+      expectEventType(findEventByPath(batches[0]?.events ?? [], file), 'create');
+    });
+  });
+
+  describe('circuitBreaker', () => {
+    /** Create `count` files in a directory of their own, as a runaway process would */
+    const storm = async (count: number) => {
+      const directory = path.join(testDirectory, 'generated');
+      await mkdir(directory, { recursive: true });
+      await Promise.all(Array.from({ length: count }, (_, index) => writeFile(path.join(directory, `file${index.toString()}.txt`), 'content')));
+      return directory;
+    };
+
+    test('should stop delivery and announce the storm with its hottest directories', async () => {
+      const batches: WatchCallbackResult[] = [];
+      subscription = subscribe(testDirectory, (batch) => batches.push(batch), { circuitBreaker: { maxEventsPerSecond: 20 } });
+      await subscription.ready;
+
+      const directory = await storm(200);
+      await sleep(300);
+
+      const opened = batches.filter((batch) => batch.storm?.state === 'open');
+      expect(opened).toHaveLength(1);
+      expect(opened[0]?.events).toEqual([]);
+      expect(opened[0]?.storm?.directories[0]?.path).toBe(directory);
+      expect(batches.flatMap((batch) => batch.events).length).toBeLessThanOrEqual(20);
+      expect(subscription.stats().stormTrips).toBe(1);
+    });
+
+    test('should deliver again after resume()', async () => {
+      const batches: WatchCallbackResult[] = [];
+      subscription = subscribe(testDirectory, (batch) => batches.push(batch), { circuitBreaker: { maxEventsPerSecond: 20 } });
+      await subscription.ready;
+
+      await storm(200);
+      await sleep(300);
+      expect(subscription.resume()).toBe(true);
+      expect(subscription.resume()).toBe(false);
+
+      const closed = batches.find((batch) => batch.storm?.state === 'closed');
+      expect(closed?.storm?.eventCount).toBe(subscription.stats().stormEvents);
+
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await sleep(300);
+      expectEventType(findEventByPath(batches.flatMap((batch) => batch.events), filePath), 'create');
+    });
+
+    test('should resume by itself once the storm has cooled down', async () => {
+      const batches: WatchCallbackResult[] = [];
+      subscription = subscribe(testDirectory, (batch) => batches.push(batch), { circuitBreaker: { maxEventsPerSecond: 20, cooldownMs: 200 } });
+      await subscription.ready;
+
+      await storm(200);
+      await sleep(1500);
+
+      expect(batches.filter((batch) => batch.storm).map((batch) => batch.storm?.state)).toEqual(['open', 'closed']);
+    });
+
+    test('should reject a limit of zero', () => {
+      expect(validateOptions(testDirectory, { circuitBreaker: { maxEventsPerSecond: 0 } }).problems.map(({ code }) => code)).toEqual([
+        'INVALID_CIRCUIT_BREAKER',
+      ]);
+    });
+  });
+
+  describe('maxMemoryBytes', () => {
+    /** Create `count` files one batch apart */
+    const writeBatches = async (count: number) => {
+      for (let index = 0; index < count; index++) {
+        await writeFile(path.join(testDirectory, `file${index.toString()}.txt`), 'content');
+        await sleep(200);
+      }
+    };
+
+    test('should drop the oldest unread batches and report what was dropped', async () => {
+      subscription = subscribe(testDirectory, null, { pull: true, maxMemoryBytes: 2000 });
+      await subscription.ready;
+
+      await writeBatches(8);
+
+      const batches = subscription.poll();
+      const shed = batches.filter((batch) => batch.memoryShed);
+      expect(shed.length).toBeGreaterThan(0);
+      expect(shed[0]?.error?.message).toContain('maxMemoryBytes');
+      expect(shed.reduce((total, batch) => total + (batch.memoryShed?.batches ?? 0), 0)).toBeGreaterThan(0);
+      expect(findEventByPath(batches.flatMap((batch) => batch.events), path.join(testDirectory, 'file0.txt'))).toBeUndefined();
+    });
+
+    test('should count what the subscription holds in stats()', async () => {
+      subscription = subscribe(testDirectory, null, { pull: true });
+      await subscription.ready;
+      const before = subscription.stats().memoryBytes;
+
+      await writeBatches(2);
+
+      expect(subscription.stats().memoryBytes).toBeGreaterThan(before);
+    });
+
+    test("should stop the subscription with 'close'", async () => {
+      subscription = subscribe(testDirectory, null, { pull: true, maxMemoryBytes: 2000, maxMemoryAction: 'close' });
+      await subscription.ready;
+
+      await writeBatches(8);
+
+      const batches = subscription.poll();
+      expect(batches.at(-1)?.error?.message).toContain('more than maxMemoryBytes');
+      expect(batches.some((batch) => batch.memoryShed)).toBe(false);
+    });
+
+    test('should reject a cap of zero and unknown actions', () => {
+      expect(validateOptions(testDirectory, { maxMemoryBytes: 0 }).problems.map(({ code }) => code)).toEqual(['INVALID_MAX_MEMORY']);
+      expect(
+        validateOptions(testDirectory, { maxMemoryBytes: 1000, maxMemoryAction: 'drop' as 'shed' }).problems.map(({ code }) => code),
+      ).toEqual(['INVALID_MAX_MEMORY']);
+    });
+  });
+
+  describe('emitMetadata', () => {
+    test.skipIf(isWindows)('should report permission and time changes as metadata events', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { emitMetadata: true });
+      await subscription.ready;
+
+      await chmod(filePath, 0o600);
+      await sleep(300);
+      await utimes(filePath, 1, 1);
+      await sleep(300);
+      await writeFile(filePath, 'changed content');
+      await sleep(300);
+
+      expect(events.map(({ type }) => type)).toEqual(['metadata', 'metadata', 'update']);
+      expect(subscription.stats()).toMatchObject({ metadataEvents: 2, updatedEvents: 1 });
+    });
+
+    test.skipIf(isWindows)('should report metadata changes as updates by default', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events));
+      await subscription.ready;
+
+      await chmod(filePath, 0o600);
+      await sleep(300);
+
+      expectEventType(findEventByPath(events, filePath), 'update');
+      expect(subscription.stats().metadataEvents).toBe(0);
+    });
+
+    test.skipIf(isWindows)('should let next() leave metadata events out', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      subscription = subscribe(testDirectory, null, { emitMetadata: true, pull: true });
+      await subscription.ready;
+
+      const next = subscription.next({ types: ['update'] });
+      await chmod(filePath, 0o600);
+      await sleep(300);
+      await writeFile(filePath, 'changed content');
+
+      expect(await next).toMatchObject({ path: filePath, type: 'update' });
+    });
+  });
+
+  describe('emitXattr', () => {
+    /** Set an extended attribute on `filePath` and remove it again, with the tools of the platform */
+    const setAndRemoveXattr = async (filePath: string) => {
+      if (process.platform === 'darwin') {
+        execFileSync('xattr', ['-w', 'com.example.tag', 'value', filePath]);
+        await sleep(300);
+        execFileSync('xattr', ['-d', 'com.example.tag', filePath]);
+      } else {
+        execFileSync('setfattr', ['-n', 'user.tag', '-v', 'value', filePath]);
+        await sleep(300);
+        execFileSync('setfattr', ['-x', 'user.tag', filePath]);
+      }
+      await sleep(300);
+    };
+
+    test.skipIf(process.platform !== 'darwin')('should report changes of extended attributes as metadata events with xattr', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { emitXattr: true, skipUnchanged: 'size+mtime' });
+      await subscription.ready;
+
+      await setAndRemoveXattr(filePath);
+
+      const changes = events.filter((event) => event.path === filePath);
+      expect(changes.length).toBeGreaterThan(0);
+      expect(changes.every(({ type, xattr }) => type === 'metadata' && xattr)).toBe(true);
+    });
+
+    test.skipIf(process.platform !== 'linux')('should report changes of extended attributes like other metadata where they cannot be told apart', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { emitXattr: true, emitMetadata: true });
+      await subscription.ready;
+
+      await setAndRemoveXattr(filePath);
+
+      const changes = events.filter((event) => event.path === filePath);
+      expect(changes.map(({ type }) => type)).toEqual(['metadata', 'metadata']);
+      expect(changes.some((event) => event.xattr)).toBe(false);
+    });
+
+    test.skipIf(!hasMock)('should mark the extended attribute changes the OS watcher reports and keep them with skipUnchanged', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      const batches: WatchCallbackResult[] = [];
+      subscription = subscribe(testDirectory, (result) => batches.push(result), { backend: 'mock', emitXattr: true, skipUnchanged: 'size+mtime' });
+
+      // The file is first seen by the update, after which neither change leaves it any different
+      for (const kind of ['Modify(Metadata(Permissions))', 'Modify(Metadata(Extended))', 'Modify(Metadata(Permissions))']) {
+        subscription.injectRaw([{ kind, paths: [filePath] }]);
+        await sleep(300);
+      }
+
+      const delivered = batches.flatMap((batch) => batch.events ?? []).map(({ type, xattr }) => ({ type, xattr }));
+      expect(delivered).toEqual([{ type: 'update' }, { type: 'metadata', xattr: true }]);
+    });
+  });
+
+  describe('coalesce', () => {
+    test('should report a file created and then written with its create alone', async () => {
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { coalesce: true });
+      await subscription.ready;
+
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await appendFile(filePath, ' more content');
+      await sleep(300);
+
+      expect(events.filter((event) => event.path === filePath).map(({ type }) => type)).toEqual(['create']);
+    });
+
+    test('should keep the events of a file created and deleted within a batch', async () => {
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { coalesce: true });
+      await subscription.ready;
+
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await appendFile(filePath, ' more content');
+      await rm(filePath);
+      await sleep(300);
+
+      expect(events.filter((event) => event.path === filePath).map(({ type }) => type)).toContain('delete');
+    });
+  });
+
+  describe('normalize', () => {
+    /** Path below the test directory with `/` as separator */
+    const relative = (eventPath: string) => path.relative(testDirectory, eventPath).split(path.sep).join('/');
+
+    // The same operations on every platform, with the one sequence of events they normalize to
+    const operations: { name: string; setup?: () => Promise<unknown>; run: () => Promise<unknown>; expected: [string, WatchEvent['type']][] }[] = [
+      {
+        name: 'a new file',
+        run: () => writeFile(path.join(testDirectory, 'file.txt'), 'content'),
+        expected: [['file.txt', 'create']],
+      },
+      {
+        name: 'an overwrite',
+        setup: () => writeFile(path.join(testDirectory, 'file.txt'), 'content'),
+        run: () => writeFile(path.join(testDirectory, 'file.txt'), 'new content'),
+        expected: [['file.txt', 'update']],
+      },
+      {
+        name: 'a rename',
+        setup: () => writeFile(path.join(testDirectory, 'old.txt'), 'content'),
+        run: () => rename(path.join(testDirectory, 'old.txt'), path.join(testDirectory, 'new.txt')),
+        expected: [
+          ['old.txt', 'delete'],
+          ['new.txt', 'create'],
+        ],
+      },
+      {
+        name: 'a delete',
+        setup: () => writeFile(path.join(testDirectory, 'file.txt'), 'content'),
+        run: () => rm(path.join(testDirectory, 'file.txt')),
+        expected: [['file.txt', 'delete']],
+      },
+      {
+        name: 'a new directory',
+        run: async () => {
+          await mkdir(path.join(testDirectory, 'directory'));
+          await writeFile(path.join(testDirectory, 'directory', 'file.txt'), 'content');
+        },
+        expected: [
+          ['directory', 'create'],
+          ['directory/file.txt', 'create'],
+        ],
+      },
+      {
+        name: 'a deleted directory',
+        setup: async () => {
+          await mkdir(path.join(testDirectory, 'directory', 'nested'), { recursive: true });
+          await writeFile(path.join(testDirectory, 'directory', 'file.txt'), 'content');
+          await writeFile(path.join(testDirectory, 'directory', 'nested', 'file.txt'), 'content');
+        },
+        run: () => rm(path.join(testDirectory, 'directory'), { recursive: true }),
+        expected: [
+          ['directory/nested/file.txt', 'delete'],
+          ['directory/file.txt', 'delete'],
+          ['directory/nested', 'delete'],
+          ['directory', 'delete'],
+        ],
+      },
+    ];
+
+    for (const { name, setup, run, expected } of operations) {
+      test(`should report ${name} the same way on every platform`, async () => {
+        await setup?.();
+        await sleep(subscribeDelay);
+        const events: WatchEvent[] = [];
+        subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { normalize: true });
+        await subscription.ready;
+
+        await run();
+        await sleep(300);
+
+        expect(events.map((event): [string, WatchEvent['type']] => [relative(event.path), event.type])).toEqual(expected);
+      });
+    }
+
+    test.skipIf(!hasMock)('should bring the sequences of each platform into the same form', async () => {
+      const batches: WatchCallbackResult[] = [];
+      subscription = subscribe(testDirectory, (batch) => batches.push(batch), { backend: 'mock', normalize: true });
+
+      const file = path.join(testDirectory, 'file.txt');
+      await writeFile(file, 'content');
+      // A new file as Windows reports it, with an update of its directory and of the file
+      subscription.injectRaw([
+        { kind: 'Create(Any)', paths: [file] },
+        { kind: 'Modify(Any)', paths: [testDirectory] },
+        { kind: 'Modify(Any)', paths: [file] },
+      ]);
+      await sleep(300);
+
+      expect(batches.flatMap((batch) => batch.events).map((event) => [relative(event.path), event.type])).toEqual([['file.txt', 'create']]);
+    });
+  });
+
+  describe('dropParentUpdates', () => {
+    test('should not report an update of the directory of a saved file', async () => {
+      const directory = path.join(testDirectory, 'directory');
+      const filePath = path.join(directory, 'file.txt');
+      await mkdir(directory);
+      await writeFile(filePath, 'content');
+      await sleep(subscribeDelay);
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { dropParentUpdates: true });
+      await subscription.ready;
+
+      // An atomic save, as editors do it
+      await writeFile(`${filePath}.tmp`, 'new content');
+      await rename(`${filePath}.tmp`, filePath);
+      await sleep(300);
+
+      expect(findEventByPath(events, filePath)).toBeDefined();
+      expect(events.filter((event) => event.path === directory && event.type === 'update')).toEqual([]);
+    });
+
+    test('should not report an update of the directory of a new file', async () => {
+      const directory = path.join(testDirectory, 'directory');
+      await mkdir(directory);
+      await sleep(subscribeDelay);
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { dropParentUpdates: true });
+      await subscription.ready;
+
+      const filePath = path.join(directory, 'file.txt');
+      await writeFile(filePath, 'content');
+      await sleep(300);
+
+      expectEventType(findEventByPath(events, filePath), 'create');
+      expect(events.filter((event) => event.path === directory && event.type === 'update')).toEqual([]);
+    });
+
+    test('should keep an update of a directory nothing else explains', async () => {
+      const directory = path.join(testDirectory, 'directory');
+      await mkdir(directory);
+      await sleep(subscribeDelay);
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { dropParentUpdates: true });
+      await subscription.ready;
+
+      await utimes(directory, 1, 1);
+      await sleep(300);
+
+      expectEventType(findEventByPath(events, directory), 'update');
+    });
+
+    test.skipIf(!hasMock)('should drop the update of the directory Windows reports with a new file', async () => {
+      const batches: WatchCallbackResult[] = [];
+      subscription = subscribe(testDirectory, (batch) => batches.push(batch), { backend: 'mock', dropParentUpdates: true });
+
+      const directory = path.join(testDirectory, 'directory');
+      const filePath = path.join(directory, 'file.txt');
+      await mkdir(directory);
+      await writeFile(filePath, 'content');
+      subscription.injectRaw([
+        { kind: 'Create(Any)', paths: [filePath] },
+        { kind: 'Modify(Any)', paths: [directory] },
+      ]);
+      await sleep(300);
+
+      expect(batches.flatMap((batch) => batch.events).map((event) => [event.path, event.type])).toEqual([[filePath, 'create']]);
+    });
+  });
+
+  describe('detectTypeChanges', () => {
+    /** The live events of `run`, as `[type, fileType]` of `target` */
+    const typesOf = async (target: string, run: () => Promise<unknown>) => {
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events.filter((event) => !event.isInitial)), {
+        emitInitial: true,
+        detectTypeChanges: true,
+      });
+      await subscription.ready;
+
+      await run();
+      await sleep(300);
+      return events.filter((event) => event.path === target).map((event) => [event.type, event.fileType]);
+    };
+
+    test('should report a file replaced by a directory as a delete and a create', async () => {
+      const target = path.join(testDirectory, 'output');
+      await writeFile(target, 'content');
+
+      const events = await typesOf(target, async () => {
+        await rm(target);
+        await mkdir(target);
+      });
+
+      expect(events).toEqual([
+        ['delete', 'file'],
+        ['create', 'directory'],
+      ]);
+    });
+
+    test('should report a directory replaced by a file as a delete and a create', async () => {
+      const target = path.join(testDirectory, 'output');
+      await mkdir(target);
+      await writeFile(path.join(target, 'file.txt'), 'content');
+
+      const events = await typesOf(target, async () => {
+        await rm(target, { recursive: true });
+        await writeFile(target, 'content');
+      });
+
+      expect(events).toEqual([
+        ['delete', 'directory'],
+        ['create', 'file'],
+      ]);
+    });
+
+    test('should report a type change spread over two batches the same way', async () => {
+      const target = path.join(testDirectory, 'output');
+      await writeFile(target, 'content');
+
+      const events = await typesOf(target, async () => {
+        await rm(target);
+        await sleep(300);
+        await mkdir(target);
+      });
+
+      expect(events).toEqual([
+        ['delete', 'file'],
+        ['create', 'directory'],
+      ]);
+    });
+
+    test('should leave events without fileType when the option is off', async () => {
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { emitInitial: true });
+      await subscription.ready;
+
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await sleep(300);
+
+      expect(findEventByPath(events, filePath)?.fileType).toBeUndefined();
+    });
+  });
+
+  describe('dedupeHardlinks', () => {
+    test.skipIf(isWindows)('should report an update of a hardlinked file once, with its other names as aliases', async () => {
+      const filePath = getFilename(testDirectory);
+      const linkPath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await link(filePath, linkPath);
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { dedupeHardlinks: true });
+      await subscription.ready;
+
+      await writeFile(filePath, 'changed content');
+      await appendFile(linkPath, ' and more');
+      await sleep(300);
+
+      const updates = events.filter((event) => event.type === 'update');
+      expect(updates).toHaveLength(1);
+      expect([updates[0]?.path, ...(updates[0]?.aliases ?? [])].toSorted()).toEqual([filePath, linkPath].toSorted());
+    });
+
+    test.skipIf(isWindows)('should keep the creates and deletes of every name', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { dedupeHardlinks: true });
+      await subscription.ready;
+
+      const linkPaths = [getFilename(testDirectory), getFilename(testDirectory)];
+      for (const linkPath of linkPaths) {
+        await link(filePath, linkPath);
+      }
+      await sleep(300);
+
+      for (const linkPath of linkPaths) {
+        expectEventType(findEventByPath(events, linkPath), 'create');
+      }
+    });
+  });
+
+  describe('skipSpecialFiles', () => {
+    test.skipIf(isWindows)('should leave out events for named pipes', async () => {
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { skipSpecialFiles: true });
+      await subscription.ready;
+
+      const fifoPath = getFilename(testDirectory);
+      const filePath = getFilename(testDirectory);
+      execFileSync('mkfifo', [fifoPath]);
+      await writeFile(filePath, 'content');
+      await sleep(300);
+      await unlink(fifoPath);
+      await sleep(300);
+
+      expect(findEventByPath(events, fifoPath)).toBeUndefined();
+      expectEventType(findEventByPath(events, filePath), 'create');
+      expect((await scan(testDirectory, { skipSpecialFiles: true })).map((entry) => entry.path)).toEqual([filePath]);
+    });
+
+    test.skipIf(isWindows)('should report named pipes by default', async () => {
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events));
+      await subscription.ready;
+
+      const fifoPath = getFilename(testDirectory);
+      execFileSync('mkfifo', [fifoPath]);
+      await sleep(300);
+
+      expectEventType(findEventByPath(events, fifoPath), 'create');
+    });
+  });
+
+  describe('includeRoot', () => {
+    test.skipIf(isWindows)('should deliver events for the watched directory itself by default', async () => {
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events));
+      await subscription.ready;
+
+      await utimes(testDirectory, 1, 1);
+      await sleep(300);
+
+      expect(findEventByPath(events, testDirectory)).toBeDefined();
+    });
+
+    test.skipIf(isWindows)('should leave out events for the watched directory itself when false', async () => {
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { includeRoot: false });
+      await subscription.ready;
+
+      const filePath = getFilename(testDirectory);
+      await utimes(testDirectory, 1, 1);
+      await writeFile(filePath, 'content');
+      await sleep(300);
+
+      expect(findEventByPath(events, testDirectory)).toBeUndefined();
+      expectEventType(findEventByPath(events, filePath), 'create');
+    });
+  });
+
+  describe('flagOutsideRoot', () => {
+    /** The events of `run` with a link in the watched directory to a directory outside it, which `run` is passed */
+    const eventsThroughLink = async (options: WatchOptions, run: (outside: string, link: string) => Promise<unknown>) => {
+      const outside = `${testDirectory}-outside`;
+      await mkdir(outside);
+      const link = path.join(testDirectory, 'link');
+      await symlink(outside, link);
+      try {
+        const events: WatchEvent[] = [];
+        subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), options);
+        await subscription.ready;
+
+        await run(outside, link);
+        await sleep(300);
+        return events;
+      } finally {
+        await rm(outside, { recursive: true, force: true });
+      }
+    };
+
+    test.skipIf(process.platform !== 'linux')('should drop events for files reached through a symlink to outside the root', async () => {
+      const events = await eventsThroughLink({}, async (outside) => {
+        await writeFile(path.join(outside, 'escaped.txt'), 'content');
+        await writeFile(path.join(testDirectory, 'inside.txt'), 'content');
+      });
+
+      expect(events.filter((event) => event.path.includes('escaped.txt'))).toEqual([]);
+      expectEventType(findEventByPath(events, path.join(testDirectory, 'inside.txt')), 'create');
+      expect(events.some((event) => event.outsideRoot)).toBe(false);
+    });
+
+    test.skipIf(process.platform !== 'linux')('should flag events outside the root when asked to', async () => {
+      const events = await eventsThroughLink({ flagOutsideRoot: true }, async (outside) => {
+        await writeFile(path.join(outside, 'escaped.txt'), 'content');
+      });
+
+      const escaped = events.filter((event) => event.path === path.join(testDirectory, 'link', 'escaped.txt'));
+      expect(escaped.length).toBeGreaterThan(0);
+      expect(escaped.every((event) => event.outsideRoot === true)).toBe(true);
+    });
+
+    test.skipIf(process.platform !== 'linux')('should report a rename out through a symlink as the file leaving the tree', async () => {
+      const from = path.join(testDirectory, 'moved.txt');
+      await writeFile(from, 'content');
+
+      const events = await eventsThroughLink({ flagOutsideRoot: true }, async (_, link) => {
+        await rename(from, path.join(link, 'moved.txt'));
+      });
+
+      expect(events.filter((event) => event.path === from).map((event) => [event.type, event.outsideRoot])).toEqual([['delete', undefined]]);
+      expect(events.filter((event) => event.path === path.join(testDirectory, 'link', 'moved.txt')).map((event) => [event.type, event.outsideRoot])).toEqual([
+        ['create', true],
+      ]);
+    });
+  });
+
+  describe('rawKinds', () => {
+    test('should attach the kind notify reported to each event', async () => {
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { rawKinds: true });
+      await subscription.ready;
+
+      const filePath = getFilename(testDirectory);
+      const directoryPath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await mkdir(directoryPath);
+      await sleep(300);
+
+      expect(findEventByPath(events, filePath)?.event.rawKind).toStartWith('Create(');
+      expect(findEventByPath(events, directoryPath)?.event.rawKind).toStartWith('Create(');
+    });
+
+    test('should leave rawKind out by default', async () => {
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events));
+      await subscription.ready;
+
+      await writeFile(getFilename(testDirectory), 'content');
+      await sleep(300);
+
+      expect(events.length).toBeGreaterThan(0);
+      expect(events.some((event) => 'rawKind' in event)).toBe(false);
+    });
+  });
+
+  describe('trackCoalescing', () => {
+    test('should count the raw events behind each event', async () => {
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { trackCoalescing: true });
+      await subscription.ready;
+
+      const filePath = getFilename(testDirectory);
+      const before = Date.now();
+      await writeFile(filePath, 'content');
+      await appendFile(filePath, ' more');
+      await appendFile(filePath, ' and more');
+      await sleep(300);
+
+      const counted = events.filter((event) => pathsEqual(event.path, filePath) && event.coalescedCount !== undefined);
+      expect(counted.length).toBeGreaterThan(0);
+      const [event] = counted;
+      expect(event.coalescedCount).toBeGreaterThan(1);
+      expect(event.firstSeenMs).toBeGreaterThanOrEqual(before - 1);
+      expect(event.lastSeenMs).toBeGreaterThanOrEqual(event.firstSeenMs ?? 0);
+    });
+  });
+
+  describe('root', () => {
+    test('should tell apart roots whose names are prefixes of each other', async () => {
+      const data = path.join(testDirectory, 'data');
+      const archive = path.join(testDirectory, 'data-archive');
+      const nested = path.join(data, 'nested');
+      await mkdir(nested, { recursive: true });
+      await mkdir(archive);
+
+      const events: WatchEvent[] = [];
+      const collect = ({ events: batch }: { events: WatchEvent[] }) => events.push(...batch);
+      const subscriptions = [subscribe(data, collect), subscribe(archive, collect), subscribe(nested, collect)];
+      try {
+        await Promise.all(subscriptions.map((each) => each.ready));
+
+        const archived = path.join(archive, 'file.txt');
+        const inside = path.join(nested, 'file.txt');
+        await writeFile(archived, 'content');
+        await writeFile(inside, 'content');
+        await sleep(300);
+
+        const rootsOf = (filePath: string) => new Set(events.filter((event) => pathsEqual(event.path, filePath)).map((event) => event.root));
+        expect(rootsOf(archived)).toEqual(new Set([archive]));
+        expect(rootsOf(inside)).toEqual(new Set([data, nested]));
+      } finally {
+        for (const each of subscriptions) {
+          each.unsubscribe();
+        }
+      }
+    });
+  });
+
+  describe('skipUnchanged', () => {
+    test('should drop an update that writes back the same contents', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { skipUnchanged: 'hash' });
+      await subscription.ready;
+
+      await writeFile(filePath, 'changed');
+      await sleep(300);
+      await writeFile(filePath, 'changed');
+      await sleep(300);
+
+      const updates = events.filter((event) => pathsEqual(event.path, filePath) && event.type === 'update');
+      expect(updates.length).toBe(1);
+      expect(subscription.stats().suppressedUnchanged).toBeGreaterThan(0);
+    });
+
+    test('should reject an unknown comparison', () => {
+      expect(() =>
+        subscribe(
+          testDirectory,
+          () => {
+            /* empty */
+          },
+          { skipUnchanged: 'contents' as 'hash' },
+        ),
+      ).toThrow("Invalid skipUnchanged 'contents', expected mtime, size+mtime or hash");
+    });
+  });
+
+  describe('lockedFileRetry', () => {
+    afterEach(() => {
+      if (hasFaults) {
+        clearFaults();
+      }
+    });
+
+    test.skipIf(!hasFaults)('should read the metadata of a locked file again after delivering its event', async () => {
+      injectFaults({ lockedPaths: ['**/locked-retried.txt'] });
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), { emitInitial: true });
+      await subscription.ready;
+
+      const filePath = path.join(testDirectory, 'locked-retried.txt');
+      await writeFile(filePath, 'content');
+      await sleep(500);
+
+      expect(findEventByPath(events, filePath)).toBeDefined();
+      expect(subscription.stats()).toMatchObject({ lockedRetries: 1, lockedFailures: 0 });
+    });
+
+    test.skipIf(!hasFaults)('should give up once the attempts run out', async () => {
+      injectFaults({ lockedPaths: ['**/locked-given-up.txt'] });
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events), {
+        emitInitial: true,
+        lockedFileRetry: { attempts: 1, delayMs: 0 },
+      });
+      await subscription.ready;
+
+      const filePath = path.join(testDirectory, 'locked-given-up.txt');
+      await writeFile(filePath, 'content');
+      await sleep(500);
+
+      expect(findEventByPath(events, filePath)).toBeDefined();
+      expect(subscription.stats()).toMatchObject({ lockedRetries: 0, lockedFailures: 1 });
+    });
+
+    test('should reject attempts of zero', () => {
+      const { problems } = validateOptions(testDirectory, { lockedFileRetry: { attempts: 0, delayMs: 0 } });
+      expect(problems.map(({ code, input }) => [code, input])).toEqual([['INVALID_RETRY', 'lockedFileRetry']]);
+    });
+  });
+
+  describe.skipIf(!isWindows)('long paths', () => {
+    // Nested deep enough that the directory alone is longer than MAX_PATH (260 characters)
+    const deepDirectory = () => path.join(testDirectory, ...Array.from({ length: 6 }, (_, index) => `${index.toString()}-${'d'.repeat(48)}`));
+
+    test('should report plain paths below a directory longer than MAX_PATH', async () => {
+      const directory = deepDirectory();
+      await mkdir(directory, { recursive: true });
+      expect(directory.length).toBeGreaterThan(300);
+
+      const events: WatchEvent[] = [];
+      subscription = subscribe(directory, (batch) => events.push(...batch.events), { ignore: ['*.log'] });
+      await subscription.ready;
+      const filePath = getFilename(directory);
+      await writeFile(filePath, 'content');
+      await writeFile(path.join(directory, 'ignored.log'), 'content');
+      await sleep(500);
+
+      expect(events.map((event) => event.path)).toEqual([filePath]);
+      expect(listSubscriptions().find(({ id }) => id === subscription?.id)?.path).toBe(directory);
+    });
+
+    test('should report plain paths for files deep below the watched directory', async () => {
+      const directory = deepDirectory();
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, (batch) => events.push(...batch.events));
+      await subscription.ready;
+      await mkdir(directory, { recursive: true });
+      const filePath = getFilename(directory);
+      await writeFile(filePath, 'content');
+      await sleep(500);
+
+      expect(hasEventWithPath(events, filePath)).toBe(true);
+      expect(events.some((event) => event.path.startsWith('\\\\?\\'))).toBe(false);
+    });
+
+    test('should scan and rescan a directory longer than MAX_PATH', async () => {
+      const directory = deepDirectory();
+      await mkdir(directory, { recursive: true });
+      const filePath = getFilename(directory);
+      await writeFile(filePath, 'content');
+
+      expect((await scan(directory)).map((entry) => entry.path)).toEqual([filePath]);
+
+      const events: WatchEvent[] = [];
+      subscription = subscribe(directory, (batch) => events.push(...batch.events), { emitInitial: true });
+      await subscription.ready;
+      expect(events.map((event) => event.path)).toEqual([filePath]);
+      expect(await subscription.triggerRescan()).toEqual({ created: 0, updated: 0, deleted: 0 });
+    });
+
+    test('should compare a directory longer than MAX_PATH against a snapshot', async () => {
+      const directory = deepDirectory();
+      await mkdir(directory, { recursive: true });
+      const changedPath = getFilename(directory);
+      const addedPath = getFilename(directory);
+      const snapshotPath = path.join(testDirectory, 'snapshot');
+      await writeFile(changedPath, 'content');
+
+      await writeSnapshot(directory, snapshotPath);
+      await writeFile(changedPath, 'changed content');
+      await writeFile(addedPath, 'content');
+
+      const events = await getEventsSince(directory, snapshotPath);
+      expect(events).toHaveLength(2);
+      expectEventType(findEventByPath(events, addedPath), 'create');
+      expectEventType(findEventByPath(events, changedPath), 'update');
+    });
+  });
+
+  describe('once', () => {
+    test('should resolve with the first matching event and unsubscribe', async () => {
+      const filePath = getFilename(testDirectory);
+      const promise = once(testDirectory, { ignore: ['*.log'], types: ['create'] });
+      await sleep(subscribeDelay);
+
+      await writeFile(path.join(testDirectory, 'ignored.log'), 'content');
+      await writeFile(filePath, 'content');
+
+      const event = await promise;
+      expect(event.path).toBe(filePath);
+      expect(event.type).toBe('create');
+      await sleep(100);
+      expectNoDispatchThreads();
+    });
+
+    test('should reject with ETIMEDOUT when nothing arrives in time', async () => {
+      await expect(once(testDirectory, { timeoutMs: 100 })).rejects.toMatchObject({ code: 'ETIMEDOUT' });
+      await sleep(100);
+      expectNoDispatchThreads();
+    });
+
+    test('should reject pending next() calls on unsubscribe', async () => {
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      });
+      const promise = subscription.next({ types: ['delete'] });
+      subscription.unsubscribe();
+
+      await expect(promise).rejects.toThrow('Subscription was closed');
+    });
+  });
+
+  describe('subscribeAsync', () => {
+    test('should resolve once the directory is watched, without losing events written right after', async () => {
+      collector = createCollector();
+      subscription = await subscribeAsync(testDirectory, ({ events }) => collector.events.push(...events), { ignore: ['*.log'] });
+
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await writeFile(path.join(testDirectory, 'ignored.log'), 'content');
+
+      const events = await waitForEvents(collector);
+      expect(findEventByPath(events, filePath)).toBeDefined();
+      expect(hasEventWithPath(events, path.join(testDirectory, 'ignored.log'))).toBe(false);
+    });
+
+    test('should reject with the errors subscribe throws', async () => {
+      const missing = path.join(testDirectory, 'missing');
+      expect(() => subscribe(missing, () => {})).toThrow(TypeError);
+      await expect(subscribeAsync(missing, () => {})).rejects.toThrow(new TypeError(`Directory does not exist: ${missing}`));
+      await expect(subscribeAsync(testDirectory, () => {}, { ignore: ['['] })).rejects.toThrow("Invalid glob pattern '['");
+
+      // Invalid callbacks reject as well instead of throwing
+      // @ts-expect-error - testing invalid input
+      const promise = subscribeAsync(testDirectory, 'not a function');
+      await expect(promise).rejects.toThrow(new TypeError('Callback must be a function'));
+    });
+  });
+
+  describe('pull', () => {
+    test('should buffer batches for nextBatch', async () => {
+      subscription = subscribe(testDirectory);
+      expect(await subscription.nextBatch(100)).toBeNull();
+
+      const filePath = getFilename(testDirectory);
+      const pending = subscription.nextBatch();
+      await writeFile(filePath, 'content');
+      expectEventType(findEventByPath((await pending)?.events ?? [], filePath), 'create');
+
+      const laterPath = getFilename(testDirectory);
+      await writeFile(laterPath, 'content');
+      await sleep(300);
+      expectEventType(findEventByPath((await subscription.nextBatch())?.events ?? [], laterPath), 'create');
+    });
+
+    test('should reject pending nextBatch() calls on unsubscribe', async () => {
+      subscription = subscribe(testDirectory, null, { pull: true });
+      const pending = subscription.nextBatch();
+      subscription.unsubscribe();
+
+      await expect(pending).rejects.toThrow('Subscription was closed');
+    });
+
+    test('should not mix callbacks and pulling', () => {
+      expect(() =>
+        subscribe(
+          testDirectory,
+          () => {
+            /* empty */
+          },
+          { pull: true },
+        ),
+      ).toThrow('A subscription with a callback cannot also be pulled');
+
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      });
+      expect(() => subscription?.nextBatch()).toThrow('nextBatch() is only available on subscriptions created without a callback');
+    });
+
+    test('should return every buffered batch from poll', async () => {
+      subscription = subscribe(testDirectory);
+      await subscription.ready;
+      expect(subscription.poll()).toEqual([]);
+
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await subscription.waitForQuiescence(200);
+      const events = subscription.poll().flatMap((batch) => batch.events);
+      expectEventType(findEventByPath(events, filePath), 'create');
+      expect(subscription.poll()).toEqual([]);
+    });
+  });
+
+  describe('subscribeRouted', () => {
+    test('should pass each callback only the events its pattern matches', async () => {
+      const styles = createCollector();
+      const rest = createCollector();
+      subscription = subscribeRouted(testDirectory, [
+        { callback: ({ events }: WatchCallbackResult) => rest.events.push(...events) },
+        { pattern: '*.css', callback: ({ events }: WatchCallbackResult) => styles.events.push(...events) },
+      ]);
+
+      const stylePath = path.join(testDirectory, 'main.css');
+      const scriptPath = path.join(testDirectory, 'main.js');
+      await writeFile(stylePath, 'body {}');
+      await writeFile(scriptPath, 'reload()');
+
+      await waitForEvents(styles);
+      await waitForEvents(rest);
+      expect(styles.events.map((event) => event.path)).toEqual([stylePath]);
+      expect(rest.events.map((event) => event.path)).toEqual([scriptPath]);
+    });
+
+    test('should pass events to every matching route with routeMatch all', async () => {
+      const first = createCollector();
+      const second = createCollector();
+      subscription = subscribeRouted(
+        testDirectory,
+        [
+          { pattern: '*.css', callback: ({ events }: WatchCallbackResult) => first.events.push(...events) },
+          { pattern: 'main.*', callback: ({ events }: WatchCallbackResult) => second.events.push(...events) },
+        ],
+        { routeMatch: 'all' },
+      );
+
+      const stylePath = path.join(testDirectory, 'main.css');
+      await writeFile(stylePath, 'body {}');
+
+      await waitForEvents(first);
+      await waitForEvents(second);
+      expect(findEventByPath(first.events, stylePath)).toBeDefined();
+      expect(findEventByPath(second.events, stylePath)).toBeDefined();
+    });
+
+    test('should reject invalid routes', () => {
+      expect(() => subscribeRouted(testDirectory, [])).toThrow('A routed subscription needs at least one route');
+      expect(() => subscribeRouted(testDirectory, [{ pattern: '[', callback: () => {} }])).toThrow("Invalid glob pattern '['");
+      expect(() =>
+        subscribeRouted(testDirectory, [
+          { callback: () => {} },
+          { callback: () => {} },
+        ]),
+      ).toThrow('Only one route can leave out its pattern');
+      // @ts-expect-error - testing invalid input
+      expect(() => subscribeRouted(testDirectory, [{ callback: () => {} }], { routeMatch: 'some' })).toThrow("Invalid routeMatch 'some'");
+    });
+  });
+
+  describe('watch', () => {
+    test('should yield batches and unsubscribe when the loop ends', async () => {
+      const filePath = getFilename(testDirectory);
+      setTimeout(() => void writeFile(filePath, 'content'), subscribeDelay);
+
+      let created: EventMatch;
+      for await (const { events } of watch(testDirectory)) {
+        created = findEventByPath(events, filePath);
+        if (created) break;
+      }
+
+      expectEventType(created, 'create');
+      await sleep(100);
+      expectNoDispatchThreads();
+    });
+
+    test('should finish once return() is called', async () => {
+      const iterator = watch(testDirectory);
+      expect(await iterator.return()).toEqual({ done: true, value: undefined });
+      expect(await iterator.next()).toEqual({ done: true, value: undefined });
+    });
+  });
+
+  describe('waitForQuiescence', () => {
+    test('should resolve once the tree has been quiet for the given time', async () => {
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      });
+      await subscription.ready;
+
+      const filePath = getFilename(testDirectory);
+      const writes = setInterval(() => void writeFile(filePath, Date.now().toString()), 50);
+      setTimeout(() => clearInterval(writes), 500);
+
+      const started = Date.now();
+      await Promise.all([subscription.waitForQuiescence(200), subscription.waitForQuiescence(300)]);
+      expect(Date.now() - started).toBeGreaterThanOrEqual(700);
+    });
+
+    test('should only count ignored paths with includeIgnored', async () => {
+      subscription = subscribe(
+        testDirectory,
+        () => {
+          /* empty */
+        },
+        { ignore: ['*.log'] },
+      );
+      await subscription.ready;
+
+      const writes = setInterval(() => void writeFile(path.join(testDirectory, 'ignored.log'), Date.now().toString()), 50);
+      try {
+        await subscription.waitForQuiescence(200, 1000);
+        await expect(subscription.waitForQuiescence(200, 500, { includeIgnored: true })).rejects.toMatchObject({ code: 'ETIMEDOUT' });
+      } finally {
+        clearInterval(writes);
+      }
+    });
+
+    test('should reject outstanding waiters on unsubscribe', async () => {
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      });
+      const pending = subscription.waitForQuiescence(10_000);
+      subscription.unsubscribe();
+
+      await expect(pending).rejects.toThrow('Subscription was closed before the tree became quiet');
+    });
+  });
+
+  describe('waitForFile', () => {
+    test('should resolve when the file is created', async () => {
+      const filePath = getFilename(testDirectory);
+      setTimeout(() => void writeFile(getFilename(testDirectory), 'content').then(() => writeFile(filePath, 'content')), subscribeDelay);
+
+      const event = await waitForFile(filePath);
+      expect(event.path).toBe(filePath);
+      expect(event.type).toBe('create');
+      expect(event.isInitial).toBe(false);
+      await sleep(100);
+      expectNoDispatchThreads();
+    });
+
+    test('should resolve right away for a file that already exists', async () => {
+      const filePath = getFilename(testDirectory);
+      const started = Date.now() - 1000;
+      await writeFile(filePath, 'content');
+
+      expect(await waitForFile(filePath, { event: 'create' })).toMatchObject({ path: filePath, type: 'create', isInitial: true });
+      expect(await waitForFile(filePath, { event: 'update', newerThan: started })).toMatchObject({ path: filePath, type: 'update', isInitial: true });
+    });
+
+    test('should wait for the next update and time out', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+
+      await expect(waitForFile(filePath, { event: 'update', timeoutMs: 200 })).rejects.toMatchObject({ code: 'ETIMEDOUT' });
+
+      setTimeout(() => void writeFile(filePath, 'changed'), subscribeDelay);
+      expect(await waitForFile(filePath, { event: 'update' })).toMatchObject({ path: filePath, type: 'update', isInitial: false });
+      await sleep(100);
+      expectNoDispatchThreads();
+    });
+  });
+
+  describe('expectChange', () => {
+    test('should swallow announced changes once each', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      const watching = subscribeWithCollector(testDirectory);
+      await watching.ready;
+
+      watching.expectChange([path.basename(filePath)]);
+      await writeFile(filePath, 'formatted');
+      await sleep(300);
+      expect(findEventByPath(collector.events, filePath)).toBeUndefined();
+      expect(watching.stats().suppressedExpectedChanges).toBe(1);
+
+      await writeFile(filePath, 'edited');
+      expectEventType(findEventByPath(await waitForEvents(collector), filePath), 'update');
+    });
+
+    test('should deliver deletes and changes after the window', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      const watching = subscribeWithCollector(testDirectory);
+      await watching.ready;
+
+      watching.expectChange([filePath], 50);
+      await sleep(100);
+      await writeFile(filePath, 'late');
+      expectEventType(findEventByPath(await waitForEvents(collector), filePath), 'update');
+
+      collector.events.length = 0;
+      watching.expectChange([filePath]);
+      await unlink(filePath);
+      expectEventType(findEventByPath(await waitForEvents(collector), filePath), 'delete');
+      expect(watching.stats().suppressedExpectedChanges).toBe(0);
+    });
+  });
+
+  describe('mute', () => {
+    test('should drop events for muted patterns until unmuted', async () => {
+      const mutedDirectory = path.join(testDirectory, 'node_modules');
+      await mkdir(mutedDirectory);
+      const watching = subscribeWithCollector(testDirectory);
+      await watching.ready;
+
+      const first = watching.mute(['node_modules/**']);
+      const second = watching.mute(['node_modules/**']);
+      const mutedPath = getFilename(mutedDirectory);
+      await writeFile(mutedPath, 'content');
+      first.unmute();
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await waitForEvents(collector);
+      expect(findEventByPath(collector.events, mutedPath)).toBeUndefined();
+      expect(watching.stats().mutedEvents).toBe(1);
+
+      second.unmute();
+      const heardPath = getFilename(mutedDirectory);
+      await writeFile(heardPath, 'content');
+      await sleep(300);
+      expectEventType(findEventByPath(collector.events, heardPath), 'create');
+    });
+
+    test('should end timed mutes on their own', async () => {
+      const watching = subscribeWithCollector(testDirectory);
+      await watching.ready;
+
+      watching.mute(['*.tmp'], 100);
+      await sleep(200);
+      const filePath = path.join(testDirectory, 'late.tmp');
+      await writeFile(filePath, 'content');
+      expectEventType(findEventByPath(await waitForEvents(collector), filePath), 'create');
+      expect(watching.stats().mutedEvents).toBe(0);
+    });
+  });
+
+  describe('listeners', () => {
+    test('should call listeners with the events of their type', async () => {
+      subscription = subscribe(testDirectory, null, { pull: false });
+      await subscription.ready;
+
+      const created = createCollector();
+      const deleted = createCollector();
+      const removed = createCollector();
+      const onRemoved = (event: WatchEvent) => removed.events.push(event);
+      subscription.on('create', (event) => created.events.push(event));
+      subscription.on('delete', (event) => deleted.events.push(event));
+      subscription.on('create', onRemoved);
+      subscription.off('create', onRemoved);
+
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      expectEventType(findEventByPath(await waitForEvents(created), filePath), 'create');
+      await unlink(filePath);
+      expectEventType(findEventByPath(await waitForEvents(deleted), filePath), 'delete');
+      expect(created.events.every((event) => event.type === 'create')).toBe(true);
+      expect(removed.events).toHaveLength(0);
+    });
+
+    test('should reject unknown event types', () => {
+      subscription = subscribe(testDirectory, null, { pull: false });
+      // @ts-expect-error - testing invalid input
+      expect(() => subscription?.on('rename', () => {})).toThrow("Invalid event 'rename'");
+    });
+  });
+
+  describe('isIgnored', () => {
+    test('should match patterns like subscriptions do', () => {
+      const options = { ignore: ['node_modules/**', '*.log', '/tmp/**'] };
+
+      expect(isIgnored('node_modules/react/index.js', testDirectory, options)).toBe(true);
+      expect(isIgnored(path.join(testDirectory, 'debug.log'), testDirectory, options)).toBe(true);
+      expect(isIgnored('/tmp/file', '/elsewhere', options)).toBe(true);
+      expect(isIgnored('src/index.js', testDirectory, options)).toBe(false);
+      expect(isIgnored('src/index.js', testDirectory)).toBe(false);
+    });
+
+    test('should ignore everything below a directory named by a plain name', () => {
+      const options = { ignore: ['node_modules', 'build/', '!node_modules/keep.js'] };
+
+      expect(isIgnored('node_modules/lodash/index.js', testDirectory, options)).toBe(true);
+      expect(isIgnored('build/out/main.js', testDirectory, options)).toBe(true);
+      expect(isIgnored('node_modules/keep.js', testDirectory, options)).toBe(false);
+      expect(isIgnored('node_modules_backup/index.js', testDirectory, options)).toBe(false);
+      expect(isIgnored('archive.log/readme.txt', testDirectory, { ignore: ['*.log'] })).toBe(false);
+    });
+
+    test.skipIf(!isWindows)('should accept backslashes as separators on Windows', () => {
+      expect(isIgnored('build\\temp\\a.obj', testDirectory, { ignore: ['build\\temp\\**'] })).toBe(true);
+      expect(isIgnored('build/temp/a.obj', testDirectory, { ignore: ['build/temp\\**'] })).toBe(true);
+      expect(isIgnored(path.join(testDirectory, 'dist', 'index.js'), testDirectory, { ignore: [path.join(testDirectory, 'dist')] })).toBe(true);
+      expect(isIgnored('notes[draft].md', testDirectory, { ignore: ['notes\\\\[draft\\\\].md'] })).toBe(true);
+    });
+
+    test('should accept negated patterns and reject invalid ones', () => {
+      expect(validateOptions(testDirectory, { ignore: ['node_modules', '!node_modules/keep.js', '!['] }).problems.map(({ input }) => input)).toEqual(['![']);
+    });
+
+    test('should reject invalid patterns', () => {
+      expect(() => isIgnored('file', testDirectory, { ignore: ['['] })).toThrow("Invalid glob pattern '['");
+    });
+  });
+
+  describe('validateOptions', () => {
+    test('should report every problem without throwing', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+
+      expect(validateOptions(testDirectory)).toEqual({ valid: true, directory: testDirectory, problems: [] });
+
+      const report = validateOptions(path.join(testDirectory, 'missing'), { ignore: ['[', 'valid/**', '{a'], retry: { attempts: 0, delayMs: 0 } });
+      expect(report.valid).toBe(false);
+      expect(report.problems.map(({ code, input }) => [code, input])).toEqual([
+        ['INVALID_PATTERN', '['],
+        ['INVALID_PATTERN', '{a'],
+        ['INVALID_RETRY', 'retry'],
+        ['DIRECTORY_NOT_FOUND', path.join(testDirectory, 'missing')],
+      ]);
+
+      expect(validateOptions(filePath).problems[0]?.code).toBe('NOT_A_DIRECTORY');
+      expectNoDispatchThreads();
+    });
+
+    test('should report the messages subscribe throws', () => {
+      const [problem] = validateOptions(testDirectory, { ignore: ['['] }).problems;
+      expect(() =>
+        subscribe(
+          testDirectory,
+          () => {
+            /* empty */
+          },
+          { ignore: ['['] },
+        ),
+      ).toThrow(problem?.message);
+    });
+  });
+
+  describe('capabilities', () => {
+    test('should describe the backend of this platform', () => {
+      const result = capabilities();
+      expect(['inotify', 'fsevents', 'windows', 'kqueue', 'polling']).toContain(result.backend);
+      expect(result.notifyVersion).toMatch(/^\d+\.\d+\.\d+/);
+      if (process.platform === 'linux') {
+        expect(result).toMatchObject({ backend: 'inotify', nativeRecursive: false, supportsCookies: true, supportsFileIds: true });
+        expect(result.watchLimit).toBeGreaterThan(0);
+      }
+    });
+
+    test('should match the backend of live subscriptions', async () => {
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      });
+      expect(subscription.backend).toBe(capabilities().backend);
+      if (process.platform === 'linux') {
+        expect(subscription.backend).toBe('inotify');
+      } else if (process.platform === 'darwin') {
+        expect(subscription.backend).toBe('fsevents');
+      } else if (process.platform === 'win32') {
+        expect(subscription.backend).toBe('windows');
+      }
+    });
+  });
+
+  describe('stats', () => {
+    test('should measure latency and count events by type until reset', async () => {
+      subscribeWithCollector(testDirectory);
+      await subscription?.ready;
+      expect(subscription?.stats()).toMatchObject({ createdEvents: 0, queuedBatches: 0, maxQueuedBatches: 0 });
+      expect(subscription?.stats().latencyAvgMs).toBeUndefined();
+
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await sleep(300);
+      await unlink(filePath);
+      await sleep(300);
+
+      const stats = subscription?.stats();
+      expect(stats).toMatchObject({ createdEvents: 1, deletedEvents: 1, queuedBatches: 0, maxQueuedBatches: 1 });
+      expect(stats?.latencyMinMs).toBeGreaterThan(0);
+      expect(stats?.latencyMaxMs).toBeGreaterThanOrEqual(stats?.latencyAvgMs ?? Infinity);
+      expect(stats?.latencyHistogram).toHaveLength(16);
+      expect(stats?.latencyHistogram.reduce((sum, count) => sum + count, 0)).toBe(2);
+
+      subscription?.resetStats();
+      expect(subscription?.stats()).toMatchObject({ createdEvents: 0, deletedEvents: 0, maxQueuedBatches: 0 });
+      expect(subscription?.stats().latencyMinMs).toBeUndefined();
+    });
+  });
+
+  describe('onLog', () => {
+    test('should log registration, ignore decisions and batches', async () => {
+      const lines: string[] = [];
+      subscription = subscribe(
+        testDirectory,
+        () => {
+          /* empty */
+        },
+        { ignore: ['*.log'], logLevel: 'debug', onLog: (level, message) => lines.push(`${level} ${message}`) },
+      );
+      await subscription.ready;
+
+      await writeFile(path.join(testDirectory, 'ignored.log'), 'content');
+      await writeFile(getFilename(testDirectory), 'content');
+      await sleep(300);
+      subscription.unsubscribe();
+      subscription = undefined;
+      await sleep(50);
+
+      expect(lines[0]).toStartWith(`info watching ${testDirectory}`);
+      expect(lines).toContain(`debug ignored create event for ${path.join(testDirectory, 'ignored.log')}`);
+      expect(lines.some((line) => line.startsWith('debug delivering 1 events'))).toBe(true);
+      expect(lines.some((line) => line.startsWith('trace'))).toBe(false);
+      expect(lines.at(-1)).toBe('info unsubscribed');
+    });
+
+    test('should reject unknown log levels', () => {
+      expect(() =>
+        subscribe(
+          testDirectory,
+          () => {
+            /* empty */
+          },
+          { logLevel: 'verbose' as WatchOptions['logLevel'] },
+        ),
+      ).toThrow("Invalid log level 'verbose'");
+      expect(validateOptions(testDirectory, { logLevel: 'verbose' as WatchOptions['logLevel'] }).problems[0]?.code).toBe('INVALID_LOG_LEVEL');
+    });
+  });
+
+  describe('debugEventLog', () => {
+    test('should capture raw events as NDJSON before filtering', async () => {
+      const watchedDirectory = path.join(testDirectory, 'watched');
+      await mkdir(watchedDirectory);
+      const logPath = path.join(testDirectory, 'events.ndjson');
+      subscribeWithCollector(watchedDirectory, { debugEventLog: logPath, ignore: ['*.tmp'] });
+      await subscription?.ready;
+
+      const ignoredPath = path.join(watchedDirectory, 'ignored.tmp');
+      const quotedPath = path.join(watchedDirectory, 'quoted "name"\n');
+      await writeFile(ignoredPath, 'content');
+      await writeFile(quotedPath, 'content');
+      await sleep(300);
+
+      const lines = (await readFile(logPath, 'utf8'))
+        .trimEnd()
+        .split('\n')
+        .map((line) => JSON.parse(line) as { time: number; kind: string; paths: string[]; tracker: number | null });
+      expect(lines.some(({ kind, paths }) => kind.startsWith('Create') && paths[0] === ignoredPath)).toBe(true);
+      expect(lines.some(({ paths }) => paths[0] === quotedPath)).toBe(true);
+      for (const line of lines) {
+        expect(line.time).toBeGreaterThan(0);
+        expect(line.tracker === null || typeof line.tracker === 'number').toBe(true);
+      }
+      expect(findEventByPath(collector.events, ignoredPath)).toBeUndefined();
+    });
+
+    test('should warn instead of failing when the file cannot be opened', async () => {
+      const logPath = path.join(testDirectory, 'missing', 'events.ndjson');
+      const warnings: WatchWarning[] = [];
+      subscription = subscribe(testDirectory, ({ warnings: batchWarnings }) => warnings.push(...(batchWarnings ?? [])), { debugEventLog: logPath });
+      await sleep(100);
+
+      expect(warnings).toEqual([expect.objectContaining({ path: logPath, kind: 'capture-failed' })]);
+    });
+  });
+
+  describe('serialization', () => {
+    test('should pass batches as JSON strings', async () => {
+      const batches: WatchCallbackResult[] = [];
+      subscription = subscribe(
+        testDirectory,
+        ({ error, json }) => {
+          expect(error).toBeUndefined();
+          batches.push(JSON.parse(json) as WatchCallbackResult);
+        },
+        { serialization: 'json' },
+      );
+      await subscription.ready;
+
+      const filename = path.join(testDirectory, 'quoted "name".txt');
+      await writeFile(filename, 'content');
+      await sleep(300);
+
+      expect(batches[0]?.seq).toBe(1);
+      expect(findEventByPath(batches.flatMap(({ events }) => events), filename)).toMatchObject({ type: 'create', isInitial: false });
+    });
+
+    test('should reject unknown serializations', () => {
+      expect(() =>
+        subscribe(
+          testDirectory,
+          () => {
+            /* empty */
+          },
+          { serialization: 'xml' as WatchOptions['serialization'] },
+        ),
+      ).toThrow("Invalid serialization 'xml'");
+      expect(validateOptions(testDirectory, { serialization: 'xml' as WatchOptions['serialization'] }).problems[0]?.code).toBe('INVALID_SERIALIZATION');
+    });
+  });
+
+  describe('callbackStyle', () => {
+    test('should call the callback like @parcel/watcher does', async () => {
+      const calls: [Error | null, ParcelEvent[]][] = [];
+      subscription = subscribe(testDirectory, (error, events) => calls.push([error, events]), { callbackStyle: 'node', heartbeatMs: 100 });
+      await subscription.ready;
+
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await sleep(300);
+
+      expect(calls.length).toBeGreaterThan(0);
+      expect(calls.every(([error, events]) => error === null && events.length > 0)).toBe(true);
+      expect(calls.flatMap(([, events]) => events)).toContainEqual({ path: filePath, type: 'create' });
+    });
+
+    test('should reject node style with JSON serialization', () => {
+      expect(validateOptions(testDirectory, { callbackStyle: 'node', serialization: 'json' }).problems.map(({ code }) => code)).toEqual(['INVALID_CALLBACK_STYLE']);
+      expect(validateOptions(testDirectory, { callbackStyle: 'promise' as 'node' }).problems.map(({ code }) => code)).toEqual(['INVALID_CALLBACK_STYLE']);
+    });
+  });
+
+  describe('ring', () => {
+    /** Create `count` files in a directory made before subscribing, with paths of varying length */
+    const createFiles = async (directory: string, count: number) => {
+      const paths: string[] = [];
+      for (let index = 0; index < count; index++) {
+        const filename = path.join(directory, `file-${index}-${'x'.repeat(index % 40)}.txt`);
+        paths.push(filename);
+        await writeFile(filename, 'content');
+      }
+      return paths;
+    };
+
+    test('should deliver every event when blocking', async () => {
+      const ring = createEventRing({ capacity: 64, arenaBytes: 2048 });
+      const reader = new EventRingReader(ring);
+      const seen = new Set<string>();
+      let signaled = 0;
+      subscription = subscribe(
+        testDirectory,
+        ({ events }) => {
+          expect(events).toEqual([]);
+          signaled++;
+          reader.read((type, filename, isInitial) => {
+            expect(['create', 'update', 'delete']).toContain(type);
+            expect(isInitial).toBe(false);
+            seen.add(filename);
+          });
+        },
+        { ring, ringOverflow: 'block' },
+      );
+      await subscription.ready;
+
+      const paths = await createFiles(testDirectory, 500);
+      await sleep(1000);
+
+      // 500 events do not fit in 64 records, so the reader had to make room while they were written
+      expect(signaled).toBeGreaterThan(1);
+      expect(paths.filter((filename) => !seen.has(filename))).toEqual([]);
+      expect(reader.lost).toBe(0);
+    });
+
+    test('should overwrite the oldest events without corrupting the ones read', async () => {
+      const ring = createEventRing({ capacity: 32, arenaBytes: 1024 });
+      const reader = new EventRingReader(ring);
+      const read: string[] = [];
+      subscription = subscribe(testDirectory, () => {
+        /* read below, falling behind the writer */
+      }, { ring });
+      await subscription.ready;
+
+      const production = createFiles(testDirectory, 500);
+      while (reader.pending > 0 || read.length === 0) {
+        reader.read((_type, filename) => read.push(filename));
+        await sleep(5);
+      }
+      const paths = new Set(await production);
+      await sleep(500);
+      reader.read((_type, filename) => read.push(filename));
+
+      expect(read.filter((filename) => !paths.has(filename))).toEqual([]);
+      expect(read.length + reader.lost).toBeGreaterThanOrEqual(paths.size);
+    });
+
+    test('should reject rings that cannot be written', () => {
+      const ring = createEventRing({ capacity: 4, arenaBytes: 64 });
+      const callback = () => {
+        /* empty */
+      };
+
+      subscription = subscribe(testDirectory, callback, { ring });
+      expect(() => subscribe(testDirectory, callback, { ring })).toThrow('Ring buffer is already written by another subscription');
+      expect(validateOptions(testDirectory, { ring }).problems[0]?.code).toBe('INVALID_RING');
+      subscription.unsubscribe();
+      expect(new EventRingReader(ring).attached).toBe(false);
+
+      expect(() => subscribe(testDirectory, null, { ring })).toThrow(TypeError);
+      expect(() => subscribe(testDirectory, callback, { ring: new SharedArrayBuffer(64) })).toThrow('not initialized by createEventRing');
+      expect(validateOptions(testDirectory, { ring, ringOverflow: 'drop' as WatchOptions['ringOverflow'] }).problems[0]?.code).toBe('INVALID_RING');
+      expect(() => createEventRing({ capacity: 3 })).toThrow(RangeError);
+    });
+  });
+
+  describe('cacheLimit', () => {
+    /** Create and delete `count` files a few times over */
+    const churn = async (count: number) => {
+      for (let round = 0; round < 3; round++) {
+        const paths = Array.from({ length: count }, () => getFilename(testDirectory));
+        for (const filename of paths) {
+          await writeFile(filename, 'content');
+        }
+        await sleep(200);
+        for (const filename of paths) {
+          await unlink(filename);
+        }
+      }
+      await sleep(300);
+    };
+
+    test('should hold the file id cache to its limit', async () => {
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      }, { cacheLimit: 50, isolated: true });
+      await subscription.ready;
+      await churn(100);
+
+      const { cacheEntries } = subscription.stats();
+      expect(cacheEntries).toBeLessThanOrEqual(50);
+      // Backends that pair renames with cookies keep no file ids at all
+      if (capabilities().supportsCookies) {
+        expect(cacheEntries).toBe(0);
+      }
+    });
+
+    test('should keep no file ids with a limit of 0', async () => {
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      }, { cacheLimit: 0, isolated: true });
+      await subscription.ready;
+      await churn(20);
+
+      expect(subscription.stats().cacheEntries).toBe(0);
+    });
+  });
+
+  describe('maxWatchedEntries', () => {
+    /** Create `count` files in the test directory */
+    const fill = async (count: number) => {
+      for (let i = 0; i < count; i++) {
+        await writeFile(getFilename(testDirectory), 'content');
+      }
+    };
+
+    test('should refuse a tree with more entries than the limit', async () => {
+      await fill(20);
+      let error: (Error & { code?: string; entries?: number; path?: string }) | undefined;
+      try {
+        subscribe(testDirectory, () => {
+          /* empty */
+        }, { maxWatchedEntries: 10 });
+      } catch (e) {
+        error = e as typeof error;
+      }
+      expect(error?.code).toBe('TOO_MANY_ENTRIES');
+      expect(error?.entries).toBeGreaterThan(10);
+      expect(error?.path).toBe(testDirectory);
+
+      await expect(subscribeAsync(testDirectory, () => {
+        /* empty */
+      }, { maxWatchedEntries: 10 })).rejects.toMatchObject({ code: 'TOO_MANY_ENTRIES' });
+    });
+
+    test('should refuse a filesystem root unless allowed', async () => {
+      const root = path.parse(testDirectory).root;
+      let error: (Error & { code?: string; path?: string }) | undefined;
+      try {
+        subscribe(root, () => {
+          /* empty */
+        });
+      } catch (e) {
+        error = e as typeof error;
+      }
+      expect(error?.code).toBe('LARGE_ROOT');
+      expect(error?.message).toContain('allowLargeRoots');
+
+      await expect(subscribeAsync(root, () => {
+        /* empty */
+      })).rejects.toMatchObject({ code: 'LARGE_ROOT' });
+      expect(validateOptions(root).problems.map(({ code }) => code)).toEqual(['LARGE_ROOT']);
+      expect(validateOptions(root, { allowLargeRoots: true }).valid).toBe(true);
+      expectNoDispatchThreads();
+    });
+
+    test.skipIf(isWindows)('should refuse a symlink to a filesystem root', async () => {
+      const link = path.join(testDirectory, 'root');
+      await symlink('/', link);
+      expect(validateOptions(link).problems.map(({ code, input }) => [code, input])).toEqual([['LARGE_ROOT', '/']]);
+    });
+
+    test('should watch the tree with a warning in warn mode', async () => {
+      await fill(20);
+      const warnings: WatchWarning[] = [];
+      subscription = subscribe(testDirectory, ({ warnings: batchWarnings }) => {
+        if (batchWarnings) warnings.push(...batchWarnings);
+      }, { maxWatchedEntries: 10, maxWatchedEntriesAction: 'warn' });
+      await subscription.ready;
+      await sleep(300);
+
+      expect(warnings.map(({ kind }) => kind)).toContain('too-many-entries');
+    });
+  });
+
+  describe('non-UTF-8 paths', () => {
+    /** A path in the test directory whose name holds bytes that are not valid UTF-8 */
+    const invalidName = (suffix: string) => Buffer.concat([Buffer.from(`${testDirectory}/name-`), Buffer.from([0xff, 0xfe]), Buffer.from(suffix)]);
+
+    test.skipIf(process.platform !== 'linux')('should flag lossy paths and pass their bytes', async () => {
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, ({ events: batchEvents }) => {
+        events.push(...batchEvents);
+      }, { ignore: ['**/*.log'] });
+      await subscription.ready;
+      await writeFile(invalidName('.txt'), 'content');
+      await writeFile(invalidName('.log'), 'content');
+      const valid = getFilename(testDirectory);
+      await writeFile(valid, 'content');
+      await sleep(300);
+
+      expect(events.map(({ path }) => path)).toEqual([`${testDirectory}/name-\uFFFD\uFFFD.txt`, valid]);
+      expect(events[0]?.lossy).toBe(true);
+      expect(Buffer.from(events[0]?.pathBytes ?? []).equals(invalidName('.txt'))).toBe(true);
+      expect(events[1]?.lossy).toBeUndefined();
+      expect(events[1]?.pathBytes).toBeUndefined();
+    });
+  });
+
+  describe('hostile file names', () => {
+    const names = [
+      'new\nline',
+      'tab\there',
+      'bidi \u202etxt.exe',
+      'emoji \u{1f600}\u{1f469}\u200d\u{1f4bb}',
+      'a'.repeat(250),
+      'trailing space ',
+      'trailing dot.',
+      'glob [*?]{a,b}',
+    ];
+
+    /** Create a file for each of `names` that the platform allows, returning the names created */
+    const create = async (suffix = '') => {
+      const created: string[] = [];
+      for (const name of names) {
+        try {
+          // The verbatim prefix keeps Windows from dropping trailing dots and spaces
+          await writeFile(isWindows ? `\\\\?\\${path.join(testDirectory, name + suffix)}` : path.join(testDirectory, name + suffix), 'content');
+          created.push(name);
+        } catch {
+          // Forbidden on this platform, such as newlines and tabs on Windows
+        }
+      }
+      return created;
+    };
+
+    test('should deliver the paths exactly', async () => {
+      const events: WatchEvent[] = [];
+      const jsonPaths: string[] = [];
+      subscription = subscribe(testDirectory, ({ events: batchEvents }) => events.push(...batchEvents));
+      const jsonSubscription = subscribe(testDirectory, ({ json }) => {
+        jsonPaths.push(...(JSON.parse(json) as WatchCallbackResult).events.map((event) => event.path));
+      }, { serialization: 'json' });
+      await subscription.ready;
+
+      const created = await create();
+      await sleep(300);
+      jsonSubscription.unsubscribe();
+
+      expect(created.length).toBeGreaterThan(0);
+      for (const name of created) {
+        const expected = path.join(testDirectory, name);
+        const event = events.find((candidate) => candidate.path === expected);
+        expect(event?.type).toBe('create');
+        expect(event?.lossy).toBeUndefined();
+        expect(jsonPaths).toContain(expected);
+      }
+    });
+
+    test('should match ignore patterns against them', async () => {
+      const ignore = ['**/*.log', `**/${'a'.repeat(250)}`];
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, ({ events: batchEvents }) => events.push(...batchEvents), { ignore });
+      await subscription.ready;
+
+      const created = await create('.log');
+      await create();
+      await sleep(300);
+
+      const paths = events.map((event) => event.path);
+      for (const name of created) {
+        expect(paths).not.toContain(path.join(testDirectory, `${name}.log`));
+        expect(isIgnored(`${name}.log`, testDirectory, { ignore })).toBe(true);
+      }
+      expect(paths).not.toContain(path.join(testDirectory, 'a'.repeat(250)));
+      expect(paths).toContain(path.join(testDirectory, 'emoji \u{1f600}\u{1f469}\u200d\u{1f4bb}'));
+    });
+
+    test('should keep every log line on one line', async () => {
+      const lines: string[] = [];
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      }, { logLevel: 'trace', onLog: (_level, message) => lines.push(message) });
+      await subscription.ready;
+
+      const created = await create();
+      await sleep(300);
+
+      if (created.includes('new\nline')) {
+        expect(lines.some((line) => line.includes('new\\nline'))).toBe(true);
+      }
+      expect(lines.filter((line) => /[\n\t\u202e]/u.test(line))).toEqual([]);
+    });
+
+    test.skipIf(!isWindows)('should not wedge on reserved device names', async () => {
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, ({ events: batchEvents }) => events.push(...batchEvents), { skipUnchanged: 'hash', skipSpecialFiles: true });
+      await subscription.ready;
+
+      for (const name of ['CON', 'NUL.txt', 'com1.log']) {
+        await writeFile(`\\\\?\\${path.join(testDirectory, name)}`, 'content');
+      }
+      const after = getFilename(testDirectory);
+      await writeFile(after, 'content');
+      await sleep(300);
+
+      expect(events.map((event) => event.path)).toEqual(expect.arrayContaining([path.join(testDirectory, 'CON'), path.join(testDirectory, 'NUL.txt'), after]));
+    });
+  });
+
+  describe('unicodeNormalization', () => {
+    const composed = 'caf\u00e9';
+    const decomposed = 'cafe\u0301';
+
+    /** Collect the paths of the events delivered for the test directory with `options` */
+    const collect = async (options: WatchOptions) => {
+      const paths: string[] = [];
+      subscription = subscribe(testDirectory, ({ events }) => {
+        paths.push(...events.map((event) => event.path));
+      }, options);
+      await subscription.ready;
+      return paths;
+    };
+
+    test.skipIf(process.platform !== 'darwin')('should deliver paths below the directory in the form asked for', async () => {
+      const paths = await collect({ unicodeNormalization: 'nfc' });
+      await writeFile(path.join(testDirectory, `${decomposed}.txt`), 'content');
+      await sleep(300);
+
+      expect(paths).toContain(`${testDirectory}/${composed}.txt`);
+      expect(paths).not.toContain(`${testDirectory}/${decomposed}.txt`);
+    });
+
+    test.skipIf(process.platform !== 'darwin')('should match ignore patterns against names in either form', async () => {
+      await mkdir(path.join(testDirectory, decomposed));
+      const paths = await collect({ unicodeNormalization: 'nfd', ignore: [`${composed}/**`] });
+      await writeFile(path.join(testDirectory, decomposed, 'ignored.txt'), 'content');
+      const kept = getFilename(testDirectory);
+      await writeFile(kept, 'content');
+      await sleep(300);
+
+      expect(paths).toEqual([kept]);
+    });
+
+    test('should reject unknown forms', () => {
+      const options = { unicodeNormalization: 'nfkc' as WatchOptions['unicodeNormalization'] };
+      expect(validateOptions(testDirectory, options).problems[0]?.code).toBe('INVALID_UNICODE_NORMALIZATION');
+      expect(() => subscribe(testDirectory, () => {
+        /* empty */
+      }, options)).toThrow("Invalid unicode normalization 'nfkc'");
+    });
+  });
+
+  describe('short names', () => {
+    test.skipIf(!isWindows)('should resolve 8.3 short names to long ones', async () => {
+      const directory = path.join(testDirectory, 'directory with a long name');
+      await mkdir(directory);
+      // Volumes can have 8.3 names turned off, leaving nothing to resolve
+      const shortDirectory = execFileSync('cmd', ['/c', `for %I in ("${directory}") do @echo %~sI`], { encoding: 'utf8' }).trim();
+      if (pathsEqual(shortDirectory, directory)) {
+        return;
+      }
+
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, ({ events: batchEvents }) => {
+        events.push(...batchEvents);
+      });
+      await subscription.ready;
+      await writeFile(path.join(shortDirectory, 'file.txt'), 'content');
+      await sleep(300);
+
+      expect(findEventByPath(events, path.join(directory, 'file.txt'))).toBeDefined();
+      expect(events.some((event) => event.path.includes(path.basename(shortDirectory)))).toBe(false);
+    });
+  });
+
+  describe('backend', () => {
+    test('should poll with backend polling', async () => {
+      const events: WatchEvent[] = [];
+      subscription = subscribe(testDirectory, ({ events: batchEvents }) => {
+        events.push(...batchEvents);
+      }, { backend: 'polling', pollIntervalMs: 100 });
+      expect(subscription.backend).toBe('polling');
+      expect(subscription.backendReason).toContain('polling');
+      expect(listSubscriptions().find(({ id }) => id === subscription?.id)?.backend).toBe('polling');
+
+      await subscription.ready;
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'content');
+      await sleep(600);
+
+      expect(findEventByPath(events, filePath)?.type).toBe('create');
+    });
+
+    test('should catch rewrites that keep the modification time when comparing contents', async () => {
+      const filePath = getFilename(testDirectory);
+      await writeFile(filePath, 'before');
+      // Like a file system keeping times in whole seconds or coarser, the rewrite below keeps the same time
+      const coarse = new Date(2020, 0, 1);
+      await utimes(filePath, coarse, coarse);
+
+      const rewrite = async (compare: 'mtime' | 'contents', content: string) => {
+        const events: WatchEvent[] = [];
+        subscription = subscribe(testDirectory, ({ events: batchEvents }) => {
+          events.push(...batchEvents);
+        }, { backend: 'polling', pollIntervalMs: 100, pollCompare: compare });
+        expect(subscription.pollCompare).toBe(compare);
+        await subscription.ready;
+        await sleep(300);
+
+        await writeFile(filePath, content);
+        await utimes(filePath, coarse, coarse);
+        await sleep(600);
+        subscription.unsubscribe();
+        subscription = undefined;
+        return events.filter((event) => pathsEqual(event.path, filePath));
+      };
+
+      expect(await rewrite('mtime', 'after!')).toEqual([]);
+      expect((await rewrite('contents', 'again!')).map((event) => event.type)).toContain('update');
+    });
+
+    test('should watch local directories natively by default', () => {
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      });
+      expect(subscription.backend).toBe(capabilities().backend);
+      expect(subscription.backendReason).toBeNull();
+      expect(subscription.pollCompare).toBeNull();
+    });
+
+    test('should not warn about local directories without autoFallback', async () => {
+      const warnings: WatchWarning[] = [];
+      subscription = subscribe(testDirectory, ({ warnings: batchWarnings }) => {
+        if (batchWarnings) warnings.push(...batchWarnings);
+      }, { autoFallback: false });
+      await subscription.ready;
+      await writeFile(getFilename(testDirectory), 'content');
+      await sleep(300);
+
+      expect(subscription.backend).toBe(capabilities().backend);
+      expect(warnings.filter(({ kind }) => kind === 'unreliable-file-system')).toEqual([]);
+    });
+
+    describe.skipIf(!hasFaults)('when the native watcher fails to start', () => {
+      afterEach(() => {
+        clearFaults();
+      });
+
+      test('should fall back to polling with a warning', async () => {
+        injectFaults({ nativeFailures: 1 });
+        const events: WatchEvent[] = [];
+        const warnings: WatchWarning[] = [];
+        subscription = subscribe(testDirectory, ({ events: batchEvents, warnings: batchWarnings }) => {
+          events.push(...batchEvents);
+          if (batchWarnings) warnings.push(...batchWarnings);
+        }, { pollIntervalMs: 100 });
+        expect(subscription.backend).toBe('polling');
+        expect(subscription.backendReason).toContain('failed to start');
+
+        await subscription.ready;
+        const filePath = getFilename(testDirectory);
+        await writeFile(filePath, 'content');
+        await sleep(600);
+
+        expect(findEventByPath(events, filePath)?.type).toBe('create');
+        expect(warnings.map(({ kind }) => kind)).toContain('backend-fallback');
+      });
+
+      test('should fail when the native watcher was asked for', () => {
+        injectFaults({ nativeFailures: 1 });
+        expect(() =>
+          subscribe(
+            testDirectory,
+            () => {
+              /* empty */
+            },
+            { backend: 'native' },
+          ),
+        ).toThrow('native watcher failure injected by injectFaults');
+      });
+    });
+
+    test('should report an invalid backend or poll interval', () => {
+      expect(validateOptions(testDirectory, { backend: 'inotify' as 'auto' }).problems.map(({ code, input }) => [code, input])).toEqual([
+        ['INVALID_BACKEND', 'inotify'],
+      ]);
+      expect(validateOptions(testDirectory, { pollIntervalMs: 0 }).problems.map(({ code, input }) => [code, input])).toEqual([
+        ['INVALID_BACKEND', 'pollIntervalMs'],
+      ]);
+    });
+
+    test.skipIf(!isWindows)('should poll UNC paths and keep their spelling', async () => {
+      // The administrative share of the drive reaches the test directory over SMB
+      const uncDirectory = `\\\\localhost\\${testDirectory.slice(0, 1)}$${testDirectory.slice(2)}`;
+      try {
+        await stat(uncDirectory);
+      } catch {
+        return;
+      }
+
+      const events: WatchEvent[] = [];
+      subscription = subscribe(uncDirectory, ({ events: batchEvents }) => {
+        events.push(...batchEvents);
+      }, { pollIntervalMs: 100 });
+      expect(subscription.backend).toBe('polling');
+      expect(subscription.backendReason).toContain('localhost');
+
+      await subscription.ready;
+      await writeFile(getFilename(testDirectory), 'content');
+      await sleep(600);
+
+      expect(events.length).toBeGreaterThan(0);
+      expect(events.every((event) => event.path.startsWith(uncDirectory))).toBe(true);
+    });
+  });
+
+  describe('listSubscriptions', () => {
+    test('should list live subscriptions until they are unsubscribed', async () => {
+      const before = Date.now();
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      }, { label: 'listed' });
+      await subscription.ready;
+      await writeFile(getFilename(testDirectory), 'content');
+      await sleep(300);
+
+      const entry = listSubscriptions().find(({ id }) => id === subscription?.id);
+      expect(entry).toMatchObject({ path: testDirectory, label: 'listed', backend: subscription.backend });
+      expect(entry?.eventCount).toBeGreaterThan(0);
+      expect(entry?.createdAt).toBeGreaterThanOrEqual(before);
+
+      const { id } = subscription;
+      subscription.unsubscribe();
+      subscription = undefined;
+      expect(listSubscriptions().some((entry) => entry.id === id)).toBe(false);
+    });
+  });
+
+  describe('journal', () => {
+    test('should replay journaled batches after a cursor', async () => {
+      const journalPath = path.join(testDirectory, 'journal');
+      const watchedDirectory = path.join(testDirectory, 'watched');
+      await mkdir(watchedDirectory);
+
+      const cursors: number[] = [];
+      subscription = subscribe(watchedDirectory, ({ cursor }) => cursors.push(cursor ?? 0), { journalPath });
+      await subscription.ready;
+      const firstPath = getFilename(watchedDirectory);
+      const secondPath = getFilename(watchedDirectory);
+      await writeFile(firstPath, 'content');
+      await sleep(300);
+      await writeFile(secondPath, 'content');
+      await sleep(300);
+      subscription.unsubscribe();
+
+      expect(cursors).toEqual([1, 2]);
+
+      // Only the batch after the cursor is replayed
+      subscription = subscribeSince(journalPath, 1, ({ events }) => collector.events.push(...events));
+      const events = await waitForEvents(collector);
+      expect(findEventByPath(events, firstPath)).toBeUndefined();
+      expectEventType(findEventByPath(events, secondPath), 'create');
+    });
+
+    test('should reject corrupt journals and cursors that are no longer kept', async () => {
+      const journalPath = path.join(testDirectory, 'journal');
+      const watchedDirectory = path.join(testDirectory, 'watched');
+      await mkdir(watchedDirectory);
+
+      subscription = subscribe(
+        watchedDirectory,
+        () => {
+          /* empty */
+        },
+        { journalMaxBytes: 1, journalPath },
+      );
+      await subscription.ready;
+      for (let index = 0; index < 3; index++) {
+        await writeFile(getFilename(watchedDirectory), 'content');
+        await sleep(300);
+      }
+      subscription.unsubscribe();
+
+      const callback = () => {
+        /* empty */
+      };
+      expect(() => subscribeSince(journalPath, 0, callback)).toThrow('Journal no longer contains the batches after cursor 0');
+
+      await writeFile(journalPath, 'not a journal');
+      expect(() => subscribeSince(journalPath, 0, callback)).toThrow('Journal is corrupt');
+    });
+  });
+
+  describe('scan', () => {
+    test('should list entries with the ignore rules of a subscription', async () => {
+      await mkdir(path.join(testDirectory, 'src'));
+      await mkdir(path.join(testDirectory, 'node_modules', 'package'), { recursive: true });
+      const filePath = getFilename(testDirectory, 'src');
+      await writeFile(filePath, 'content');
+      await writeFile(path.join(testDirectory, 'debug.log'), 'content');
+
+      const entries = await scan(testDirectory, { ignore: ['node_modules/**', '*.log'] });
+
+      expect(entries.map((entry) => entry.path).sort()).toEqual([path.join(testDirectory, 'node_modules'), path.join(testDirectory, 'src'), filePath]);
+      const file = entries.find((entry) => entry.path === filePath);
+      expect(file?.fileType).toBe('file');
+      expect(file?.size).toBe(7);
+      expect(file?.mtimeMs).toBeGreaterThan(0);
+      expect(entries.find((entry) => entry.path === path.join(testDirectory, 'src'))?.fileType).toBe('directory');
+    });
+
+    test('should reject for a missing directory', async () => {
+      await expect(scan(path.join(testDirectory, 'missing'))).rejects.toThrow('Directory does not exist');
+    });
+
+    test.skipIf(process.platform !== 'linux')('should not descend into other file systems with oneFileSystem', async () => {
+      // devpts is mounted on /dev/pts, below the devtmpfs or tmpfs of /dev
+      const below = (entries: ScanEntry[]) => entries.filter((entry) => entry.path.startsWith('/dev/pts/'));
+      expect(below(await scan('/dev'))).not.toEqual([]);
+
+      const entries = await scan('/dev', { oneFileSystem: true });
+      expect(below(entries)).toEqual([]);
+      expect(entries.find((entry) => entry.path === '/dev/pts')?.fileType).toBe('directory');
+    });
+
+    test.skipIf(!canDenyPermissions)('should skip unreadable subdirectories unless strict', async () => {
+      const lockedDirectory = path.join(testDirectory, 'locked');
+      await mkdir(path.join(lockedDirectory, 'inner'), { recursive: true });
+      await chmod(lockedDirectory, 0o000);
+
+      try {
+        expect((await scan(testDirectory)).map((entry) => entry.path)).toEqual([lockedDirectory]);
+        await expect(scan(testDirectory, { strict: true })).rejects.toThrow('Permission denied');
+      } finally {
+        await chmod(lockedDirectory, 0o755);
+      }
+    });
+  });
+
+  describe('diffTrees', () => {
+    test('should turn the difference between two scans into events', async () => {
+      const keptPath = getFilename(testDirectory);
+      const changedPath = getFilename(testDirectory);
+      const removedPath = getFilename(testDirectory);
+      const addedPath = getFilename(testDirectory);
+      await writeFile(keptPath, 'content');
+      await writeFile(changedPath, 'content');
+      await writeFile(removedPath, 'content');
+
+      const before = await scan(testDirectory);
+      await writeFile(changedPath, 'changed content');
+      await unlink(removedPath);
+      await writeFile(addedPath, 'content');
+      const after = await scan(testDirectory);
+
+      const events = diffTrees(before, after);
+      expect(events).toHaveLength(3);
+      expectEventType(findEventByPath(events, addedPath), 'create');
+      expectEventType(findEventByPath(events, changedPath), 'update');
+      expectEventType(findEventByPath(events, removedPath), 'delete');
+    });
+
+    test('should pass large results to the chunk callback', () => {
+      const entries = Array.from({ length: 25_000 }, (_, index) => ({ fileType: 'file' as const, mtimeMs: 0, path: `/file${index.toString()}`, size: 0 }));
+
+      const chunks: number[] = [];
+      expect(diffTrees([], entries, (events) => chunks.push(events.length))).toEqual([]);
+      expect(chunks).toEqual([10_000, 10_000, 5000]);
+    });
+  });
+
+  describe('snapshots', () => {
+    test('should report changes made since a snapshot was written', async () => {
+      const snapshotPath = path.join(testDirectory, 'snapshot');
+      const watchedDirectory = path.join(testDirectory, 'watched');
+      await mkdir(watchedDirectory);
+      const changedPath = getFilename(watchedDirectory);
+      const removedPath = getFilename(watchedDirectory);
+      const addedPath = getFilename(watchedDirectory);
+      await writeFile(changedPath, 'content');
+      await writeFile(removedPath, 'content');
+      await writeFile(path.join(watchedDirectory, 'ignored.log'), 'content');
+
+      await writeSnapshot(watchedDirectory, snapshotPath, { ignore: ['*.log'] });
+      await writeFile(changedPath, 'changed content');
+      await unlink(removedPath);
+      await writeFile(addedPath, 'content');
+      await writeFile(path.join(watchedDirectory, 'other.log'), 'content');
+
+      const events = await getEventsSince(watchedDirectory, snapshotPath, { ignore: ['*.log'] });
+      expect(events).toHaveLength(3);
+      expectEventType(findEventByPath(events, addedPath), 'create');
+      expectEventType(findEventByPath(events, changedPath), 'update');
+      expectEventType(findEventByPath(events, removedPath), 'delete');
+    });
+
+    test('should reject for missing or corrupt snapshots', async () => {
+      const snapshotPath = path.join(testDirectory, 'snapshot');
+      await expect(getEventsSince(testDirectory, snapshotPath)).rejects.toThrow('Snapshot does not exist');
+
+      await writeFile(snapshotPath, 'not a snapshot');
+      await expect(getEventsSince(testDirectory, snapshotPath)).rejects.toThrow('Snapshot is corrupt');
+    });
+
+    test('should report the same changes with changeJournal, from the journal or by walking the tree', async () => {
+      const snapshotPath = path.join(testDirectory, 'snapshot');
+      const watchedDirectory = path.join(testDirectory, 'watched');
+      const movedDirectory = path.join(watchedDirectory, 'moved');
+      await mkdir(path.join(watchedDirectory, 'directory'), { recursive: true });
+      const removedPath = getFilename(watchedDirectory, 'directory');
+      await writeFile(removedPath, 'content');
+      const options = { changeJournal: true, ignore: ['*.log'] };
+
+      await writeSnapshot(watchedDirectory, snapshotPath, options);
+      const addedPath = getFilename(watchedDirectory);
+      await writeFile(addedPath, 'content');
+      // Brings the snapshot up to date, from the journal where it can be read
+      await writeSnapshot(watchedDirectory, snapshotPath, options);
+      expect(await getEventsSince(watchedDirectory, snapshotPath, options)).toEqual([]);
+
+      await rename(path.join(watchedDirectory, 'directory'), movedDirectory);
+      await writeFile(path.join(watchedDirectory, 'ignored.log'), 'content');
+      const events = await getEventsSince(watchedDirectory, snapshotPath, options);
+      expect(events).toHaveLength(4);
+      expectEventType(findEventByPath(events, path.join(watchedDirectory, 'directory')), 'delete');
+      expectEventType(findEventByPath(events, removedPath), 'delete');
+      expectEventType(findEventByPath(events, movedDirectory), 'create');
+      expectEventType(findEventByPath(events, path.join(movedDirectory, path.basename(removedPath))), 'create');
+    });
+  });
+
+  describe('subscription object', () => {
+    test('should return a subscription object with unsubscribe method', () => {
+      subscription = subscribe(testDirectory, () => {
+        /* empty */
+      });
+
+      expect(subscription).toBeDefined();
+      expect(typeof subscription.unsubscribe).toBe('function');
+    });
+
+    test('should report statistics', () => {
+      subscription = subscribe(
+        testDirectory,
+        () => {
+          /* empty */
+        },
+        { ignorePermissionErrors: true },
+      );
+
+      expect(subscription.stats()).toEqual({
+        suppressedPermissionErrors: 0,
+        suppressedExpectedChanges: 0,
+        mutedEvents: 0,
+        suppressedUnchanged: 0,
+        latencyHistogram: Array.from({ length: 16 }, () => 0),
+        queuedBatches: 0,
+        maxQueuedBatches: 0,
+        droppedBatches: 0,
+        createdEvents: 0,
+        updatedEvents: 0,
+        deletedEvents: 0,
+      });
+    });
+
+    test('should deliver changes made right after ready resolves', async () => {
+      for (let index = 0; index < 20; index++) {
+        await mkdir(path.join(testDirectory, `directory${index.toString()}`, 'nested'), { recursive: true });
+      }
+      subscribeWithCollector(testDirectory);
+      await subscription?.ready;
+
+      const filePath = getFilename(testDirectory, 'directory19', 'nested');
+      await writeFile(filePath, 'content');
+
+      const events = await waitForEvents(collector);
+      expect(findEventByPath(events, filePath)).toBeDefined();
     });
 
     test('unsubscribe should be callable multiple times without error', () => {
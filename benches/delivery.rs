@@ -0,0 +1,147 @@
+//! Time turning a large debounced batch, such as the one a branch switch produces, into the events of a subscription.
+//!
+//! Usage: cargo bench --bench delivery
+//!
+//! The addon cannot be linked into an executable without Node, so the work is timed in `watcher-core`, which does it.
+
+use std::hint::black_box;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use globset::{Glob, GlobSetBuilder};
+use notify::Event;
+use notify::event::{CreateKind, DataChange, EventKind, ModifyKind, RemoveKind};
+use notify_debouncer_full::DebouncedEvent;
+
+const BATCH_SIZE: usize = 100_000;
+
+/// Creations, changes and removals spread over a few hundred directories, a tenth of them below `node_modules`
+fn synthetic_batch(root: &Path) -> Vec<DebouncedEvent> {
+  let time = Instant::now();
+  (0..BATCH_SIZE)
+    .map(|i| {
+      let kind = match i % 3 {
+        0 => EventKind::Create(CreateKind::File),
+        1 => EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+        _ => EventKind::Remove(RemoveKind::File),
+      };
+      let directory = if i % 10 == 0 { format!("node_modules/package-{}", i % 50) } else { format!("src/module-{}", i % 300) };
+      let path: PathBuf = root.join(directory).join(format!("file-{}.ts", i));
+      DebouncedEvent::new(Event::new(kind).add_path(path), time)
+    })
+    .collect()
+}
+
+fn ignore_patterns() -> Vec<String> {
+  vec!["**/node_modules/**".to_string(), "**/*.log".to_string()]
+}
+
+/// Patterns along the lines of a large monorepo's ignore file: build output and caches of every tool and generated
+/// code of every package, which ignore whole directories, and files by extension or name
+fn realistic_patterns() -> Vec<String> {
+  let directories = [
+    "node_modules",
+    ".git",
+    "dist",
+    "build",
+    "out",
+    "coverage",
+    ".next",
+    ".nuxt",
+    ".cache",
+    ".turbo",
+    ".parcel-cache",
+    "target",
+    "vendor",
+    "tmp",
+    ".idea",
+    ".vscode",
+    "__pycache__",
+    ".pytest_cache",
+    ".mypy_cache",
+    ".gradle",
+    "bin",
+    "obj",
+    ".terraform",
+    "storybook-static",
+    ".svelte-kit",
+  ];
+  let files = [
+    "*.log",
+    "*.tmp",
+    "*.swp",
+    "*.swo",
+    "*~",
+    ".DS_Store",
+    "Thumbs.db",
+    "*.pyc",
+    "*.class",
+    "*.o",
+    "*.so",
+    "*.dylib",
+    "*.dll",
+    "*.exe",
+    "*.map",
+    "*.tsbuildinfo",
+    ".env",
+    ".env.*",
+    "*.pem",
+    "*.key",
+    "npm-debug.log*",
+    "yarn-error.log*",
+    "*.orig",
+    "*.rej",
+    "*.bak",
+  ];
+  let mut patterns: Vec<String> = directories.iter().map(|directory| format!("**/{}/**", directory)).collect();
+  patterns.extend(files.iter().map(|file| format!("**/{}", file)));
+  // Generated and vendored code of each package of the workspace
+  for package in 0..50 {
+    for directory in ["generated", "fixtures", "snapshots"] {
+      patterns.push(format!("packages/package-{}/{}/**", package, directory));
+    }
+  }
+  patterns
+}
+
+fn select_events(c: &mut Criterion) {
+  let root = Path::new("/home/user/project");
+  let batch = synthetic_batch(root);
+
+  let mut group = c.benchmark_group("delivery");
+  group.throughput(Throughput::Elements(BATCH_SIZE as u64));
+  group.sample_size(20);
+  for (name, patterns) in [("select 100k events", ignore_patterns()), ("select 100k events, 200 patterns", realistic_patterns())] {
+    // Built once like a subscription's, so batches after the first find their directories remembered
+    let ignores = watcher_core::IgnoreFilter::new(&patterns).unwrap();
+    group.bench_function(name, |b| {
+      let mut reported = Vec::new();
+      b.iter(|| {
+        // Built like the `WatchEvent`s handed to JS, with an owned path and type
+        let mut events = Vec::new();
+        let new_event = |path: &Path, event_type: &str, _: &_| (watcher_core::path_to_clean_string(path), event_type.to_string());
+        watcher_core::select_events(black_box(&batch), root, &ignores, &mut events, &mut reported, new_event, |_, _, _| {});
+        events
+      })
+    });
+  }
+
+  // Matching every pattern against every path, as filtering did before directories were remembered
+  let mut builder = GlobSetBuilder::new();
+  for pattern in realistic_patterns() {
+    builder.add(Glob::new(&pattern).unwrap());
+  }
+  let glob_set = builder.build().unwrap();
+  group.bench_function("match 100k paths, 200 patterns", |b| {
+    b.iter(|| black_box(&batch).iter().filter(|event| !watcher_core::should_ignore(&event.event.paths[0], &glob_set, root)).count())
+  });
+  let ignores = watcher_core::IgnoreFilter::new(&realistic_patterns()).unwrap();
+  group.bench_function("filter 100k paths, 200 patterns", |b| {
+    b.iter(|| black_box(&batch).iter().filter(|event| !ignores.is_ignored(&event.event.paths[0], root)).count())
+  });
+  group.finish();
+}
+
+criterion_group!(benches, select_events);
+criterion_main!(benches);
@@ -0,0 +1,71 @@
+/**
+ * Measure how long delivering about 100k events takes with batches passed as objects and as JSON strings.
+ *
+ * Usage: bun benchmark/delivery.ts [directory] [--runs 5]
+ *
+ * The events are the initial scan of the tree (`emitInitial`), so the scan takes the same time in both modes and the
+ * difference is the cost of getting the batches to JS. Without a directory, a tree of about 100k files is generated.
+ */
+import { mkdtemp, rm } from 'node:fs/promises';
+import { tmpdir } from 'node:os';
+import path from 'node:path';
+import { parseArgs } from 'node:util';
+
+// eslint-disable-next-line n/no-missing-import, n/no-unpublished-import
+import { subscribe, type WatchCallbackResult, type WatchOptions } from '../index.js';
+import { createFixture } from './fixture.ts';
+
+const { positionals, values } = parseArgs({ allowPositionals: true, options: { runs: { default: '5', type: 'string' } } });
+
+const runs = Number(values.runs);
+const generated = positionals[0] ? undefined : await mkdtemp(path.join(tmpdir(), 'watcher-bench-'));
+const root = positionals[0] ? path.resolve(positionals[0]) : generated!;
+
+if (generated) {
+  const counts = await createFixture(generated, { files: 16 });
+  console.log(`Generated ${counts.directories} directories and ${counts.files} files`);
+}
+
+/** Subscribe with `options` and resolve with the time until the initial scan was read, and the number of events in it */
+const deliverScan = (options: WatchOptions, read: (result: unknown) => WatchCallbackResult) =>
+  new Promise<{ events: number; totalMs: number }>((resolve) => {
+    let events = 0;
+    const start = performance.now();
+    const subscription = subscribe(
+      root,
+      (result: unknown) => {
+        const batch = read(result);
+        events += batch.events.length;
+
+        if (batch.initial === 'complete') {
+          subscription.unsubscribe();
+          resolve({ events, totalMs: performance.now() - start });
+        }
+      },
+      { ...options, emitInitial: true },
+    );
+  });
+
+const scenarios: [string, WatchOptions, (result: unknown) => WatchCallbackResult][] = [
+  ['objects', { serialization: 'object' }, (result) => result as WatchCallbackResult],
+  ['json', { serialization: 'json' }, (result) => JSON.parse((result as { json: string }).json) as WatchCallbackResult],
+];
+
+for (const [name, options, read] of scenarios) {
+  const durations: number[] = [];
+  let events = 0;
+
+  for (let run = 0; run < runs; run++) {
+    const result = await deliverScan(options, read);
+    durations.push(result.totalMs);
+    events = result.events;
+  }
+
+  durations.sort((a, b) => a - b);
+  const median = durations[Math.floor(durations.length / 2)] ?? 0;
+  console.log(`${name}: ${median.toFixed(1)}ms median over ${runs} runs for ${events} events`);
+}
+
+if (generated) {
+  await rm(generated, { force: true, recursive: true });
+}
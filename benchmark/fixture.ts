@@ -0,0 +1,76 @@
+/**
+ * Generate a monorepo-like directory tree to benchmark subscribing against.
+ *
+ * Usage: bun benchmark/fixture.ts <directory> [--packages 20] [--depth 3] [--breadth 4] [--files 8] [--dependencies 30]
+ */
+import { mkdir, writeFile } from 'node:fs/promises';
+import path from 'node:path';
+import { parseArgs } from 'node:util';
+
+/** Shape of a generated tree */
+export type FixtureOptions = {
+  /** Number of packages, each with a source tree and a `node_modules` directory */
+  packages: number;
+  /** Number of directory levels in a package's source tree */
+  depth: number;
+  /** Number of subdirectories in every source directory above the deepest level */
+  breadth: number;
+  /** Number of files in every directory */
+  files: number;
+  /** Number of dependencies in every `node_modules` directory */
+  dependencies: number;
+};
+
+/** Defaults small enough to generate in a few seconds on CI */
+export const defaultFixtureOptions: FixtureOptions = { breadth: 4, dependencies: 30, depth: 3, files: 8, packages: 20 };
+
+/** Create the tree below `root` and return the number of directories and files in it */
+export const createFixture = async (root: string, options: Partial<FixtureOptions> = {}) => {
+  const { breadth, dependencies, depth, files, packages } = { ...defaultFixtureOptions, ...options };
+  const counts = { directories: 0, files: 0 };
+
+  const fill = async (directory: string, levels: number, subdirectories: number): Promise<void> => {
+    await mkdir(directory, { recursive: true });
+    counts.directories++;
+
+    await Promise.all(Array.from({ length: files }, (_, index) => writeFile(path.join(directory, `file-${index}.js`), `export default ${index};\n`)));
+    counts.files += files;
+
+    if (levels > 0) {
+      await Promise.all(Array.from({ length: subdirectories }, (_, index) => fill(path.join(directory, `dir-${index}`), levels - 1, subdirectories)));
+    }
+  };
+
+  const fillPackage = async (directory: string) => {
+    await fill(path.join(directory, 'src'), depth, breadth);
+    await Promise.all(Array.from({ length: dependencies }, (_, index) => fill(path.join(directory, 'node_modules', `dependency-${index}`), 2, 2)));
+  };
+
+  await fillPackage(root);
+  await Promise.all(Array.from({ length: packages }, (_, index) => fillPackage(path.join(root, 'packages', `package-${index}`))));
+  return counts;
+};
+
+if (import.meta.main) {
+  const usage = 'Usage: bun benchmark/fixture.ts <directory> [--packages 20] [--depth 3] [--breadth 4] [--files 8] [--dependencies 30]';
+  const { positionals, values } = parseArgs({
+    allowPositionals: true,
+    options: {
+      breadth: { type: 'string' },
+      dependencies: { type: 'string' },
+      depth: { type: 'string' },
+      files: { type: 'string' },
+      packages: { type: 'string' },
+    },
+  });
+
+  const [root] = positionals;
+  if (!root) {
+    console.error(usage);
+    process.exit(1);
+  }
+
+  const options = Object.fromEntries(Object.entries(values).map(([key, value]) => [key, Number(value)]));
+  const counts = await createFixture(path.resolve(root), options);
+  console.log(`Created ${counts.directories} directories and ${counts.files} files in ${root}`);
+}
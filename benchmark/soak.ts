@@ -0,0 +1,56 @@
+/**
+ * Keep creating and deleting files in a watched tree and report memory use and the size of the watcher's file id
+ * cache, to check that both stay flat over time.
+ *
+ * Usage: bun benchmark/soak.ts [--minutes 10] [--files 1000] [--cache-limit 100000]
+ *
+ * The tree is generated, and the files of every round are created and deleted again in its directories. Leave out
+ * `--cache-limit` to watch without a limit; the cache only holds entries where the OS does not pair renames itself.
+ */
+import { mkdtemp, readdir, rm, unlink, writeFile } from 'node:fs/promises';
+import { tmpdir } from 'node:os';
+import path from 'node:path';
+import { parseArgs } from 'node:util';
+
+// eslint-disable-next-line n/no-missing-import, n/no-unpublished-import
+import { subscribe } from '../index.js';
+import { createFixture } from './fixture.ts';
+
+const { values } = parseArgs({
+  options: {
+    'cache-limit': { type: 'string' },
+    files: { default: '1000', type: 'string' },
+    minutes: { default: '10', type: 'string' },
+  },
+});
+
+const root = await mkdtemp(path.join(tmpdir(), 'watcher-soak-'));
+const counts = await createFixture(root);
+console.log(`Generated ${counts.directories} directories and ${counts.files} files`);
+
+const directories = (await readdir(root, { recursive: true, withFileTypes: true })).filter((entry) => entry.isDirectory()).map((entry) => path.join(entry.parentPath, entry.name));
+const cacheLimit = values['cache-limit'] === undefined ? undefined : Number(values['cache-limit']);
+
+let events = 0;
+const subscription = subscribe(root, ({ events: batch }) => (events += batch.length), { cacheLimit });
+await subscription.ready;
+
+const end = Date.now() + Number(values.minutes) * 60_000;
+for (let round = 1; Date.now() < end; round++) {
+  const files = Array.from({ length: Number(values.files) }, (_, index) => path.join(directories[index % directories.length]!, `soak-${round}-${index}.txt`));
+  for (const file of files) {
+    await writeFile(file, 'content');
+  }
+  for (const file of files) {
+    await unlink(file);
+  }
+
+  if (round % 10 === 0) {
+    const { cacheEntries } = subscription.stats();
+    const rssMb = process.memoryUsage().rss / 1024 / 1024;
+    console.log(`round ${round}: ${events} events, ${cacheEntries} cache entries, ${rssMb.toFixed(1)} MiB resident`);
+  }
+}
+
+subscription.unsubscribe();
+await rm(root, { force: true, recursive: true });
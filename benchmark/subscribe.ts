@@ -0,0 +1,52 @@
+/**
+ * Measure how long subscribing to a large tree takes.
+ *
+ * Usage: bun benchmark/subscribe.ts [directory] [--runs 5]
+ *
+ * Without a directory, a tree is generated in a temporary directory with the fixture defaults.
+ */
+import { mkdtemp, rm } from 'node:fs/promises';
+import { tmpdir } from 'node:os';
+import path from 'node:path';
+import { parseArgs } from 'node:util';
+
+// eslint-disable-next-line n/no-missing-import, n/no-unpublished-import
+import { subscribe, type WatchOptions } from '../index.js';
+import { createFixture } from './fixture.ts';
+
+const { positionals, values } = parseArgs({ allowPositionals: true, options: { runs: { default: '5', type: 'string' } } });
+
+const runs = Number(values.runs);
+const generated = positionals[0] ? undefined : await mkdtemp(path.join(tmpdir(), 'watcher-bench-'));
+const root = positionals[0] ? path.resolve(positionals[0]) : generated!;
+
+if (generated) {
+  const counts = await createFixture(generated);
+  console.log(`Generated ${counts.directories} directories and ${counts.files} files`);
+}
+
+const scenarios: [string, WatchOptions][] = [
+  ['whole tree', {}],
+  ['ignoring node_modules', { ignore: ['**/node_modules/**'] }],
+];
+
+for (const [name, options] of scenarios) {
+  const durations: number[] = [];
+  let watchCount = 0;
+
+  for (let run = 0; run < runs; run++) {
+    const start = performance.now();
+    const subscription = subscribe(root, () => {}, options);
+    durations.push(performance.now() - start);
+    watchCount = subscription.watchCount;
+    subscription.unsubscribe();
+  }
+
+  durations.sort((a, b) => a - b);
+  const median = durations[Math.floor(durations.length / 2)] ?? 0;
+  console.log(`${name}: ${median.toFixed(1)}ms median over ${runs} runs, ${watchCount} directories watched`);
+}
+
+if (generated) {
+  await rm(generated, { force: true, recursive: true });
+}
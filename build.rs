@@ -1,3 +1,15 @@
+use std::fs;
+
 fn main() {
   napi_build::setup();
+
+  // Reported by `capabilities()`, taken from the lock file as dependencies do not see each other's versions
+  println!("cargo:rerun-if-changed=Cargo.lock");
+  let lock = fs::read_to_string("Cargo.lock").unwrap_or_default();
+  let version = lock
+    .split("[[package]]")
+    .find(|package| package.lines().any(|line| line == "name = \"notify\""))
+    .and_then(|package| package.lines().find_map(|line| line.strip_prefix("version = \"")?.strip_suffix('"')))
+    .unwrap_or("unknown");
+  println!("cargo:rustc-env=NOTIFY_VERSION={}", version);
 }
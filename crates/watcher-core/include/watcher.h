@@ -0,0 +1,49 @@
+/*
+ * The C API of watcher-core, built with its `ffi` Cargo feature. Link against the static or dynamic library
+ * built with `cargo rustc -p watcher-core --lib --features ffi --crate-type staticlib` (or `cdylib`).
+ *
+ * Ownership: every string passed in stays the caller's and is copied before the call returns. Every string
+ * passed out belongs to the library: a batch only during the callback it is passed to, an error until the next
+ * `watcher_subscribe` on the same thread. Nothing has to be freed except a handle, with `watcher_unsubscribe`.
+ */
+
+#ifndef WATCHER_H
+#define WATCHER_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A subscription, opaque to C */
+typedef struct WatcherHandle WatcherHandle;
+
+/*
+ * Called on the watcher's thread with each batch, as NUL-terminated UTF-8 JSON:
+ * {"events":[{"path":"/abs/path","type":"create"}]} with a type of create, update, delete or metadata, or
+ * {"error":"message","events":[]} when the OS watcher reported errors instead. Batches without events are
+ * not passed on.
+ */
+typedef void (*watcher_callback)(const char *batch_json, void *user_data);
+
+/*
+ * Watch `path` recursively until `watcher_unsubscribe`. `options_json` is NULL or a JSON object such as
+ * {"ignore":["node_modules","*.log"]}. Returns NULL when the directory cannot be watched or the options are
+ * invalid, with the reason in `watcher_last_error`. `user_data` is passed to every call of `callback` and must
+ * stay valid until `watcher_unsubscribe` returns.
+ */
+WatcherHandle *watcher_subscribe(const char *path, const char *options_json, watcher_callback callback, void *user_data);
+
+/*
+ * Stop watching and free `handle`. The callback is not called again once this returns, so this must not be
+ * called from the callback. NULL is ignored.
+ */
+void watcher_unsubscribe(WatcherHandle *handle);
+
+/* Why the last `watcher_subscribe` on this thread failed, or NULL */
+const char *watcher_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,563 @@
+//! Turning the debounced events of the OS watcher into the events of a subscription. The hot path, benchmarked
+//! on its own by `benches/delivery.rs` of the addon.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+#[cfg(not(target_family = "wasm"))]
+use std::time::Instant;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+#[cfg(not(target_family = "wasm"))]
+use notify_debouncer_full::DebouncedEvent;
+#[cfg(not(target_family = "wasm"))]
+use notify_types::event::RenameMode;
+use notify_types::event::{CreateKind, EventKind, MetadataKind, ModifyKind, RemoveKind};
+
+/// Most directories an `IgnoreFilter` remembers before starting over
+const REMEMBERED_DIRECTORIES: usize = 16_384;
+
+/// Characters that let a glob pattern match more than one name, or escape one that would
+const WILDCARDS: &[char] = &['*', '?', '[', '{', '\\'];
+
+/// Ignore patterns compiled for matching paths one at a time. A path is ignored when it or a directory it is in
+/// matches a pattern, relative to the root or in full, and no pattern starting with `!` re-includes it, whatever
+/// the order of the patterns. See `expand` for what each pattern matches.
+#[derive(Default)]
+pub struct IgnoreSet {
+  ignored: GlobSet,
+  kept: GlobSet,
+  /// The re-including patterns up to their first wildcard, which every path they match starts with
+  kept_prefixes: Vec<String>,
+  /// Whether some of the ignoring globs end in `**`, see `may_ignore_trees`
+  trees: bool,
+}
+
+impl IgnoreSet {
+  pub fn new(patterns: &[String]) -> Result<IgnoreSet, globset::Error> {
+    let (mut ignored, mut kept) = (GlobSetBuilder::new(), GlobSetBuilder::new());
+    let mut kept_prefixes = Vec::new();
+    let mut trees = false;
+    for pattern in patterns {
+      let (keeps, globs) = expand(pattern);
+      for glob in globs {
+        if keeps {
+          kept_prefixes.push(glob[..glob.find(WILDCARDS).unwrap_or(glob.len())].to_string());
+          kept.add(Glob::new(&glob)?);
+        } else {
+          trees |= glob.ends_with("**");
+          ignored.add(Glob::new(&glob)?);
+        }
+      }
+    }
+
+    Ok(IgnoreSet { ignored: ignored.build()?, kept: kept.build()?, kept_prefixes, trees })
+  }
+
+  /// Whether `ignores_tree` can hold for any directory: some pattern ignores everything below the directories
+  /// it matches, either ending in `**` or naming them, as `node_modules` and `build/` do
+  pub fn may_ignore_trees(&self) -> bool {
+    self.trees
+  }
+
+  /// Check if `path` below `root` is ignored
+  pub fn is_ignored(&self, path: &Path, root: &Path) -> bool {
+    should_ignore(path, &self.ignored, root) && (self.kept.is_empty() || !should_ignore(path, &self.kept, root))
+  }
+
+  /// Check if the patterns exclude everything below `directory`, so it does not have to be watched or walked
+  pub fn ignores_tree(&self, directory: &Path, root: &Path) -> bool {
+    // Names a pattern has to match for any file below the directory, whatever its name or depth
+    ["\0", "\0.\0", "\0/\0"].iter().all(|probe| self.is_ignored(&directory.join(probe), root)) && !self.may_keep_below(directory, root)
+  }
+
+  /// Whether a re-including pattern could match a path below `directory`: its part before the first wildcard
+  /// leads into the directory, or stops above it so that the wildcard can
+  fn may_keep_below(&self, directory: &Path, root: &Path) -> bool {
+    if self.kept_prefixes.is_empty() {
+      return false;
+    }
+
+    let as_prefix = |path: &Path| {
+      let path = path.to_string_lossy().replace(std::path::MAIN_SEPARATOR, "/");
+      if path.is_empty() { path } else { format!("{}/", path) }
+    };
+    let (directory, root) = (plain(directory), plain(root));
+    let forms = [Some(as_prefix(&directory)), directory.strip_prefix(&root).ok().map(as_prefix)];
+    forms.iter().flatten().any(|form| self.kept_prefixes.iter().any(|prefix| prefix.starts_with(form.as_str()) || form.starts_with(prefix.as_str())))
+  }
+}
+
+/// Whether `pattern` re-includes what it matches, and the globs it stands for. A pattern whose last component
+/// is a plain name, such as `node_modules` or `packages/*/dist`, also matches everything below the directories
+/// it names, as in a `.gitignore`, while one with a wildcard there, such as `*.log` or `dist/**`, only matches
+/// the paths it describes. A trailing `/` is left out. On Windows, backslashes separate components as well.
+fn expand(pattern: &str) -> (bool, Vec<String>) {
+  let (keeps, pattern) = match pattern.strip_prefix('!') {
+    Some(pattern) => (true, pattern),
+    None => (false, pattern),
+  };
+  let separated = if cfg!(windows) { forward_slashes(pattern) } else { pattern.to_string() };
+  let pattern = separated.as_str();
+  let pattern = pattern.strip_suffix('/').filter(|directory| !directory.is_empty()).unwrap_or(pattern);
+  let name = pattern.rsplit('/').next().unwrap_or(pattern);
+  if name.is_empty() || name.contains(WILDCARDS) { (keeps, vec![pattern.to_string()]) } else { (keeps, vec![pattern.to_string(), format!("{}/**", pattern)]) }
+}
+
+/// `pattern` with its backslashes turned into the forward slashes globset separates components with, which is
+/// how Windows users write paths. As backslashes cannot escape anything then, a doubled backslash escapes the
+/// character after it instead, except at the start, where it begins a UNC path.
+fn forward_slashes(pattern: &str) -> String {
+  let (start, rest) = match pattern.strip_prefix(r"\\") {
+    Some(rest) => ("//", rest),
+    None => ("", pattern),
+  };
+  let mut separated = String::with_capacity(pattern.len());
+  separated.push_str(start);
+  let mut chars = rest.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      separated.push(c);
+    } else if chars.as_str().starts_with('\\') {
+      chars.next();
+      match chars.next() {
+        // A class matches a wildcard literally, without an escape
+        Some(escaped @ ('*' | '?' | '[' | ']' | '{' | '}')) => separated.extend(['[', escaped, ']']),
+        Some(escaped) => separated.push(escaped),
+        None => {}
+      }
+    } else {
+      separated.push('/');
+    }
+  }
+  separated
+}
+
+/// The ignore patterns of a subscription, split for filtering large batches. Patterns ending in `/**` ignore
+/// everything below the directories they match, so whether they apply is decided once per directory and
+/// remembered, and only the other patterns are matched against every path. Built from the same patterns as
+/// the subscription's `IgnoreSet`, so the two are always replaced together.
+pub struct IgnoreFilter {
+  subtrees: GlobSet,
+  rest: GlobSet,
+  /// Whether some of the other patterns can match a path relative to the root without matching it in full
+  rest_relative: bool,
+  /// The patterns starting with `!`, which let paths through whatever else matches them
+  kept: GlobSet,
+  /// Whether the subtree patterns ignore everything in a directory, by directory
+  directories: Mutex<HashMap<OsString, bool>>,
+}
+
+impl IgnoreFilter {
+  pub fn new(patterns: &[String]) -> Result<IgnoreFilter, globset::Error> {
+    let (mut subtrees, mut rest, mut kept) = (GlobSetBuilder::new(), GlobSetBuilder::new(), GlobSetBuilder::new());
+    let mut rest_relative = false;
+    for pattern in patterns {
+      let (keeps, globs) = expand(pattern);
+      for pattern in globs {
+        let glob = Glob::new(&pattern)?;
+        if keeps {
+          kept.add(glob);
+        } else if ignores_subtree(&pattern) {
+          subtrees.add(glob);
+        } else {
+          // A pattern starting with `**/` that matches the relative path also matches the full one
+          rest_relative |= !pattern.starts_with("**/");
+          rest.add(glob);
+        }
+      }
+    }
+
+    let directories = Mutex::new(HashMap::new());
+    Ok(IgnoreFilter { subtrees: subtrees.build()?, rest: rest.build()?, rest_relative, kept: kept.build()?, directories })
+  }
+
+  /// Check if `path` should be ignored, with the same outcome as `IgnoreSet::is_ignored` with the same patterns
+  pub fn is_ignored(&self, path: &Path, root: &Path) -> bool {
+    self.check(&mut self.directories(), path, root)
+  }
+
+  fn directories(&self) -> std::sync::MutexGuard<'_, HashMap<OsString, bool>> {
+    self.directories.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  fn check(&self, directories: &mut HashMap<OsString, bool>, path: &Path, root: &Path) -> bool {
+    if !self.kept.is_empty() && should_ignore(path, &self.kept, root) {
+      return false;
+    }
+    if !self.subtrees.is_empty() {
+      let in_ignored_tree = match path.parent() {
+        Some(parent) => match directories.get(parent.as_os_str()) {
+          Some(&ignored) => ignored,
+          None => {
+            if directories.len() >= REMEMBERED_DIRECTORIES {
+              directories.clear();
+            }
+            // A subtree pattern matches a child of the directory exactly if it matches any name in it
+            let ignored = should_ignore(&parent.join("\0"), &self.subtrees, root);
+            directories.insert(parent.as_os_str().to_owned(), ignored);
+            ignored
+          }
+        },
+        None => should_ignore(path, &self.subtrees, root),
+      };
+      if in_ignored_tree {
+        return true;
+      }
+    }
+
+    match (self.rest.is_empty(), self.rest_relative) {
+      (true, _) => false,
+      (false, true) => should_ignore(path, &self.rest, root),
+      (false, false) => self.rest.is_match(path),
+    }
+  }
+}
+
+/// Whether a pattern only matches paths below the directories matched by its part before the trailing `/**`.
+/// That part must not end in `**`, which can match nothing and let the pattern match names without a separator.
+fn ignores_subtree(pattern: &str) -> bool {
+  pattern.strip_suffix("/**").is_some_and(|directory| !directory.ends_with("**"))
+}
+
+/// Append the events of `batch` below `root` that the ignore patterns let through to `events`, built by `event`
+/// from their path, type and the kind notify reported, and when the OS watcher reported them to `reported`.
+/// `observe` sees every event below `root`, with its type if it was ignored. Returns whether any event concerned
+/// the tree, ignored or not.
+#[cfg(not(target_family = "wasm"))]
+pub fn select_events<E>(
+  batch: &[DebouncedEvent],
+  root: &Path,
+  ignores: &IgnoreFilter,
+  events: &mut Vec<E>,
+  reported: &mut Vec<Instant>,
+  mut event: impl FnMut(&Path, &'static str, &EventKind) -> E,
+  mut observe: impl FnMut(&Path, &EventKind, Option<&'static str>),
+) -> bool {
+  reported.clear();
+  let mut active = false;
+  let mut directories = ignores.directories();
+
+  for (index, debounced_event) in batch.iter().enumerate() {
+    let Some(event_type) = event_kind_to_type(&debounced_event.event.kind) else {
+      continue;
+    };
+
+    for path in &debounced_event.event.paths {
+      if !path.starts_with(root) {
+        continue;
+      }
+      active = true;
+      if ignores.check(&mut directories, path, root) {
+        observe(path, &debounced_event.event.kind, Some(event_type));
+        continue;
+      }
+      observe(path, &debounced_event.event.kind, None);
+
+      // Most batches concern a single subscription, so its first event sizes the list for the rest
+      if events.capacity() == 0 {
+        events.reserve(batch.len() - index);
+        reported.reserve(batch.len() - index);
+      }
+      let kind = &debounced_event.event.kind;
+      events.push(event(path, rename_type(kind, &debounced_event.event.paths, path, root).unwrap_or(event_type), kind));
+      reported.push(debounced_event.time);
+    }
+  }
+
+  active
+}
+
+/// Type of the event for `path`, one of `paths` of a rename of `kind`, as seen from the tree at `root`. A half
+/// the debouncer could not pair, because the other half happened outside the watched directories or the file
+/// id needed to pair it was not known, is the file leaving or entering the tree, as is a paired rename with its
+/// other half outside `root`. None for renames within the tree, which are updates of both paths, and other events.
+#[cfg(not(target_family = "wasm"))]
+fn rename_type(kind: &EventKind, paths: &[PathBuf], path: &Path, root: &Path) -> Option<&'static str> {
+  let EventKind::Modify(ModifyKind::Name(mode)) = kind else {
+    return None;
+  };
+
+  match mode {
+    RenameMode::From => Some("delete"),
+    RenameMode::To => Some("create"),
+    // FSEvents does not tell the halves apart, the path of the half that left is gone
+    RenameMode::Any => Some(if path.symlink_metadata().is_ok() { "create" } else { "delete" }),
+    RenameMode::Both => match paths {
+      [from, to] if !to.starts_with(root) => (path == from).then_some("delete"),
+      [from, to] if !from.starts_with(root) => (path == to).then_some("create"),
+      _ => None,
+    },
+    RenameMode::Other => None,
+  }
+}
+
+/// Convert a path to a clean string, stripping the Windows extended-length path prefix if present
+pub fn path_to_clean_string(path: &Path) -> String {
+  let path_str = path.to_string_lossy();
+
+  // Strip the \\?\ prefix on Windows, turning \\?\UNC\server\share back into \\server\share
+  #[cfg(windows)]
+  if let Some(stripped) = path_str.strip_prefix(r"\\?\") {
+    return match stripped.strip_prefix(r"UNC\") {
+      Some(share) => format!(r"\\{}", share),
+      None => stripped.to_string(),
+    };
+  }
+
+  // Valid UTF-8 is copied once, and a lossy conversion is already owned
+  path_str.into_owned()
+}
+
+/// Check if a path should be ignored. Either path may carry the `\\?\` prefix of Windows, which the patterns
+/// never do, and globset matches either separator on Windows.
+pub fn should_ignore(path: &Path, glob_set: &GlobSet, base_path: &Path) -> bool {
+  let (path, base_path) = (plain(path), plain(base_path));
+  let (path, base_path) = (path.as_ref(), base_path.as_ref());
+
+  // Try matching against relative path first
+  if let Ok(relative) = path.strip_prefix(base_path)
+    && glob_set.is_match(relative)
+  {
+    return true;
+  }
+
+  // Also try matching against full path
+  glob_set.is_match(path)
+}
+
+/// `path` without the extended-length prefix of Windows, if it has one
+fn plain(path: &Path) -> Cow<'_, Path> {
+  if cfg!(windows) && path.as_os_str().as_encoded_bytes().starts_with(br"\\?\") {
+    Cow::Owned(PathBuf::from(path_to_clean_string(path)))
+  } else {
+    Cow::Borrowed(path)
+  }
+}
+
+/// Convert notify event kind to our event type. Changes of permissions, owner or times are `metadata`, which
+/// subscriptions without `emitMetadata` report as `update`. The polling watcher only notices changed modification
+/// times, which mostly come with changed contents, so those stay updates.
+pub fn event_kind_to_type(kind: &EventKind) -> Option<&'static str> {
+  match kind {
+    EventKind::Create(CreateKind::File | CreateKind::Folder | CreateKind::Any) => Some("create"),
+    EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime)) => Some("update"),
+    EventKind::Modify(ModifyKind::Metadata(_)) => Some("metadata"),
+    EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any) => Some("update"),
+    EventKind::Remove(RemoveKind::File | RemoveKind::Folder | RemoveKind::Any) => Some("delete"),
+    _ => None,
+  }
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+  use notify::Event;
+  use notify::event::{AccessKind, DataChange, ModifyKind, RenameMode};
+
+  use super::*;
+
+  fn glob_set(patterns: &[&str]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+      builder.add(Glob::new(pattern).unwrap());
+    }
+    builder.build().unwrap()
+  }
+
+  fn debounced(kind: EventKind, paths: &[&Path]) -> DebouncedEvent {
+    let event = paths.iter().fold(Event::new(kind), |event, path| event.add_path(path.to_path_buf()));
+    DebouncedEvent::new(event, Instant::now())
+  }
+
+  /// Events `select_events` lets through, with their types
+  fn select(batch: &[DebouncedEvent], root: &Path, patterns: &[String]) -> Vec<(PathBuf, &'static str)> {
+    let ignores = IgnoreFilter::new(patterns).unwrap();
+    let mut events = Vec::new();
+    select_events(batch, root, &ignores, &mut events, &mut Vec::new(), |path, event_type, _| (path.to_path_buf(), event_type), |_, _, _| {});
+    events
+  }
+
+  #[test]
+  fn classifies_event_kinds() {
+    assert_eq!(event_kind_to_type(&EventKind::Create(CreateKind::File)), Some("create"));
+    assert_eq!(event_kind_to_type(&EventKind::Modify(ModifyKind::Data(DataChange::Content))), Some("update"));
+    assert_eq!(event_kind_to_type(&EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime))), Some("update"));
+    assert_eq!(event_kind_to_type(&EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions))), Some("metadata"));
+    assert_eq!(event_kind_to_type(&EventKind::Modify(ModifyKind::Name(RenameMode::Both))), Some("update"));
+    assert_eq!(event_kind_to_type(&EventKind::Remove(RemoveKind::Folder)), Some("delete"));
+    assert_eq!(event_kind_to_type(&EventKind::Access(AccessKind::Read)), None);
+    assert_eq!(event_kind_to_type(&EventKind::Create(CreateKind::Other)), None);
+  }
+
+  #[test]
+  fn matches_patterns_relative_to_the_root_and_in_full() {
+    let root = Path::new("/project");
+    let glob_set = glob_set(&["dist/**", "/project/secret.txt"]);
+    assert!(should_ignore(Path::new("/project/dist/index.js"), &glob_set, root));
+    assert!(should_ignore(Path::new("/project/secret.txt"), &glob_set, root));
+    assert!(!should_ignore(Path::new("/project/src/dist.js"), &glob_set, root));
+  }
+
+  #[test]
+  fn filters_like_matching_every_pattern() {
+    let root = Path::new("/project");
+    let patterns: Vec<String> =
+      ["**/node_modules/**", "build/**", "**/*.log", "src/*.tmp", "dist", "!node_modules/keep", "**/**"].iter().map(|pattern| pattern.to_string()).collect();
+    let paths = [
+      "/project/node_modules/a/b.js",
+      "/project/node_modules/keep/b.js",
+      "/project/build/out.js",
+      "/project/src/build/out.js",
+      "/project/app.log",
+      "/project/src/a.tmp",
+      "/project/dist/a/b.js",
+      "/project/src/a.ts",
+    ];
+    for count in 1..patterns.len() {
+      let filter = IgnoreFilter::new(&patterns[..count]).unwrap();
+      let ignores = IgnoreSet::new(&patterns[..count]).unwrap();
+      for path in paths.iter().map(Path::new) {
+        assert_eq!(filter.is_ignored(path, root), ignores.is_ignored(path, root), "{:?} with {:?}", path, &patterns[..count]);
+      }
+    }
+  }
+
+  /// What a pattern ignores: the patterns, a path below `/project` and whether it is ignored
+  const SEMANTICS: &[(&[&str], &str, bool)] = &[
+    // A plain name ignores the directories of that name and everything below them, at the root only unless
+    // it starts with `**/`
+    (&["node_modules"], "node_modules", true),
+    (&["node_modules"], "node_modules/lodash/index.js", true),
+    (&["node_modules"], "packages/a/node_modules/lodash/index.js", false),
+    (&["**/node_modules"], "packages/a/node_modules/lodash/index.js", true),
+    (&["node_modules"], "node_modules_backup/index.js", false),
+    (&["packages/*/dist"], "packages/a/dist/index.js", true),
+    (&["dist/"], "dist/index.js", true),
+    (&["/project/secret"], "secret/key.pem", true),
+    // A wildcard in the last component matches the paths it describes, not what is below them
+    (&["*.log"], "logs/today.log", true),
+    (&["*.log"], "archive.log/readme.txt", false),
+    (&["src/*.tmp"], "src/a.tmp", true),
+    (&["dist/**"], "dist/index.js", true),
+    (&["dist/**"], "dist", false),
+    // `!` re-includes what it matches, and what is below a plain name, whatever the order
+    (&["node_modules", "!node_modules/keep.js"], "node_modules/keep.js", false),
+    (&["!node_modules/keep.js", "node_modules"], "node_modules/other.js", true),
+    (&["node_modules/**", "!node_modules/@scope"], "node_modules/@scope/pkg/index.js", false),
+    (&["*.log", "!important.log"], "important.log", false),
+    (&["!important.log"], "today.log", false),
+  ];
+
+  #[test]
+  fn ignores_what_a_gitignore_would() {
+    let root = Path::new("/project");
+    for (patterns, path, ignored) in SEMANTICS {
+      let patterns: Vec<String> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+      let path = root.join(path);
+      assert_eq!(IgnoreSet::new(&patterns).unwrap().is_ignored(&path, root), *ignored, "{:?} with {:?}", path, patterns);
+      assert_eq!(IgnoreFilter::new(&patterns).unwrap().is_ignored(&path, root), *ignored, "{:?} with {:?} filtered", path, patterns);
+    }
+  }
+
+  #[test]
+  fn turns_backslashes_into_separators() {
+    assert_eq!(forward_slashes(r"build\temp\**"), "build/temp/**");
+    assert_eq!(forward_slashes(r"src/generated\*.ts"), "src/generated/*.ts");
+    assert_eq!(forward_slashes(r"C:\Users\me\project\dist"), "C:/Users/me/project/dist");
+    assert_eq!(forward_slashes(r"\\server\share\cache"), "//server/share/cache");
+    assert_eq!(forward_slashes(r"notes\\[draft\\].md"), "notes[[]draft[]].md");
+    assert_eq!(forward_slashes(r"what\\?\\*.txt"), "what[?][*].txt");
+  }
+
+  #[cfg(windows)]
+  #[test]
+  fn matches_either_separator_on_windows() {
+    let root = Path::new(r"C:\project");
+    let cases: &[(&str, &str, bool)] = &[
+      (r"build\temp\**", r"C:\project\build\temp\a.obj", true),
+      (r"build\temp\**", r"C:\project\build\other\a.obj", false),
+      ("build/temp/**", r"C:\project\build\temp\a.obj", true),
+      (r"build/temp\**", r"C:\project\build\temp\a.obj", true),
+      (r"node_modules\", r"C:\project\node_modules\lodash\index.js", true),
+      (r"C:\project\dist", r"C:\project\dist\index.js", true),
+      (r"C:\project\dist", r"\\?\C:\project\dist\index.js", true),
+      (r"**\*.log", r"C:\project\logs\today.log", true),
+      (r"notes\\[draft\\].md", r"C:\project\notes[draft].md", true),
+    ];
+    for (pattern, path, ignored) in cases {
+      let ignores = IgnoreSet::new(&[pattern.to_string()]).unwrap();
+      assert_eq!(ignores.is_ignored(Path::new(path), root), *ignored, "{:?} with {:?}", path, pattern);
+      assert_eq!(IgnoreFilter::new(&[pattern.to_string()]).unwrap().is_ignored(Path::new(path), root), *ignored, "{:?} with {:?} filtered", path, pattern);
+    }
+    let kept = IgnoreSet::new(&[r"node_modules".to_string(), r"!node_modules\keep\**".to_string()]).unwrap();
+    assert!(!kept.is_ignored(Path::new(r"C:\project\node_modules\keep\index.js"), root));
+    assert!(!kept.ignores_tree(Path::new(r"C:\project\node_modules"), root));
+  }
+
+  #[test]
+  fn keeps_watching_directories_with_re_included_paths() {
+    let root = Path::new("/project");
+    let ignores = IgnoreSet::new(&["node_modules".to_string(), "!node_modules/keep/**".to_string()]).unwrap();
+    assert!(!ignores.ignores_tree(&root.join("node_modules"), root));
+    assert!(ignores.ignores_tree(&root.join("node_modules/lodash"), root));
+    assert!(!ignores.ignores_tree(&root.join("node_modules/keep"), root));
+    assert!(!IgnoreSet::new(&["node_modules".to_string(), "!**/keep.js".to_string()]).unwrap().ignores_tree(&root.join("node_modules/lodash"), root));
+  }
+
+  #[test]
+  fn tells_whether_patterns_may_ignore_trees() {
+    let may_ignore_trees =
+      |patterns: &[&str]| IgnoreSet::new(&patterns.iter().map(|pattern| pattern.to_string()).collect::<Vec<_>>()).unwrap().may_ignore_trees();
+    assert!(may_ignore_trees(&["node_modules"]));
+    assert!(may_ignore_trees(&["**/node_modules"]));
+    assert!(may_ignore_trees(&["build/"]));
+    assert!(may_ignore_trees(&["dist/**"]));
+    assert!(!may_ignore_trees(&["*.log", "!keep/**"]));
+    assert!(!may_ignore_trees(&[]));
+  }
+
+  #[test]
+  fn tells_which_patterns_ignore_subtrees() {
+    assert!(ignores_subtree("node_modules/**"));
+    assert!(ignores_subtree("**/dist/**"));
+    assert!(!ignores_subtree("**/**"));
+    assert!(!ignores_subtree("*.log"));
+  }
+
+  #[test]
+  fn selects_events_below_the_root_that_are_not_ignored() {
+    let root = Path::new("/project");
+    let batch = [
+      debounced(EventKind::Create(CreateKind::File), &[Path::new("/project/a.ts")]),
+      debounced(EventKind::Create(CreateKind::File), &[Path::new("/project/a.log")]),
+      debounced(EventKind::Create(CreateKind::File), &[Path::new("/elsewhere/b.ts")]),
+      debounced(EventKind::Access(AccessKind::Read), &[Path::new("/project/c.ts")]),
+    ];
+    assert_eq!(select(&batch, root, &["*.log".to_string()]), [(PathBuf::from("/project/a.ts"), "create")]);
+  }
+
+  #[test]
+  fn types_renames_by_where_their_halves_are() {
+    let root = Path::new("/project");
+    let within = debounced(EventKind::Modify(ModifyKind::Name(RenameMode::Both)), &[Path::new("/project/a"), Path::new("/project/b")]);
+    let out = debounced(EventKind::Modify(ModifyKind::Name(RenameMode::Both)), &[Path::new("/project/c"), Path::new("/elsewhere/c")]);
+    let into = debounced(EventKind::Modify(ModifyKind::Name(RenameMode::Both)), &[Path::new("/elsewhere/d"), Path::new("/project/d")]);
+    let from = debounced(EventKind::Modify(ModifyKind::Name(RenameMode::From)), &[Path::new("/project/e")]);
+    let to = debounced(EventKind::Modify(ModifyKind::Name(RenameMode::To)), &[Path::new("/project/f")]);
+
+    let events = select(&[within, out, into, from, to], root, &[]);
+    assert_eq!(
+      events,
+      [
+        (PathBuf::from("/project/a"), "update"),
+        (PathBuf::from("/project/b"), "update"),
+        (PathBuf::from("/project/c"), "delete"),
+        (PathBuf::from("/project/d"), "create"),
+        (PathBuf::from("/project/e"), "delete"),
+        (PathBuf::from("/project/f"), "create"),
+      ]
+    );
+  }
+}
@@ -0,0 +1,87 @@
+//! What watching a directory reports, whichever way it is watched
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+/// A change in the watched directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+  pub path: PathBuf,
+  /// `create`, `update`, `delete` or `metadata`, as `event_kind_to_type` tells them apart
+  pub event_type: &'static str,
+}
+
+/// Why a directory could not be watched
+#[derive(Debug)]
+pub enum Error {
+  Pattern(globset::Error),
+  /// The directory could not be read
+  Io(io::Error),
+  #[cfg(not(target_family = "wasm"))]
+  Watch(notify::Error),
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::Pattern(e) => write!(f, "Invalid glob pattern: {}", e),
+      Error::Io(e) => write!(f, "Failed to read the directory: {}", e),
+      #[cfg(not(target_family = "wasm"))]
+      Error::Watch(e) => write!(f, "Failed to watch: {}", e),
+    }
+  }
+}
+
+impl std::error::Error for Error {}
+
+impl From<globset::Error> for Error {
+  fn from(error: globset::Error) -> Error {
+    Error::Pattern(error)
+  }
+}
+
+impl From<io::Error> for Error {
+  fn from(error: io::Error) -> Error {
+    Error::Io(error)
+  }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl From<notify::Error> for Error {
+  fn from(error: notify::Error) -> Error {
+    Error::Watch(error)
+  }
+}
+
+/// Compile ignore patterns into one set, failing on the first invalid pattern
+pub fn build_glob_set(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+  let mut builder = GlobSetBuilder::new();
+  for pattern in patterns {
+    builder.add(Glob::new(pattern)?);
+  }
+  builder.build()
+}
+
+/// A fresh directory for a test, removed when it ends
+#[cfg(test)]
+pub(crate) struct TestDirectory(pub PathBuf);
+
+#[cfg(test)]
+impl TestDirectory {
+  pub fn new(name: &str) -> TestDirectory {
+    let path = std::env::temp_dir().join(format!("watcher-core-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    TestDirectory(dunce::canonicalize(path).unwrap())
+  }
+}
+
+#[cfg(test)]
+impl Drop for TestDirectory {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_dir_all(&self.0);
+  }
+}
@@ -0,0 +1,210 @@
+//! A C API over `Watcher`, for programs that are not Rust or Node, with the `ffi` feature. `include/watcher.h`
+//! declares it. Batches reach the C callback as JSON strings on the watcher's thread; the strings belong to this
+//! library and are only valid during the call.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::path::Path;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{Value, json};
+
+use crate::batch::path_to_clean_string;
+use crate::watcher::{BatchResult, Watcher};
+
+/// Called with each batch as a NUL-terminated JSON string and the `user_data` passed to `watcher_subscribe`
+pub type WatcherCallback = extern "C" fn(batch_json: *const c_char, user_data: *mut c_void);
+
+/// A subscription handed to C as an opaque pointer
+pub struct WatcherHandle {
+  _watcher: Watcher,
+  /// The callback and its data while subscribed, held while calling it so unsubscribing waits for a call to end
+  target: Arc<Mutex<Option<Target>>>,
+}
+
+struct Target {
+  callback: WatcherCallback,
+  user_data: *mut c_void,
+}
+
+// The caller of `watcher_subscribe` vouches that `user_data` can be used from the watcher's thread
+unsafe impl Send for Target {}
+
+thread_local! {
+  static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Watch `path` recursively, calling `callback` with every batch until `watcher_unsubscribe`. `options_json` is
+/// NULL or a JSON object whose only key so far is `ignore`, an array of ignore patterns. Returns NULL when the
+/// directory cannot be watched or the options are invalid, with the reason in `watcher_last_error`.
+///
+/// # Safety
+/// `path` and `options_json`, if not NULL, must be NUL-terminated strings, and `user_data` must stay valid and
+/// usable from another thread until `watcher_unsubscribe` returns.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn watcher_subscribe(
+  path: *const c_char,
+  options_json: *const c_char,
+  callback: Option<WatcherCallback>,
+  user_data: *mut c_void,
+) -> *mut WatcherHandle {
+  let subscribed = unsafe { subscribe(path, options_json, callback, user_data) };
+  match subscribed {
+    Ok(handle) => Box::into_raw(Box::new(handle)),
+    Err(message) => {
+      set_last_error(message);
+      ptr::null_mut()
+    }
+  }
+}
+
+/// Stop watching and free `handle`. Once it returns the callback is not called again, so it must not be called
+/// from the callback itself, which would wait for itself. NULL is ignored.
+///
+/// # Safety
+/// `handle` must come from `watcher_subscribe` and not have been unsubscribed before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn watcher_unsubscribe(handle: *mut WatcherHandle) {
+  if handle.is_null() {
+    return;
+  }
+
+  let handle = unsafe { Box::from_raw(handle) };
+  handle.target.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+/// Why the last `watcher_subscribe` on this thread failed, or NULL. The string belongs to the library and is
+/// valid until the next call to `watcher_subscribe` on the thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn watcher_last_error() -> *const c_char {
+  LAST_ERROR.with(|error| error.borrow().as_ref().map_or(ptr::null(), |error| error.as_ptr()))
+}
+
+unsafe fn subscribe(
+  path: *const c_char,
+  options_json: *const c_char,
+  callback: Option<WatcherCallback>,
+  user_data: *mut c_void,
+) -> Result<WatcherHandle, String> {
+  set_last_error(String::new());
+  let callback = callback.ok_or("The callback must not be NULL")?;
+  if path.is_null() {
+    return Err("The path must not be NULL".to_string());
+  }
+  let path = unsafe { CStr::from_ptr(path) }.to_str().map_err(|_| "The path must be UTF-8".to_string())?;
+  let ignore = if options_json.is_null() { Vec::new() } else { ignore_patterns(unsafe { CStr::from_ptr(options_json) })? };
+
+  let target = Arc::new(Mutex::new(Some(Target { callback, user_data })));
+  let called = Arc::clone(&target);
+  let watcher = Watcher::new(Path::new(path), &ignore, move |batch: BatchResult| {
+    let Ok(batch) = CString::new(encode(batch).to_string()) else {
+      return;
+    };
+    if let Some(target) = called.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+      (target.callback)(batch.as_ptr(), target.user_data);
+    }
+  })
+  .map_err(|e| e.to_string())?;
+
+  Ok(WatcherHandle { _watcher: watcher, target })
+}
+
+/// The ignore patterns of the JSON options
+fn ignore_patterns(options_json: &CStr) -> Result<Vec<String>, String> {
+  let options: Value = serde_json::from_slice(options_json.to_bytes()).map_err(|e| format!("Invalid options JSON: {}", e))?;
+  let Value::Object(options) = options else {
+    return Err("The options must be a JSON object".to_string());
+  };
+
+  let mut ignore = Vec::new();
+  for (key, value) in options {
+    match (key.as_str(), value) {
+      ("ignore", Value::Array(patterns)) => {
+        for pattern in patterns {
+          let Value::String(pattern) = pattern else {
+            return Err("ignore must be an array of strings".to_string());
+          };
+          ignore.push(pattern);
+        }
+      }
+      ("ignore", _) => return Err("ignore must be an array of strings".to_string()),
+      (key, _) => return Err(format!("Unknown option '{}'", key)),
+    }
+  }
+  Ok(ignore)
+}
+
+/// A batch as the callback receives it: `{"events":[{"path":...,"type":...}]}`, or `{"error":...,"events":[]}`
+/// when the OS watcher reported errors instead
+fn encode(batch: BatchResult) -> Value {
+  match batch {
+    Ok(events) => {
+      let events: Vec<Value> = events.iter().map(|event| json!({ "path": path_to_clean_string(&event.path), "type": event.event_type })).collect();
+      json!({ "events": events })
+    }
+    Err(errors) => {
+      let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+      json!({ "error": messages.join("; "), "events": [] })
+    }
+  }
+}
+
+fn set_last_error(message: String) {
+  // Messages have no NUL bytes, a path in one that has would cut it short
+  let message = (!message.is_empty()).then(|| CString::new(message.replace('\0', "")).unwrap_or_default());
+  LAST_ERROR.with(|error| *error.borrow_mut() = message);
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+  use std::sync::mpsc::{Receiver, Sender, channel};
+  use std::time::Duration;
+
+  use super::*;
+  use crate::event::TestDirectory;
+
+  extern "C" fn forward(batch_json: *const c_char, user_data: *mut c_void) {
+    let sender = unsafe { &*(user_data as *const Sender<String>) };
+    let _ = sender.send(unsafe { CStr::from_ptr(batch_json) }.to_string_lossy().into_owned());
+  }
+
+  fn subscribe(path: &Path, options: Option<&str>, sender: &Sender<String>) -> *mut WatcherHandle {
+    let path = CString::new(path.to_str().unwrap()).unwrap();
+    let options = options.map(|options| CString::new(options).unwrap());
+    let options = options.as_ref().map_or(ptr::null(), |options| options.as_ptr());
+    unsafe { watcher_subscribe(path.as_ptr(), options, Some(forward), sender as *const Sender<String> as *mut c_void) }
+  }
+
+  fn next_batch(receiver: &Receiver<String>) -> Value {
+    serde_json::from_str(&receiver.recv_timeout(Duration::from_secs(2)).unwrap()).unwrap()
+  }
+
+  #[test]
+  fn passes_batches_as_json() {
+    let directory = TestDirectory::new("ffi");
+    let (sender, receiver) = channel();
+    let handle = subscribe(&directory.0, Some(r#"{"ignore":["*.log"]}"#), &sender);
+    assert!(!handle.is_null());
+
+    fs::write(directory.0.join("debug.log"), "").unwrap();
+    fs::write(directory.0.join("quoted \"name\".txt"), "").unwrap();
+    let batch = next_batch(&receiver);
+    unsafe { watcher_unsubscribe(handle) };
+
+    let path = path_to_clean_string(&directory.0.join("quoted \"name\".txt"));
+    assert_eq!(batch["events"][0], json!({ "path": path, "type": "create" }), "{}", batch);
+    assert!(batch.get("error").is_none());
+  }
+
+  #[test]
+  fn reports_why_subscribing_failed() {
+    let directory = TestDirectory::new("ffi-errors");
+    let (sender, _receiver) = channel();
+    assert!(subscribe(&directory.0, Some(r#"{"ignored":[]}"#), &sender).is_null());
+    assert_eq!(unsafe { CStr::from_ptr(watcher_last_error()) }.to_str().unwrap(), "Unknown option 'ignored'");
+    assert!(subscribe(&directory.0.join("missing"), None, &sender).is_null());
+    assert!(!watcher_last_error().is_null());
+  }
+}
@@ -0,0 +1,26 @@
+//! The behavior of the watcher addon that does not need Node: ignore patterns, telling creates, updates and
+//! deletes apart, and debouncing, for Rust programs that watch a directory the same way. The addon binds
+//! this crate to N-API, so both report the same events for the same changes.
+//!
+//! `Watcher` uses the OS watcher. `Poller` scans the directory instead, which also works on WASI targets
+//! such as `wasm32-wasip1`, where the OS watchers and `Watcher` are left out.
+//!
+//! With the `ffi` feature, `ffi` exposes `Watcher` to C, declared in `include/watcher.h`.
+
+#![deny(clippy::all)]
+
+mod batch;
+mod event;
+#[cfg(all(feature = "ffi", not(target_family = "wasm")))]
+pub mod ffi;
+mod poll;
+#[cfg(not(target_family = "wasm"))]
+mod watcher;
+
+#[cfg(not(target_family = "wasm"))]
+pub use batch::select_events;
+pub use batch::{IgnoreFilter, IgnoreSet, event_kind_to_type, path_to_clean_string, should_ignore};
+pub use event::{Error, Event, build_glob_set};
+pub use poll::Poller;
+#[cfg(not(target_family = "wasm"))]
+pub use watcher::{BatchHandler, BatchResult, DEBOUNCE_TIMEOUT, Watcher};
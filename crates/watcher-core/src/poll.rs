@@ -0,0 +1,207 @@
+//! Watching a directory by scanning it and comparing each scan with the one before, which works wherever the
+//! file system can be read, including WASI where there is no OS watcher
+
+use std::collections::BTreeMap;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use notify_types::event::{CreateKind, DataChange, EventKind, ModifyKind, RemoveKind};
+
+use crate::batch::{IgnoreFilter, event_kind_to_type};
+use crate::event::{Error, Event};
+
+/// What a scan saw of a path
+#[derive(PartialEq)]
+struct Entry {
+  is_dir: bool,
+  len: u64,
+  modified: Option<SystemTime>,
+}
+
+impl Entry {
+  fn new(metadata: &Metadata) -> Entry {
+    Entry { is_dir: metadata.is_dir(), len: metadata.len(), modified: metadata.modified().ok() }
+  }
+}
+
+/// A directory polled for changes. Nothing runs in the background: each call to `poll` scans the tree and
+/// reports what changed since the scan before, so the caller decides how often to poll, on whatever thread.
+pub struct Poller {
+  root: PathBuf,
+  ignores: IgnoreFilter,
+  /// Paths of the last scan, by path so parents come before their children
+  entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl Poller {
+  /// Scan `root` for the first time, leaving out the paths matching the glob patterns of `ignore` relative to
+  /// it or in full, as `Watcher` does
+  pub fn new(root: &Path, ignore: &[String]) -> Result<Poller, Error> {
+    let ignores = IgnoreFilter::new(ignore)?;
+    let root = dunce::canonicalize(root)?;
+    if !root.is_dir() {
+      return Err(Error::Io(io::Error::new(io::ErrorKind::NotADirectory, format!("{} is not a directory", root.display()))));
+    }
+
+    let mut poller = Poller { root, ignores, entries: BTreeMap::new() };
+    poller.entries = poller.scan();
+    Ok(poller)
+  }
+
+  /// The polled directory, with symlinks resolved
+  pub fn root(&self) -> &Path {
+    &self.root
+  }
+
+  /// Scan the tree again and report what changed since the last scan, in the order of the paths with the
+  /// deletes last. Files count as updated when their size or modification time changed, directories only
+  /// when they are created or deleted, and a path that turned from a file into a directory or back is deleted
+  /// and created again.
+  pub fn poll(&mut self) -> Vec<Event> {
+    let entries = self.scan();
+    let mut events = Vec::new();
+    for (path, entry) in &entries {
+      let kind = match self.entries.get(path) {
+        None => Some(created(entry)),
+        Some(last) if last.is_dir != entry.is_dir => {
+          push(&mut events, path, &removed(last));
+          Some(created(entry))
+        }
+        Some(last) if !entry.is_dir && last != entry => Some(EventKind::Modify(ModifyKind::Data(DataChange::Any))),
+        Some(_) => None,
+      };
+      if let Some(kind) = kind {
+        push(&mut events, path, &kind);
+      }
+    }
+    for (path, last) in &self.entries {
+      if !entries.contains_key(path) {
+        push(&mut events, path, &removed(last));
+      }
+    }
+
+    self.entries = entries;
+    events
+  }
+
+  /// Every path below the root that is not ignored, without following symlinks. Directories that cannot be
+  /// read, or vanish while being read, are left with what could be read of them.
+  fn scan(&self) -> BTreeMap<PathBuf, Entry> {
+    let mut entries = BTreeMap::new();
+    let mut directories = vec![self.root.clone()];
+    while let Some(directory) = directories.pop() {
+      let Ok(children) = fs::read_dir(&directory) else {
+        continue;
+      };
+      for child in children.flatten() {
+        let path = child.path();
+        let Ok(metadata) = child.metadata() else {
+          continue;
+        };
+        // Ignored directories are still walked, patterns may let paths below them through
+        if metadata.is_dir() {
+          directories.push(path.clone());
+        }
+        if !self.ignores.is_ignored(&path, &self.root) {
+          entries.insert(path, Entry::new(&metadata));
+        }
+      }
+    }
+    entries
+  }
+}
+
+fn created(entry: &Entry) -> EventKind {
+  EventKind::Create(if entry.is_dir { CreateKind::Folder } else { CreateKind::File })
+}
+
+fn removed(entry: &Entry) -> EventKind {
+  EventKind::Remove(if entry.is_dir { RemoveKind::Folder } else { RemoveKind::File })
+}
+
+/// Add the event of `kind` for `path`, typed the way the OS watchers' events are
+fn push(events: &mut Vec<Event>, path: &Path, kind: &EventKind) {
+  if let Some(event_type) = event_kind_to_type(kind) {
+    events.push(Event { path: path.to_path_buf(), event_type });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::event::TestDirectory;
+
+  fn types(events: &[Event], root: &Path) -> Vec<(String, &'static str)> {
+    events.iter().map(|event| (event.path.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"), event.event_type)).collect()
+  }
+
+  #[test]
+  fn reports_nothing_without_changes() {
+    let directory = TestDirectory::new("poll-quiet");
+    fs::write(directory.0.join("file.txt"), "content").unwrap();
+    let mut poller = Poller::new(&directory.0, &[]).unwrap();
+    assert_eq!(poller.poll(), []);
+  }
+
+  #[test]
+  fn reports_creates_updates_and_deletes() {
+    let directory = TestDirectory::new("poll-changes");
+    let root = &directory.0;
+    fs::write(root.join("changed.txt"), "content").unwrap();
+    fs::write(root.join("deleted.txt"), "content").unwrap();
+    let mut poller = Poller::new(root, &[]).unwrap();
+
+    fs::create_dir(root.join("created")).unwrap();
+    fs::write(root.join("created").join("file.txt"), "content").unwrap();
+    fs::write(root.join("changed.txt"), "more content").unwrap();
+    fs::remove_file(root.join("deleted.txt")).unwrap();
+
+    assert_eq!(
+      types(&poller.poll(), root),
+      [
+        ("changed.txt".to_string(), "update"),
+        ("created".to_string(), "create"),
+        ("created/file.txt".to_string(), "create"),
+        ("deleted.txt".to_string(), "delete")
+      ]
+    );
+    assert_eq!(poller.poll(), []);
+  }
+
+  #[test]
+  fn replaces_a_file_turned_into_a_directory() {
+    let directory = TestDirectory::new("poll-replaced");
+    let root = &directory.0;
+    fs::write(root.join("entry"), "content").unwrap();
+    let mut poller = Poller::new(root, &[]).unwrap();
+
+    fs::remove_file(root.join("entry")).unwrap();
+    fs::create_dir(root.join("entry")).unwrap();
+
+    assert_eq!(types(&poller.poll(), root), [("entry".to_string(), "delete"), ("entry".to_string(), "create")]);
+  }
+
+  #[test]
+  fn leaves_out_ignored_paths() {
+    let directory = TestDirectory::new("poll-ignore");
+    let root = &directory.0;
+    fs::create_dir(root.join("node_modules")).unwrap();
+    let mut poller = Poller::new(root, &["node_modules/**".to_string(), "**/*.log".to_string()]).unwrap();
+
+    fs::write(root.join("node_modules").join("package.json"), "{}").unwrap();
+    fs::write(root.join("debug.log"), "").unwrap();
+    fs::write(root.join("index.js"), "").unwrap();
+
+    assert_eq!(types(&poller.poll(), root), [("index.js".to_string(), "create")]);
+  }
+
+  #[test]
+  fn rejects_files() {
+    let directory = TestDirectory::new("poll-file");
+    let file = directory.0.join("file.txt");
+    fs::write(&file, "content").unwrap();
+    assert!(matches!(Poller::new(&file, &[]), Err(Error::Io(_))));
+  }
+}
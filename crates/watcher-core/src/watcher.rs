@@ -0,0 +1,172 @@
+//! Watching a directory from Rust, with its debounced and filtered events handed to a callback or a channel
+//! in batches
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_full::{DebounceEventResult, Debouncer, RecommendedCache, new_debouncer};
+
+use crate::batch::{IgnoreFilter, select_events};
+use crate::event::{Error, Event};
+
+/// How long events are held back to coalesce rapid changes
+pub const DEBOUNCE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The events of a batch, or the errors the OS watcher reported instead
+pub type BatchResult = Result<Vec<Event>, Vec<notify::Error>>;
+
+/// Receives the batches of a `Watcher`, on the thread of its debouncer
+pub trait BatchHandler: Send + 'static {
+  fn handle_batch(&mut self, batch: BatchResult);
+}
+
+impl<F: FnMut(BatchResult) + Send + 'static> BatchHandler for F {
+  fn handle_batch(&mut self, batch: BatchResult) {
+    self(batch)
+  }
+}
+
+impl BatchHandler for Sender<BatchResult> {
+  fn handle_batch(&mut self, batch: BatchResult) {
+    // The receiver going away only means nobody listens anymore
+    let _ = self.send(batch);
+  }
+}
+
+/// A directory watched recursively, until the watcher is dropped. Batches without any event left after the
+/// ignore patterns are not handed on.
+pub struct Watcher {
+  root: PathBuf,
+  _debouncer: Debouncer<RecommendedWatcher, RecommendedCache>,
+}
+
+impl Watcher {
+  /// Watch `root`, leaving out the paths matching the glob patterns of `ignore` relative to it or in full
+  pub fn new(root: &Path, ignore: &[String], mut handler: impl BatchHandler) -> Result<Watcher, Error> {
+    let ignores = IgnoreFilter::new(ignore)?;
+    // Events carry resolved paths, which `root` has to be a prefix of
+    let root = dunce::canonicalize(root)?;
+
+    let filtered_root = root.clone();
+    let mut reported = Vec::new();
+    let mut debouncer = new_debouncer(DEBOUNCE_TIMEOUT, None, move |result: DebounceEventResult| match result {
+      Ok(batch) => {
+        let mut events = Vec::new();
+        let event = |path: &Path, event_type, _: &_| Event { path: path.to_path_buf(), event_type };
+        select_events(&batch, &filtered_root, &ignores, &mut events, &mut reported, event, |_, _, _| {});
+        if !events.is_empty() {
+          handler.handle_batch(Ok(events));
+        }
+      }
+      Err(errors) => handler.handle_batch(Err(errors)),
+    })?;
+    debouncer.watch(&root, RecursiveMode::Recursive)?;
+
+    Ok(Watcher { root, _debouncer: debouncer })
+  }
+
+  /// The watched directory, with symlinks resolved
+  pub fn root(&self) -> &Path {
+    &self.root
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+  use std::sync::mpsc;
+  use std::time::Instant;
+
+  use super::*;
+  use crate::build_glob_set;
+  use crate::event::TestDirectory;
+
+  /// Events received until `path` shows up or two seconds pass
+  fn events_until(receiver: &mpsc::Receiver<BatchResult>, path: &Path) -> Vec<Event> {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut events = Vec::new();
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+      let Ok(batch) = receiver.recv_timeout(left) else {
+        break;
+      };
+      events.extend(batch.unwrap());
+      if events.iter().any(|event| event.path == path) {
+        break;
+      }
+    }
+    events
+  }
+
+  #[test]
+  fn delivers_creates_through_a_channel() {
+    let directory = TestDirectory::new("channel");
+    let (sender, receiver) = mpsc::channel();
+    let watcher = Watcher::new(&directory.0, &[], sender).unwrap();
+    assert_eq!(watcher.root(), directory.0);
+
+    let file = directory.0.join("file.txt");
+    fs::write(&file, "content").unwrap();
+
+    let events = events_until(&receiver, &file);
+    assert!(events.iter().any(|event| event.path == file && event.event_type == "create"), "{:?}", events);
+  }
+
+  #[test]
+  fn leaves_out_ignored_paths() {
+    let directory = TestDirectory::new("ignore");
+    fs::create_dir(directory.0.join("node_modules")).unwrap();
+    let (sender, receiver) = mpsc::channel();
+    let _watcher = Watcher::new(&directory.0, &["node_modules/**".to_string()], move |batch| sender.send(batch).unwrap()).unwrap();
+
+    let ignored = directory.0.join("node_modules").join("package.json");
+    let kept = directory.0.join("index.js");
+    fs::write(&ignored, "{}").unwrap();
+    fs::write(&kept, "").unwrap();
+
+    let events = events_until(&receiver, &kept);
+    assert!(events.iter().any(|event| event.path == kept));
+    assert!(!events.iter().any(|event| event.path == ignored), "{:?}", events);
+  }
+
+  #[test]
+  fn reports_hostile_names_exactly() {
+    let directory = TestDirectory::new("hostile");
+    let (sender, receiver) = mpsc::channel();
+    let _watcher = Watcher::new(&directory.0, &["**/*.log".to_string()], sender).unwrap();
+
+    let long = "a".repeat(250);
+    let mut names = vec!["tab\there", "bidi\u{202e}txt.exe", "emoji \u{1f600}\u{1f469}\u{200d}\u{1f4bb}", long.as_str()];
+    // Windows forbids these in names
+    if cfg!(not(windows)) {
+      names.extend(["new\nline", "trailing space ", "trailing dot."]);
+    }
+    for name in &names {
+      fs::write(directory.0.join(name), "content").unwrap();
+      fs::write(directory.0.join(format!("{}.log", name)), "content").unwrap();
+    }
+    let last = directory.0.join(names[names.len() - 1]);
+
+    let events = events_until(&receiver, &last);
+    for name in &names {
+      let path = directory.0.join(name);
+      assert!(events.iter().any(|event| event.path == path && event.event_type == "create"), "{:?} in {:?}", name, events);
+    }
+    assert!(!events.iter().any(|event| event.path.extension().is_some_and(|extension| extension == "log")), "{:?}", events);
+  }
+
+  #[test]
+  fn rejects_invalid_patterns() {
+    let directory = TestDirectory::new("invalid");
+    let result = Watcher::new(&directory.0, &["a[".to_string()], |_| {});
+    assert!(matches!(result, Err(Error::Pattern(_))));
+  }
+
+  #[test]
+  fn builds_glob_sets() {
+    let glob_set = build_glob_set(&["**/*.log".to_string()]).unwrap();
+    assert!(glob_set.is_match("logs/today.log"));
+    assert!(build_glob_set(&["a[".to_string()]).is_err());
+  }
+}
@@ -0,0 +1,56 @@
+/* Watches the directory given as the first argument through the C API, run by tests/ffi.rs */
+
+#include <stdatomic.h>
+#include <stdio.h>
+#include <string.h>
+#include <unistd.h>
+
+#include "watcher.h"
+
+static atomic_int created;
+
+static void on_batch(const char *batch_json, void *user_data) {
+  const char *name = user_data;
+  if (strstr(batch_json, name) != NULL && strstr(batch_json, "\"type\":\"create\"") != NULL) {
+    atomic_store(&created, 1);
+  }
+}
+
+int main(int argc, char **argv) {
+  if (argc != 2) {
+    fprintf(stderr, "usage: %s <directory>\n", argv[0]);
+    return 2;
+  }
+
+  if (watcher_subscribe(argv[1], "{\"ignored\":[]}", on_batch, NULL) != NULL || watcher_last_error() == NULL) {
+    fprintf(stderr, "unknown options were accepted\n");
+    return 1;
+  }
+
+  char *name = "created.txt";
+  WatcherHandle *handle = watcher_subscribe(argv[1], "{\"ignore\":[\"*.log\"]}", on_batch, name);
+  if (handle == NULL) {
+    fprintf(stderr, "subscribing failed: %s\n", watcher_last_error());
+    return 1;
+  }
+
+  char path[4096];
+  snprintf(path, sizeof path, "%s/%s", argv[1], name);
+  FILE *file = fopen(path, "w");
+  if (file == NULL) {
+    perror("fopen");
+    return 1;
+  }
+  fclose(file);
+
+  for (int waited = 0; waited < 200 && !atomic_load(&created); waited++) {
+    usleep(10000);
+  }
+  watcher_unsubscribe(handle);
+
+  if (!atomic_load(&created)) {
+    fprintf(stderr, "no create event for %s\n", path);
+    return 1;
+  }
+  return 0;
+}
@@ -0,0 +1,39 @@
+//! Builds the static library with the C API and runs `ffi.c` against it, with `cargo test -p watcher-core
+//! --features ffi`. Needs a C compiler as `cc`.
+
+#![cfg(all(feature = "ffi", unix))]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Run `command`, failing the test with its output unless it succeeds
+fn run(command: &mut Command) {
+  let output = command.output().unwrap_or_else(|e| panic!("{:?} could not start: {}", command, e));
+  assert!(output.status.success(), "{:?} failed:\n{}{}", command, String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn c_program_receives_batches() {
+  let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+  let scratch = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+  let target = scratch.join("target");
+  run(
+    Command::new(env!("CARGO")).current_dir(manifest).args(["rustc", "--lib", "--features", "ffi", "--crate-type", "staticlib", "--target-dir"]).arg(&target),
+  );
+
+  let program = scratch.join("ffi-test");
+  let mut cc = Command::new("cc");
+  cc.arg(manifest.join("tests/ffi.c")).arg("-I").arg(manifest.join("include")).arg(target.join("debug/libwatcher_core.a")).arg("-o").arg(&program);
+  if cfg!(target_os = "macos") {
+    cc.args(["-framework", "CoreServices", "-framework", "CoreFoundation"]);
+  } else {
+    cc.args(["-lpthread", "-ldl", "-lm"]);
+  }
+  run(&mut cc);
+
+  let directory = scratch.join("watched");
+  let _ = fs::remove_dir_all(&directory);
+  fs::create_dir_all(&directory).unwrap();
+  run(Command::new(&program).arg(fs::canonicalize(&directory).unwrap()));
+}
@@ -1,9 +1,334 @@
 /* auto-generated by NAPI-RS */
 /* eslint-disable */
+/** A mute started with `Subscription.mute` */
+export declare class MuteHandle {
+  /** End the mute, unless it already ended. Patterns muted by other calls as well stay muted. */
+  unmute(): void;
+}
+
 /** An active subscription that can be unsubscribed */
 export declare class Subscription {
   /** Stop watching for file system changes */
   unsubscribe(): void;
+  /**
+   * Stop watching after delivering events that are still pending in the debouncer.
+   * Resolves once the watcher has fully stopped and the callback has been released.
+   */
+  unsubscribeAsync(): Promise<void>;
+  /** Whether events are taken from a watcher registered for a parent directory instead of a watch of its own */
+  get attached(): boolean;
+  /** Number of directories registered with the OS watcher for this subscription's tree */
+  get watchCount(): number;
+  /** Identifies the subscription in `listSubscriptions`, unique within the process */
+  get id(): number;
+  /** Watcher implementation this subscription's events come from */
+  get backend(): 'inotify' | 'fsevents' | 'windows' | 'kqueue' | 'polling' | 'mock';
+  /** Why the subscription polls instead of using the native watcher, if it does */
+  get backendReason(): string | null;
+  /** How the subscription tells that a file changed, if it polls */
+  get pollCompare(): 'mtime' | 'contents' | null;
+  /** FSEvents event id to pass as `sinceEventId` to resume from now, on macOS with the native watcher */
+  get eventId(): number | null;
+  /**
+   * Resolves once the OS watcher reports changes for the whole tree and the initial scan has been delivered,
+   * or rejects if the watcher failed
+   */
+  get ready(): Promise<void>;
+  /** Get statistics about this subscription */
+  stats(): SubscriptionStats;
+  /** Start the statistics over from zero. The number of queued batches is kept, as it describes the present. */
+  resetStats(): void;
+  /**
+   * Swallow the next create or update event for each of `paths` (relative to the watched directory or absolute)
+   * that arrives within `window_ms`, for changes the calling process is about to make itself. Deletes are
+   * still delivered, and announcing a path several times swallows as many events.
+   */
+  expectChange(paths: Array<string>, windowMs?: number): void;
+  /**
+   * Drop events for paths matching `patterns` until the returned handle is unmuted, or for `duration_ms`.
+   * The watches stay in place, so events are heard again as soon as the mute ends.
+   */
+  mute(patterns: Array<string>, durationMs?: number): MuteHandle;
+  /**
+   * Call `listener` with each delivered event of type `event`, or with each error or warning delivered for
+   * `'error'` and `'warning'`. Listeners are called besides the callback, in the order they were added.
+   */
+  on<E extends 'create' | 'update' | 'delete' | 'metadata' | 'error' | 'warning'>(event: E, listener: (value: E extends 'error' ? Error : E extends 'warning' ? WatchWarning : WatchEvent) => void): void;
+  /** Remove `listener` from the listeners of `event`, the one added last if it was added more than once */
+  off(event: 'create' | 'update' | 'delete' | 'metadata' | 'error' | 'warning', listener: (value: any) => void): void;
+  /** Resolves with the next delivered event matching `options`, or rejects once the subscription stops */
+  next(options?: NextOptions): Promise<WatchEvent>;
+  /**
+   * Take every batch buffered for a subscription created without a callback, without waiting for more.
+   * Returns an empty array when nothing was delivered since the last call.
+   */
+  poll(): Array<WatchCallbackResult>;
+  /**
+   * Resolves with the next batch of a subscription created without a callback, or with `null` when
+   * `timeout_ms` passes first. Rejects once the subscription stops with nothing left to read.
+   */
+  nextBatch(timeoutMs?: number): Promise<WatchCallbackResult | null>;
+  /**
+   * Resolves once no events have arrived for the tree for `quiet_ms`, or rejects with an `ETIMEDOUT` error
+   * when that does not happen within `timeout_ms`. Rejects once the subscription stops.
+   */
+  waitForQuiescence(quietMs: number, timeoutMs?: number, options?: QuiescenceOptions): Promise<void>;
+  /**
+   * Walk `subpath` (the whole tree by default) again and deliver synthetic events for everything that
+   * differs from what was delivered so far. Resolves with the number of events of each type.
+   */
+  triggerRescan(subpath?: string): Promise<RescanResult>;
+  /**
+   * The entries at or below `prefix` (the whole tree by default) as the subscription knows them from the events
+   * it delivered, without ignored paths. Needs the state `emitInitial`, `reconcileIntervalMs` or
+   * `triggerRescan()` records. With `onChunk`, entries are passed to it in chunks instead and an empty array is returned.
+   */
+  getTree(prefix?: string, onChunk?: (entries: Array<ScanEntry>) => void): Array<ScanEntry>;
+  /**
+   * The events the debouncer holds for the subscription and has not delivered yet, after its `ignore` patterns
+   * and mutes, left in place for delivery. The snapshot can be stale as soon as it is taken.
+   */
+  getPendingEvents(): Array<WatchEvent>;
+  /**
+   * Deliver events again after `circuitBreaker` stopped delivery during an event storm, announcing it with a
+   * batch carrying `storm`. Returns whether delivery was stopped.
+   */
+  resume(): boolean;
+  /**
+   * Hand raw events to a subscription with `backend: 'mock'` as if the OS watcher had reported them, to be
+   * debounced, filtered and delivered like any other
+   */
+  injectRaw(events: Array<RawEvent>): void;
+  /**
+   * Change how long the debouncer waits for further events of a path, for a subscription with a watcher of its
+   * own. Events received before the call are delivered under the interval they were received with.
+   */
+  setDebounce(ms: number): void;
+}
+
+/**
+ * Batches of a subscription as an async iterator. Breaking out of the loop closes the subscription,
+ * and an error delivered by the watcher is thrown from `next()` after closing it.
+ */
+export declare class WatchIterator {
+  [Symbol.asyncIterator](): WatchIterator;
+  /** Resolve with the next batch, waiting for one unless some are buffered */
+  next(): Promise<IteratorResult<WatchCallbackResult, undefined>>;
+  /** Close the subscription and finish the iteration */
+  return(): Promise<IteratorResult<WatchCallbackResult, undefined>>;
+}
+
+/** Describe the file system watcher backend used on this platform */
+export declare function capabilities(): Capabilities;
+
+/** What the file system watcher backend of this platform can do */
+export interface Capabilities {
+  /** Where events come from */
+  backend: 'inotify' | 'fsevents' | 'windows' | 'kqueue' | 'polling';
+  /** Whether the OS watches whole trees itself, instead of one watch per directory */
+  nativeRecursive: boolean;
+  /** Whether the two halves of a rename carry a cookie that pairs them */
+  supportsCookies: boolean;
+  /** Whether files have an id that survives renames, used by `getEventsSince` to notice files replaced since the snapshot */
+  supportsFileIds: boolean;
+  /** Number of directories one user may watch at a time, where the OS sets such a limit */
+  watchLimit?: number;
+  /** Version of the `notify` crate the watcher is built with */
+  notifyVersion: string;
+}
+
+/** When `circuitBreaker` stops delivery and when it resumes */
+export interface CircuitBreakerOptions {
+  /** Most events delivered within a second before delivery stops */
+  maxEventsPerSecond: number;
+  /**
+   * Resume delivery once no second had more than `maxEventsPerSecond` events for this many milliseconds.
+   * Without it, delivery stays stopped until `resume()`.
+   */
+  cooldownMs?: number;
+}
+
+/** Drop every fault still pending. Only available in builds with the `faults` feature. */
+export declare function clearFaults(): void;
+
+/**
+ * Compare two results of `scan`, returning the events that turn the first into the second.
+ * With `onChunk`, events are passed to it in chunks instead and an empty array is returned.
+ */
+export declare function diffTrees(before: Array<ScanEntry>, after: Array<ScanEntry>, onChunk?: (events: Array<WatchEvent>) => void): Array<WatchEvent>;
+
+/** Number of events in a directory, for `StormReport` */
+export interface DirectoryCount {
+  path: string;
+  count: number;
+}
+
+/**
+ * Number of background threads dispatching events to subscriptions.
+ * Subscriptions share one thread unless they are isolated. Only available in builds with the `faults` feature.
+ */
+export declare function dispatchThreadCount(): number;
+
+/** Faults to inject, each counting down as it is used */
+export interface Faults {
+  /** Number of batches of the OS watcher to precede with a backend error */
+  backendErrors?: number;
+  /** Number of watches of the native watcher to fail as if it could not start */
+  nativeFailures?: number;
+  /** Number of calls of subscription callbacks to fail as if the N-API queue were full */
+  queueFull?: number;
+  /** Glob patterns of absolute paths whose metadata cannot be read, until `clearFaults()` */
+  statFailures?: Array<string>;
+  /**
+   * Glob patterns of absolute paths the first read of whose metadata fails as if another process held them
+   * locked, until `clearFaults()`
+   */
+  lockedPaths?: Array<string>;
+  /**
+   * Glob patterns of absolute paths whose events are dropped as if the OS watcher missed them, until
+   * `clearFaults()`
+   */
+  missedPaths?: Array<string>;
+  /** Number of batches to panic on while processing them for a subscription, each stopping only that one */
+  panics?: number;
+}
+
+/** Get the events that turn the state recorded by `writeSnapshot` into the current state of the directory tree */
+export declare function getEventsSince(directory: string, snapshotPath: string, options?: WatchOptions): Promise<Array<WatchEvent>>;
+
+/**
+ * Inject `faults`, adding to the counts of those still pending and replacing the patterns of `statFailures`,
+ * `lockedPaths` and `missedPaths` if given. Only available in builds with the `faults` feature.
+ */
+export declare function injectFaults(faults: Faults): void;
+
+/**
+ * Check if the watcher would leave out `path` when watching `base_dir` with `options`, using the same
+ * pattern matching as subscriptions. A relative `path` is taken as relative to `base_dir`; neither is resolved
+ * against the file system.
+ */
+export declare function isIgnored(path: string, baseDir: string, options?: WatchOptions): boolean;
+
+/** List the live subscriptions of the process, in the order they were created */
+export declare function listSubscriptions(): Array<SubscriptionInfo>;
+
+/** What was dropped to stay within `maxMemoryBytes`, on the batch reporting it */
+export interface MemoryShed {
+  /** Batches that were waiting to be read, oldest first */
+  batches: number;
+  /** Events of those batches, and of the batch that could not be delivered within the cap */
+  events: number;
+  /** Entries of the file id cache */
+  cacheEntries: number;
+  /** Estimated memory freed, in bytes */
+  bytes: number;
+}
+
+/** Which event `next` and `once` wait for */
+export interface NextOptions {
+  /** Only settle for events of these types (any type by default) */
+  types?: Array<'create' | 'update' | 'delete' | 'metadata'>;
+  /** Reject with an `ETIMEDOUT` error if no matching event arrives within this many milliseconds */
+  timeoutMs?: number;
+}
+
+/**
+ * Resolve with the first event delivered for a directory that matches `options`, unsubscribing right after
+ * the promise settles
+ */
+export declare function once(directory: string, options?: WatchOptions & NextOptions): Promise<WatchEvent>;
+
+/** A problem with a directory or options found by `validateOptions` */
+export interface OptionsProblem {
+  /** What is wrong */
+  code: 'EMPTY_DIRECTORY' | 'DIRECTORY_NOT_FOUND' | 'NOT_A_DIRECTORY' | 'DIRECTORY_UNREADABLE' | 'LARGE_ROOT' | 'INVALID_PATTERN' | 'INVALID_RETRY' | 'INVALID_LOG_LEVEL' | 'INVALID_SERIALIZATION' | 'INVALID_JOURNAL' | 'INVALID_RING' | 'INVALID_MAX_WATCHED_ENTRIES' | 'INVALID_UNICODE_NORMALIZATION' | 'INVALID_BACKEND' | 'INVALID_RECONCILE_INTERVAL' | 'INVALID_HEARTBEAT_INTERVAL' | 'INVALID_DEBOUNCE' | 'INVALID_SINCE_EVENT_ID' | 'INVALID_ROUTE_MATCH' | 'INVALID_SKIP_UNCHANGED' | 'INVALID_POLL_COMPARE';
+  /** The directory, pattern or option at fault */
+  input: string;
+  /** The error `subscribe` would throw for it */
+  message: string;
+}
+
+/** Result of `validateOptions` */
+export interface OptionsReport {
+  /** Whether no problems were found */
+  valid: boolean;
+  /** The directory as it would be watched, with symlinks resolved, if it could be resolved */
+  directory?: string;
+  problems: Array<OptionsProblem>;
+}
+
+/** Which events `waitForQuiescence` counts */
+/** An event the way `@parcel/watcher` reports it, for `callbackStyle: 'node'` */
+export interface ParcelEvent {
+  path: string;
+  type: 'create' | 'update' | 'delete' | 'metadata';
+}
+
+export interface QuiescenceOptions {
+  /** Count events for paths matched by the ignore patterns as well, instead of only the events that are delivered */
+  includeIgnored?: boolean;
+}
+
+/** A raw event as the OS watcher would report it, for `injectRaw` */
+export interface RawEvent {
+  /** Kind of event the way `rawKind` and `debugEventLog` render it, such as `Create(File)` or `Modify(Name(From))` */
+  kind: string;
+  paths: Array<string>;
+  /** Pairs the halves of a rename, as the cookies of inotify and Windows do */
+  tracker?: number;
+}
+
+/** Number of synthetic events delivered by `triggerRescan` */
+export interface RescanResult {
+  created: number;
+  updated: number;
+  deleted: number;
+}
+
+/** Retry policy for establishing the watch */
+export interface RetryOptions {
+  /** Maximum number of attempts, including the first one */
+  attempts: number;
+  /** Delay before the first retry in milliseconds */
+  delayMs: number;
+  /** Factor the delay is multiplied by after each failed attempt, up to 10 seconds (defaults to 1) */
+  backoff?: number;
+}
+
+/** A callback of `subscribeRouted` and the events it is called with */
+export interface Route {
+  /**
+   * Pattern the paths of the events for the callback match, relative to the directory or as a whole. The one
+   * route without a pattern is called with the events no other route takes.
+   */
+  pattern?: string;
+  callback: (result: WatchCallbackResult | WatchCallbackJson) => void;
+}
+
+/**
+ * List the entries of a directory tree that a subscription with the same options would report,
+ * without registering any watches
+ */
+export declare function scan(directory: string, options?: WatchOptions): Promise<Array<ScanEntry>>;
+
+/** An entry found by `scan` */
+export interface ScanEntry {
+  path: string;
+  fileType: 'file' | 'directory' | 'symlink';
+  /** Last modification time in milliseconds since the Unix epoch */
+  mtimeMs: number;
+  /** Size in bytes */
+  size: number;
+}
+
+/** The circuit breaker opening or closing, on the batch announcing it */
+export interface StormReport {
+  /** `open` when delivery stopped, `closed` when it resumed */
+  state: 'open' | 'closed';
+  /** Events within the second that tripped the breaker, or events left undelivered while it was open */
+  eventCount: number;
+  /** The directories with the most of those events, most first */
+  directories: Array<DirectoryCount>;
 }
 
 /**
@@ -11,28 +336,451 @@ export declare class Subscription {
  *
  * # Arguments
  * * `directory` - The directory path to watch
- * * `callback` - Function called with (error, events) when changes occur
- * * `options` - Optional configuration including ignore patterns
+ * * `callback` - Function called with (error, events) when changes occur, or nothing to read batches with `nextBatch`
+ * * `options` - Optional configuration including ignore patterns and retry policy
  *
  * # Returns
  * A subscription that can be used to stop watching
  */
-export declare function subscribe(directory: string, callback: (result: WatchCallbackResult) => void, options?: WatchOptions): Subscription;
+export declare function subscribe<O extends WatchOptions = WatchOptions>(directory: string, callback?: (O extends { callbackStyle: 'node' } ? (error: Error | null, events: ParcelEvent[]) => void : (result: O extends { serialization: 'json' } ? WatchCallbackJson : WatchCallbackResult) => void) | null, options?: O): Subscription;
+
+/**
+ * Subscribe to file system changes in a directory without blocking the JS thread, resolving the directory and
+ * registering it with the OS watcher on a background thread. Takes the same arguments as `subscribe`.
+ *
+ * # Returns
+ * A promise for the subscription, resolved once the directory is watched, or rejected with the error
+ * `subscribe` would throw
+ */
+export declare function subscribeAsync<O extends WatchOptions = WatchOptions>(directory: string, callback?: (O extends { callbackStyle: 'node' } ? (error: Error | null, events: ParcelEvent[]) => void : (result: O extends { serialization: 'json' } ? WatchCallbackJson : WatchCallbackResult) => void) | null, options?: O): Promise<Subscription>;
+
+/**
+ * Subscribe to file system changes in a directory, splitting each batch between several callbacks by the
+ * patterns the paths of its events match. One watcher serves every route.
+ *
+ * # Arguments
+ * * `directory` - The directory path to watch
+ * * `routes` - Patterns and the callbacks called with the events whose paths match them, with at most one
+ *   route leaving out its pattern to take the events no other route takes
+ * * `options` - The options of `subscribe`, with `routeMatch` choosing between the first and every matching route
+ *
+ * # Returns
+ * A subscription that stops every route when unsubscribed
+ */
+export declare function subscribeRouted(directory: string, routes: Array<Route>, options?: WatchOptions): Subscription;
+
+/**
+ * Resume a subscription whose batches were journaled, first delivering the batches recorded after `cursor`
+ *
+ * # Arguments
+ * * `journal_path` - The journal the subscription was writing to
+ * * `cursor` - The `cursor` of the last batch that was handled, or 0 to replay everything the journal kept
+ * * `callback` - Function called with (error, events) when changes occur
+ * * `options` - Optional configuration, `journalPath` is replaced by `journal_path`
+ *
+ * # Returns
+ * A subscription to the directory the journal was written for
+ */
+export declare function subscribeSince<O extends WatchOptions = WatchOptions>(journalPath: string, cursor: number, callback: O extends { callbackStyle: 'node' } ? (error: Error | null, events: ParcelEvent[]) => void : (result: O extends { serialization: 'json' } ? WatchCallbackJson : WatchCallbackResult) => void, options?: O): Subscription;
+
+/** A live subscription as listed by `listSubscriptions` */
+export interface SubscriptionInfo {
+  /** Same as the subscription's `id` */
+  id: number;
+  /** The watched directory, with symlinks resolved */
+  path: string;
+  /** The `label` option the subscription was created with */
+  label?: string;
+  /** Watcher implementation the subscription's events come from */
+  backend: 'inotify' | 'fsevents' | 'windows' | 'kqueue' | 'polling' | 'mock';
+  /** Why the subscription polls instead of using the native watcher, if it does */
+  backendReason?: string;
+  /** Number of events delivered to the subscription so far */
+  eventCount: number;
+  /** When the subscription was created, in milliseconds since the Unix epoch */
+  createdAt: number;
+}
+
+/** Statistics about a subscription */
+export interface SubscriptionStats {
+  /** Number of permission errors left out of error reporting by `ignorePermissionErrors` */
+  suppressedPermissionErrors: number;
+  /** Number of events swallowed because they were announced with `expectChange` */
+  suppressedExpectedChanges: number;
+  /** Number of events dropped because their path was muted */
+  mutedEvents: number;
+  /** Number of updates dropped by `skipUnchanged` because they left the file as it was */
+  suppressedUnchanged: number;
+  /** Shortest time from the OS watcher reporting an event to its batch being dispatched to the callback, in milliseconds */
+  latencyMinMs?: number;
+  /** Average time from the OS watcher reporting an event to its batch being dispatched, in milliseconds */
+  latencyAvgMs?: number;
+  /** Longest time from the OS watcher reporting an event to its batch being dispatched, in milliseconds */
+  latencyMaxMs?: number;
+  /** Events by latency: below 1ms, then doubling from 1-2ms up to 8-16s, then 16s and more */
+  latencyHistogram: Array<number>;
+  /** Batches waiting for the callback to handle them, or in the buffer of a subscription without a callback */
+  queuedBatches: number;
+  /** Most batches that were waiting at once */
+  maxQueuedBatches: number;
+  /** Batches dropped because the callback could not be called with them, such as with the N-API queue full */
+  droppedBatches: number;
+  /** Number of create events delivered */
+  createdEvents: number;
+  /** Number of update events delivered */
+  updatedEvents: number;
+  /** Number of delete events delivered */
+  deletedEvents: number;
+  /** Number of metadata events delivered, with `emitMetadata` */
+  metadataEvents: number;
+  /** Paths whose file id the watcher keeps to pair the halves of renames, for all subscriptions sharing it */
+  cacheEntries: number;
+  /** Number of times reading the metadata of a file another process held locked was attempted again */
+  lockedRetries: number;
+  /** Number of locked files whose metadata could still not be read after `lockedFileRetry` ran out */
+  lockedFailures: number;
+  /** Number of times `circuitBreaker` stopped delivery */
+  stormTrips: number;
+  /** Number of events left undelivered while `circuitBreaker` had delivery stopped */
+  stormEvents: number;
+  /** Estimated memory the subscription holds natively in batches and the file id cache, in bytes */
+  memoryBytes: number;
+}
+
+/**
+ * Unsubscribe every subscription created in this thread, delivering their pending events first as
+ * `unsubscribeAsync` does. Resolves with the number of subscriptions closed.
+ */
+export declare function unsubscribeAll(): Promise<number>;
+
+/**
+ * Check a directory and options the way `subscribe` does, reporting every problem found instead of throwing
+ * the first. Nothing is watched, and the directory is resolved once, without retries.
+ */
+export declare function validateOptions(directory: string, options?: WatchOptions): OptionsReport;
+
+/**
+ * Resolve once a file exists or changes, watching its parent directory until then. A file that is already
+ * there, or was modified after `newer_than`, resolves right away with an event whose `is_initial` is set.
+ */
+export declare function waitForFile(path: string, options?: WaitForFileOptions): Promise<WatchEvent>;
+
+/** What `waitForFile` waits for */
+export interface WaitForFileOptions {
+  /** `create` waits for the file to exist, `update` for its next change and `any` (the default) for either */
+  event?: 'create' | 'update' | 'any';
+  /** Reject with an `ETIMEDOUT` error if the file does not appear or change within this many milliseconds */
+  timeoutMs?: number;
+  /** With `update`, resolve right away if the file was modified after this time, in milliseconds since the epoch */
+  newerThan?: number;
+}
+
+/** Iterate over the batches delivered for a directory, closing the subscription when the iteration ends */
+export declare function watch(directory: string, options?: WatchOptions): WatchIterator;
+
+/** Callback result of a subscription with `serialization: 'json'` */
+export interface WatchCallbackJson {
+  error?: Error;
+  /** The `WatchCallbackResult` of the batch without `error`, serialized to JSON */
+  json: string;
+}
 
 /** Callback result type for the watcher */
 export interface WatchCallbackResult {
+  /** Sequence number of this callback invocation, starting at 1 */
+  seq: number;
   error?: Error;
   events: Array<WatchEvent>;
+  warnings?: Array<WatchWarning>;
+  /** Set on batches of the initial scan, `'complete'` on the last one */
+  initial?: 'partial' | 'complete';
+  /** Set on batches produced by `triggerRescan` rather than by the OS watcher */
+  synthetic?: boolean;
+  /** Position of the batch in the journal, to pass to `subscribeSince` after a restart */
+  cursor?: number;
+  /** Set on the empty batches `heartbeatMs` sends while nothing else was delivered */
+  heartbeat?: boolean;
+  /** Set on the batch that stands for the events dropped because too many were waiting to be read */
+  overflowed?: boolean;
+  /** Set on the empty batches announcing that `circuitBreaker` stopped or resumed delivery */
+  storm?: StormReport;
+  /** Set along with `error` on the batch reporting what was dropped to stay within `maxMemoryBytes` */
+  memoryShed?: MemoryShed;
+  /**
+   * Set along with `error` on the batch summarizing how many times the error before it came again within
+   * `repeatedErrorWindowMs` without being delivered
+   */
+  repeated?: number;
+  /** Set on the batch of the changes FSEvents recorded since `sinceEventId`, delivered before any live batch */
+  historical?: boolean;
+  /** FSEvents event id to pass as `sinceEventId` to resume after this batch, on macOS with the native watcher */
+  eventId?: number;
 }
 
 /** A file system event */
 export interface WatchEvent {
   path: string;
-  type: 'create' | 'update' | 'delete';
+  /** Watched directory the event belongs to, as it was resolved when subscribing */
+  root?: string;
+  type: 'create' | 'update' | 'delete' | 'metadata';
+  /** Whether the event comes from the initial scan rather than a live change */
+  isInitial: boolean;
+  /** Set when the path is not valid UTF-8, so that `path` has replacement characters in place of the invalid bytes */
+  lossy?: boolean;
+  /** The path as the OS reported it, set along with `lossy` */
+  pathBytes?: Buffer;
+  /**
+   * Set on Windows when the path has 8.3 short names that could not be resolved to long ones, for example
+   * because the file was deleted
+   */
+  shortName?: boolean;
+  /**
+   * The kind of event notify reported, such as `Modify(Name(From))`, with `rawKinds`. Kinds of events merged
+   * into this one are joined with `+`.
+   */
+  rawKind?: string;
+  /** With `dedupeHardlinks`, the other paths of the same file that were changed along with this one */
+  aliases?: Array<string>;
+  /** With `trackCoalescing`, how many events the OS watcher reported for the path before this one was delivered */
+  coalescedCount?: number;
+  /** With `trackCoalescing`, when the first of those events was received, in milliseconds since the Unix epoch */
+  firstSeenMs?: number;
+  /** With `trackCoalescing`, when the last of those events was received, in milliseconds since the Unix epoch */
+  lastSeenMs?: number;
+  /** With `detectTypeChanges`, the type of the entry a `create` brought or a `delete` took away */
+  fileType?: 'file' | 'directory' | 'symlink';
+  /** With `flagOutsideRoot`, set when the path resolves to one outside the watched directory through a symlink */
+  outsideRoot?: boolean;
+  /** With `emitXattr`, set when the event stands for a change of the path's extended attributes */
+  xattr?: boolean;
 }
 
 /** Options for configuring the watcher */
 export interface WatchOptions {
-  /** Patterns to ignore (file paths or glob patterns) */
+  /**
+   * Patterns to ignore (file paths or glob patterns). A plain name also ignores everything below the
+   * directories it names, and a pattern starting with `!` re-includes what it matches
+   */
   ignore?: Array<string>;
+  /** Retry transient failures while establishing the watch (no retry by default) */
+  retry?: RetryOptions;
+  /** Fail instead of skipping subdirectories that cannot be watched due to permissions */
+  strict?: boolean;
+  /**
+   * Watch a filesystem root such as `/` or `C:\\`, or the home directory, which is refused with a `LARGE_ROOT`
+   * error otherwise (false by default)
+   */
+  allowLargeRoots?: boolean;
+  /**
+   * Leave out directories on another file system than the watched directory, such as mounts of network shares
+   * or a tmpfs, with everything below them, reporting each with an `other-file-system` warning (false by default)
+   */
+  oneFileSystem?: boolean;
+  /** Leave permission errors out of error reporting (they are still counted in `stats()`) */
+  ignorePermissionErrors?: boolean;
+  /** Warn through `onLog`, or on stderr without it, when the subscription is garbage collected without being unsubscribed */
+  warnIfLeaked?: boolean;
+  /** Use a dedicated OS watcher and dispatch thread instead of the ones shared with other subscriptions */
+  isolated?: boolean;
+  /**
+   * Watcher the events come from: the native one, or scanning the tree every `pollIntervalMs` (`'polling'`).
+   * `'auto'`, the default, polls directories on network shares and host mounts, where native watching misses changes.
+   */
+  backend?: 'auto' | 'native' | 'polling' | 'kqueue' | 'mock';
+  /** Milliseconds between scans of a polling subscription (1000 by default) */
+  pollIntervalMs?: number;
+  /**
+   * How a polling subscription tells that a file changed: by its modification time, which the poller keeps in
+   * whole seconds (`'mtime'`, the default), or also by a hash of its contents, read on every scan (`'contents'`)
+   */
+  pollCompare?: 'mtime' | 'contents';
+  /**
+   * With `backend: 'auto'`, poll directories on file systems where native watching misses changes (true by
+   * default). When false they are watched natively with an `unreliable-file-system` warning instead.
+   */
+  autoFallback?: boolean;
+  /** Also walk the tree every this many milliseconds and deliver what the watcher missed as synthetic events */
+  reconcileIntervalMs?: number;
+  /** Send an empty batch with `heartbeat: true` whenever no batch has been delivered for this many milliseconds */
+  heartbeatMs?: number;
+  /**
+   * Milliseconds the debouncer waits for further events of a path before delivering them (100 by default). A
+   * subscription with this option gets a watcher of its own, like an `isolated` one, and can change it with
+   * `setDebounce()`.
+   */
+  debounceMs?: number;
+  /**
+   * Deliver an error of the OS watcher that repeats the last one within this many milliseconds of it only once,
+   * with a summary counting the repeats when a different error comes or the window closes. 0 delivers every
+   * repeat (30000 by default)
+   */
+  repeatedErrorWindowMs?: number;
+  /**
+   * Retry reading the metadata of a changed file another process briefly holds locked, as antivirus and
+   * indexing services do on Windows (3 attempts 25ms apart by default)
+   */
+  lockedFileRetry?: RetryOptions;
+  /**
+   * Stop delivering events when more than `maxEventsPerSecond` arrive within a second, announcing it with a
+   * batch carrying `storm` and only counting the events by directory until delivery resumes
+   */
+  circuitBreaker?: CircuitBreakerOptions;
+  /**
+   * Register the tree again when read access to the directory is regained after an `access-lost` warning
+   * (true by default)
+   */
+  rewatchOnAccess?: boolean;
+  /** Report changes of permissions, owner or times as `metadata` events instead of `update` (false by default) */
+  emitMetadata?: boolean;
+  /**
+   * Report changes of extended attributes as `metadata` events marked with `xattr`, where the OS watcher tells
+   * them apart from other changes (false by default)
+   */
+  emitXattr?: boolean;
+  /** Report a path created and then changed within one batch with its `create` event alone (false by default) */
+  coalesce?: boolean;
+  /**
+   * Deliver the same canonical sequence of events for the same changes on every platform: at most one event
+   * per path and batch, no updates of directories for changes of their entries, and deletes, creates and
+   * updates in that order (false by default)
+   */
+  normalize?: boolean;
+  /**
+   * Drop the `update` a directory gets when an entry of it is created, deleted or renamed in the same batch,
+   * keeping updates of directories nothing else in the batch explains (false by default, true with `normalize`)
+   */
+  dropParentUpdates?: boolean;
+  /**
+   * Report a path that turned from a file into a directory or back as a `delete` of the old entry and a
+   * `create` of the new one, and give creates and deletes their `fileType`. Needs the state of the tree
+   * `emitInitial`, `reconcileIntervalMs` or `triggerRescan()` records (false by default)
+   */
+  detectTypeChanges?: boolean;
+  /**
+   * Report the updates of one file changed through several hardlinks within a batch once, with the other
+   * paths in `aliases` (false by default)
+   */
+  dedupeHardlinks?: boolean;
+  /**
+   * Leave out FIFOs, sockets and device nodes, reporting only regular files, directories and symlinks (false
+   * by default)
+   */
+  skipSpecialFiles?: boolean;
+  /**
+   * Deliver events whose path is the watched directory itself, such as changes of its permissions or times
+   * (true by default)
+   */
+  includeRoot?: boolean;
+  /**
+   * Deliver events whose path resolves to one outside the watched directory through a symlink with
+   * `outsideRoot: true` instead of dropping them (false by default)
+   */
+  flagOutsideRoot?: boolean;
+  /**
+   * Attach the kind of event notify reported to every event it reported as `rawKind`, for diagnosing
+   * platform differences (false by default)
+   */
+  rawKinds?: boolean;
+  /**
+   * Drop updates that leave a file as it was last seen: with the same modification time (`'mtime'`), size and
+   * modification time (`'size+mtime'`), or size and contents (`'hash'`). Creates and deletes are always
+   * delivered, as is the first update of a file the subscription has not seen yet (no updates are dropped by default)
+   */
+  skipUnchanged?: 'mtime' | 'size+mtime' | 'hash';
+  /**
+   * Attach to every event from the OS watcher how many raw events it stands for as `coalescedCount`, and when
+   * the first and last of them were received as `firstSeenMs` and `lastSeenMs`, for tuning (false by default)
+   */
+  trackCoalescing?: boolean;
+  /** Start by delivering create events for everything already in the directory */
+  emitInitial?: boolean;
+  /**
+   * Start by delivering the changes FSEvents recorded since this event id, the `eventId` of the last batch
+   * handled, in a batch with `historical`. Only on macOS with the native watcher.
+   */
+  sinceEventId?: number;
+  /**
+   * With `writeSnapshot` and `getEventsSince` on Windows, read what changed since the snapshot from the NTFS
+   * change journal instead of walking the tree, where the volume keeps one and it can be read (false by default)
+   */
+  changeJournal?: boolean;
+  /** Append every delivered batch to a journal at this path, for `subscribeSince` to resume from */
+  journalPath?: string;
+  /** Size in bytes the journal may grow to before it is rotated (64 MiB by default) */
+  journalMaxBytes?: number;
+  /**
+   * Buffer batches to be read with `nextBatch` instead of passing them to a callback. `false` without a callback
+   * leaves the events to the listeners added with `on()`.
+   */
+  pull?: boolean;
+  /** Name shown for the subscription by `listSubscriptions` */
+  label?: string;
+  /** Called with diagnostics about what the watcher observed, filtered and delivered */
+  onLog?: (level: 'error' | 'warn' | 'info' | 'debug' | 'trace', message: string) => void;
+  /** Least severe level passed to `onLog` (`'info'` by default) */
+  logLevel?: 'error' | 'warn' | 'info' | 'debug' | 'trace';
+  /** Append every event the OS watcher reports to an NDJSON file at this path, before debouncing and filtering */
+  debugEventLog?: string;
+  /** Pass batches to the callback as JSON strings to parse instead of objects (`'object'` by default) */
+  serialization?: 'object' | 'json';
+  /**
+   * Call the callback as `@parcel/watcher` does, with an error or `null` and the events, instead of with the
+   * batch (`'object'` by default)
+   */
+  callbackStyle?: 'object' | 'node';
+  /**
+   * Write events to this buffer from `createEventRing` instead of passing them to the callback, which is only
+   * called to signal them
+   */
+  ring?: SharedArrayBuffer;
+  /**
+   * What happens when the reader of `ring` falls behind: the oldest records are overwritten (`'overwrite'`, the
+   * default), or dispatching waits for the reader (`'block'`)
+   */
+  ringOverflow?: 'overwrite' | 'block';
+  /**
+   * Which routes of `subscribeRouted` an event goes to: the first whose pattern it matches (`'first'`, the
+   * default), or every one (`'all'`)
+   */
+  routeMatch?: 'first' | 'all';
+  /** Most paths whose file id the watcher keeps to pair the halves of renames, 0 to keep none (no limit by default) */
+  cacheLimit?: number;
+  /**
+   * Most files and directories the tree may have, counted while it is registered and by the initial scan, to
+   * guard against watching a much larger tree than intended (no limit by default)
+   */
+  maxWatchedEntries?: number;
+  /**
+   * What happens when the tree has more than `maxWatchedEntries`: subscribing fails with a `TOO_MANY_ENTRIES`
+   * error before anything is watched (`'fail'`, the default), or the tree is watched with a warning (`'warn'`)
+   */
+  maxWatchedEntriesAction?: 'fail' | 'warn';
+  /**
+   * Most memory in bytes the subscription may hold natively, in batches waiting for the callback or to be read
+   * and in the file id cache, as estimated (no limit by default)
+   */
+  maxMemoryBytes?: number;
+  /**
+   * What happens when a batch would take the subscription over `maxMemoryBytes`: the oldest batches waiting to
+   * be read, then file id cache entries, then the batch itself are dropped, reported with an error carrying
+   * `memoryShed` (`'shed'`, the default), or the subscription stops with an error (`'close'`)
+   */
+  maxMemoryAction?: 'shed' | 'close';
+  /**
+   * Unicode normalization form of the part below the directory of event paths, with ignore patterns matching
+   * names in either form (`'none'` by default, which leaves both alone). Only applied on macOS.
+   */
+  unicodeNormalization?: 'nfc' | 'nfd' | 'none';
+}
+
+/** A non-fatal problem encountered while watching */
+export interface WatchWarning {
+  path: string;
+  kind: 'permission-denied' | 'capture-failed' | 'too-many-entries' | 'unreliable-file-system' | 'backend-fallback' | 'access-lost' | 'access-regained' | 'symlink-cycle' | 'history-unavailable' | 'other-file-system';
+  message: string;
+  /** The file system an `unreliable-file-system` warning is about, such as `nfs` or `9p` */
+  fileSystem?: string;
 }
+
+/** Record the current state of a directory tree in `snapshotPath`, for `getEventsSince` to compare against later */
+export declare function writeSnapshot(directory: string, snapshotPath: string, options?: WatchOptions): Promise<void>;
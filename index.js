@@ -631,6 +631,26 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`);
 }
 
-const { Subscription, subscribe } = nativeBinding;
+const { MuteHandle, Subscription, WatchIterator, capabilities, clearFaults, diffTrees, dispatchThreadCount, getEventsSince, injectFaults, isIgnored, listSubscriptions, once, scan, subscribe, subscribeAsync, subscribeRouted, subscribeSince, unsubscribeAll, validateOptions, waitForFile, watch, writeSnapshot } = nativeBinding;
+export { MuteHandle };
 export { Subscription };
+export { WatchIterator };
+export { capabilities };
+export { clearFaults };
+export { diffTrees };
+export { dispatchThreadCount };
+export { getEventsSince };
+export { injectFaults };
+export { isIgnored };
+export { listSubscriptions };
+export { once };
+export { scan };
 export { subscribe };
+export { subscribeAsync };
+export { subscribeRouted };
+export { subscribeSince };
+export { unsubscribeAll };
+export { validateOptions };
+export { waitForFile };
+export { watch };
+export { writeSnapshot };
@@ -0,0 +1,29 @@
+/** Options of `createEventRing` */
+export interface EventRingOptions {
+  /** Number of events the ring holds, a power of two (65536 by default) */
+  capacity?: number;
+  /** Bytes available for the UTF-8 paths of the events, a power of two (64 per event by default) */
+  arenaBytes?: number;
+}
+
+/** Allocate and initialize a ring to pass as the `ring` option */
+export declare function createEventRing(options?: EventRingOptions): SharedArrayBuffer;
+
+/** Reads the events a subscription wrote to a ring, without creating an object per event */
+export declare class EventRingReader {
+  constructor(buffer: SharedArrayBuffer);
+  /** Events overwritten before they were read */
+  get lost(): number;
+  /** Events left out by the writer because their path is larger than the arena */
+  get dropped(): number;
+  /** Whether a subscription is writing to the ring */
+  get attached(): boolean;
+  /** Events written and not read yet, including ones that will turn out to be lost */
+  get pending(): number;
+  /**
+   * Pass every event written since the last call to `onEvent`, oldest first, and make room for new ones.
+   * Events whose path is not valid UTF-8 also get the bytes of the path, with replacement characters in `path`.
+   * Returns the number of events passed to `onEvent`.
+   */
+  read(onEvent: (type: 'create' | 'update' | 'delete' | 'metadata', path: string, isInitial: boolean, seq: number, pathBytes?: Uint8Array) => void): number;
+}
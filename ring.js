@@ -0,0 +1,155 @@
+/**
+ * Reader for the event ring of subscriptions created with the `ring` option. See `src/ring.rs` for the layout the
+ * native side writes.
+ */
+
+const MAGIC = 0x474e5257;
+const VERSION = 1;
+
+const HEADER_WORDS = 16;
+const RECORD_WORDS = 4;
+
+const CAPACITY = 2;
+const ARENA_BYTES = 3;
+const CLAIMED = 4;
+const COMMITTED = 5;
+const READ = 6;
+const ARENA_CLAIMED = 7;
+const ARENA_READ = 8;
+const DROPPED = 9;
+const WRITER = 10;
+
+const INITIAL = 1 << 8;
+const LOSSY = 1 << 9;
+const TYPES = ['create', 'update', 'delete', 'metadata'];
+
+const isPowerOfTwo = (value) => Number.isInteger(value) && value > 0 && value <= 2 ** 31 && (value & (value - 1)) === 0;
+
+/**
+ * Allocate and initialize a ring to pass as the `ring` option.
+ *
+ * @param {{ capacity?: number, arenaBytes?: number }} [options]
+ * @returns {SharedArrayBuffer}
+ */
+export const createEventRing = ({ capacity = 65536, arenaBytes = capacity * 64 } = {}) => {
+  if (!isPowerOfTwo(capacity) || !isPowerOfTwo(arenaBytes)) {
+    throw new RangeError(`Ring capacity and arenaBytes must be powers of two, got ${capacity} and ${arenaBytes}`);
+  }
+
+  const buffer = new SharedArrayBuffer((HEADER_WORDS + capacity * RECORD_WORDS) * 4 + arenaBytes);
+  const header = new Uint32Array(buffer, 0, HEADER_WORDS);
+  header[0] = MAGIC;
+  header[1] = VERSION;
+  header[CAPACITY] = capacity;
+  header[ARENA_BYTES] = arenaBytes;
+  return buffer;
+};
+
+/** Reads the events a subscription wrote to a ring, without creating an object per event */
+export class EventRingReader {
+  #header;
+  #records;
+  #arena;
+  #capacity;
+  #arenaBytes;
+  /** Copy of a path taken before it is checked and decoded, as the writer may overwrite the ring meanwhile */
+  #scratch = new Uint8Array(256);
+  #decoder = new TextDecoder();
+  #lost = 0;
+
+  /** @param {SharedArrayBuffer} buffer */
+  constructor(buffer) {
+    const header = new Uint32Array(buffer, 0, HEADER_WORDS);
+    if (header[0] !== MAGIC || header[1] !== VERSION) {
+      throw new TypeError('Ring buffer was not initialized by createEventRing of this version');
+    }
+
+    this.#header = header;
+    this.#capacity = header[CAPACITY];
+    this.#arenaBytes = header[ARENA_BYTES];
+    this.#records = new Uint32Array(buffer, HEADER_WORDS * 4, this.#capacity * RECORD_WORDS);
+    this.#arena = new Uint8Array(buffer, (HEADER_WORDS + this.#capacity * RECORD_WORDS) * 4, this.#arenaBytes);
+  }
+
+  /** Events overwritten before they were read */
+  get lost() {
+    return this.#lost;
+  }
+
+  /** Events left out by the writer because their path is larger than the arena */
+  get dropped() {
+    return Atomics.load(this.#header, DROPPED);
+  }
+
+  /** Whether a subscription is writing to the ring */
+  get attached() {
+    return Atomics.load(this.#header, WRITER) === 1;
+  }
+
+  /** Events written and not read yet, including ones that will turn out to be lost */
+  get pending() {
+    return (Atomics.load(this.#header, COMMITTED) - Atomics.load(this.#header, READ)) >>> 0;
+  }
+
+  /**
+   * Pass every event written since the last call to `onEvent`, oldest first, and make room for new ones.
+   *
+   * Events whose path is not valid UTF-8 also get the bytes of the path, with replacement characters in `path`.
+   *
+   * @param {(type: 'create' | 'update' | 'delete' | 'metadata', path: string, isInitial: boolean, seq: number, pathBytes?: Uint8Array) => void} onEvent
+   * @returns {number} Number of events passed to `onEvent`
+   */
+  read(onEvent) {
+    const header = this.#header;
+    const capacity = this.#capacity;
+    const arenaBytes = this.#arenaBytes;
+    const committed = Atomics.load(header, COMMITTED);
+    let read = Atomics.load(header, READ);
+    let arenaRead = Atomics.load(header, ARENA_READ);
+    let count = 0;
+
+    // Positions are wrapping u32 counters, compared through their difference
+    while (((committed - read) | 0) > 0) {
+      // Skip records the writer already reused
+      const behind = (Atomics.load(header, CLAIMED) - read) >>> 0;
+      if (behind > capacity) {
+        this.#lost += behind - capacity;
+        read = (read + behind - capacity) >>> 0;
+        continue;
+      }
+
+      const slot = (read & (capacity - 1)) * RECORD_WORDS;
+      const word = this.#records[slot];
+      const seq = this.#records[slot + 1];
+      const start = this.#records[slot + 2];
+      const length = this.#records[slot + 3];
+
+      if (length > this.#scratch.length) {
+        this.#scratch = new Uint8Array(Math.min(arenaBytes, 2 ** Math.ceil(Math.log2(length))));
+      }
+      const offset = start & (arenaBytes - 1);
+      const valid = length <= arenaBytes && offset + length <= arenaBytes;
+      if (valid) {
+        this.#scratch.set(this.#arena.subarray(offset, offset + length));
+      }
+
+      // The copy only counts if neither the record nor its path were claimed for rewriting meanwhile
+      if (!valid || (Atomics.load(header, CLAIMED) - read) >>> 0 > capacity || (Atomics.load(header, ARENA_CLAIMED) - start) >>> 0 > arenaBytes) {
+        this.#lost++;
+        read = (read + 1) >>> 0;
+        continue;
+      }
+
+      read = (read + 1) >>> 0;
+      arenaRead = (start + length) >>> 0;
+      count++;
+      const bytes = this.#scratch.subarray(0, length);
+      const pathBytes = (word & LOSSY) !== 0 ? bytes.slice() : undefined;
+      onEvent(TYPES[word & 0xff] ?? 'delete', this.#decoder.decode(bytes), (word & INITIAL) !== 0, seq, pathBytes);
+    }
+
+    Atomics.store(header, ARENA_READ, arenaRead);
+    Atomics.store(header, READ, read);
+    return count;
+  }
+}
@@ -0,0 +1,52 @@
+//! Noticing when a subscription loses read access to its directory, because someone changed its permissions
+//! or owner. The OS watcher keeps quiet about that, and on most platforms the events below simply stop, so a
+//! metadata change of the directory itself is taken as the cue to check whether it can still be listed.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use crate::hub::Subscriber;
+use crate::{WatchWarning, path_to_clean_string};
+
+/// Whether read access to the directory was lost or regained
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Change {
+  Lost,
+  Regained,
+}
+
+/// Check whether `subscriber` can list its directory after the directory's metadata changed. Returns the
+/// change if that differs from the last check, so a `chmod` that leaves access as it was goes unreported.
+pub(crate) fn check(subscriber: &Subscriber) -> Option<Change> {
+  let accessible = match fs::read_dir(&subscriber.root) {
+    Ok(_) => true,
+    Err(e) if e.kind() == io::ErrorKind::PermissionDenied => false,
+    // A directory that is gone is reported by the events of its removal
+    Err(_) => return None,
+  };
+
+  match (subscriber.accessible.swap(accessible, Ordering::SeqCst), accessible) {
+    (true, false) => Some(Change::Lost),
+    (false, true) => Some(Change::Regained),
+    _ => None,
+  }
+}
+
+/// The warning telling a subscription about `change`, and whether its tree was `rewatched` after regaining access
+pub(crate) fn warning(root: &Path, change: Change, rewatched: bool) -> WatchWarning {
+  let path = path_to_clean_string(root);
+  let (kind, message) = match change {
+    Change::Lost => ("access-lost", format!("Lost read access to {}, changes below it may not be reported until access is restored", path)),
+    Change::Regained => (
+      "access-regained",
+      format!(
+        "Read access to {} was restored{}, changes made while it was lost were not reported; call triggerRescan() to catch up",
+        path,
+        if rewatched { " and the directory is watched again" } else { "" }
+      ),
+    ),
+  };
+  WatchWarning { path, kind: kind.to_string(), message, file_system: None }
+}
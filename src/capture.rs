@@ -0,0 +1,326 @@
+//! Raw events of the OS watcher appended to an NDJSON file, for debugging reports offline, and counted by
+//! path for `trackCoalescing`. Events are captured as the OS watcher reports them, before they are debounced
+//! or filtered by any subscription.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use notify::{Config, Event, EventHandler, NullWatcher, PathsMut, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher, WatcherKind};
+use notify_debouncer_full::{DebounceEventHandler, DebouncedEvent, new_debouncer_opt};
+
+use crate::file_ids::FileIds;
+use crate::hub::{Subscriber, lock};
+use crate::json;
+use crate::logging::Level;
+use crate::long_paths;
+use crate::mock::MockSource;
+use crate::pending::Pending;
+use crate::{Debouncer, WatchCallbackResult, WatchWarning, path_to_clean_string};
+
+/// The file raw events of a subscription are written to, until writing fails
+pub(crate) struct Capture {
+  path: String,
+  writer: Mutex<Option<BufWriter<File>>>,
+}
+
+impl Capture {
+  /// Append to the capture file at `path`, creating it if needed
+  pub fn open(path: &str) -> io::Result<Capture> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(Capture { path: path.to_string(), writer: Mutex::new(Some(BufWriter::new(file))) })
+  }
+
+  /// Append `line`, returning the error that disabled the capture if writing failed
+  fn write(&self, line: &str) -> Option<io::Error> {
+    let mut writer = lock(&self.writer);
+    let result = writer.as_mut()?.write_all(line.as_bytes());
+    result.err().inspect(|_| *writer = None)
+  }
+
+  /// Write out the buffered lines, so a capture is complete up to the last delivered batch
+  pub fn flush(&self) -> Option<io::Error> {
+    let mut writer = lock(&self.writer);
+    let result = writer.as_mut()?.flush();
+    result.err().inspect(|_| *writer = None)
+  }
+
+  /// Flush and close the file once the subscription stopped
+  pub fn close(&self) {
+    if let Some(mut writer) = lock(&self.writer).take() {
+      let _ = writer.flush();
+    }
+  }
+
+  /// Warning delivered when the capture file cannot be opened or written
+  pub fn warning(path: &str, e: &io::Error) -> WatchWarning {
+    WatchWarning {
+      path: path.to_string(),
+      kind: "capture-failed".to_string(),
+      message: format!("Event capture disabled, failed to write {}: {}", path, e),
+      file_system: None,
+    }
+  }
+}
+
+/// Report a failed capture once, through the subscription's warnings and log
+pub(crate) fn disable(subscriber: &Subscriber, capture: &Capture, e: &io::Error) {
+  let warning = Capture::warning(&capture.path, e);
+  subscriber.log(Level::Warn, || warning.message.clone());
+  subscriber.deliver(WatchCallbackResult { warnings: Some(vec![warning]), ..Default::default() });
+}
+
+/// The raw events of a path since it was last part of a debounced batch, for `trackCoalescing`
+#[derive(Clone, Copy)]
+pub(crate) struct Seen {
+  pub count: u32,
+  /// When the first and the last of them were received, in milliseconds since the Unix epoch
+  pub first_ms: f64,
+  pub last_ms: f64,
+}
+
+/// The subscribers of a hub that capture or count raw events, shared between the hub and its OS watcher
+#[derive(Default)]
+pub(crate) struct Captures {
+  subscribers: Mutex<Vec<(u32, Arc<Subscriber>)>>,
+  seen: Mutex<HashMap<PathBuf, Seen>>,
+  /// The raw events the debouncer holds, for `getPendingEvents`
+  pub pending: Pending,
+}
+
+impl Captures {
+  pub fn add(&self, id: u32, subscriber: &Arc<Subscriber>) {
+    if subscriber.capture.is_some() || subscriber.track_coalescing {
+      lock(&self.subscribers).push((id, Arc::clone(subscriber)));
+    }
+  }
+
+  pub fn remove(&self, id: u32) {
+    lock(&self.subscribers).retain(|(capturing, _)| *capturing != id);
+  }
+
+  pub fn clear(&self) {
+    lock(&self.subscribers).clear();
+  }
+
+  /// Take the counts of the raw events behind the paths of a debounced batch. Counts of paths the debouncer
+  /// dropped altogether, such as files created and deleted again, are dropped once they are stale.
+  pub fn take_seen(&self, batch: &[DebouncedEvent]) -> HashMap<PathBuf, Seen> {
+    let mut seen = lock(&self.seen);
+    if seen.is_empty() {
+      return HashMap::new();
+    }
+
+    let taken = batch.iter().flat_map(|debounced_event| &debounced_event.event.paths).filter_map(|path| seen.remove_entry(path)).collect();
+    let stale = now_ms() - STALE_SEEN.as_secs_f64() * 1000.0;
+    seen.retain(|_, seen| seen.last_ms >= stale);
+    taken
+  }
+
+  /// Append `event` to the capture of every subscriber whose tree it concerns, and count it for its paths
+  /// if a subscriber tracks coalescing
+  fn record(&self, event: &Event) {
+    let subscribers = lock(&self.subscribers);
+    if subscribers.is_empty() {
+      return;
+    }
+
+    if subscribers.iter().any(|(_, subscriber)| subscriber.track_coalescing) {
+      let now = now_ms();
+      let mut seen = lock(&self.seen);
+      for path in &event.paths {
+        let seen = seen.entry(path.clone()).or_insert(Seen { count: 0, first_ms: now, last_ms: now });
+        seen.count += 1;
+        seen.last_ms = now;
+      }
+    }
+
+    let mut line = None;
+    for (_, subscriber) in subscribers.iter() {
+      let Some(capture) = &subscriber.capture else {
+        continue;
+      };
+      if !event.paths.iter().any(|path| path.starts_with(&subscriber.root)) {
+        continue;
+      }
+
+      let line = line.get_or_insert_with(|| encode(event));
+      if let Some(e) = capture.write(line) {
+        disable(subscriber, capture, &e);
+      }
+    }
+  }
+}
+
+/// How long the count of a path is kept without a raw event for it, well past the time it takes the
+/// debouncer to deliver the path
+const STALE_SEEN: Duration = Duration::from_secs(10);
+
+/// The current time in milliseconds since the Unix epoch
+fn now_ms() -> f64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |duration| duration.as_secs_f64() * 1000.0)
+}
+
+/// One NDJSON line for an event: when it was received in milliseconds since the Unix epoch, its kind as
+/// notify names it, its paths, and the tracker pairing the halves of a rename, if any
+fn encode(event: &Event) -> String {
+  let time = now_ms();
+  let paths = event.paths.iter().map(|path| json::string(&path_to_clean_string(path))).collect::<Vec<_>>().join(",");
+  let tracker = event.attrs.tracker().map_or("null".to_string(), |tracker| tracker.to_string());
+  format!("{{\"time\":{},\"kind\":{},\"paths\":[{}],\"tracker\":{}}}\n", time, json::string(&format!("{:?}", event.kind)), paths, tracker)
+}
+
+/// Where the OS watcher of a debouncer takes its events from
+#[derive(Clone, Default)]
+pub(crate) enum Source {
+  #[default]
+  Native,
+  /// Scanning the tree at an interval, hashing the contents of files as well when the flag says so, since the
+  /// poller compares modification times in whole seconds
+  Polling(Duration, bool),
+  /// Events handed in with `injectRaw`
+  Mock(Arc<MockSource>),
+}
+
+thread_local! {
+  /// Captures handed to the OS watcher `new_debouncer` is about to create on this thread, and its source
+  static PENDING: RefCell<Option<(Arc<Captures>, Source)>> = const { RefCell::new(None) };
+}
+
+/// Create a debouncer waiting `timeout` for further events, whose OS watcher takes its events from `source` and
+/// passes every raw event to `captures` before debouncing it
+pub(crate) fn new_debouncer<F: DebounceEventHandler>(
+  captures: Arc<Captures>,
+  source: Source,
+  handler: F,
+  file_ids: FileIds,
+  timeout: Duration,
+) -> notify::Result<Debouncer> {
+  let config = match source {
+    Source::Polling(interval, compare_contents) => Config::default().with_poll_interval(interval).with_compare_contents(compare_contents),
+    _ => Config::default(),
+  };
+  // The debouncer constructs its watcher on this thread, without a way to hand it anything but a config
+  PENDING.with(|pending| pending.replace(Some((captures, source))));
+  let debouncer = new_debouncer_opt::<F, TappedWatcher, FileIds>(timeout, None, handler, file_ids, config);
+  PENDING.with(|pending| pending.take());
+  debouncer
+}
+
+/// Check if `message` is that of a native watcher failure injected with `injectFaults`, which is taken for one of
+/// the native watcher not starting, as FSEvents does in some sandboxed processes, to exercise the fallback to
+/// polling on every platform
+#[cfg(feature = "faults")]
+pub(crate) fn is_injected_failure(message: &str) -> bool {
+  message == crate::faults::NATIVE_FAILURE
+}
+
+#[cfg(not(feature = "faults"))]
+pub(crate) fn is_injected_failure(_message: &str) -> bool {
+  false
+}
+
+/// The recommended watcher, the polling one or a mock, with its events passed through `Captures::record`
+pub(crate) struct TappedWatcher {
+  inner: Box<dyn Watcher + Send>,
+  /// Whether `inner` is the native watcher, whose watches `injectFaults` can make fail
+  #[cfg(feature = "faults")]
+  native: bool,
+}
+
+struct Tap<F> {
+  captures: Arc<Captures>,
+  inner: F,
+}
+
+impl<F: EventHandler> EventHandler for Tap<F> {
+  fn handle_event(&mut self, mut event: notify::Result<Event>) {
+    // Events below a directory watched by its verbatim path carry the prefix, which nothing else expects
+    let paths = match &mut event {
+      Ok(event) => &mut event.paths,
+      Err(error) => &mut error.paths,
+    };
+    for path in paths.iter_mut() {
+      if let Cow::Owned(simplified) = long_paths::simplified(path) {
+        *path = simplified;
+      }
+    }
+
+    if let Ok(event) = &event {
+      self.captures.pending.record(event);
+      self.captures.record(event);
+    }
+    self.inner.handle_event(event);
+  }
+}
+
+/// Changes to the watched paths as a batch, made by their verbatim paths like `TappedWatcher::watch`
+struct TappedPaths<'me> {
+  inner: Box<dyn PathsMut + 'me>,
+}
+
+impl PathsMut for TappedPaths<'_> {
+  fn add(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
+    self.inner.add(&long_paths::verbatim(path), recursive_mode)
+  }
+
+  fn remove(&mut self, path: &Path) -> notify::Result<()> {
+    self.inner.remove(&long_paths::verbatim(path))
+  }
+
+  fn commit(self: Box<Self>) -> notify::Result<()> {
+    self.inner.commit()
+  }
+}
+
+impl Watcher for TappedWatcher {
+  fn new<F: EventHandler>(event_handler: F, config: Config) -> notify::Result<Self> {
+    let (captures, source) = PENDING.with(|pending| pending.borrow_mut().take()).unwrap_or_default();
+    let tap = Tap { captures, inner: event_handler };
+    #[cfg(feature = "faults")]
+    let native = matches!(source, Source::Native);
+    let inner: Box<dyn Watcher + Send> = match source {
+      Source::Native => Box::new(RecommendedWatcher::new(tap, config)?),
+      Source::Polling(..) => Box::new(PollWatcher::new(tap, config)?),
+      Source::Mock(mock) => {
+        mock.attach(Box::new(tap));
+        Box::new(NullWatcher)
+      }
+    };
+    Ok(TappedWatcher {
+      inner,
+      #[cfg(feature = "faults")]
+      native,
+    })
+  }
+
+  fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
+    #[cfg(feature = "faults")]
+    if self.native && crate::faults::native_failure() {
+      return Err(notify::Error::generic(crate::faults::NATIVE_FAILURE));
+    }
+    // The native watcher opens directories by the path as it is, which fails for paths longer than `MAX_PATH`
+    self.inner.watch(&long_paths::verbatim(path), recursive_mode)
+  }
+
+  fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+    self.inner.unwatch(&long_paths::verbatim(path))
+  }
+
+  fn paths_mut<'me>(&'me mut self) -> Box<dyn PathsMut + 'me> {
+    Box::new(TappedPaths { inner: self.inner.paths_mut() })
+  }
+
+  fn configure(&mut self, option: Config) -> notify::Result<bool> {
+    self.inner.configure(option)
+  }
+
+  fn kind() -> WatcherKind {
+    RecommendedWatcher::kind()
+  }
+}
@@ -0,0 +1,90 @@
+//! Case-only renames on case-insensitive volumes, the default on macOS and Windows. Renaming `Readme.md` to
+//! `README.md` there leaves a path that both spellings resolve to, so FSEvents reports two creates by existence
+//! and Windows an update of both paths or a delete and a create, depending on whether the halves were paired.
+//! The events of a batch whose paths differ only in case are taken as one such rename and reported as a single
+//! `update` of the spelling the entry has on disk.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::WatchEvent;
+
+/// Check if names on the volume of `root` are case-insensitive, probed with the first entry of the root with a
+/// letter in its name, or the nearest component of the root itself when it has none
+pub(crate) fn is_case_insensitive(root: &Path) -> bool {
+  let entries = fs::read_dir(root).into_iter().flatten().flatten().map(|entry| entry.path());
+  let ancestors = root.ancestors().map(Path::to_path_buf);
+  for path in entries.chain(ancestors) {
+    let Some(swapped) = swap_case(&path) else {
+      continue;
+    };
+    return same_file(&path, &swapped);
+  }
+  false
+}
+
+/// Collapse the events of paths that differ only in case into one `update` of the spelling on disk, returning
+/// how many events were dropped. Paths none of whose spellings is on disk any more are left as they are.
+pub(crate) fn renames(events: &mut Vec<WatchEvent>) -> u32 {
+  let mut spellings: HashMap<String, Vec<usize>> = HashMap::new();
+  for (index, event) in events.iter().enumerate() {
+    if event.lossy.is_none() {
+      spellings.entry(event.path.to_lowercase()).or_default().push(index);
+    }
+  }
+
+  let mut dropped = vec![false; events.len()];
+  for indexes in spellings.into_values().filter(|indexes| indexes.len() > 1) {
+    let Some(current) = on_disk(indexes.iter().map(|&index| Path::new(&events[index].path))) else {
+      continue;
+    };
+    let kept = indexes.iter().copied().find(|&index| Path::new(&events[index].path) == current).unwrap_or(indexes[0]);
+    let first = indexes[0];
+    for &index in &indexes {
+      dropped[index] = index != first;
+    }
+    let mut event = events[kept].clone();
+    event.path = current.to_string_lossy().into_owned();
+    event.event_type = "update".to_string();
+    events[first] = event;
+  }
+
+  let before = events.len();
+  let mut dropped = dropped.into_iter();
+  events.retain(|_| !dropped.next().unwrap_or(false));
+  u32::try_from(before - events.len()).unwrap_or(u32::MAX)
+}
+
+/// The one of `paths` whose spelling the entry has in its directory
+fn on_disk<'a>(paths: impl Iterator<Item = &'a Path>) -> Option<PathBuf> {
+  let paths: Vec<&Path> = paths.collect();
+  let directory = paths[0].parent()?;
+  fs::read_dir(directory).ok()?.flatten().map(|entry| directory.join(entry.file_name())).find(|entry| paths.iter().any(|path| path == entry))
+}
+
+/// `path` with the case of the ASCII letters of its last component swapped, or None if it has none
+fn swap_case(path: &Path) -> Option<PathBuf> {
+  let name = path.file_name()?.to_str()?;
+  let swapped: String = name.chars().map(|c| if c.is_ascii_uppercase() { c.to_ascii_lowercase() } else { c.to_ascii_uppercase() }).collect();
+  (swapped != name).then(|| path.with_file_name(swapped))
+}
+
+#[cfg(unix)]
+fn same_file(path: &Path, other: &Path) -> bool {
+  use std::os::unix::fs::MetadataExt;
+
+  match (fs::symlink_metadata(path), fs::symlink_metadata(other)) {
+    (Ok(metadata), Ok(other)) => metadata.dev() == other.dev() && metadata.ino() == other.ino(),
+    _ => false,
+  }
+}
+
+/// The final path of a file has the spelling on disk, so two spellings of the same file resolve to the same one
+#[cfg(not(unix))]
+fn same_file(path: &Path, other: &Path) -> bool {
+  match (dunce::canonicalize(path), dunce::canonicalize(other)) {
+    (Ok(path), Ok(other)) => path == other,
+    _ => false,
+  }
+}
@@ -0,0 +1,228 @@
+//! Passes over the events selected for a subscription from one flush of the debouncer, before they are delivered
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use notify_debouncer_full::file_id::{FileId, get_file_id};
+
+use crate::WatchEvent;
+use crate::long_paths;
+
+/// Fold the updates that follow the creation of a path into its `create` event, for `coalesce`. A path whose
+/// create is followed by a delete, or created again, keeps all its events.
+pub(crate) fn creates(events: &mut Vec<WatchEvent>) {
+  // Whether only updates followed the create of each path
+  let mut created: HashMap<&str, bool> = HashMap::new();
+  let mut folded = false;
+  for event in events.iter() {
+    match (event.event_type.as_str(), created.get_mut(event.path.as_str())) {
+      ("create", None) => {
+        created.insert(&event.path, true);
+      }
+      ("update" | "metadata", Some(only_updates)) => folded |= *only_updates,
+      (_, Some(only_updates)) => *only_updates = false,
+      _ => {}
+    }
+  }
+  if !folded {
+    return;
+  }
+
+  let created: HashSet<String> = created.into_iter().filter(|(_, only_updates)| *only_updates).map(|(path, _)| path.to_string()).collect();
+  let mut at: HashMap<String, usize> = HashMap::new();
+  let mut kept: Vec<WatchEvent> = Vec::with_capacity(events.len());
+  for mut event in events.drain(..) {
+    if !created.contains(&event.path) {
+      kept.push(event);
+      continue;
+    }
+    match at.get(&event.path) {
+      Some(&index) => kept[index].absorb(&mut event),
+      None if event.event_type == "create" => {
+        at.insert(event.path.clone(), kept.len());
+        kept.push(event);
+      }
+      None => kept.push(event),
+    }
+  }
+  *events = kept;
+}
+
+/// Move the deletes of a path that was replaced within the batch ahead of its creates, so a consumer applying
+/// the events in order ends up with the path existing. Only paths that exist once the batch is delivered are
+/// reordered, a path created and then deleted is gone and keeps its order.
+pub(crate) fn replacements(events: &mut [WatchEvent]) {
+  // Most batches have no deletes, which spares them the map of paths
+  if !events.iter().any(|event| event.event_type == "delete") {
+    return;
+  }
+
+  // Whether each path was created and deleted, and whether a delete came last
+  let mut seen: HashMap<&str, (bool, bool, bool)> = HashMap::new();
+  for event in events.iter() {
+    let (created, deleted, deleted_last) = seen.entry(&event.path).or_default();
+    match event.event_type.as_str() {
+      "create" => {
+        *created = true;
+        *deleted_last = false;
+      }
+      "delete" => {
+        *deleted = true;
+        *deleted_last = true;
+      }
+      _ => {}
+    }
+  }
+
+  let replaced: HashSet<String> = seen
+    .into_iter()
+    .filter(|&(path, (created, deleted, deleted_last))| created && deleted && deleted_last && long_paths::verbatim(Path::new(path)).symlink_metadata().is_ok())
+    .map(|(path, _)| path.to_string())
+    .collect();
+
+  for path in &replaced {
+    let positions: Vec<usize> = events.iter().enumerate().filter(|(_, event)| &event.path == path).map(|(index, _)| index).collect();
+    let mut ordered: Vec<WatchEvent> = positions.iter().map(|&index| events[index].clone()).collect();
+    ordered.sort_by_key(|event| event.event_type != "delete");
+    for (index, event) in positions.into_iter().zip(ordered) {
+      events[index] = event;
+    }
+  }
+}
+
+/// Bring the events of a batch into the canonical sequence `normalize` documents, whatever the platform
+/// reported: each path gets at most one event for the difference between before and after the batch, and
+/// deletes come first, deepest first, then creates, shallowest first, then updates, each by path. The updates a
+/// directory gets for changes of its entries are left to `parent_updates`.
+pub(crate) fn canonical(events: &mut Vec<WatchEvent>) {
+  if events.len() < 2 {
+    return;
+  }
+
+  let mut order: Vec<String> = Vec::new();
+  let mut by_path: HashMap<String, Vec<WatchEvent>> = HashMap::new();
+  for event in events.drain(..) {
+    by_path
+      .entry(event.path.clone())
+      .or_insert_with(|| {
+        order.push(event.path.clone());
+        Vec::new()
+      })
+      .push(event);
+  }
+  let mut kept: Vec<WatchEvent> = order.iter().filter_map(|path| by_path.remove(path)).filter_map(net).collect();
+  // Ordered by path where the rest is the same, as platforms report the entries of a directory in any order
+  kept.sort_by_cached_key(|event| {
+    let depth = Path::new(&event.path).components().count() as isize;
+    let rank = match event.event_type.as_str() {
+      "delete" => (0, -depth),
+      "create" => (1, depth),
+      _ => (2, 0),
+    };
+    (rank, event.path.clone())
+  });
+  *events = kept;
+}
+
+/// Drop the updates of directories that have entries with events in the same batch, which creating, deleting or
+/// renaming the entries explains, for `dropParentUpdates`. Windows reports such an update along with the change
+/// of the entry, and so does FSEvents for some changes. Returns how many were dropped. An update of a directory
+/// nothing else in the batch is below, such as from a `touch`, is kept, and so is one of a path that is no
+/// directory by now.
+pub(crate) fn parent_updates(events: &mut Vec<WatchEvent>) -> u32 {
+  if events.len() < 2 {
+    return 0;
+  }
+
+  let parents: HashSet<&Path> = events.iter().filter_map(|event| Path::new(&event.path).parent()).collect();
+  let explained: HashSet<String> = events
+    .iter()
+    .filter(|event| event.event_type == "update" && parents.contains(Path::new(&event.path)))
+    .filter(|event| long_paths::verbatim(Path::new(&event.path)).symlink_metadata().is_ok_and(|metadata| metadata.is_dir()))
+    .map(|event| event.path.clone())
+    .collect();
+  if explained.is_empty() {
+    return 0;
+  }
+
+  let before = events.len();
+  events.retain(|event| event.event_type != "update" || !explained.contains(&event.path));
+  u32::try_from(before - events.len()).unwrap_or(u32::MAX)
+}
+
+/// The one event for the difference `events` made to their path, if any
+fn net(mut events: Vec<WatchEvent>) -> Option<WatchEvent> {
+  let (existed, exists) = existence(&events)?;
+  let only_metadata = events.iter().all(|event| event.event_type == "metadata");
+  // Deleted and created again, as a rename over the path does
+  let replaced = events.iter().position(|event| event.event_type == "delete").is_some_and(|at| events[at..].iter().any(|event| event.event_type == "create"));
+
+  let mut rest = events.split_off(1);
+  let mut event = events.pop()?;
+  for later in &mut rest {
+    event.absorb(later);
+  }
+  event.event_type = match (existed, exists) {
+    (false, false) => return None,
+    (false, true) => "create",
+    (true, false) => "delete",
+    (true, true) if replaced => "create",
+    (true, true) if only_metadata => "metadata",
+    (true, true) => "update",
+  }
+  .to_string();
+  Some(event)
+}
+
+/// Whether the path of `events` existed before the batch and whether it exists after it, as told by its first
+/// and last event
+#[cfg(not(target_os = "macos"))]
+fn existence(events: &[WatchEvent]) -> Option<(bool, bool)> {
+  Some((events.first()?.event_type != "create", events.last()?.event_type != "delete"))
+}
+
+/// Whether the path of `events` existed before the batch and whether it exists after it. FSEvents reports the
+/// flags of everything that happened to a path lately at once, which notify turns into events in an order of
+/// its own and which can include a create from before the batch, so the file is looked at instead: a file born
+/// before the batch existed already, and a path with a delete that is gone is taken to have existed.
+#[cfg(target_os = "macos")]
+fn existence(events: &[WatchEvent]) -> Option<(bool, bool)> {
+  let metadata = long_paths::verbatim(Path::new(&events.first()?.path)).symlink_metadata();
+  let exists = metadata.is_ok();
+  let born_earlier = metadata.and_then(|metadata| metadata.created()).is_ok_and(|born| born.elapsed().is_ok_and(|age| age > crate::DEBOUNCE_TIMEOUT * 3));
+  let deleted = events.iter().any(|event| event.event_type == "delete");
+  Some((events[0].event_type != "create" || born_earlier || (deleted && !exists), exists))
+}
+
+/// Deliver the updates of a file changed through several of its hardlinks once, for `dedupeHardlinks`. The
+/// first path keeps the event and lists the others as `aliases`. Creates and deletes are left alone, as every
+/// name of a file comes and goes on its own, and so are files gone by now, whose id cannot be read.
+pub(crate) fn hardlinks(events: &mut Vec<WatchEvent>) {
+  let updated = |event: &WatchEvent| matches!(event.event_type.as_str(), "update" | "metadata");
+  if events.iter().filter(|event| updated(event)).nth(1).is_none() {
+    return;
+  }
+
+  // Position of the event each file is reported with, by file id and type
+  let mut primaries: HashMap<(FileId, String), usize> = HashMap::new();
+  let mut kept: Vec<WatchEvent> = Vec::with_capacity(events.len());
+  for event in events.drain(..) {
+    let id = if updated(&event) { get_file_id(Path::new(&event.path)).ok() } else { None };
+    let primary = id.and_then(|id| match primaries.entry((id, event.event_type.clone())) {
+      Entry::Occupied(entry) => Some(*entry.get()).filter(|&primary| kept[primary].path != event.path),
+      Entry::Vacant(entry) => {
+        entry.insert(kept.len());
+        None
+      }
+    });
+    match primary {
+      Some(primary) => {
+        kept[primary].add_seen(&event);
+        kept[primary].aliases.get_or_insert_with(Vec::new).push(event.path);
+      }
+      None => kept.push(event),
+    }
+  }
+  *events = kept;
+}
@@ -0,0 +1,84 @@
+//! Keeping events inside the watched directory. The OS watcher follows symlinks, so a change it reports below
+//! the root can be one outside it once the links are resolved, and a consumer deleting what it is told changed
+//! would then reach outside the directory it meant to watch. Such events are dropped, or flagged with
+//! `outsideRoot` for `flagOutsideRoot`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use notify::EventKind;
+use notify::event::{ModifyKind, RenameMode};
+use notify_debouncer_full::DebouncedEvent;
+
+use crate::{WatchEvent, long_paths, path_to_clean_string};
+
+/// The watched directory of a subscription with its symlinks resolved
+pub(crate) struct Containment {
+  root: PathBuf,
+  /// The root as it was resolved when subscribing
+  canonical_root: PathBuf,
+  flag: bool,
+}
+
+impl Containment {
+  pub fn new(root: &Path, flag: bool) -> Containment {
+    Containment { root: root.to_path_buf(), canonical_root: canonical(root).unwrap_or_else(|| root.to_path_buf()), flag }
+  }
+
+  /// Drop the events of `batch` whose path resolves to one outside the root, or flag them with `outsideRoot`.
+  /// A rename with one side outside is the file leaving or entering the tree, a `delete` of the path it was
+  /// renamed from and a `create` of the one it was renamed to. Returns how many events were dropped.
+  pub fn confine(&self, batch: &[DebouncedEvent], events: &mut Vec<WatchEvent>) -> u32 {
+    // Whether each parent directory is outside, resolved once per batch as links may change between batches
+    let mut directories = HashMap::new();
+
+    let mut retyped = HashMap::new();
+    for debounced_event in batch {
+      if let (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to]) = (&debounced_event.event.kind, debounced_event.event.paths.as_slice())
+        && from.starts_with(&self.root)
+        && to.starts_with(&self.root)
+        && self.is_outside(&mut directories, from) != self.is_outside(&mut directories, to)
+      {
+        retyped.insert(path_to_clean_string(from), "delete");
+        retyped.insert(path_to_clean_string(to), "create");
+      }
+    }
+
+    let before = events.len();
+    events.retain_mut(|event| {
+      if event.event_type == "update"
+        && let Some(event_type) = retyped.get(&event.path)
+      {
+        event.event_type = event_type.to_string();
+      }
+      if !self.is_outside(&mut directories, Path::new(&event.path)) {
+        return true;
+      }
+      event.outside_root = self.flag.then_some(true);
+      self.flag
+    });
+    u32::try_from(before - events.len()).unwrap_or(u32::MAX)
+  }
+
+  /// Check if the directory `path` is in resolves to one outside the root. The path itself may be a symlink,
+  /// whose change is one inside the tree, and directories that are gone are judged by the nearest one left.
+  fn is_outside(&self, directories: &mut HashMap<PathBuf, bool>, path: &Path) -> bool {
+    let mut directory = path.parent();
+    while let Some(current) = directory.filter(|current| current.starts_with(&self.root)) {
+      if let Some(&outside) = directories.get(current) {
+        return outside;
+      }
+      if let Some(resolved) = canonical(current) {
+        let outside = !resolved.starts_with(&self.canonical_root);
+        directories.insert(current.to_path_buf(), outside);
+        return outside;
+      }
+      directory = current.parent();
+    }
+    false
+  }
+}
+
+fn canonical(path: &Path) -> Option<PathBuf> {
+  dunce::canonicalize(long_paths::verbatim(path)).ok().map(|resolved| long_paths::simplified(&resolved).into_owned())
+}
@@ -0,0 +1,58 @@
+//! Changes a subscription's own process is about to make, whose events are swallowed instead of delivered
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+use crate::WatchEvent;
+
+/// Pending expectations per path, each one swallowing a single create or update event until its deadline
+#[derive(Default)]
+pub(crate) struct Expectations {
+  deadlines: HashMap<String, VecDeque<Instant>>,
+}
+
+impl Expectations {
+  /// Expect one more change to `path` before `until`
+  pub fn expect(&mut self, path: String, until: Instant) {
+    self.evict(Instant::now());
+    let deadlines = self.deadlines.entry(path).or_default();
+    // Kept sorted, so the expectation closest to expiring is used first
+    let index = deadlines.partition_point(|deadline| *deadline <= until);
+    deadlines.insert(index, until);
+  }
+
+  /// Remove the events that were expected, using up one expectation each, and return how many were removed.
+  /// Deletes are always delivered.
+  pub fn swallow(&mut self, events: &mut Vec<WatchEvent>) -> u32 {
+    if self.deadlines.is_empty() {
+      return 0;
+    }
+
+    self.evict(Instant::now());
+    let before = events.len();
+    events.retain(|event| event.event_type == "delete" || !self.take(&event.path));
+    u32::try_from(before - events.len()).unwrap_or(u32::MAX)
+  }
+
+  fn take(&mut self, path: &str) -> bool {
+    let Some(deadlines) = self.deadlines.get_mut(path) else {
+      return false;
+    };
+
+    deadlines.pop_front();
+    if deadlines.is_empty() {
+      self.deadlines.remove(path);
+    }
+    true
+  }
+
+  /// Drop the expectations that ran out without a matching event
+  fn evict(&mut self, now: Instant) {
+    self.deadlines.retain(|_, deadlines| {
+      while deadlines.front().is_some_and(|deadline| *deadline <= now) {
+        deadlines.pop_front();
+      }
+      !deadlines.is_empty()
+    });
+  }
+}
@@ -0,0 +1,134 @@
+//! Faults injected with `injectFaults`, in builds with the `faults` feature, to exercise error paths that are
+//! hard to provoke otherwise: errors of the OS watcher, native watchers that fail to start, callback calls the
+//! N-API queue refuses, metadata that cannot be read or is briefly locked, events the OS watcher misses and
+//! panics on the watcher thread. Faults apply to every subscription in the process.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+use globset::GlobSet;
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use watcher_core::build_glob_set;
+
+use crate::hub::lock;
+
+/// Error the native watcher fails to watch with while `nativeFailures` are left
+pub(crate) const NATIVE_FAILURE: &str = "native watcher failure injected by injectFaults";
+
+/// Error the OS watcher reports while `backendErrors` are left
+const BACKEND_ERROR: &str = "backend error injected by injectFaults";
+
+/// Message of the panics raised while `panics` are left
+pub(crate) const PANIC: &str = "panic injected by injectFaults";
+
+/// Faults to inject, each counting down as it is used
+#[napi(object, object_to_js = false)]
+pub struct Faults {
+  /// Number of batches of the OS watcher to precede with a backend error
+  pub backend_errors: Option<u32>,
+  /// Number of watches of the native watcher to fail as if it could not start
+  pub native_failures: Option<u32>,
+  /// Number of calls of subscription callbacks to fail as if the N-API queue were full
+  pub queue_full: Option<u32>,
+  /// Glob patterns of absolute paths whose metadata cannot be read, until `clearFaults()`
+  pub stat_failures: Option<Vec<String>>,
+  /// Glob patterns of absolute paths the first read of whose metadata fails as if another process held them
+  /// locked, until `clearFaults()`
+  pub locked_paths: Option<Vec<String>>,
+  /// Glob patterns of absolute paths whose events are dropped as if the OS watcher missed them, until
+  /// `clearFaults()`
+  pub missed_paths: Option<Vec<String>>,
+  /// Number of batches to panic on while processing them for a subscription, each stopping only that one
+  pub panics: Option<u32>,
+}
+
+#[derive(Default)]
+struct Pending {
+  backend_errors: u32,
+  native_failures: u32,
+  queue_full: u32,
+  stat_failures: Option<GlobSet>,
+  locked_paths: Option<GlobSet>,
+  /// Paths of `locked_paths` whose metadata was read once, which reads normally from then on
+  unlocked: HashSet<PathBuf>,
+  missed_paths: Option<GlobSet>,
+  panics: u32,
+}
+
+static PENDING: LazyLock<Mutex<Pending>> = LazyLock::new(Mutex::default);
+
+/// Inject `faults`, adding to the counts of those still pending and replacing the patterns of `statFailures`,
+/// `lockedPaths` and `missedPaths` if given
+pub(crate) fn inject(faults: Faults) -> Result<()> {
+  let patterns = |patterns: Option<&[String]>| patterns.map(build_glob_set).transpose().map_err(|e| Error::new(Status::InvalidArg, e.to_string()));
+  let stat_failures = patterns(faults.stat_failures.as_deref())?;
+  let locked_paths = patterns(faults.locked_paths.as_deref())?;
+  let missed_paths = patterns(faults.missed_paths.as_deref())?;
+
+  let mut pending = lock(&PENDING);
+  pending.backend_errors = pending.backend_errors.saturating_add(faults.backend_errors.unwrap_or(0));
+  pending.native_failures = pending.native_failures.saturating_add(faults.native_failures.unwrap_or(0));
+  pending.queue_full = pending.queue_full.saturating_add(faults.queue_full.unwrap_or(0));
+  pending.panics = pending.panics.saturating_add(faults.panics.unwrap_or(0));
+  if stat_failures.is_some() {
+    pending.stat_failures = stat_failures;
+  }
+  if locked_paths.is_some() {
+    pending.locked_paths = locked_paths;
+    pending.unlocked.clear();
+  }
+  if missed_paths.is_some() {
+    pending.missed_paths = missed_paths;
+  }
+  Ok(())
+}
+
+/// Drop every fault still pending
+pub(crate) fn clear() {
+  *lock(&PENDING) = Pending::default();
+}
+
+/// Take one of `count`, returning whether there was one left
+fn take(count: &mut u32) -> bool {
+  let left = *count > 0;
+  *count = count.saturating_sub(1);
+  left
+}
+
+/// A backend error to report before the next batch of the OS watcher, if one is pending
+pub(crate) fn backend_error() -> Option<notify::Error> {
+  take(&mut lock(&PENDING).backend_errors).then(|| notify::Error::generic(BACKEND_ERROR))
+}
+
+/// Whether the next watch of the native watcher fails
+pub(crate) fn native_failure() -> bool {
+  take(&mut lock(&PENDING).native_failures)
+}
+
+/// Whether the next call of a subscription callback is refused as if the queue were full
+pub(crate) fn queue_full() -> bool {
+  take(&mut lock(&PENDING).queue_full)
+}
+
+/// Whether reading the metadata of `path` fails
+pub(crate) fn stat_fails(path: &Path) -> bool {
+  lock(&PENDING).stat_failures.as_ref().is_some_and(|patterns| patterns.is_match(path))
+}
+
+/// Whether reading the metadata of `path` fails as locked, which it does the first time only
+pub(crate) fn locked(path: &Path) -> bool {
+  let mut pending = lock(&PENDING);
+  pending.locked_paths.as_ref().is_some_and(|patterns| patterns.is_match(path)) && pending.unlocked.insert(path.to_path_buf())
+}
+
+/// Whether the OS watcher missed the events of `path`
+pub(crate) fn missed(path: &Path) -> bool {
+  lock(&PENDING).missed_paths.as_ref().is_some_and(|patterns| patterns.is_match(path))
+}
+
+/// Whether to panic while processing the next batch for a subscription
+pub(crate) fn panic() -> bool {
+  take(&mut lock(&PENDING).panics)
+}
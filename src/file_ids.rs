@@ -0,0 +1,210 @@
+//! The file id cache of the debouncer, which pairs the two halves of a rename on platforms whose backend does not
+//! tag them with a cookie. Unlike `notify_debouncer_full::FileIdMap`, it can be held to a number of entries,
+//! reports how many it holds and how much memory they take, and can be pruned from outside the debouncer.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use notify::RecursiveMode;
+use notify_debouncer_full::FileIdCache;
+use notify_debouncer_full::file_id::{FileId, get_file_id};
+
+use crate::hub::lock;
+use crate::platform;
+
+/// Memory an entry takes besides the bytes of its path, roughly, with the share of the map's nodes
+const ENTRY_BYTES: usize = size_of::<PathBuf>() + size_of::<Entry>() + 32;
+
+/// Limit, size and entries of a cache, shared between a hub and the cache inside its debouncer
+#[derive(Debug)]
+pub(crate) struct CacheBudget {
+  /// Most entries to keep, `usize::MAX` without a limit and 0 to not track file ids at all
+  limit: AtomicUsize,
+  entries: AtomicUsize,
+  /// Estimated memory the entries take
+  bytes: AtomicUsize,
+  cache: Mutex<Cache>,
+}
+
+impl Default for CacheBudget {
+  fn default() -> CacheBudget {
+    CacheBudget { limit: AtomicUsize::new(usize::MAX), entries: AtomicUsize::new(0), bytes: AtomicUsize::new(0), cache: Mutex::default() }
+  }
+}
+
+impl CacheBudget {
+  /// Hold the cache to `limit` entries from its next change on
+  pub fn set_limit(&self, limit: Option<usize>) {
+    self.limit.store(limit.unwrap_or(usize::MAX), Ordering::SeqCst);
+  }
+
+  pub fn entries(&self) -> usize {
+    self.entries.load(Ordering::SeqCst)
+  }
+
+  pub fn bytes(&self) -> usize {
+    self.bytes.load(Ordering::SeqCst)
+  }
+
+  /// Drop the entries added longest ago until at least `bytes` are freed or the cache is empty. Returns the
+  /// number of entries and bytes dropped.
+  pub fn shed(&self, bytes: usize) -> (usize, usize) {
+    let mut cache = lock(&self.cache);
+    let before = (cache.entries.len(), cache.bytes);
+    let mut freed = 0;
+    let count = cache.oldest(|path| {
+      freed += entry_bytes(path);
+      freed >= bytes
+    });
+    cache.drop_oldest(count);
+    self.publish(&cache);
+    (before.0 - cache.entries.len(), before.1 - cache.bytes)
+  }
+
+  fn publish(&self, cache: &Cache) {
+    self.entries.store(cache.entries.len(), Ordering::SeqCst);
+    self.bytes.store(cache.bytes, Ordering::SeqCst);
+  }
+}
+
+#[derive(Debug)]
+struct Entry {
+  id: FileId,
+  /// When the path was last added, counted in additions
+  added: u64,
+}
+
+/// File ids by path, ordered so the entries below a directory are next to each other
+#[derive(Debug, Default)]
+struct Cache {
+  entries: BTreeMap<PathBuf, Entry>,
+  additions: u64,
+  bytes: usize,
+}
+
+impl Cache {
+  fn insert(&mut self, path: PathBuf) {
+    if let Ok(id) = get_file_id(&path) {
+      self.additions += 1;
+      let bytes = entry_bytes(&path);
+      if self.entries.insert(path, Entry { id, added: self.additions }).is_none() {
+        self.bytes += bytes;
+      }
+    }
+  }
+
+  fn remove(&mut self, path: &Path) {
+    if self.entries.remove(path).is_some() {
+      self.bytes -= entry_bytes(path);
+    }
+  }
+
+  /// Number of the entries added longest ago, oldest first, up to and including the one `enough` is true for
+  fn oldest(&self, mut enough: impl FnMut(&Path) -> bool) -> usize {
+    let mut added: Vec<(u64, &Path)> = self.entries.iter().map(|(path, entry)| (entry.added, path.as_path())).collect();
+    added.sort_unstable_by_key(|(added, _)| *added);
+    added.iter().position(|(_, path)| enough(path)).map_or(added.len(), |index| index + 1)
+  }
+
+  /// Drop the entries added longest ago once there are more than `limit`, making room for an eighth more so
+  /// pruning does not run on every addition
+  fn prune(&mut self, limit: usize) {
+    if self.entries.len() <= limit {
+      return;
+    }
+
+    let keep = limit - limit / 8;
+    self.drop_oldest(self.entries.len() - keep);
+  }
+
+  /// Drop the `count` entries added longest ago
+  fn drop_oldest(&mut self, count: usize) {
+    if count == 0 {
+      return;
+    }
+
+    let mut added: Vec<u64> = self.entries.values().map(|entry| entry.added).collect();
+    let (_, cutoff, _) = added.select_nth_unstable(count - 1);
+    let cutoff = *cutoff;
+    let mut bytes = self.bytes;
+    self.entries.retain(|path, entry| {
+      let kept = entry.added > cutoff;
+      if !kept {
+        bytes -= entry_bytes(path);
+      }
+      kept
+    });
+    self.bytes = bytes;
+  }
+}
+
+/// Memory an entry for `path` takes, roughly
+fn entry_bytes(path: &Path) -> usize {
+  ENTRY_BYTES + path.as_os_str().len()
+}
+
+/// The cache inside the debouncer, whose entries its budget holds
+pub(crate) struct FileIds {
+  budget: Arc<CacheBudget>,
+}
+
+impl FileIds {
+  pub fn new(budget: Arc<CacheBudget>) -> FileIds {
+    FileIds { budget }
+  }
+
+  fn limit(&self) -> usize {
+    // Backends with rename cookies do not need file ids, as with `notify_debouncer_full::RecommendedCache`
+    if platform::BACKEND.cookies { 0 } else { self.budget.limit.load(Ordering::SeqCst) }
+  }
+}
+
+impl FileIdCache for FileIds {
+  fn cached_file_id(&self, path: &Path) -> Option<impl AsRef<FileId>> {
+    lock(&self.budget.cache).entries.get(path).map(|entry| entry.id)
+  }
+
+  fn add_path(&mut self, path: &Path, recursive_mode: RecursiveMode) {
+    let limit = self.limit();
+    let mut cache = lock(&self.budget.cache);
+    if limit == 0 {
+      if !cache.entries.is_empty() {
+        *cache = Cache::default();
+        self.budget.publish(&cache);
+      }
+      return;
+    }
+
+    cache.insert(path.to_path_buf());
+    let mut directories = vec![path.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+      let Ok(entries) = fs::read_dir(&directory) else {
+        continue;
+      };
+      for entry in entries.flatten() {
+        let child = entry.path();
+        if recursive_mode == RecursiveMode::Recursive && entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+          directories.push(child.clone());
+        }
+        cache.insert(child);
+      }
+      cache.prune(limit);
+    }
+
+    cache.prune(limit);
+    self.budget.publish(&cache);
+  }
+
+  fn remove_path(&mut self, path: &Path) {
+    let mut cache = lock(&self.budget.cache);
+    let below: Vec<PathBuf> = cache.entries.range(path.to_path_buf()..).map(|(entry, _)| entry).take_while(|entry| entry.starts_with(path)).cloned().collect();
+    for entry in below {
+      cache.remove(&entry);
+    }
+    self.budget.publish(&cache);
+  }
+}
@@ -0,0 +1,49 @@
+//! Empty batches sent with `heartbeatMs` while nothing else was delivered, so a consumer can tell a quiet
+//! tree from a watcher that stopped delivering. They come from a thread of their own and leave the
+//! debouncer alone, so a heartbeat neither flushes nor delays events that are still being debounced.
+
+use std::io;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::WatchCallbackResult;
+use crate::hub::{Subscriber, lock};
+
+/// Longest the thread sleeps before checking whether the subscription is still running
+const STEP: Duration = Duration::from_millis(100);
+
+/// Send the subscriber a heartbeat whenever `interval` passes without a batch, until the subscription stops
+pub(crate) fn spawn(subscriber: &Arc<Subscriber>, interval: Duration) -> io::Result<()> {
+  let subscriber = Arc::downgrade(subscriber);
+  thread::Builder::new().name("watcher-heartbeat".to_string()).spawn(move || {
+    loop {
+      let Some(due) = due(&subscriber, interval) else {
+        return;
+      };
+      let now = Instant::now();
+      if now < due {
+        thread::sleep(STEP.min(due - now));
+        continue;
+      }
+
+      let Some(subscriber) = subscriber.upgrade() else {
+        return;
+      };
+      // Sending the heartbeat counts as a delivery, so the next one is due an interval later
+      subscriber.send(WatchCallbackResult { heartbeat: Some(true), ..Default::default() });
+    }
+  })?;
+
+  Ok(())
+}
+
+/// When the next heartbeat is due, or `None` once the subscription stopped
+fn due(subscriber: &Weak<Subscriber>, interval: Duration) -> Option<Instant> {
+  let subscriber = subscriber.upgrade()?;
+  if !subscriber.running.load(Ordering::SeqCst) {
+    return None;
+  }
+  Some(*lock(&subscriber.delivered_at) + interval)
+}
@@ -0,0 +1,310 @@
+//! Changes FSEvents recorded before a subscription started, replayed for `sinceEventId` on macOS, and the event
+//! ids batches carry to resume from. notify starts its FSEvents stream at the current event and does not pass
+//! event ids on, so the history is read by a stream of its own, stopped once FSEvents has replayed it, while
+//! notify's stream reports what follows.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use notify::event::{CreateKind, ModifyKind, RemoveKind};
+use notify::{Event, EventKind};
+use notify_debouncer_full::DebouncedEvent;
+use watcher_core::select_events;
+
+use crate::hub::{Subscriber, lock};
+use crate::{WatchCallbackResult, WatchEvent, WatchWarning, path_to_clean_string};
+
+/// How much earlier than the oldest event the debouncer holds the event id of a batch is taken, for the delay
+/// with which FSEvents reports changes
+const SLACK: Duration = Duration::from_secs(1);
+
+/// The paths FSEvents recorded changes of since an event id
+pub(crate) struct Replay {
+  /// Each path, in the order its first change was recorded, and whether it was created since
+  pub changes: Vec<(PathBuf, bool)>,
+  /// Whether FSEvents could not replay everything, as for an id older than the history it keeps
+  pub incomplete: bool,
+}
+
+/// Check if the event history can be replayed on this platform
+pub(crate) fn is_supported() -> bool {
+  cfg!(target_os = "macos")
+}
+
+/// Device of `root`, whose event ids batches carry, on macOS
+#[cfg(target_os = "macos")]
+pub(crate) fn device(root: &Path) -> Option<u64> {
+  use std::os::unix::fs::MetadataExt;
+
+  std::fs::metadata(root).ok().map(|metadata| metadata.dev() as u64)
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn device(_root: &Path) -> Option<u64> {
+  None
+}
+
+/// Event id a consumer that handled a batch delivered now resumes from with `sinceEventId`: the last one FSEvents
+/// recorded for the device of the root before the oldest event the debouncer still holds, received at `oldest`.
+/// Changes the debouncer delivered after it are replayed again, but none that it had not delivered are missed.
+pub(crate) fn resume_id(subscriber: &Subscriber, oldest: Option<Instant>) -> Option<i64> {
+  let device = subscriber.fsevents_device?;
+  let age = oldest.map_or(Duration::ZERO, |oldest| oldest.elapsed()) + SLACK;
+  let time = SystemTime::now().checked_sub(age)?;
+  last_event_id_before(device, time).and_then(|id| i64::try_from(id).ok())
+}
+
+#[cfg(target_os = "macos")]
+fn last_event_id_before(device: u64, time: SystemTime) -> Option<u64> {
+  macos::last_event_id_before(device, time)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn last_event_id_before(_device: u64, _time: SystemTime) -> Option<u64> {
+  None
+}
+
+#[cfg(target_os = "macos")]
+fn replay(root: &Path, since: u64) -> io::Result<Replay> {
+  macos::replay(root, since)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn replay(_root: &Path, _since: u64) -> io::Result<Replay> {
+  Err(io::Error::new(io::ErrorKind::Unsupported, "only FSEvents keeps a history of events"))
+}
+
+/// The changes FSEvents recorded below the root of `subscriber` since event `since`, as one batch with
+/// `historical`, filtered like live batches. When the history is incomplete or cannot be read the batch carries
+/// a `history-unavailable` warning instead, suggesting a rescan.
+pub(crate) fn batch(subscriber: &Subscriber, since: u64) -> WatchCallbackResult {
+  let (changes, problem) = match replay(&subscriber.root, since) {
+    Ok(Replay { changes, incomplete }) => {
+      let problem = incomplete
+        .then(|| format!("FSEvents no longer has the full history since event id {}, call triggerRescan() to find the changes made meanwhile", since));
+      (changes, problem)
+    }
+    Err(e) => (Vec::new(), Some(format!("Failed to replay the FSEvents history since event id {}: {}", since, e))),
+  };
+
+  // What became of each path is told by what is there now, as FSEvents combines the changes of a path
+  let now = Instant::now();
+  let replayed: Vec<DebouncedEvent> = changes
+    .into_iter()
+    .map(|(path, created)| {
+      let kind = match (path.symlink_metadata().is_ok(), created) {
+        (false, _) => EventKind::Remove(RemoveKind::Any),
+        (true, true) => EventKind::Create(CreateKind::Any),
+        (true, false) => EventKind::Modify(ModifyKind::Any),
+      };
+      DebouncedEvent::new(Event::new(kind).add_path(path), now)
+    })
+    .collect();
+
+  let mut events = Vec::new();
+  select_events(
+    &replayed,
+    &subscriber.root,
+    &subscriber.ignore_filter,
+    &mut events,
+    &mut Vec::new(),
+    |path, event_type, _| WatchEvent::at(path, event_type, false),
+    |_, _, _| {},
+  );
+  if !subscriber.include_root {
+    events.retain(|event| Path::new(&event.path) != subscriber.root);
+  }
+  lock(&subscriber.mutes).drop_muted(&mut events, &subscriber.root);
+
+  let warnings = problem
+    .map(|message| vec![WatchWarning { path: path_to_clean_string(&subscriber.root), kind: "history-unavailable".to_string(), message, file_system: None }]);
+  WatchCallbackResult { events, warnings, historical: Some(true), event_id: resume_id(subscriber, Some(now)), ..Default::default() }
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+  use std::collections::HashMap;
+  use std::ffi::{CStr, CString, OsStr, c_char, c_void};
+  use std::io;
+  use std::os::unix::ffi::OsStrExt;
+  use std::path::{Path, PathBuf};
+  use std::ptr;
+  use std::thread;
+  use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+  use super::Replay;
+
+  type CFRef = *mut c_void;
+
+  /// How long FSEvents may take to replay the history before what it replayed so far is delivered
+  const REPLAY_TIMEOUT: Duration = Duration::from_secs(30);
+
+  /// Seconds from the Unix epoch to the one of Core Foundation, the start of 2001
+  const CF_EPOCH: f64 = 978_307_200.0;
+  const UTF8: u32 = 0x0800_0100;
+
+  const CREATE_NO_DEFER: u32 = 0x02;
+  const CREATE_FILE_EVENTS: u32 = 0x10;
+
+  const MUST_SCAN_SUB_DIRS: u32 = 0x01;
+  const USER_DROPPED: u32 = 0x02;
+  const KERNEL_DROPPED: u32 = 0x04;
+  const EVENT_IDS_WRAPPED: u32 = 0x08;
+  const HISTORY_DONE: u32 = 0x10;
+  const ROOT_CHANGED: u32 = 0x20;
+  const ITEM_CREATED: u32 = 0x100;
+
+  /// Flags telling that changes are missing from the history
+  const INCOMPLETE: u32 = MUST_SCAN_SUB_DIRS | USER_DROPPED | KERNEL_DROPPED | EVENT_IDS_WRAPPED | ROOT_CHANGED;
+
+  #[repr(C)]
+  struct StreamContext {
+    version: isize,
+    info: *mut c_void,
+    retain: *const c_void,
+    release: *const c_void,
+    copy_description: *const c_void,
+  }
+
+  type StreamCallback = extern "C" fn(CFRef, *mut c_void, usize, *mut c_void, *const u32, *const u64);
+
+  #[link(name = "CoreFoundation", kind = "framework")]
+  unsafe extern "C" {
+    static kCFTypeArrayCallBacks: u8;
+    static kCFRunLoopDefaultMode: CFRef;
+    fn CFArrayCreateMutable(allocator: CFRef, capacity: isize, callbacks: *const c_void) -> CFRef;
+    fn CFArrayAppendValue(array: CFRef, value: CFRef);
+    fn CFStringCreateWithCString(allocator: CFRef, string: *const c_char, encoding: u32) -> CFRef;
+    fn CFRelease(object: CFRef);
+    fn CFRunLoopGetCurrent() -> CFRef;
+    fn CFRunLoopRunInMode(mode: CFRef, seconds: f64, return_after_source_handled: u8) -> i32;
+    fn CFRunLoopStop(run_loop: CFRef);
+  }
+
+  #[link(name = "CoreServices", kind = "framework")]
+  unsafe extern "C" {
+    fn FSEventStreamCreate(
+      allocator: CFRef,
+      callback: StreamCallback,
+      context: *const StreamContext,
+      paths: CFRef,
+      since: u64,
+      latency: f64,
+      flags: u32,
+    ) -> CFRef;
+    fn FSEventStreamScheduleWithRunLoop(stream: CFRef, run_loop: CFRef, mode: CFRef);
+    fn FSEventStreamStart(stream: CFRef) -> u8;
+    fn FSEventStreamStop(stream: CFRef);
+    fn FSEventStreamInvalidate(stream: CFRef);
+    fn FSEventStreamRelease(stream: CFRef);
+    fn FSEventsGetCurrentEventId() -> u64;
+    fn FSEventsGetLastEventIdForDeviceBeforeTime(device: libc::dev_t, time: f64) -> u64;
+  }
+
+  /// What the stream replayed so far, owned by the thread running it
+  struct Collector {
+    records: Vec<(PathBuf, bool)>,
+    incomplete: bool,
+    done: bool,
+    run_loop: CFRef,
+  }
+
+  pub fn last_event_id_before(device: u64, time: SystemTime) -> Option<u64> {
+    let seconds = time.duration_since(UNIX_EPOCH).ok()?.as_secs_f64() - CF_EPOCH;
+    Some(unsafe { FSEventsGetLastEventIdForDeviceBeforeTime(device as libc::dev_t, seconds) })
+  }
+
+  pub fn replay(root: &Path, since: u64) -> io::Result<Replay> {
+    // An id FSEvents has not reached yet comes from another machine or a reset history
+    if since > unsafe { FSEventsGetCurrentEventId() } {
+      return Ok(Replay { changes: Vec::new(), incomplete: true });
+    }
+
+    let root = CString::new(root.as_os_str().as_bytes()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+    // The stream runs on a run loop of its own, leaving the one of the thread subscribing alone
+    let replaying = thread::Builder::new().name("watcher-history".to_string()).spawn(move || unsafe { run(&root, since) })?;
+    replaying.join().unwrap_or_else(|_| Err(io::Error::other("replaying the history panicked")))
+  }
+
+  unsafe fn run(root: &CStr, since: u64) -> io::Result<Replay> {
+    unsafe {
+      let path = CFStringCreateWithCString(ptr::null_mut(), root.as_ptr(), UTF8);
+      let paths = CFArrayCreateMutable(ptr::null_mut(), 1, &raw const kCFTypeArrayCallBacks as *const c_void);
+      CFArrayAppendValue(paths, path);
+      CFRelease(path);
+
+      // Only reached through the stream's context from here on, including by this thread
+      let collector = Box::into_raw(Box::new(Collector { records: Vec::new(), incomplete: false, done: false, run_loop: CFRunLoopGetCurrent() }));
+      let context = StreamContext { version: 0, info: collector as *mut c_void, retain: ptr::null(), release: ptr::null(), copy_description: ptr::null() };
+      let stream = FSEventStreamCreate(ptr::null_mut(), collect, &context, paths, since, 0.0, CREATE_NO_DEFER | CREATE_FILE_EVENTS);
+      CFRelease(paths);
+      if stream.is_null() {
+        drop(Box::from_raw(collector));
+        return Err(io::Error::other("failed to create an FSEvents stream"));
+      }
+
+      FSEventStreamScheduleWithRunLoop(stream, (*collector).run_loop, kCFRunLoopDefaultMode);
+      let started = FSEventStreamStart(stream) != 0;
+      let deadline = Instant::now() + REPLAY_TIMEOUT;
+      while started && !(*collector).done {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+          break;
+        }
+        CFRunLoopRunInMode(kCFRunLoopDefaultMode, remaining.as_secs_f64(), 0);
+      }
+      if started {
+        FSEventStreamStop(stream);
+      }
+      FSEventStreamInvalidate(stream);
+      FSEventStreamRelease(stream);
+
+      let collector = Box::from_raw(collector);
+      if !started {
+        return Err(io::Error::other("failed to start an FSEvents stream"));
+      }
+      // Running out of time leaves the rest of the history out
+      Ok(Replay { changes: merge(collector.records), incomplete: collector.incomplete || !collector.done })
+    }
+  }
+
+  extern "C" fn collect(_stream: CFRef, info: *mut c_void, count: usize, paths: *mut c_void, flags: *const u32, _ids: *const u64) {
+    let collector = unsafe { &mut *(info as *mut Collector) };
+    if collector.done {
+      return;
+    }
+
+    let paths = paths as *const *const c_char;
+    for index in 0..count {
+      let (path, flags) = unsafe { (CStr::from_ptr(*paths.add(index)), *flags.add(index)) };
+      // Changes after this one are live, which notify's stream reports
+      if flags & HISTORY_DONE != 0 {
+        collector.done = true;
+        unsafe { CFRunLoopStop(collector.run_loop) };
+        return;
+      }
+      if flags & INCOMPLETE != 0 {
+        collector.incomplete = true;
+        continue;
+      }
+      collector.records.push((PathBuf::from(OsStr::from_bytes(path.to_bytes())), flags & ITEM_CREATED != 0));
+    }
+  }
+
+  /// The changes of each path merged into one, in the order their first change was recorded
+  fn merge(records: Vec<(PathBuf, bool)>) -> Vec<(PathBuf, bool)> {
+    let mut positions = HashMap::new();
+    let mut changes: Vec<(PathBuf, bool)> = Vec::new();
+    for (path, created) in records {
+      match positions.get(&path) {
+        Some(&position) => changes[position].1 |= created,
+        None => {
+          positions.insert(path.clone(), changes.len());
+          changes.push((path, created));
+        }
+      }
+    }
+    changes
+  }
+}
@@ -0,0 +1,1417 @@
+//! OS watchers shared between subscriptions
+
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::fs::DirEntry;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::ThreadsafeFunctionCallMode;
+use notify::EventKind;
+use notify::event::{MetadataKind, ModifyKind};
+use notify_debouncer_full::{DebounceEventHandler, DebounceEventResult};
+use watcher_core::{DEBOUNCE_TIMEOUT, IgnoreFilter, IgnoreSet, select_events};
+
+use crate::access::{self, Change};
+use crate::capture::{self, Capture, Captures, Source};
+use crate::case;
+use crate::coalesce;
+use crate::containment::Containment;
+use crate::expectations::Expectations;
+use crate::file_ids::{CacheBudget, FileIds};
+use crate::history;
+use crate::journal::Journal;
+use crate::listeners::{self, Listener};
+use crate::locked::{self, Access};
+use crate::logging::{self, Level, Logger};
+use crate::memory::{self, Excess, MemoryCap};
+use crate::mock::MockSource;
+use crate::mounts::Mounts;
+use crate::mutes::Mutes;
+use crate::network::Polling;
+use crate::normalization::{self, Form};
+use crate::platform;
+use crate::pull::{self, PullBuffer};
+use crate::quiescence::{self, Activity};
+use crate::registration::{
+  EntryLimit, Pruning, Registration, Survey, is_permission_error, outermost, permission_warning, register_tree, too_many_entries_warning, watch_tree,
+};
+use crate::repeats::{self, Repeats, Seen};
+use crate::ring::Ring;
+use crate::routes::Router;
+use crate::scan::{self, Baseline};
+use crate::short_names;
+use crate::special::{self, SpecialFiles};
+use crate::storm::Breaker;
+use crate::type_changes;
+use crate::unchanged::Unchanged;
+use crate::waiters::{self, Waiter};
+use crate::{
+  Callback, Debouncer, Failure, Payload, RetryPolicy, Serialization, StatsCounters, WatchCallbackResult, WatchEvent, WatchWarning, abort_callback,
+  attempts_suffix, is_backend_failure, is_transient_watch_error, path_to_clean_string, release_callback,
+};
+
+/// One subscription's view of a shared watcher
+pub(crate) struct Subscriber {
+  pub root: PathBuf,
+  pub ignore_set: IgnoreSet,
+  /// The same patterns, split to filter batches quickly
+  pub ignore_filter: IgnoreFilter,
+  pub callback: Arc<Callback>,
+  pub running: AtomicBool,
+  pub counters: Arc<StatsCounters>,
+  /// When a batch was last passed to the callback or buffered, for `heartbeatMs`
+  pub delivered_at: Mutex<Instant>,
+  pub strict: bool,
+  pub ignore_permission_errors: bool,
+  /// Whether the ignore patterns or `oneFileSystem` may exclude whole directories from the registration
+  pub prunes: bool,
+  /// Error that stopped the watcher, if it failed
+  pub failure: Mutex<Option<String>>,
+  /// Live events held back while the initial scan or a rescan is being delivered
+  pub held: Mutex<Option<Vec<WatchEvent>>>,
+  /// State of the tree as last delivered, recorded once the tree has been scanned for the subscription
+  pub baseline: Mutex<Option<Baseline>>,
+  /// Journal every delivered batch is appended to
+  pub journal: Option<Mutex<Journal>>,
+  /// Promises waiting for the next matching event
+  pub waiters: Mutex<Vec<Waiter>>,
+  /// Batches waiting to be read, for subscriptions without a callback
+  pub pull: Option<Mutex<PullBuffer>>,
+  /// Stops delivery during event storms, from `circuitBreaker`
+  pub breaker: Option<Breaker>,
+  /// Most memory to hold, from `maxMemoryBytes`
+  pub memory_cap: Option<MemoryCap>,
+  /// Timeout of the debouncer of its own hub, from `debounceMs`
+  pub debounce: Option<Duration>,
+  /// The last error delivered and its repeats, unless `repeatedErrorWindowMs` is 0
+  pub repeats: Option<Mutex<Repeats>>,
+  /// Device of the root whose FSEvents event ids batches carry, on macOS with the native watcher
+  pub fsevents_device: Option<u64>,
+  /// File id cache of the hub the subscriber is attached to, counted against `memory_cap`
+  pub cache_budget: Mutex<Option<Arc<CacheBudget>>>,
+  /// When events last arrived, for promises waiting for the tree to stay quiet
+  pub activity: Mutex<Activity>,
+  /// Changes announced with `expectChange`, whose events are swallowed
+  pub expectations: Mutex<Expectations>,
+  /// Patterns whose events are dropped for the time being
+  pub mutes: Mutex<Mutes>,
+  /// Where diagnostics go, if the subscription has an `onLog` hook
+  pub logger: Option<Logger>,
+  /// File the raw events of the tree are captured to, for `debugEventLog`
+  pub capture: Option<Capture>,
+  /// How batches are passed to the callback
+  pub serialization: Serialization,
+  /// Shared buffer the events are written to instead of being passed to the callback
+  pub ring: Option<Arc<Ring>>,
+  /// Most entries the file id cache of the watcher may hold for this subscription's sake
+  pub cache_limit: Option<usize>,
+  /// Most entries the registration of the tree and the initial scan may find
+  pub entry_limit: Option<EntryLimit>,
+  /// Form event paths are brought into below the root, from `unicodeNormalization`
+  pub normalization: Option<Form>,
+  /// Set when the subscriber's tree is polled instead of watched natively, which gives it a hub of its own.
+  /// Set late when the native watcher fails to start and the subscriber falls back to polling.
+  pub polling: OnceLock<Polling>,
+  /// Interval to poll at should the native watcher fail to start, unless the native watcher was asked for
+  pub fallback: Option<Duration>,
+  /// Whether polling also hashes the contents of files to tell that they changed, from `pollCompare`
+  pub poll_contents: bool,
+  /// Where the events come from with `backend: 'mock'`, which gives the subscriber a hub of its own
+  pub mock: Option<Arc<MockSource>>,
+  /// How often and how long to retry reading the metadata of a changed file another process holds locked
+  pub locked_file_retry: RetryPolicy,
+  /// Whether the directory could be listed when its metadata last changed
+  pub accessible: AtomicBool,
+  /// Register the tree again once read access to the directory is regained, from `rewatchOnAccess`
+  pub rewatch_on_access: bool,
+  /// Report changes of permissions, owner or times as `metadata` rather than `update`
+  pub emit_metadata: bool,
+  /// Report changes of extended attributes as `metadata` marked with `xattr`, from `emitXattr`
+  pub emit_xattr: bool,
+  /// Fold updates following the create of a path within a batch into the create, from `coalesce`
+  pub coalesce: bool,
+  /// Bring the events of each batch into the canonical sequence, from `normalize`
+  pub normalize_events: bool,
+  /// Drop the updates of directories explained by events of their entries in the same batch, from
+  /// `dropParentUpdates` or `normalize`
+  pub drop_parent_updates: bool,
+  /// Report entries whose type changed as a delete and a create, from `detectTypeChanges`
+  pub detect_type_changes: bool,
+  /// Report the updates of a file changed through several hardlinks once, from `dedupeHardlinks`
+  pub dedupe_hardlinks: bool,
+  /// Deliver events for the root itself, from `includeRoot`
+  pub include_root: bool,
+  /// Decides which events resolve to paths outside the root, dropped unless `flagOutsideRoot` asks to flag them
+  pub containment: Containment,
+  /// Device of the root and the mount points found below it, with `oneFileSystem`
+  pub mounts: Option<Mounts>,
+  /// The root is on a case-insensitive volume, probed when subscribing, where case-only renames are reported as
+  /// one `update`
+  pub case_insensitive: bool,
+  /// Special files events were dropped for, with `skipSpecialFiles`
+  pub special_files: Option<Mutex<SpecialFiles>>,
+  /// Attach the kind notify reported to the events it reported, from `rawKinds`
+  pub raw_kinds: bool,
+  /// Attach the number and times of the raw events behind the events from the OS watcher, from `trackCoalescing`
+  pub track_coalescing: bool,
+  /// Files as they were last seen, to drop the updates that leave them so, from `skipUnchanged`
+  pub unchanged: Option<Mutex<Unchanged>>,
+  /// Callbacks the events are split between instead of going to `callback`, for `subscribeRouted`
+  pub router: Option<Router>,
+  /// Listeners added with `on()`, called with the events of their type besides the callback
+  pub listeners: Mutex<Vec<Listener>>,
+}
+
+impl Subscriber {
+  /// Check if `path` is part of the tree this subscriber watches
+  fn covers(&self, path: &Path) -> bool {
+    path.starts_with(&self.root)
+  }
+
+  /// Check if the ignore patterns exclude everything below `directory`, or it is on another file system with
+  /// `oneFileSystem`
+  pub fn ignores_tree(&self, directory: &Path) -> bool {
+    self.ignore_set.ignores_tree(directory, &self.root) || self.mounts.as_ref().is_some_and(|mounts| mounts.crosses(directory))
+  }
+
+  /// The event for a change of `kind` the OS watcher reported for `path`, as a `metadata` event only with
+  /// `emitMetadata`, or with `emitXattr` for a change of extended attributes
+  fn new_event(&self, path: &Path, event_type: &str, kind: &EventKind) -> WatchEvent {
+    let xattr = self.emit_xattr && matches!(kind, EventKind::Modify(ModifyKind::Metadata(MetadataKind::Extended)));
+    let event_type = if event_type == "metadata" && !self.emit_metadata && !xattr { "update" } else { event_type };
+    let mut event = WatchEvent::at(path, event_type, false);
+    event.xattr = xattr.then_some(true);
+    event
+  }
+
+  /// Check if `entry` is a special file that `skipSpecialFiles` leaves out of scans
+  pub fn skips_special(&self, entry: &DirEntry) -> bool {
+    self.special_files.is_some() && entry.file_type().is_ok_and(special::is_special)
+  }
+
+  /// Release the JS callback and those of the routes and listeners, so they and everything they close over can be collected
+  pub fn release_callbacks(&self) {
+    release_callback(&self.callback.handle);
+    self.router.iter().flat_map(Router::callbacks).for_each(|callback| release_callback(&callback.handle));
+    lock(&self.listeners).iter().for_each(|listener| release_callback(&listener.callback.handle));
+  }
+
+  /// Abort the JS callback and those of the routes and listeners, discarding queued calls
+  pub fn abort_callbacks(&self) {
+    abort_callback(&self.callback.handle);
+    self.router.iter().flat_map(Router::callbacks).for_each(|callback| abort_callback(&callback.handle));
+    lock(&self.listeners).iter().for_each(|listener| abort_callback(&listener.callback.handle));
+  }
+
+  /// Batches waiting for the JS callback, or to be read from the pull buffer
+  pub fn queued_batches(&self) -> u32 {
+    match &self.pull {
+      Some(buffer) => u32::try_from(lock(buffer).len()).unwrap_or(u32::MAX),
+      None => self.counters.in_flight_batches.load(Ordering::SeqCst),
+    }
+  }
+
+  /// Pass a line to the `onLog` hook if there is one and `level` is enabled, only formatting it then
+  pub fn log(&self, level: Level, message: impl FnOnce() -> String) {
+    if let Some(logger) = &self.logger
+      && logger.enabled(level)
+    {
+      logger.write(level, message());
+    }
+  }
+
+  /// Send a payload to the JS callback, holding its events back while a scan is being delivered
+  pub fn deliver(&self, mut batch: WatchCallbackResult) {
+    if let Some(held) = lock(&self.held).as_mut() {
+      held.append(&mut batch.events);
+      if batch.error.is_none() && batch.warnings.is_none() && batch.storm.is_none() {
+        return;
+      }
+      self.send(batch);
+      return;
+    }
+
+    let locked = self.record(&batch.events);
+    self.send(batch);
+    self.settle_locked(locked);
+  }
+
+  /// Stop delivering after an unrecoverable error, reporting it to the callback
+  pub fn stop_with(&self, message: &str) {
+    self.deliver(WatchCallbackResult { error: Some(Error::new(Status::GenericFailure, message.to_string())), ..Default::default() });
+    self.running.store(false, Ordering::SeqCst);
+    *lock(&self.failure) = Some(message.to_string());
+    self.stop_waiting();
+    // A dead subscription must not keep the event loop alive
+    self.release_callbacks();
+  }
+
+  /// Close the circuit `circuitBreaker` opened, once it has cooled down or right away if `force`d, and
+  /// announce it. Returns whether it was closed.
+  pub fn close_circuit(&self, force: bool) -> bool {
+    let Some(report) = self.breaker.as_ref().and_then(|breaker| breaker.close(force)) else {
+      return false;
+    };
+    self.log(Level::Info, || format!("delivering events again after leaving {} undelivered during an event storm", report.event_count));
+    self.deliver(WatchCallbackResult { storm: Some(report), ..Default::default() });
+    true
+  }
+
+  /// Start holding live events back, once no other scan holds them
+  pub fn hold(&self) {
+    loop {
+      let mut held = lock(&self.held);
+      if held.is_none() || !self.running.load(Ordering::SeqCst) {
+        *held = Some(Vec::new());
+        return;
+      }
+      drop(held);
+      thread::sleep(Duration::from_millis(5));
+    }
+  }
+
+  /// Deliver the batches of a scan followed by the live events held back meanwhile, and stop holding them.
+  /// Held events below `rescanned` that the scan already accounted for are left out.
+  pub fn deliver_walked(&self, batches: Vec<WatchCallbackResult>, rescanned: Option<&Path>) {
+    // Holding the lock keeps live events from slipping in between the last scan batch and the held ones
+    let mut held = lock(&self.held);
+    for batch in batches {
+      self.send(batch);
+    }
+
+    let mut live = held.take().unwrap_or_default();
+    if let Some(directory) = rescanned
+      && let Some(baseline) = lock(&self.baseline).as_ref()
+    {
+      live.retain(|event| !Path::new(&event.path).starts_with(directory) || !scan::reflects(baseline, event));
+    }
+    if !live.is_empty() {
+      let locked = self.record(&live);
+      self.send(WatchCallbackResult { events: live, ..Default::default() });
+      drop(held);
+      self.settle_locked(locked);
+    }
+  }
+
+  /// Bring the baseline up to date with delivered events. Returns the paths whose metadata could not be read
+  /// because another process holds them locked, which keep their earlier state until `settle_locked`.
+  fn record(&self, events: &[WatchEvent]) -> Vec<PathBuf> {
+    let mut locked = Vec::new();
+    let mut baseline = lock(&self.baseline);
+    let Some(baseline) = baseline.as_mut() else {
+      return locked;
+    };
+
+    for event in events {
+      let path = Path::new(&event.path);
+      if event.event_type == "delete" {
+        for key in scan::keys_below(baseline, path) {
+          baseline.remove(&key);
+        }
+      } else {
+        match locked::symlink_metadata(path) {
+          Access::Found(metadata) => {
+            baseline.insert(event.path.clone(), scan::scanned(event.path.clone(), &metadata));
+          }
+          Access::Missing => {
+            baseline.remove(&event.path);
+          }
+          Access::Locked => locked.push(path.to_path_buf()),
+        }
+      }
+    }
+    locked
+  }
+
+  /// Record the paths `record` found locked once they can be read, or leave their earlier state in the
+  /// baseline if they stay locked for all the attempts `lockedFileRetry` allows
+  fn settle_locked(&self, paths: Vec<PathBuf>) {
+    if paths.is_empty() {
+      return;
+    }
+
+    let settled = locked::retry(self, paths);
+    let mut baseline = lock(&self.baseline);
+    let Some(baseline) = baseline.as_mut() else {
+      return;
+    };
+    for (path, metadata) in settled {
+      let key = path_to_clean_string(&path);
+      match metadata {
+        Some(metadata) => {
+          baseline.insert(key.clone(), scan::scanned(key, &metadata));
+        }
+        None => {
+          baseline.remove(&key);
+        }
+      }
+    }
+  }
+
+  /// Send a payload to the JS callback unless the subscription has been closed. Batches with events are
+  /// appended to the journal first, unless they are being replayed from it.
+  pub fn send(&self, mut batch: WatchCallbackResult) {
+    if !self.running.load(Ordering::SeqCst) {
+      return;
+    }
+    if let Some(cap) = self.memory_cap
+      && let Some(excess) = memory::make_room(self, cap, &mut batch)
+    {
+      match excess {
+        Excess::Shed(shed) => {
+          let message = format!(
+            "Dropped {} batches, {} events and {} file id cache entries to stay within maxMemoryBytes; call triggerRescan() to catch up",
+            shed.batches, shed.events, shed.cache_entries
+          );
+          self.log(Level::Warn, || message.clone());
+          self.send(WatchCallbackResult { error: Some(Error::new(Status::GenericFailure, message)), memory_shed: Some(shed), ..Default::default() });
+        }
+        Excess::Close(message) => {
+          self.log(Level::Error, || message.clone());
+          self.stop_with(&message);
+          return;
+        }
+      }
+      if batch.events.is_empty() && batch.error.is_none() && batch.warnings.is_none() && batch.storm.is_none() {
+        return;
+      }
+    }
+    self.normalize(&mut batch.events);
+    short_names::expand(&mut batch.events);
+    self.attach_root(&mut batch.events);
+
+    if let Some(journal) = &self.journal
+      && batch.cursor.is_none()
+      && !batch.events.is_empty()
+    {
+      match lock(journal).append(&batch.events) {
+        Ok(cursor) => batch.cursor = Some(cursor as i64),
+        Err(e) if batch.error.is_none() => batch.error = Some(Error::new(Status::GenericFailure, format!("Failed to write journal: {}", e))),
+        Err(_) => {}
+      }
+    }
+
+    *lock(&self.delivered_at) = Instant::now();
+    self.counters.delivered_events.fetch_add(u32::try_from(batch.events.len()).unwrap_or(u32::MAX), Ordering::Relaxed);
+    count_types(&self.counters, &batch.events);
+    self.log(Level::Debug, || describe_batch(&batch));
+    waiters::notify(self, &batch.events);
+    listeners::emit(self, &batch);
+
+    if let Some(buffer) = &self.pull {
+      batch.seq = next_seq(&self.counters);
+      let mut buffer = lock(buffer);
+      buffer.push(batch);
+      self.counters.max_queued_batches.fetch_max(u32::try_from(buffer.len()).unwrap_or(u32::MAX), Ordering::Relaxed);
+      return;
+    }
+
+    if deliver(self, batch) == Status::Closing {
+      // The JS side has released the callback, nothing more can be delivered
+      self.running.store(false, Ordering::SeqCst);
+    }
+  }
+
+  /// Name of the watcher implementation the subscriber's events come from
+  pub fn backend_name(&self) -> &'static str {
+    if self.mock.is_some() {
+      "mock"
+    } else if self.polling.get().is_some() {
+      "polling"
+    } else {
+      platform::backend_name()
+    }
+  }
+
+  /// Bring the part below the root of event paths into the form asked for with `unicodeNormalization`.
+  /// Paths given as bytes are left alone, as they are not valid UTF-8 to begin with.
+  fn normalize(&self, events: &mut [WatchEvent]) {
+    let Some(form) = self.normalization else {
+      return;
+    };
+
+    let mut root = None;
+    for event in events.iter_mut().filter(|event| !event.path.is_ascii() && event.path_bytes.is_none()) {
+      let root = root.get_or_insert_with(|| path_to_clean_string(&self.root));
+      if let Some(relative) = event.path.strip_prefix(root.as_str())
+        && let Some(normalized) = normalization::normalize(relative, form)
+      {
+        event.path.truncate(root.len());
+        event.path.push_str(&normalized);
+      }
+    }
+  }
+
+  /// Mark every event with the root it was delivered for, which telling apart by path alone gets wrong when
+  /// one watched directory is inside another or a prefix of its name, like `/data` and `/data-archive`
+  fn attach_root(&self, events: &mut [WatchEvent]) {
+    if events.is_empty() {
+      return;
+    }
+    let root = path_to_clean_string(&self.root);
+    for event in events.iter_mut() {
+      event.root = Some(root.clone());
+    }
+  }
+
+  /// Reject the promises still waiting for events or batches and close the event capture, once the subscription stopped
+  pub fn stop_waiting(&self) {
+    waiters::stop(self);
+    pull::stop(self);
+    quiescence::stop(self);
+    if let Some(capture) = &self.capture {
+      capture.close();
+    }
+    if let Some(ring) = &self.ring {
+      ring.close();
+    }
+  }
+}
+
+/// An OS watcher and debouncer serving any number of roots. All subscriptions that are not isolated
+/// share a single hub, so its debouncer thread is the only one dispatching their events.
+pub(crate) struct Hub {
+  debouncer: Mutex<Option<Debouncer>>,
+  /// How long the debouncer waits for further events of a path, from `debounceMs` or `setDebounce()`
+  timeout: Mutex<Duration>,
+  /// Where the OS watcher takes its events from, for a debouncer replacing the current one
+  source: Source,
+  /// When the current debouncer handed over to the one `setDebounce()` replaced it with, once it did
+  handover: Mutex<Arc<OnceLock<Instant>>>,
+  registration: Mutex<Registration>,
+  subscribers: Mutex<HashMap<u32, Arc<Subscriber>>>,
+  /// Subscribers capturing raw events, kept apart from `subscribers` as the OS watcher's thread reads them
+  /// while the hub may be waiting for that thread to register a directory
+  captures: Arc<Captures>,
+  /// Limit and size of the debouncer's file id cache
+  file_ids: Arc<CacheBudget>,
+}
+
+/// The hub shared by all subscriptions that are not isolated
+static SHARED: Mutex<Option<Arc<Hub>>> = Mutex::new(None);
+
+/// Number of hubs with a running debouncer thread
+static DISPATCH_THREADS: AtomicU32 = AtomicU32::new(0);
+
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Number of background threads dispatching events to subscriptions
+#[cfg(feature = "faults")]
+pub(crate) fn dispatch_threads() -> u32 {
+  DISPATCH_THREADS.load(Ordering::SeqCst)
+}
+
+impl Hub {
+  /// Attach a subscriber to the shared hub, registering its root unless an ancestor is already watched.
+  /// Isolated and polling subscribers, and those the shared registration cannot serve, get a hub of their own.
+  /// Returns the hub together with warnings about directories below the root that could not be watched.
+  /// The subscriber receives events while its root is being registered, so none are lost for the parts
+  /// of a large tree that are already watched. If the native watcher fails to start, as FSEvents does in some
+  /// sandboxed processes, the subscriber falls back to polling with a warning.
+  pub fn attach(id: u32, subscriber: Arc<Subscriber>, shared: bool, retry: &RetryPolicy) -> std::result::Result<(Arc<Hub>, Vec<WatchWarning>), Failure> {
+    let error = match Hub::attach_to(id, &subscriber, shared, retry) {
+      Err(Failure::Backend(error)) => error,
+      result => return result,
+    };
+    let Some(interval) = subscriber.fallback.filter(|_| subscriber.polling.get().is_none()) else {
+      return Err(Failure::Backend(error));
+    };
+
+    let root = path_to_clean_string(&subscriber.root);
+    let reason = format!("the {} watcher failed to start: {}", platform::backend_name(), error.reason);
+    let message = format!("Polling {} every {}ms because {}, each poll scans the whole tree", root, interval.as_millis(), reason);
+    let _ = subscriber.polling.set(Polling { interval, reason });
+    let (hub, mut warnings) = Hub::attach_to(id, &subscriber, false, retry)?;
+    warnings.push(WatchWarning { path: root, kind: "backend-fallback".to_string(), message, file_system: None });
+    Ok((hub, warnings))
+  }
+
+  fn attach_to(id: u32, subscriber: &Arc<Subscriber>, shared: bool, retry: &RetryPolicy) -> std::result::Result<(Arc<Hub>, Vec<WatchWarning>), Failure> {
+    if shared && subscriber.polling.get().is_none() && subscriber.mock.is_none() {
+      let mut shared_hub = lock(&SHARED);
+      let hub = match shared_hub.as_ref() {
+        Some(hub) => Arc::clone(hub),
+        None => Hub::create(Source::Native, DEBOUNCE_TIMEOUT)?,
+      };
+
+      hub.insert(id, subscriber);
+      match hub.add_root(subscriber, retry) {
+        Ok(Some(warnings)) => {
+          *shared_hub = Some(Arc::clone(&hub));
+          return Ok((hub, warnings));
+        }
+        // The shared registration cannot serve this root, fall back to a hub of its own
+        Ok(None) => {
+          hub.remove(id);
+        }
+        Err(e) => {
+          hub.remove(id);
+          if shared_hub.is_none() {
+            drop(hub.retire());
+          }
+          return Err(e);
+        }
+      }
+    }
+
+    let source = match (subscriber.polling.get(), &subscriber.mock) {
+      (_, Some(mock)) => Source::Mock(Arc::clone(mock)),
+      (Some(polling), None) => Source::Polling(polling.interval, subscriber.poll_contents),
+      (None, None) => Source::Native,
+    };
+    let hub = Hub::create(source, subscriber.debounce.unwrap_or(DEBOUNCE_TIMEOUT))?;
+    hub.insert(id, subscriber);
+    match hub.add_root(subscriber, retry) {
+      Ok(warnings) => Ok((hub, warnings.unwrap_or_default())),
+      Err(e) => {
+        drop(hub.retire());
+        Err(e)
+      }
+    }
+  }
+
+  fn insert(&self, id: u32, subscriber: &Arc<Subscriber>) {
+    let mut subscribers = lock(&self.subscribers);
+    subscribers.insert(id, Arc::clone(subscriber));
+    *lock(&subscriber.cache_budget) = Some(Arc::clone(&self.file_ids));
+    self.limit_cache(&subscribers);
+    self.captures.add(id, subscriber);
+  }
+
+  fn remove(&self, id: u32) {
+    let mut subscribers = lock(&self.subscribers);
+    subscribers.remove(&id);
+    self.limit_cache(&subscribers);
+    self.captures.remove(id);
+  }
+
+  /// Hold the file id cache to the smallest limit any subscriber asked for
+  fn limit_cache(&self, subscribers: &HashMap<u32, Arc<Subscriber>>) {
+    self.file_ids.set_limit(subscribers.values().filter_map(|subscriber| subscriber.cache_limit).min());
+  }
+
+  /// Number of paths whose file id the debouncer keeps to pair the halves of renames
+  pub fn cache_entries(&self) -> usize {
+    self.file_ids.entries()
+  }
+
+  /// Create a hub without any roots, whose watcher takes its events from `source` and whose debouncer waits
+  /// `timeout` for further events
+  fn create(source: Source, timeout: Duration) -> std::result::Result<Arc<Hub>, Failure> {
+    let hub = Arc::new(Hub {
+      debouncer: Mutex::new(None),
+      timeout: Mutex::new(timeout),
+      source,
+      handover: Mutex::new(Arc::new(OnceLock::new())),
+      registration: Mutex::new(Registration::default()),
+      subscribers: Mutex::new(HashMap::new()),
+      captures: Arc::new(Captures::default()),
+      file_ids: Arc::new(CacheBudget::default()),
+    });
+
+    let until = Arc::clone(&lock(&hub.handover));
+    let debouncer =
+      hub.new_debouncer(timeout, None, until).map_err(|e| Failure::Backend(Error::new(Status::GenericFailure, format!("Failed to create watcher: {}", e))))?;
+
+    *lock(&hub.debouncer) = Some(debouncer);
+    DISPATCH_THREADS.fetch_add(1, Ordering::SeqCst);
+    Ok(hub)
+  }
+
+  /// A debouncer dispatching to this hub the events it received after `since` and before `until` are set
+  fn new_debouncer(self: &Arc<Hub>, timeout: Duration, since: Option<Arc<OnceLock<Instant>>>, until: Arc<OnceLock<Instant>>) -> notify::Result<Debouncer> {
+    let handler = EventHandler { hub: Arc::downgrade(self), reported: Vec::new(), since, until };
+    capture::new_debouncer(Arc::clone(&self.captures), self.source.clone(), handler, FileIds::new(Arc::clone(&self.file_ids)), timeout)
+  }
+
+  /// Replace the debouncer with one that waits `timeout` for further events, registering the roots with it
+  /// again. The old debouncer still delivers the events it received before the new one took over, under its
+  /// own timeout, and is stopped once it has.
+  pub fn set_debounce(self: &Arc<Hub>, timeout: Duration) -> Result<()> {
+    let subscribers = self.subscribers();
+    let pruning = Pruning::new(subscribers.iter().map(Arc::as_ref));
+
+    let mut debouncer = lock(&self.debouncer);
+    if debouncer.is_none() {
+      return Err(Error::new(Status::GenericFailure, "The watcher has stopped"));
+    }
+    let mut current = lock(&self.timeout);
+    if *current == timeout {
+      return Ok(());
+    }
+
+    let handover = Arc::clone(&lock(&self.handover));
+    let until = Arc::new(OnceLock::new());
+    let mut next = self
+      .new_debouncer(timeout, Some(Arc::clone(&handover)), Arc::clone(&until))
+      .map_err(|e| Error::new(Status::GenericFailure, format!("Failed to create watcher: {}", e)))?;
+
+    let mut registration = lock(&self.registration);
+    let mut replacement = Registration::default();
+    for root in &registration.roots {
+      let strict = subscribers.iter().filter(|subscriber| subscriber.covers(root)).all(|subscriber| subscriber.strict);
+      watch_tree(&mut next, root, strict, &mut replacement, &pruning)
+        .map_err(|e| Error::new(Status::GenericFailure, format!("Failed to watch directory: {}", e)))?;
+      replacement.roots.insert(root.clone());
+    }
+
+    // Everything received from here on is the new debouncer's to deliver
+    let _ = handover.set(Instant::now());
+    *registration = replacement;
+    *lock(&self.handover) = until;
+    let flush = flush_delay(*current);
+    *current = timeout;
+
+    if let Some(old) = debouncer.replace(next) {
+      // A debouncer that cannot be stopped in the background is dropped, losing the events it still holds
+      let _ = thread::Builder::new().name("watcher-handover".to_string()).spawn(move || {
+        thread::sleep(flush);
+        old.stop();
+      });
+    }
+    Ok(())
+  }
+
+  /// The events the debouncer holds for `subscriber`, filtered like the batches it delivers to it
+  pub fn pending_events(&self, subscriber: &Subscriber) -> Vec<WatchEvent> {
+    // Paths the debouncer did not deliver in twice the time it takes were dropped by it
+    let held = self.captures.pending.snapshot(self.flush_delay() * 2);
+    let mut events = Vec::new();
+    select_events(
+      &held,
+      &subscriber.root,
+      &subscriber.ignore_filter,
+      &mut events,
+      &mut Vec::new(),
+      |path, event_type, kind| subscriber.new_event(path, event_type, kind),
+      |_, _, _| {},
+    );
+
+    if !subscriber.include_root {
+      events.retain(|event| Path::new(&event.path) != subscriber.root);
+    }
+    lock(&subscriber.mutes).drop_muted(&mut events, &subscriber.root);
+    events
+  }
+
+  /// FSEvents event id `subscriber` resumes from after the batches delivered so far, for `eventId`
+  pub fn event_id(&self, subscriber: &Subscriber) -> Option<i64> {
+    subscriber.fsevents_device?;
+    history::resume_id(subscriber, self.captures.pending.oldest())
+  }
+
+  /// How long to wait for the events the debouncer holds to be delivered
+  pub fn flush_delay(&self) -> Duration {
+    flush_delay(*lock(&self.timeout))
+  }
+
+  /// Check if this is the hub shared by the subscriptions that are not isolated
+  pub fn is_shared(&self) -> bool {
+    lock(&SHARED).as_ref().is_some_and(|hub| std::ptr::eq(hub.as_ref(), self))
+  }
+
+  /// Make sure events for the subscriber's root flow through this hub. Returns `None` when the root lies
+  /// in a part of the registration that cannot serve it, such as a skipped directory.
+  fn add_root(&self, subscriber: &Subscriber, retry: &RetryPolicy) -> std::result::Result<Option<Vec<WatchWarning>>, Failure> {
+    let subscribers = self.subscribers();
+    let pruning = Pruning::new(subscribers.iter().map(Arc::as_ref));
+
+    let mut debouncer = lock(&self.debouncer);
+    let Some(debouncer) = debouncer.as_mut() else {
+      return Ok(None);
+    };
+    let mut registration = lock(&self.registration);
+    let root = subscriber.root.as_path();
+
+    if registration.roots.iter().any(|watched| root.starts_with(watched)) {
+      registration.restore_pruned(debouncer, root, &pruning);
+
+      // A strict subscription must not silently miss directories that were skipped
+      let unusable = registration.skipped.iter().any(|skipped| root.starts_with(skipped) || (subscriber.strict && skipped.starts_with(root)));
+      return Ok(if unusable { None } else { Some(registration.warnings(root)) });
+    }
+
+    // Roots below the new one are folded into its recursive watch
+    let nested: Vec<PathBuf> = registration.roots.iter().filter(|watched| watched.starts_with(root)).cloned().collect();
+    for watched in &nested {
+      registration.remove_root(debouncer, watched);
+    }
+
+    let limit = subscriber.entry_limit;
+    let mut entries = 0;
+    let result = retry.run(is_transient_watch_error, || {
+      registration.remove_root(debouncer, root);
+      let survey = Survey::walk(root, &pruning, limit);
+      entries = survey.entries;
+
+      // Nothing is registered for a tree that is too large
+      if limit.is_some_and(|limit| limit.fail && entries > limit.max) {
+        return Ok(());
+      }
+      register_tree(debouncer, root, subscriber.strict, &mut registration, &pruning, &survey)
+    });
+
+    let failure = match (result, limit.filter(|limit| entries > limit.max)) {
+      (Ok(()), Some(limit)) if limit.fail => Failure::TooManyEntries { root: root.to_path_buf(), entries, limit: limit.max },
+      (Ok(()), exceeded) => {
+        registration.roots.insert(root.to_path_buf());
+        let mut warnings = registration.warnings(root);
+        warnings.extend(exceeded.map(|limit| too_many_entries_warning(root, entries, limit.max)));
+        return Ok(Some(warnings));
+      }
+      (Err((e, attempts)), _) => {
+        let error = Error::new(Status::GenericFailure, format!("Failed to watch directory: {}{}", e, attempts_suffix(attempts)));
+        if is_backend_failure(&e) { Failure::Backend(error) } else { Failure::Error(error) }
+      }
+    };
+
+    registration.remove_root(debouncer, root);
+
+    // Give the existing subscriptions their watches back
+    for watched in nested {
+      if watch_tree(debouncer, &watched, false, &mut registration, &pruning).is_ok() {
+        registration.roots.insert(watched);
+      }
+    }
+
+    Err(failure)
+  }
+
+  /// Detach a subscriber. When it was the last one, the hub is retired and its debouncer is returned
+  /// so the caller can decide how to stop it. Otherwise subscriptions that were attached below the
+  /// departing one's root are promoted to watches of their own.
+  pub fn detach(&self, id: u32) -> Option<Debouncer> {
+    let mut shared_hub = lock(&SHARED);
+    let mut subscribers = lock(&self.subscribers);
+
+    subscribers.remove(&id)?;
+    self.limit_cache(&subscribers);
+    self.captures.remove(id);
+
+    if subscribers.is_empty() {
+      if shared_hub.as_ref().is_some_and(|hub| std::ptr::eq(hub.as_ref(), self)) {
+        *shared_hub = None;
+      }
+      return self.retire();
+    }
+
+    self.reroot(&subscribers);
+    None
+  }
+
+  /// Take the debouncer out of the hub so no further events are dispatched
+  fn retire(&self) -> Option<Debouncer> {
+    let debouncer = lock(&self.debouncer).take();
+    if debouncer.is_some() {
+      DISPATCH_THREADS.fetch_sub(1, Ordering::SeqCst);
+    }
+    debouncer
+  }
+
+  /// Narrow the watched roots down to the ones the remaining subscribers need
+  fn reroot(&self, subscribers: &HashMap<u32, Arc<Subscriber>>) {
+    let mut debouncer = lock(&self.debouncer);
+    let Some(debouncer) = debouncer.as_mut() else {
+      return;
+    };
+    let mut registration = lock(&self.registration);
+
+    let roots = outermost(subscribers.values().map(|subscriber| subscriber.root.as_path()));
+    let pruning = Pruning::new(subscribers.values().map(Arc::as_ref));
+    let removed: Vec<PathBuf> = registration.roots.difference(&roots).cloned().collect();
+    if removed.is_empty() {
+      return;
+    }
+
+    for root in &removed {
+      registration.remove_root(debouncer, root);
+    }
+
+    for root in roots.difference(&registration.roots.clone()) {
+      let affected: Vec<&Arc<Subscriber>> = subscribers.values().filter(|subscriber| subscriber.covers(root)).collect();
+      let strict = affected.iter().all(|subscriber| subscriber.strict);
+
+      match watch_tree(debouncer, root, strict, &mut registration, &pruning) {
+        Ok(()) => {
+          registration.roots.insert(root.clone());
+        }
+        Err(e) => {
+          let message = format!("Failed to watch directory: {}", e);
+          for subscriber in affected {
+            subscriber.deliver(WatchCallbackResult { error: Some(Error::new(Status::GenericFailure, message.clone())), ..Default::default() });
+          }
+        }
+      }
+    }
+  }
+
+  /// Check if a subscription on `root` is served by a watch registered for one of its ancestors
+  pub fn is_attached(&self, root: &Path) -> bool {
+    !lock(&self.registration).roots.contains(root)
+  }
+
+  /// Number of directories watched below `root`
+  pub fn watch_count(&self, root: &Path) -> u32 {
+    lock(&self.registration).watch_count(root)
+  }
+
+  /// Stop the hub after an unrecoverable error, reporting it to every subscriber
+  fn fail(&self, message: &str) {
+    let debouncer = {
+      let mut shared_hub = lock(&SHARED);
+      if shared_hub.as_ref().is_some_and(|hub| std::ptr::eq(hub.as_ref(), self)) {
+        *shared_hub = None;
+      }
+
+      self.captures.clear();
+      for subscriber in lock(&self.subscribers).drain().map(|(_, subscriber)| subscriber) {
+        subscriber.log(Level::Error, || format!("watcher stopped: {}", message));
+        subscriber.stop_with(message);
+      }
+
+      self.retire()
+    };
+
+    drop(debouncer);
+  }
+
+  /// Current subscribers, so callbacks can be invoked without holding the lock
+  fn subscribers(&self) -> Vec<Arc<Subscriber>> {
+    lock(&self.subscribers).values().cloned().collect()
+  }
+
+  /// Register the tree of a subscriber's own watch again, after read access to its root was regained and
+  /// directories below may have gone unwatched. Subscribers attached to the watch of a parent keep it.
+  fn rewatch(&self, root: &Path) -> notify::Result<bool> {
+    let subscribers = self.subscribers();
+    let pruning = Pruning::new(subscribers.iter().map(Arc::as_ref));
+    let mut debouncer = lock(&self.debouncer);
+    let Some(debouncer) = debouncer.as_mut() else {
+      return Ok(false);
+    };
+    let mut registration = lock(&self.registration);
+    if !registration.roots.contains(root) {
+      return Ok(false);
+    }
+
+    registration.remove_root(debouncer, root);
+    watch_tree(debouncer, root, false, &mut registration, &pruning)?;
+    registration.roots.insert(root.to_path_buf());
+    Ok(true)
+  }
+
+  /// Try to watch directories that were skipped or appeared below a partially watched directory, and
+  /// unwatch ignored directories the OS watcher registered on its own below a recursive watch.
+  /// Returns warnings for directories that still cannot be watched, and the entries found in new
+  /// directories since their creation may have happened before they were watched.
+  fn pick_up_directories(&self, paths: Vec<PathBuf>, pruning: &Pruning) -> (Vec<WatchWarning>, Vec<PathBuf>) {
+    let mut debouncer = lock(&self.debouncer);
+    let Some(debouncer) = debouncer.as_mut() else {
+      return (vec![], vec![]);
+    };
+    let mut registration = lock(&self.registration);
+    let mut warnings = Vec::new();
+    let mut discovered = Vec::new();
+
+    for path in paths {
+      let was_skipped = registration.skipped.remove(&path);
+      let in_partial = path.parent().is_some_and(|parent| registration.partial.contains(parent));
+
+      if !path.is_dir() {
+        continue;
+      }
+
+      if pruning.prunes(&path) {
+        if !in_partial {
+          let _ = debouncer.unwatch(&path);
+        }
+        registration.pruned.insert(path);
+        continue;
+      }
+
+      if !in_partial && !was_skipped {
+        for pruned in Survey::walk(&path, pruning, None).pruned {
+          let _ = debouncer.unwatch(&pruned);
+          registration.pruned.insert(pruned);
+        }
+        discovered.extend(registration.entries_below(&path));
+        continue;
+      }
+
+      match watch_tree(debouncer, &path, false, &mut registration, pruning) {
+        Err(e) if is_permission_error(&e) => {
+          // Only warn the first time a directory is skipped
+          if !was_skipped {
+            warnings.push(permission_warning(&path));
+          }
+          registration.skipped.insert(path);
+        }
+        Ok(()) if !was_skipped => discovered.extend(registration.entries_below(&path)),
+        _ => {}
+      }
+    }
+
+    (warnings, discovered)
+  }
+}
+
+/// Turns debounced events into callback invocations on the watcher thread
+struct EventHandler {
+  hub: Weak<Hub>,
+  /// When the OS watcher reported each event selected for a subscriber, reused across batches
+  reported: Vec<Instant>,
+  /// For a debouncer replacing another, when it took over, before which it delivers nothing
+  since: Option<Arc<OnceLock<Instant>>>,
+  /// When a debouncer replacing this one took over, from which on this one delivers nothing
+  until: Arc<OnceLock<Instant>>,
+}
+
+impl DebounceEventHandler for EventHandler {
+  fn handle_event(&mut self, result: DebounceEventResult) {
+    let Some(hub) = self.hub.upgrade() else {
+      return;
+    };
+
+    let process = || {
+      // Injected errors go out ahead of the batch they were taken for
+      #[cfg(feature = "faults")]
+      if let Some(error) = crate::faults::backend_error() {
+        self.process(&hub, Err(vec![error]));
+      }
+      self.process(&hub, result)
+    };
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(process)) {
+      hub.fail(&panic_message(payload.as_ref()));
+    }
+  }
+}
+
+impl EventHandler {
+  /// Fan a debounced result out to every subscriber it concerns
+  fn process(&mut self, hub: &Hub, result: DebounceEventResult) {
+    let subscribers = hub.subscribers();
+
+    for subscriber in &subscribers {
+      if let Some(capture) = &subscriber.capture
+        && let Some(e) = capture.flush()
+      {
+        capture::disable(subscriber, capture, &e);
+      }
+    }
+
+    match result {
+      Ok(mut batch) => {
+        #[cfg(feature = "faults")]
+        batch.retain(|debounced_event| !debounced_event.event.paths.iter().any(|path| crate::faults::missed(path)));
+        // While `setDebounce()` hands over from one debouncer to the next, each delivers what it received on its side of the handover
+        if let Some(since) = &self.since {
+          let since = since.get().copied();
+          batch.retain(|debounced_event| since.is_some_and(|since| debounced_event.time >= since));
+        }
+        if let Some(&until) = self.until.get() {
+          batch.retain(|debounced_event| debounced_event.time < until);
+        }
+        hub.captures.pending.release(&batch);
+        let mut pick_up = Vec::new();
+        let prunes = subscribers.iter().any(|subscriber| subscriber.prunes);
+
+        for debounced_event in &batch {
+          let event = &debounced_event.event;
+
+          // Directories below a partially watched directory are not registered automatically, and the
+          // ones below a recursive watch are registered even if they are ignored
+          let appeared = event.kind.is_create() || matches!(event.kind, EventKind::Modify(ModifyKind::Name(_)));
+          let registration = lock(&hub.registration);
+          for path in &event.paths {
+            let in_partial = path.parent().is_some_and(|parent| registration.partial.contains(parent));
+            if registration.skipped.contains(path) || (appeared && (in_partial || prunes)) {
+              pick_up.push(path.clone());
+            }
+          }
+        }
+
+        let (warnings, discovered) =
+          if pick_up.is_empty() { (vec![], vec![]) } else { hub.pick_up_directories(pick_up, &Pruning::new(subscribers.iter().map(Arc::as_ref))) };
+
+        // A changed owner or mode of a subscriber's directory may have taken away or given back read access
+        let retouched: HashSet<&Path> = batch
+          .iter()
+          .filter(|debounced_event| matches!(debounced_event.event.kind, EventKind::Modify(ModifyKind::Metadata(_))))
+          .flat_map(|debounced_event| debounced_event.event.paths.iter().map(PathBuf::as_path))
+          .collect();
+
+        let seen = hub.captures.take_seen(&batch);
+        let oldest = if subscribers.iter().any(|subscriber| subscriber.fsevents_device.is_some()) { hub.captures.pending.oldest() } else { None };
+
+        for subscriber in &subscribers {
+          // A subscription stopped by a panic while its events were processed is left alone until it is unsubscribed
+          if !subscriber.running.load(Ordering::SeqCst) {
+            continue;
+          }
+          isolate(subscriber, || {
+            #[cfg(feature = "faults")]
+            if crate::faults::panic() {
+              panic!("{}", crate::faults::PANIC);
+            }
+
+            let mut events = Vec::new();
+            // The first event of a path stands for all its raw events, the events following it are counted with it
+            let mut seen = if subscriber.track_coalescing { seen.clone() } else { HashMap::new() };
+            let new_event = |path: &Path, event_type: &str, kind: &EventKind| {
+              let mut event = subscriber.new_event(path, event_type, kind);
+              if subscriber.raw_kinds {
+                event.raw_kind = Some(format!("{:?}", kind));
+              }
+              if let Some(seen) = seen.remove(path) {
+                event.coalesced_count = Some(seen.count);
+                event.first_seen_ms = Some(seen.first_ms);
+                event.last_seen_ms = Some(seen.last_ms);
+              }
+              event
+            };
+            let active =
+              select_events(&batch, &subscriber.root, &subscriber.ignore_filter, &mut events, &mut self.reported, new_event, |path, kind, event_type| {
+                if let Some(logger) = &subscriber.logger {
+                  logger.raw_event(path, kind);
+                }
+                if let Some(event_type) = event_type {
+                  subscriber.log(Level::Debug, || format!("ignored {} event for {}", event_type, logging::printable(&path_to_clean_string(path))));
+                }
+              });
+
+            if !subscriber.include_root {
+              events.retain(|event| Path::new(&event.path) != subscriber.root);
+            }
+
+            if !discovered.is_empty() {
+              let mut known: HashSet<String> = HashSet::new();
+              let selected: HashSet<&str> = events.iter().map(|event| event.path.as_str()).collect();
+              let found: Vec<WatchEvent> = discovered
+                .iter()
+                .filter(|path| subscriber.covers(path) && !subscriber.ignore_filter.is_ignored(path, &subscriber.root))
+                .map(|path| WatchEvent::at(path, "create", false))
+                .filter(|event| !selected.contains(event.path.as_str()) && known.insert(event.path.clone()))
+                .collect();
+              for event in found {
+                subscriber.log(Level::Debug, || format!("found {} in a newly watched directory", logging::printable(&event.path)));
+                events.push(event);
+              }
+            }
+
+            let outside = subscriber.containment.confine(&batch, &mut events);
+            if outside > 0 {
+              subscriber.log(Level::Debug, || format!("dropped {} events for paths outside the root", outside));
+            }
+            if let Some(mounts) = &subscriber.mounts {
+              let mounted = mounts.confine(&mut events);
+              if mounted > 0 {
+                subscriber.log(Level::Debug, || format!("dropped {} events for paths on another file system", mounted));
+              }
+            }
+            if subscriber.case_insensitive {
+              let renamed = case::renames(&mut events);
+              if renamed > 0 {
+                subscriber.log(Level::Debug, || format!("merged {} events of case-only renames", renamed));
+              }
+            }
+
+            if let Some(special_files) = &subscriber.special_files {
+              let skipped = lock(special_files).drop_special(&mut events);
+              if skipped > 0 {
+                subscriber.log(Level::Debug, || format!("skipped {} events for special files", skipped));
+              }
+            }
+            if subscriber.coalesce {
+              coalesce::creates(&mut events);
+            }
+            coalesce::replacements(&mut events);
+            if subscriber.normalize_events {
+              coalesce::canonical(&mut events);
+            }
+            if subscriber.drop_parent_updates {
+              let dropped = coalesce::parent_updates(&mut events);
+              if dropped > 0 {
+                subscriber.log(Level::Debug, || format!("dropped {} updates of directories whose entries changed", dropped));
+              }
+            }
+            if subscriber.detect_type_changes
+              && let Some(baseline) = lock(&subscriber.baseline).as_ref()
+            {
+              let changed = type_changes::split(baseline, &mut events);
+              if changed > 0 {
+                subscriber.log(Level::Debug, || format!("reported {} paths whose type changed as a delete and a create", changed));
+              }
+            }
+            if let Some(unchanged) = &subscriber.unchanged {
+              let dropped = lock(unchanged).drop_unchanged(&mut events);
+              subscriber.counters.suppressed_unchanged.fetch_add(dropped, Ordering::Relaxed);
+              if dropped > 0 {
+                subscriber.log(Level::Debug, || format!("dropped {} updates that left their file unchanged", dropped));
+              }
+            }
+
+            let muted = lock(&subscriber.mutes).drop_muted(&mut events, &subscriber.root);
+            subscriber.counters.muted_events.fetch_add(muted, Ordering::Relaxed);
+            if muted > 0 {
+              subscriber.log(Level::Debug, || format!("dropped {} events for muted paths", muted));
+            }
+            let swallowed = lock(&subscriber.expectations).swallow(&mut events);
+            subscriber.counters.suppressed_expected_changes.fetch_add(swallowed, Ordering::Relaxed);
+            if swallowed > 0 {
+              subscriber.log(Level::Debug, || format!("swallowed {} expected changes", swallowed));
+            }
+            if subscriber.dedupe_hardlinks {
+              coalesce::hardlinks(&mut events);
+            }
+
+            if active || !events.is_empty() {
+              quiescence::observe(subscriber, !events.is_empty());
+            }
+
+            let mut warnings: Vec<WatchWarning> = warnings.iter().filter(|w| subscriber.covers(Path::new(&w.path))).cloned().collect();
+            warnings.extend(subscriber.mounts.iter().flat_map(Mounts::warnings));
+            if retouched.contains(subscriber.root.as_path())
+              && let Some(change) = access::check(subscriber)
+            {
+              let rewatched = change == Change::Regained
+                && subscriber.rewatch_on_access
+                && hub.rewatch(&subscriber.root).unwrap_or_else(|e| {
+                  subscriber.log(Level::Warn, || format!("failed to watch {} again: {}", logging::printable(&path_to_clean_string(&subscriber.root)), e));
+                  false
+                });
+              warnings.push(access::warning(&subscriber.root, change, rewatched));
+            }
+            for warning in &warnings {
+              subscriber.log(Level::Warn, || warning.message.clone());
+            }
+
+            let before = events.len();
+            let storm = subscriber.breaker.as_ref().and_then(|breaker| breaker.admit(&mut events));
+            subscriber.counters.storm_events.fetch_add(u32::try_from(before - events.len()).unwrap_or(u32::MAX), Ordering::Relaxed);
+            if let Some(report) = &storm {
+              subscriber.counters.storm_trips.fetch_add(1, Ordering::Relaxed);
+              subscriber.log(Level::Warn, || format!("stopped delivering events after {} within a second", report.event_count));
+            }
+
+            if !events.is_empty() || !warnings.is_empty() || storm.is_some() {
+              // Only measured when the batch is dispatched right away and its events are the ones reported by the OS watcher
+              let measured = muted == 0 && swallowed == 0 && lock(&subscriber.held).is_none();
+              let warnings = if warnings.is_empty() { None } else { Some(warnings) };
+              let event_id = history::resume_id(subscriber, oldest);
+              subscriber.deliver(WatchCallbackResult { events, warnings, storm, event_id, ..Default::default() });
+              if measured {
+                let dispatched = Instant::now();
+                for reported in &self.reported {
+                  subscriber.counters.latency.record(dispatched.saturating_duration_since(*reported));
+                }
+              }
+            }
+          });
+        }
+      }
+      Err(errors) => {
+        for subscriber in &subscribers {
+          // Errors about specific paths only concern the subscribers watching them
+          let mut errors: Vec<&notify::Error> = errors.iter().filter(|e| e.paths.is_empty() || e.paths.iter().any(|path| subscriber.covers(path))).collect();
+
+          if subscriber.ignore_permission_errors {
+            let before = errors.len();
+            errors.retain(|e| !is_permission_error(e));
+            let suppressed = u32::try_from(before - errors.len()).unwrap_or(u32::MAX);
+            subscriber.counters.suppressed_permission_errors.fetch_add(suppressed, Ordering::Relaxed);
+            if suppressed > 0 {
+              subscriber.log(Level::Debug, || format!("suppressed {} permission errors", suppressed));
+            }
+          }
+
+          if errors.is_empty() {
+            continue;
+          }
+
+          let error_msg = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; ");
+          if let Some(repeats) = &subscriber.repeats {
+            let seen = lock(repeats).see(&error_msg, Instant::now());
+            match seen {
+              Seen::New(summary) => {
+                if let Some(summary) = summary {
+                  subscriber.deliver(summary.into_batch());
+                }
+              }
+              Seen::Repeat { first, closes } => {
+                subscriber.log(Level::Debug, || format!("suppressed a repeat of: {}", error_msg));
+                if first && let Err(e) = repeats::summarize_at(subscriber, closes) {
+                  subscriber.log(Level::Warn, || format!("failed to schedule the summary of repeated errors: {}", e));
+                }
+                continue;
+              }
+            }
+          }
+          subscriber.log(Level::Error, || error_msg.clone());
+          subscriber.deliver(WatchCallbackResult { error: Some(Error::new(Status::GenericFailure, error_msg)), ..Default::default() });
+        }
+      }
+    }
+  }
+}
+
+/// Send a payload to the JS callback, numbering it with the next sequence number. With a ring, the events
+/// are written to it and the callback is called with the rest of the batch to signal them. A ring that blocks
+/// and fills up is signaled early, and the remaining events follow under the next sequence number.
+pub(crate) fn deliver(subscriber: &Subscriber, mut batch: WatchCallbackResult) -> Status {
+  if let Some(router) = &subscriber.router {
+    return router.dispatch(subscriber, next_seq(&subscriber.counters), batch);
+  }
+  let Some(ring) = &subscriber.ring else {
+    return dispatch(subscriber, next_seq(&subscriber.counters), batch);
+  };
+
+  let events = std::mem::take(&mut batch.events);
+  let mut remaining = &events[..];
+  loop {
+    let seq = next_seq(&subscriber.counters);
+    remaining = &remaining[ring.write(remaining, seq)..];
+    let Some(next) = remaining.first() else {
+      return dispatch(subscriber, seq, batch);
+    };
+
+    subscriber.log(Level::Debug, || format!("ring is full, waiting for the reader with {} events left", remaining.len()));
+    let status = dispatch(subscriber, seq, WatchCallbackResult::default());
+    if status != Status::Ok || !ring.wait_for_room(next.path.len(), &subscriber.running) {
+      return status;
+    }
+  }
+}
+
+/// Call the JS callback with `batch` as number `seq`
+fn dispatch(subscriber: &Subscriber, seq: u32, batch: WatchCallbackResult) -> Status {
+  call(subscriber, &subscriber.callback, seq, batch)
+}
+
+/// Call `callback`, the subscriber's own or one of its routes, with `batch` as number `seq`.
+/// Exceptions thrown by the callback are rethrown as uncaught exceptions that identify the batch.
+pub(crate) fn call(subscriber: &Subscriber, callback: &Callback, seq: u32, mut batch: WatchCallbackResult) -> Status {
+  let counters = &subscriber.counters;
+  batch.seq = seq;
+  // A node-style callback expects events or an error, not notifications such as heartbeats
+  if subscriber.serialization == Serialization::Node && batch.events.is_empty() && batch.error.is_none() {
+    return Status::Ok;
+  }
+  #[cfg(feature = "faults")]
+  if crate::faults::queue_full() {
+    return drop_batch(subscriber, seq, Status::QueueFull);
+  }
+  let queued = counters.in_flight_batches.fetch_add(1, Ordering::SeqCst) + 1;
+  counters.max_queued_batches.fetch_max(queued, Ordering::Relaxed);
+
+  let bytes = memory::batch_bytes(&batch);
+  counters.queued_bytes.fetch_add(bytes, Ordering::SeqCst);
+
+  let in_flight = Arc::clone(counters);
+  let payload = Payload::new(batch, subscriber.serialization);
+  let status = callback.call_with_return_value(payload, ThreadsafeFunctionCallMode::NonBlocking, move |result, _env| {
+    in_flight.in_flight_batches.fetch_sub(1, Ordering::SeqCst);
+    in_flight.queued_bytes.fetch_sub(bytes, Ordering::SeqCst);
+    result.map(|_| ()).map_err(|e| {
+      let mut error =
+        Error::new(Status::GenericFailure, format!("Watch callback threw while handling batch {}: {}", seq, e.reason.lines().next().unwrap_or_default()));
+      error.set_cause(e);
+      error
+    })
+  });
+
+  if status != Status::Ok {
+    counters.in_flight_batches.fetch_sub(1, Ordering::SeqCst);
+    counters.queued_bytes.fetch_sub(bytes, Ordering::SeqCst);
+    if status != Status::Closing {
+      return drop_batch(subscriber, seq, status);
+    }
+  }
+
+  status
+}
+
+/// Count batch number `seq` as dropped because the callback could not be called with it
+fn drop_batch(subscriber: &Subscriber, seq: u32, status: Status) -> Status {
+  subscriber.counters.dropped_batches.fetch_add(1, Ordering::Relaxed);
+  subscriber.log(Level::Warn, || format!("dropped batch {}, the callback could not be called: {}", seq, status));
+  status
+}
+
+/// Count the delivered events by type
+fn count_types(counters: &StatsCounters, events: &[WatchEvent]) {
+  let (mut created, mut updated, mut deleted, mut metadata) = (0, 0, 0, 0);
+  for event in events {
+    match event.event_type.as_str() {
+      "create" => created += 1,
+      "update" => updated += 1,
+      "metadata" => metadata += 1,
+      _ => deleted += 1,
+    }
+  }
+  counters.created_events.fetch_add(created, Ordering::Relaxed);
+  counters.updated_events.fetch_add(updated, Ordering::Relaxed);
+  counters.deleted_events.fetch_add(deleted, Ordering::Relaxed);
+  counters.metadata_events.fetch_add(metadata, Ordering::Relaxed);
+}
+
+/// Summary of a batch for the `onLog` hook
+fn describe_batch(batch: &WatchCallbackResult) -> String {
+  let mut description = format!("delivering {} events", batch.events.len());
+  if let Some(warnings) = &batch.warnings {
+    description.push_str(&format!(", {} warnings", warnings.len()));
+  }
+  if batch.error.is_some() {
+    description.push_str(", an error");
+  }
+  if let Some(initial) = &batch.initial {
+    description.push_str(&format!(" (initial scan, {})", initial));
+  } else if batch.synthetic == Some(true) {
+    description.push_str(" (rescan)");
+  } else if batch.heartbeat == Some(true) {
+    description.push_str(" (heartbeat)");
+  } else if batch.memory_shed.is_some() {
+    description.push_str(" (memory cap)");
+  } else if let Some(repeated) = batch.repeated {
+    description.push_str(&format!(" (error repeated {} times)", repeated));
+  } else if batch.historical == Some(true) {
+    description.push_str(" (history)");
+  } else if let Some(storm) = &batch.storm {
+    description.push_str(&format!(" (event storm, circuit {})", storm.state));
+  }
+  description
+}
+
+/// How long to wait for the events a debouncer with `timeout` holds to be delivered, a quarter more than the timeout
+/// for the tick of the debouncer that delivers them
+pub(crate) fn flush_delay(timeout: Duration) -> Duration {
+  timeout + timeout / 4
+}
+
+/// Sequence number of the next batch delivered to a subscription
+fn next_seq(counters: &StatsCounters) -> u32 {
+  counters.delivered_batches.fetch_add(1, Ordering::Relaxed).wrapping_add(1)
+}
+
+/// Run `work` for `subscriber`, stopping only that subscription with the panic as its error if it panics, so
+/// the others sharing the watcher keep receiving events
+fn isolate(subscriber: &Subscriber, work: impl FnOnce()) {
+  if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(work)) {
+    let message = panic_message(payload.as_ref());
+    subscriber.log(Level::Error, || format!("subscription stopped: {}", message));
+    subscriber.stop_with(&message);
+  }
+}
+
+/// Extract a readable message from a panic payload
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+  let detail = payload.downcast_ref::<&str>().copied().or_else(|| payload.downcast_ref::<String>().map(String::as_str)).unwrap_or("unknown panic");
+  format!("Watcher thread panicked: {}", detail)
+}
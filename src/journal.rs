@@ -0,0 +1,247 @@
+//! Journal of delivered batches persisted to disk, so a restarted consumer can resume where it stopped
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::snapshot::{self, FormatError, Reader, write_str};
+use crate::{PathBytes, WatchEvent};
+
+/// Leading bytes identifying a journal file
+const MAGIC: &[u8; 8] = b"WTCHJRNL";
+
+/// Version of the journal format, bumped whenever the layout changes
+const VERSION: u32 = 1;
+
+/// Size a journal file may grow to before it is rotated, unless configured otherwise
+pub(crate) const DEFAULT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// A batch read back from a journal
+pub(crate) struct Record {
+  pub cursor: u64,
+  pub events: Vec<WatchEvent>,
+}
+
+/// A journal file open for appending. Once the file would grow beyond `max_bytes` it is moved to
+/// `{path}.1`, replacing the previous one, and a new file is started, so at most two files are kept.
+pub(crate) struct Journal {
+  path: PathBuf,
+  root: String,
+  file: File,
+  len: u64,
+  max_bytes: u64,
+  cursor: u64,
+}
+
+impl Journal {
+  /// Open the journal at `path` for appending, continuing after `cursor`, the last one it contains
+  pub fn open(path: &Path, root: &str, cursor: u64, max_bytes: u64) -> io::Result<Journal> {
+    let file = match OpenOptions::new().append(true).open(path) {
+      Ok(file) => file,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => create(path, root)?,
+      Err(e) => return Err(e),
+    };
+    let len = file.metadata()?.len();
+
+    Ok(Journal { path: path.to_path_buf(), root: root.to_string(), file, len, max_bytes, cursor })
+  }
+
+  /// Append a batch, returning the cursor assigned to it
+  pub fn append(&mut self, events: &[WatchEvent]) -> io::Result<u64> {
+    let cursor = self.cursor + 1;
+    let record = encode_record(cursor, events);
+
+    if self.len > header_len(&self.root) && self.len + record.len() as u64 > self.max_bytes {
+      fs::rename(&self.path, rotated_path(&self.path))?;
+      self.file = create(&self.path, &self.root)?;
+      self.len = header_len(&self.root);
+    }
+
+    // A single write keeps a record in one piece as long as the process does not die halfway
+    self.file.write_all(&record)?;
+    self.len += record.len() as u64;
+    self.cursor = cursor;
+    Ok(cursor)
+  }
+}
+
+/// Read every batch still kept in the journal at `path`, oldest first, together with the root it was written for
+pub(crate) fn read(path: &Path) -> Result<(String, Vec<Record>), FormatError> {
+  let mut root = None;
+  let mut records = Vec::new();
+
+  for path in [rotated_path(path), path.to_path_buf()] {
+    let bytes = match snapshot::read(&path) {
+      Ok(bytes) => bytes,
+      Err(FormatError::NotFound) => continue,
+      Err(e) => return Err(e),
+    };
+
+    let (file_root, file_records) = decode(&bytes)?;
+    if root.as_ref().is_some_and(|root| *root != file_root) {
+      return Err(FormatError::Corrupt("rotated file belongs to another directory"));
+    }
+    root = Some(file_root);
+    records.extend(file_records);
+  }
+
+  if records.windows(2).any(|pair| pair[1].cursor <= pair[0].cursor) {
+    return Err(FormatError::Corrupt("cursors out of order"));
+  }
+
+  root.map(|root| (root, records)).ok_or(FormatError::NotFound)
+}
+
+/// Read the root a journal was written for, without reading its records
+pub(crate) fn root(path: &Path) -> Result<String, FormatError> {
+  let bytes = match snapshot::read(path) {
+    Err(FormatError::NotFound) => snapshot::read(&rotated_path(path))?,
+    result => result?,
+  };
+
+  read_header(&mut Reader { bytes: &bytes })
+}
+
+/// Path a journal file is moved to when it is rotated
+fn rotated_path(path: &Path) -> PathBuf {
+  let mut rotated = path.as_os_str().to_owned();
+  rotated.push(".1");
+  PathBuf::from(rotated)
+}
+
+/// Start a journal file consisting of only the header. The layout is the magic bytes, the format version
+/// and the root the journal is written for, followed by records.
+fn create(path: &Path, root: &str) -> io::Result<File> {
+  let mut header = Vec::with_capacity(header_len(root) as usize);
+  header.extend_from_slice(MAGIC);
+  header.extend_from_slice(&VERSION.to_le_bytes());
+  write_str(&mut header, root);
+
+  let mut file = OpenOptions::new().append(true).create(true).truncate(false).open(path)?;
+  file.write_all(&header)?;
+  Ok(file)
+}
+
+fn header_len(root: &str) -> u64 {
+  (MAGIC.len() + 8 + root.len()) as u64
+}
+
+/// Set in an event's flags when its path is stored as the bytes the OS reported, as it is not valid UTF-8
+const LOSSY: u8 = 2;
+
+/// Serialize a batch as its payload length, the checksum of the payload and the payload: the cursor,
+/// the number of events and every event's type, flags and path, with all numbers little-endian
+fn encode_record(cursor: u64, events: &[WatchEvent]) -> Vec<u8> {
+  let mut payload = Vec::with_capacity(12 + events.iter().map(|event| event.path.len() + 6).sum::<usize>());
+  payload.extend_from_slice(&cursor.to_le_bytes());
+  payload.extend_from_slice(&(events.len() as u32).to_le_bytes());
+
+  for event in events {
+    payload.push(match event.event_type.as_str() {
+      "create" => 0,
+      "update" => 1,
+      "metadata" => 3,
+      _ => 2,
+    });
+    match &event.path_bytes {
+      Some(PathBytes(bytes)) => {
+        payload.push(u8::from(event.is_initial) | LOSSY);
+        payload.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        payload.extend_from_slice(bytes);
+      }
+      None => {
+        payload.push(u8::from(event.is_initial));
+        write_str(&mut payload, &event.path);
+      }
+    }
+  }
+
+  let mut record = Vec::with_capacity(payload.len() + 12);
+  record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+  record.extend_from_slice(&checksum(&payload).to_le_bytes());
+  record.extend_from_slice(&payload);
+  record
+}
+
+fn read_header(reader: &mut Reader) -> Result<String, FormatError> {
+  if reader.take(MAGIC.len())? != MAGIC {
+    return Err(FormatError::Corrupt("not a journal file"));
+  }
+
+  let version = u32::from_le_bytes(reader.array()?);
+  if version != VERSION {
+    return Err(FormatError::UnsupportedVersion(version));
+  }
+
+  reader.string()
+}
+
+/// Parse one journal file, returning the root it was written for and its records
+fn decode(bytes: &[u8]) -> Result<(String, Vec<Record>), FormatError> {
+  let mut reader = Reader { bytes };
+  let root = read_header(&mut reader)?;
+  let mut records = Vec::new();
+
+  while !reader.bytes.is_empty() {
+    let len = u32::from_le_bytes(reader.array()?) as usize;
+    let expected = u64::from_le_bytes(reader.array()?);
+    let payload = reader.take(len)?;
+    if checksum(payload) != expected {
+      return Err(FormatError::Corrupt("checksum mismatch"));
+    }
+
+    let mut payload = Reader { bytes: payload };
+    let cursor = u64::from_le_bytes(payload.array()?);
+    let count = u32::from_le_bytes(payload.array()?);
+    let mut events = Vec::new();
+
+    for _ in 0..count {
+      let event_type = match payload.array::<1>()?[0] {
+        0 => "create",
+        1 => "update",
+        2 => "delete",
+        3 => "metadata",
+        _ => return Err(FormatError::Corrupt("unknown event type")),
+      };
+      let flags = payload.array::<1>()?[0];
+      let is_initial = flags & 1 != 0;
+      if flags & LOSSY == 0 {
+        events.push(WatchEvent::new(payload.string()?, event_type, is_initial));
+        continue;
+      }
+
+      let len = u32::from_le_bytes(payload.array()?) as usize;
+      let bytes = payload.take(len)?.to_vec();
+      let path = String::from_utf8_lossy(&bytes).into_owned();
+      events.push(WatchEvent {
+        path,
+        root: None,
+        event_type: event_type.to_string(),
+        is_initial,
+        lossy: Some(true),
+        path_bytes: Some(PathBytes(bytes)),
+        short_name: None,
+        raw_kind: None,
+        aliases: None,
+        coalesced_count: None,
+        first_seen_ms: None,
+        last_seen_ms: None,
+        file_type: None,
+        outside_root: None,
+        xattr: None,
+      });
+    }
+
+    if !payload.bytes.is_empty() {
+      return Err(FormatError::Corrupt("unexpected data after the last event of a record"));
+    }
+    records.push(Record { cursor, events });
+  }
+
+  Ok((root, records))
+}
+
+/// FNV-1a hash of a record's payload, to tell a damaged record from a valid one
+fn checksum(bytes: &[u8]) -> u64 {
+  bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
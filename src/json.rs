@@ -0,0 +1,182 @@
+//! JSON encoding of batches for subscriptions with `serialization: 'json'`, and of captured raw events
+
+use std::fmt::Write;
+
+use crate::storm::StormReport;
+use crate::{PathBytes, WatchCallbackResult, WatchEvent, WatchWarning};
+
+/// `batch` as its JS object would look like, without `error`, which cannot be represented in JSON
+pub(crate) fn encode_batch(batch: &WatchCallbackResult) -> String {
+  // Paths make up most of a batch, with some room left for the keys around them
+  let capacity = batch.events.iter().map(|event| event.path.len() + 48).sum::<usize>() + 64;
+  let mut json = String::with_capacity(capacity);
+
+  let _ = write!(json, "{{\"seq\":{},\"events\":[", batch.seq);
+  for (index, event) in batch.events.iter().enumerate() {
+    if index > 0 {
+      json.push(',');
+    }
+    write_event(&mut json, event);
+  }
+  json.push(']');
+
+  if let Some(warnings) = &batch.warnings {
+    json.push_str(",\"warnings\":[");
+    for (index, warning) in warnings.iter().enumerate() {
+      if index > 0 {
+        json.push(',');
+      }
+      write_warning(&mut json, warning);
+    }
+    json.push(']');
+  }
+  if let Some(initial) = &batch.initial {
+    json.push_str(",\"initial\":");
+    write_string(&mut json, initial);
+  }
+  if let Some(synthetic) = batch.synthetic {
+    let _ = write!(json, ",\"synthetic\":{}", synthetic);
+  }
+  if let Some(cursor) = batch.cursor {
+    let _ = write!(json, ",\"cursor\":{}", cursor);
+  }
+  if let Some(heartbeat) = batch.heartbeat {
+    let _ = write!(json, ",\"heartbeat\":{}", heartbeat);
+  }
+  if let Some(storm) = &batch.storm {
+    write_storm(&mut json, storm);
+  }
+  if let Some(shed) = &batch.memory_shed {
+    let _ = write!(
+      json,
+      ",\"memoryShed\":{{\"batches\":{},\"events\":{},\"cacheEntries\":{},\"bytes\":{}}}",
+      shed.batches, shed.events, shed.cache_entries, shed.bytes
+    );
+  }
+  if let Some(repeated) = batch.repeated {
+    let _ = write!(json, ",\"repeated\":{}", repeated);
+  }
+  if let Some(historical) = batch.historical {
+    let _ = write!(json, ",\"historical\":{}", historical);
+  }
+  if let Some(event_id) = batch.event_id {
+    let _ = write!(json, ",\"eventId\":{}", event_id);
+  }
+
+  json.push('}');
+  json
+}
+
+fn write_event(json: &mut String, event: &WatchEvent) {
+  json.push_str("{\"path\":");
+  write_string(json, &event.path);
+  if let Some(root) = &event.root {
+    json.push_str(",\"root\":");
+    write_string(json, root);
+  }
+  json.push_str(",\"type\":");
+  write_string(json, &event.event_type);
+  let _ = write!(json, ",\"isInitial\":{}", event.is_initial);
+  if event.short_name.is_some() {
+    json.push_str(",\"shortName\":true");
+  }
+  if let Some(raw_kind) = &event.raw_kind {
+    json.push_str(",\"rawKind\":");
+    write_string(json, raw_kind);
+  }
+  if let Some(aliases) = &event.aliases {
+    json.push_str(",\"aliases\":[");
+    for (index, alias) in aliases.iter().enumerate() {
+      if index > 0 {
+        json.push(',');
+      }
+      write_string(json, alias);
+    }
+    json.push(']');
+  }
+  if let Some(count) = event.coalesced_count {
+    let _ = write!(json, ",\"coalescedCount\":{}", count);
+  }
+  if let Some(first_seen_ms) = event.first_seen_ms {
+    let _ = write!(json, ",\"firstSeenMs\":{}", first_seen_ms);
+  }
+  if let Some(last_seen_ms) = event.last_seen_ms {
+    let _ = write!(json, ",\"lastSeenMs\":{}", last_seen_ms);
+  }
+  if let Some(file_type) = &event.file_type {
+    json.push_str(",\"fileType\":");
+    write_string(json, file_type);
+  }
+  if event.outside_root.is_some() {
+    json.push_str(",\"outsideRoot\":true");
+  }
+  if event.xattr.is_some() {
+    json.push_str(",\"xattr\":true");
+  }
+  // Like `JSON.stringify` of the event object, which turns a Buffer into its type and bytes
+  if let Some(PathBytes(bytes)) = &event.path_bytes {
+    json.push_str(",\"lossy\":true,\"pathBytes\":{\"type\":\"Buffer\",\"data\":[");
+    for (index, byte) in bytes.iter().enumerate() {
+      if index > 0 {
+        json.push(',');
+      }
+      let _ = write!(json, "{}", byte);
+    }
+    json.push_str("]}");
+  }
+  json.push('}');
+}
+
+fn write_storm(json: &mut String, storm: &StormReport) {
+  json.push_str(",\"storm\":{\"state\":");
+  write_string(json, &storm.state);
+  let _ = write!(json, ",\"eventCount\":{},\"directories\":[", storm.event_count);
+  for (index, directory) in storm.directories.iter().enumerate() {
+    if index > 0 {
+      json.push(',');
+    }
+    json.push_str("{\"path\":");
+    write_string(json, &directory.path);
+    let _ = write!(json, ",\"count\":{}}}", directory.count);
+  }
+  json.push_str("]}");
+}
+
+fn write_warning(json: &mut String, warning: &WatchWarning) {
+  json.push_str("{\"path\":");
+  write_string(json, &warning.path);
+  json.push_str(",\"kind\":");
+  write_string(json, &warning.kind);
+  json.push_str(",\"message\":");
+  write_string(json, &warning.message);
+  if let Some(file_system) = &warning.file_system {
+    json.push_str(",\"fileSystem\":");
+    write_string(json, file_system);
+  }
+  json.push('}');
+}
+
+/// `value` as a JSON string literal
+pub(crate) fn string(value: &str) -> String {
+  let mut json = String::with_capacity(value.len() + 2);
+  write_string(&mut json, value);
+  json
+}
+
+fn write_string(json: &mut String, value: &str) {
+  json.push('"');
+  for c in value.chars() {
+    match c {
+      '"' => json.push_str("\\\""),
+      '\\' => json.push_str("\\\\"),
+      '\n' => json.push_str("\\n"),
+      '\r' => json.push_str("\\r"),
+      '\t' => json.push_str("\\t"),
+      c if c < ' ' => {
+        let _ = write!(json, "\\u{:04x}", c as u32);
+      }
+      c => json.push(c),
+    }
+  }
+  json.push('"');
+}
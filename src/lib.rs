@@ -1,49 +1,789 @@
 #![deny(clippy::all)]
 
-use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{fs, io, thread};
 
-use globset::{Glob, GlobSet, GlobSetBuilder};
+use globset::GlobSet;
 use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionHandle};
+use napi::tokio::sync::oneshot;
 use napi::{Env, JsTypeError, ValueType};
 use napi_derive::napi;
-use notify::event::{CreateKind, ModifyKind, RemoveKind};
-use notify::{Config, EventKind, RecommendedWatcher, RecursiveMode};
-use notify_debouncer_full::{DebounceEventResult, new_debouncer};
+use watcher_core::{DEBOUNCE_TIMEOUT, IgnoreFilter, IgnoreSet, path_to_clean_string};
+
+use crate::capture::{Capture, TappedWatcher};
+use crate::containment::Containment;
+use crate::expectations::Expectations;
+use crate::file_ids::FileIds;
+use crate::hub::{Hub, Subscriber};
+use crate::journal::Journal;
+use crate::listeners::{Emitted, Listener};
+use crate::logging::{Level, LogHook, Logger};
+use crate::memory::{MemoryCap, MemoryShed};
+use crate::metrics::Latency;
+use crate::mock::{MockSource, RawEvent};
+use crate::mounts::Mounts;
+use crate::mutes::Mutes;
+use crate::network::Choice;
+use crate::normalization::Form;
+use crate::pull::{Overflow, PullBuffer};
+use crate::quiescence::Activity;
+use crate::registration::EntryLimit;
+use crate::repeats::Repeats;
+use crate::ring::{Ring, RingBuffer, RingReference};
+use crate::routes::Router;
+use crate::scan::Scanned;
+use crate::snapshot::{FormatError, Snapshot};
+use crate::special::SpecialFiles;
+use crate::storm::{Breaker, StormReport};
+use crate::unchanged::{Compare, Unchanged};
+use crate::validation::Problem;
+
+mod access;
+mod capture;
+mod case;
+mod coalesce;
+mod containment;
+mod expectations;
+#[cfg(feature = "faults")]
+mod faults;
+mod file_ids;
+mod heartbeat;
+mod history;
+mod hub;
+mod journal;
+mod json;
+mod listeners;
+mod locked;
+mod logging;
+mod long_paths;
+mod memory;
+mod metrics;
+mod mock;
+mod mounts;
+mod mutes;
+mod network;
+mod normalization;
+mod pending;
+mod platform;
+mod pull;
+mod quiescence;
+mod reconcile;
+mod registration;
+mod registry;
+mod repeats;
+mod ring;
+mod routes;
+mod scan;
+mod short_names;
+mod snapshot;
+mod special;
+mod storm;
+mod type_changes;
+mod unchanged;
+mod usn;
+mod validation;
+mod waiters;
+
+type Debouncer = notify_debouncer_full::Debouncer<TappedWatcher, FileIds>;
+
+/// Number of events or entries passed to the chunk callbacks of `diffTrees` and `getTree` at a time
+const DIFF_CHUNK_SIZE: usize = 10_000;
+
+static NEXT_MUTE_ID: AtomicU64 = AtomicU64::new(1);
+
+static NEXT_LISTENER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// How long `expectChange` waits for an announced change unless told otherwise
+const EXPECT_CHANGE_WINDOW: Duration = Duration::from_secs(1);
+
+/// `code` of the error thrown for a tree with more entries than `maxWatchedEntries`
+const TOO_MANY_ENTRIES_CODE: &str = "TOO_MANY_ENTRIES";
+
+/// `code` of the error thrown for a filesystem root or the home directory without `allowLargeRoots`
+const LARGE_ROOT_CODE: &str = "LARGE_ROOT";
+
+/// Time an FSEvents stream needs after being started before it reliably reports changes
+const READY_DELAY: Duration = if cfg!(target_os = "macos") { Duration::from_millis(50) } else { Duration::ZERO };
+
+type Callback = ThreadsafeFunction<Payload, Unknown<'static>, Payload, Status, false>;
 
 /// A file system event
 #[napi(object)]
 #[derive(Debug, Clone)]
 pub struct WatchEvent {
   pub path: String,
-  #[napi(js_name = "type", ts_type = "'create' | 'update' | 'delete'")]
+  /// Watched directory the event belongs to, as it was resolved when subscribing
+  pub root: Option<String>,
+  #[napi(js_name = "type", ts_type = "'create' | 'update' | 'delete' | 'metadata'")]
   pub event_type: String,
+  /// Whether the event comes from the initial scan rather than a live change
+  pub is_initial: bool,
+  /// Set when the path is not valid UTF-8, so that `path` has replacement characters in place of the invalid bytes
+  pub lossy: Option<bool>,
+  /// The path as the OS reported it, set along with `lossy`
+  #[napi(ts_type = "Buffer")]
+  pub path_bytes: Option<PathBytes>,
+  /// Set on Windows when the path has 8.3 short names that could not be resolved to long ones, for example
+  /// because the file was deleted
+  pub short_name: Option<bool>,
+  /// The kind of event notify reported, such as `Modify(Name(From))`, with `rawKinds`. Kinds of events merged
+  /// into this one are joined with `+`.
+  pub raw_kind: Option<String>,
+  /// With `dedupeHardlinks`, the other paths of the same file that were changed along with this one
+  pub aliases: Option<Vec<String>>,
+  /// With `trackCoalescing`, how many events the OS watcher reported for the path before this one was delivered
+  pub coalesced_count: Option<u32>,
+  /// With `trackCoalescing`, when the first of those events was received, in milliseconds since the Unix epoch
+  pub first_seen_ms: Option<f64>,
+  /// With `trackCoalescing`, when the last of those events was received, in milliseconds since the Unix epoch
+  pub last_seen_ms: Option<f64>,
+  /// With `detectTypeChanges`, the type of the entry a `create` brought or a `delete` took away
+  #[napi(ts_type = "'file' | 'directory' | 'symlink'")]
+  pub file_type: Option<String>,
+  /// With `flagOutsideRoot`, set when the path resolves to one outside the watched directory through a symlink
+  pub outside_root: Option<bool>,
+  /// With `emitXattr`, set when the event stands for a change of the path's extended attributes
+  pub xattr: Option<bool>,
 }
 
-/// Options for configuring the watcher
+impl WatchEvent {
+  /// An event with a path that may not be valid UTF-8, keeping its bytes when it is not
+  pub(crate) fn at(path: &Path, event_type: &str, is_initial: bool) -> WatchEvent {
+    let path_bytes = lossy_path_bytes(path).map(PathBytes);
+    let lossy = path_bytes.is_some().then_some(true);
+    WatchEvent {
+      path: path_to_clean_string(path),
+      root: None,
+      event_type: event_type.to_string(),
+      is_initial,
+      lossy,
+      path_bytes,
+      short_name: None,
+      raw_kind: None,
+      aliases: None,
+      coalesced_count: None,
+      first_seen_ms: None,
+      last_seen_ms: None,
+      file_type: None,
+      outside_root: None,
+      xattr: None,
+    }
+  }
+
+  /// An event with a path known to be valid UTF-8
+  pub(crate) fn new(path: String, event_type: &str, is_initial: bool) -> WatchEvent {
+    WatchEvent {
+      path,
+      root: None,
+      event_type: event_type.to_string(),
+      is_initial,
+      lossy: None,
+      path_bytes: None,
+      short_name: None,
+      raw_kind: None,
+      aliases: None,
+      coalesced_count: None,
+      first_seen_ms: None,
+      last_seen_ms: None,
+      file_type: None,
+      outside_root: None,
+      xattr: None,
+    }
+  }
+
+  /// Take in a later event for the same path that this one stands for from now on, joining the kinds notify
+  /// reported for both and counting the raw events behind both
+  pub(crate) fn absorb(&mut self, later: &mut WatchEvent) {
+    self.raw_kind = match (self.raw_kind.take(), later.raw_kind.take()) {
+      (Some(first), Some(then)) => Some(format!("{}+{}", first, then)),
+      (first, then) => first.or(then),
+    };
+    self.xattr = self.xattr.or(later.xattr);
+    self.add_seen(later);
+  }
+
+  /// Count the raw events behind `other` with those behind this event, widening the time they span
+  pub(crate) fn add_seen(&mut self, other: &WatchEvent) {
+    if let Some(count) = other.coalesced_count {
+      self.coalesced_count = Some(self.coalesced_count.unwrap_or(0) + count);
+    }
+    self.first_seen_ms = match (self.first_seen_ms, other.first_seen_ms) {
+      (Some(first), Some(then)) => Some(first.min(then)),
+      (first, then) => first.or(then),
+    };
+    self.last_seen_ms = match (self.last_seen_ms, other.last_seen_ms) {
+      (Some(first), Some(then)) => Some(first.max(then)),
+      (first, then) => first.or(then),
+    };
+  }
+}
+
+/// The bytes of a path that is not valid UTF-8, which `path_to_clean_string` cannot convert without loss.
+/// These are the raw bytes of the name on Unix, and WTF-8 with unpaired surrogates on Windows.
+fn lossy_path_bytes(path: &Path) -> Option<Vec<u8>> {
+  if path.to_str().is_some() {
+    return None;
+  }
+
+  let bytes = path.as_os_str().as_encoded_bytes();
+  // Strip the \\?\ prefix on Windows, like `path_to_clean_string`
+  #[cfg(windows)]
+  if let Some(stripped) = bytes.strip_prefix(br"\\?\") {
+    return Some(match stripped.strip_prefix(br"UNC\") {
+      Some(share) => [br"\\".as_slice(), share].concat(),
+      None => stripped.to_vec(),
+    });
+  }
+  Some(bytes.to_vec())
+}
+
+/// The raw bytes of a path, handed to JS as a `Buffer`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathBytes(pub Vec<u8>);
+
+impl ToNapiValue for PathBytes {
+  unsafe fn to_napi_value(env: napi::sys::napi_env, bytes: PathBytes) -> Result<napi::sys::napi_value> {
+    unsafe { Buffer::to_napi_value(env, Buffer::from(bytes.0)) }
+  }
+}
+
+impl FromNapiValue for PathBytes {
+  unsafe fn from_napi_value(env: napi::sys::napi_env, value: napi::sys::napi_value) -> Result<PathBytes> {
+    Ok(PathBytes(unsafe { Buffer::from_napi_value(env, value) }?.to_vec()))
+  }
+}
+
+/// Retry policy for establishing the watch
 #[napi(object)]
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
+pub struct RetryOptions {
+  /// Maximum number of attempts, including the first one
+  pub attempts: u32,
+  /// Delay before the first retry in milliseconds
+  pub delay_ms: u32,
+  /// Factor the delay is multiplied by after each failed attempt, up to 10 seconds (defaults to 1)
+  pub backoff: Option<f64>,
+}
+
+/// When `circuitBreaker` stops delivery and when it resumes
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerOptions {
+  /// Most events delivered within a second before delivery stops
+  pub max_events_per_second: u32,
+  /// Resume delivery once no second had more than `maxEventsPerSecond` events for this many milliseconds.
+  /// Without it, delivery stays stopped until `resume()`.
+  pub cooldown_ms: Option<u32>,
+}
+
+/// Options for configuring the watcher
+#[napi(object, object_to_js = false)]
+#[derive(Default)]
 pub struct WatchOptions {
   /// Patterns to ignore (file paths or glob patterns)
   pub ignore: Option<Vec<String>>,
+  /// Retry transient failures while establishing the watch (no retry by default)
+  pub retry: Option<RetryOptions>,
+  /// Fail instead of skipping subdirectories that cannot be watched due to permissions
+  pub strict: Option<bool>,
+  /// Watch a filesystem root such as `/` or `C:\\`, or the home directory, which is refused with a `LARGE_ROOT`
+  /// error otherwise (false by default)
+  pub allow_large_roots: Option<bool>,
+  /// Leave out directories on another file system than the watched directory, such as mounts of network shares
+  /// or a tmpfs, with everything below them, reporting each with an `other-file-system` warning (false by default)
+  pub one_file_system: Option<bool>,
+  /// Leave permission errors out of error reporting (they are still counted in `stats()`)
+  pub ignore_permission_errors: Option<bool>,
+  /// Warn through `onLog`, or on stderr without it, when the subscription is garbage collected without being unsubscribed
+  pub warn_if_leaked: Option<bool>,
+  /// Use a dedicated OS watcher and dispatch thread instead of the ones shared with other subscriptions
+  pub isolated: Option<bool>,
+  /// Watcher the events come from: the native one, or scanning the tree every `pollIntervalMs` (`'polling'`).
+  /// `'auto'`, the default, polls directories on network shares and host mounts, where native watching misses changes.
+  #[napi(ts_type = "'auto' | 'native' | 'polling' | 'kqueue' | 'mock'")]
+  pub backend: Option<String>,
+  /// Milliseconds between scans of a polling subscription (1000 by default)
+  pub poll_interval_ms: Option<u32>,
+  /// How a polling subscription tells that a file changed: by its modification time, which the poller keeps in
+  /// whole seconds (`'mtime'`, the default), or also by a hash of its contents, read on every scan (`'contents'`)
+  #[napi(ts_type = "'mtime' | 'contents'")]
+  pub poll_compare: Option<String>,
+  /// With `backend: 'auto'`, poll directories on file systems where native watching misses changes (true by
+  /// default). When false they are watched natively with an `unreliable-file-system` warning instead.
+  pub auto_fallback: Option<bool>,
+  /// Also walk the tree every this many milliseconds and deliver what the watcher missed as synthetic events
+  pub reconcile_interval_ms: Option<u32>,
+  /// Send an empty batch with `heartbeat: true` whenever no batch has been delivered for this many milliseconds
+  pub heartbeat_ms: Option<u32>,
+  /// Milliseconds the debouncer waits for further events of a path before delivering them (100 by default). A
+  /// subscription with this option gets a watcher of its own, like an `isolated` one, and can change it with
+  /// `setDebounce()`.
+  pub debounce_ms: Option<u32>,
+  /// Deliver an error of the OS watcher that repeats the last one within this many milliseconds of it only once,
+  /// with a summary counting the repeats when a different error comes or the window closes. 0 delivers every
+  /// repeat (30000 by default)
+  pub repeated_error_window_ms: Option<u32>,
+  /// Retry reading the metadata of a changed file another process briefly holds locked, as antivirus and
+  /// indexing services do on Windows (3 attempts 25ms apart by default)
+  pub locked_file_retry: Option<RetryOptions>,
+  /// Stop delivering events when more than `maxEventsPerSecond` arrive within a second, announcing it with a
+  /// batch carrying `storm` and only counting the events by directory until delivery resumes
+  pub circuit_breaker: Option<CircuitBreakerOptions>,
+  /// Register the tree again when read access to the directory is regained after an `access-lost` warning
+  /// (true by default)
+  pub rewatch_on_access: Option<bool>,
+  /// Report changes of permissions, owner or times as `metadata` events instead of `update` (false by default)
+  pub emit_metadata: Option<bool>,
+  /// Report changes of extended attributes as `metadata` events marked with `xattr`, where the OS watcher tells
+  /// them apart from other changes (false by default)
+  pub emit_xattr: Option<bool>,
+  /// Report a path created and then changed within one batch with its `create` event alone (false by default)
+  pub coalesce: Option<bool>,
+  /// Deliver the same canonical sequence of events for the same changes on every platform: at most one event
+  /// per path and batch, no updates of directories for changes of their entries, and deletes, creates and
+  /// updates in that order (false by default)
+  pub normalize: Option<bool>,
+  /// Drop the `update` a directory gets when an entry of it is created, deleted or renamed in the same batch,
+  /// keeping updates of directories nothing else in the batch explains (false by default, true with `normalize`)
+  pub drop_parent_updates: Option<bool>,
+  /// Report a path that turned from a file into a directory or back as a `delete` of the old entry and a
+  /// `create` of the new one, and give creates and deletes their `fileType`. Needs the state of the tree
+  /// `emitInitial`, `reconcileIntervalMs` or `triggerRescan()` records (false by default)
+  pub detect_type_changes: Option<bool>,
+  /// Report the updates of one file changed through several hardlinks within a batch once, with the other
+  /// paths in `aliases` (false by default)
+  pub dedupe_hardlinks: Option<bool>,
+  /// Leave out FIFOs, sockets and device nodes, reporting only regular files, directories and symlinks (false
+  /// by default)
+  pub skip_special_files: Option<bool>,
+  /// Deliver events whose path is the watched directory itself, such as changes of its permissions or times
+  /// (true by default)
+  pub include_root: Option<bool>,
+  /// Deliver events whose path resolves to one outside the watched directory through a symlink with
+  /// `outsideRoot: true` instead of dropping them (false by default)
+  pub flag_outside_root: Option<bool>,
+  /// Attach the kind of event notify reported to every event it reported as `rawKind`, for diagnosing
+  /// platform differences (false by default)
+  pub raw_kinds: Option<bool>,
+  /// Drop updates that leave a file as it was last seen: with the same modification time (`'mtime'`), size and
+  /// modification time (`'size+mtime'`), or size and contents (`'hash'`). Creates and deletes are always
+  /// delivered, as is the first update of a file the subscription has not seen yet (no updates are dropped by default)
+  #[napi(ts_type = "'mtime' | 'size+mtime' | 'hash'")]
+  pub skip_unchanged: Option<String>,
+  /// Attach to every event from the OS watcher how many raw events it stands for as `coalescedCount`, and when
+  /// the first and last of them were received as `firstSeenMs` and `lastSeenMs`, for tuning (false by default)
+  pub track_coalescing: Option<bool>,
+  /// Start by delivering create events for everything already in the directory
+  pub emit_initial: Option<bool>,
+  /// Start by delivering the changes FSEvents recorded since this event id, the `eventId` of the last batch
+  /// handled, in a batch with `historical`. Only on macOS with the native watcher.
+  pub since_event_id: Option<i64>,
+  /// With `writeSnapshot` and `getEventsSince` on Windows, read what changed since the snapshot from the NTFS
+  /// change journal instead of walking the tree, where the volume keeps one and it can be read (false by default)
+  pub change_journal: Option<bool>,
+  /// Append every delivered batch to a journal at this path, for `subscribeSince` to resume from
+  pub journal_path: Option<String>,
+  /// Size in bytes the journal may grow to before it is rotated (64 MiB by default)
+  pub journal_max_bytes: Option<u32>,
+  /// Buffer batches to be read with `nextBatch` instead of passing them to a callback. `false` without a callback
+  /// leaves the events to the listeners added with `on()`.
+  pub pull: Option<bool>,
+  /// Name shown for the subscription by `listSubscriptions`
+  pub label: Option<String>,
+  /// Called with diagnostics about what the watcher observed, filtered and delivered
+  #[napi(ts_type = "(level: 'error' | 'warn' | 'info' | 'debug' | 'trace', message: string) => void")]
+  pub on_log: Option<LogHook>,
+  /// Least severe level passed to `onLog` (`'info'` by default)
+  #[napi(ts_type = "'error' | 'warn' | 'info' | 'debug' | 'trace'")]
+  pub log_level: Option<String>,
+  /// Append every event the OS watcher reports to an NDJSON file at this path, before debouncing and filtering
+  pub debug_event_log: Option<String>,
+  /// Pass batches to the callback as JSON strings to parse instead of objects (`'object'` by default)
+  #[napi(ts_type = "'object' | 'json'")]
+  pub serialization: Option<String>,
+  /// Call the callback as `@parcel/watcher` does, with an error or `null` and the events, instead of with the
+  /// batch (`'object'` by default)
+  #[napi(ts_type = "'object' | 'node'")]
+  pub callback_style: Option<String>,
+  /// Write events to this buffer from `createEventRing` instead of passing them to the callback, which is only
+  /// called to signal them
+  #[napi(ts_type = "SharedArrayBuffer")]
+  pub ring: Option<RingBuffer>,
+  /// What happens when the reader of `ring` falls behind: the oldest records are overwritten (`'overwrite'`, the
+  /// default), or dispatching waits for the reader (`'block'`)
+  #[napi(ts_type = "'overwrite' | 'block'")]
+  pub ring_overflow: Option<String>,
+  /// Which routes of `subscribeRouted` an event goes to: the first whose pattern it matches (`'first'`, the
+  /// default), or every one (`'all'`)
+  #[napi(ts_type = "'first' | 'all'")]
+  pub route_match: Option<String>,
+  /// Most paths whose file id the watcher keeps to pair the halves of renames, 0 to keep none (no limit by default)
+  pub cache_limit: Option<u32>,
+  /// Most files and directories the tree may have, counted while it is registered and by the initial scan, to
+  /// guard against watching a much larger tree than intended (no limit by default)
+  pub max_watched_entries: Option<u32>,
+  /// What happens when the tree has more than `maxWatchedEntries`: subscribing fails with a `TOO_MANY_ENTRIES`
+  /// error before anything is watched (`'fail'`, the default), or the tree is watched with a warning (`'warn'`)
+  #[napi(ts_type = "'fail' | 'warn'")]
+  pub max_watched_entries_action: Option<String>,
+  /// Most memory in bytes the subscription may hold natively, in batches waiting for the callback or to be read
+  /// and in the file id cache, as estimated (no limit by default)
+  pub max_memory_bytes: Option<f64>,
+  /// What happens when a batch would take the subscription over `maxMemoryBytes`: the oldest batches waiting to
+  /// be read, then file id cache entries, then the batch itself are dropped, reported with an error carrying
+  /// `memoryShed` (`'shed'`, the default), or the subscription stops with an error (`'close'`)
+  #[napi(ts_type = "'shed' | 'close'")]
+  pub max_memory_action: Option<String>,
+  /// Unicode normalization form of the part below the directory of event paths, with ignore patterns matching
+  /// names in either form (`'none'` by default, which leaves both alone). Only applied on macOS.
+  #[napi(ts_type = "'nfc' | 'nfd' | 'none'")]
+  pub unicode_normalization: Option<String>,
+}
+
+/// A non-fatal problem encountered while watching
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct WatchWarning {
+  pub path: String,
+  #[napi(
+    ts_type = "'permission-denied' | 'capture-failed' | 'too-many-entries' | 'unreliable-file-system' | 'backend-fallback' | 'access-lost' | 'access-regained' | 'symlink-cycle' | 'history-unavailable' | 'other-file-system'"
+  )]
+  pub kind: String,
+  pub message: String,
+  /// The file system an `unreliable-file-system` warning is about, such as `nfs` or `9p`
+  pub file_system: Option<String>,
+}
+
+/// An entry found by `scan`
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct ScanEntry {
+  pub path: String,
+  #[napi(ts_type = "'file' | 'directory' | 'symlink'")]
+  pub file_type: String,
+  /// Last modification time in milliseconds since the Unix epoch
+  pub mtime_ms: f64,
+  /// Size in bytes
+  pub size: f64,
 }
 
 /// Callback result type for the watcher
 #[napi(object, object_from_js = false)]
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct WatchCallbackResult {
+  /// Sequence number of this callback invocation, starting at 1
+  pub seq: u32,
   pub error: Option<Error>,
   pub events: Vec<WatchEvent>,
+  pub warnings: Option<Vec<WatchWarning>>,
+  /// Set on batches of the initial scan, `'complete'` on the last one
+  #[napi(ts_type = "'partial' | 'complete'")]
+  pub initial: Option<String>,
+  /// Set on batches produced by `triggerRescan` rather than by the OS watcher
+  pub synthetic: Option<bool>,
+  /// Position of the batch in the journal, to pass to `subscribeSince` after a restart
+  pub cursor: Option<i64>,
+  /// Set on the empty batches `heartbeatMs` sends while nothing else was delivered
+  pub heartbeat: Option<bool>,
+  /// Set on the batch that stands for the events dropped because too many were waiting to be read
+  pub overflowed: Option<bool>,
+  /// Set on the empty batches announcing that `circuitBreaker` stopped or resumed delivery
+  pub storm: Option<StormReport>,
+  /// Set along with `error` on the batch reporting what was dropped to stay within `maxMemoryBytes`
+  pub memory_shed: Option<MemoryShed>,
+  /// Set along with `error` on the batch summarizing how many times the error before it came again within
+  /// `repeatedErrorWindowMs` without being delivered
+  pub repeated: Option<u32>,
+  /// Set on the batch of the changes FSEvents recorded since `sinceEventId`, delivered before any live batch
+  pub historical: Option<bool>,
+  /// FSEvents event id to pass as `sinceEventId` to resume after this batch, on macOS with the native watcher
+  pub event_id: Option<i64>,
+}
+
+/// Callback result of a subscription with `serialization: 'json'`
+#[napi(object, object_from_js = false)]
+#[derive(Debug, Default)]
+pub struct WatchCallbackJson {
+  pub error: Option<Error>,
+  /// The `WatchCallbackResult` of the batch without `error`, serialized to JSON
+  pub json: String,
+}
+
+/// An event the way `@parcel/watcher` reports it, for `callbackStyle: 'node'`
+#[napi(object, object_from_js = false)]
+#[derive(Debug)]
+pub struct ParcelEvent {
+  pub path: String,
+  #[napi(js_name = "type", ts_type = "'create' | 'update' | 'delete' | 'metadata'")]
+  pub event_type: String,
+}
+
+/// How batches are passed to the JS callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Serialization {
+  Object,
+  Json,
+  /// As an error or `null` and the events, from `callbackStyle: 'node'`
+  Node,
+}
+
+impl Serialization {
+  fn parse(name: &str) -> Option<Serialization> {
+    match name {
+      "object" => Some(Serialization::Object),
+      "json" => Some(Serialization::Json),
+      _ => None,
+    }
+  }
+}
+
+/// What the JS callback is called with
+pub enum Payload {
+  Object(WatchCallbackResult),
+  Json(WatchCallbackJson),
+  Node(Option<Error>, Vec<ParcelEvent>),
+}
+
+impl Payload {
+  pub(crate) fn new(batch: WatchCallbackResult, serialization: Serialization) -> Payload {
+    match serialization {
+      Serialization::Object => Payload::Object(batch),
+      Serialization::Json => Payload::Json(WatchCallbackJson { json: json::encode_batch(&batch), error: batch.error }),
+      Serialization::Node => {
+        let events = batch.events.into_iter().map(|event| ParcelEvent { path: event.path, event_type: event.event_type }).collect();
+        Payload::Node(batch.error, events)
+      }
+    }
+  }
+}
+
+impl JsValuesTupleIntoVec for Payload {
+  // The trait is not unsafe, `env` is the one napi calls the callback in
+  #[allow(clippy::not_unsafe_ptr_arg_deref)]
+  fn into_vec(self, env: napi::sys::napi_env) -> Result<Vec<napi::sys::napi_value>> {
+    match self {
+      Payload::Object(batch) => Ok(vec![unsafe { WatchCallbackResult::to_napi_value(env, batch)? }]),
+      Payload::Json(batch) => Ok(vec![unsafe { WatchCallbackJson::to_napi_value(env, batch)? }]),
+      Payload::Node(error, events) => {
+        Ok(vec![unsafe { Option::<Error>::to_napi_value(env, error)? }, unsafe { Vec::<ParcelEvent>::to_napi_value(env, events)? }])
+      }
+    }
+  }
+}
+
+/// Statistics about a subscription
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct SubscriptionStats {
+  /// Number of permission errors left out of error reporting by `ignorePermissionErrors`
+  pub suppressed_permission_errors: u32,
+  /// Number of events swallowed because they were announced with `expectChange`
+  pub suppressed_expected_changes: u32,
+  /// Number of events dropped because their path was muted
+  pub muted_events: u32,
+  /// Number of updates dropped by `skipUnchanged` because they left the file as it was
+  pub suppressed_unchanged: u32,
+  /// Shortest time from the OS watcher reporting an event to its batch being dispatched to the callback, in milliseconds
+  pub latency_min_ms: Option<f64>,
+  /// Average time from the OS watcher reporting an event to its batch being dispatched, in milliseconds
+  pub latency_avg_ms: Option<f64>,
+  /// Longest time from the OS watcher reporting an event to its batch being dispatched, in milliseconds
+  pub latency_max_ms: Option<f64>,
+  /// Events by latency: below 1ms, then doubling from 1-2ms up to 8-16s, then 16s and more
+  pub latency_histogram: Vec<u32>,
+  /// Batches waiting for the callback to handle them, or in the buffer of a subscription without a callback
+  pub queued_batches: u32,
+  /// Most batches that were waiting at once
+  pub max_queued_batches: u32,
+  /// Batches dropped because the callback could not be called with them, such as with the N-API queue full
+  pub dropped_batches: u32,
+  /// Number of create events delivered
+  pub created_events: u32,
+  /// Number of update events delivered
+  pub updated_events: u32,
+  /// Number of delete events delivered
+  pub deleted_events: u32,
+  /// Number of metadata events delivered, with `emitMetadata`
+  pub metadata_events: u32,
+  /// Paths whose file id the watcher keeps to pair the halves of renames, for all subscriptions sharing it
+  pub cache_entries: u32,
+  /// Number of times reading the metadata of a file another process held locked was attempted again
+  pub locked_retries: u32,
+  /// Number of locked files whose metadata could still not be read after `lockedFileRetry` ran out
+  pub locked_failures: u32,
+  /// Number of times `circuitBreaker` stopped delivery
+  pub storm_trips: u32,
+  /// Number of events left undelivered while `circuitBreaker` had delivery stopped
+  pub storm_events: u32,
+  /// Estimated memory the subscription holds natively in batches and the file id cache, in bytes
+  pub memory_bytes: f64,
+}
+
+/// A live subscription as listed by `listSubscriptions`
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct SubscriptionInfo {
+  /// Same as the subscription's `id`
+  pub id: u32,
+  /// The watched directory, with symlinks resolved
+  pub path: String,
+  /// The `label` option the subscription was created with
+  pub label: Option<String>,
+  /// Watcher implementation the subscription's events come from
+  #[napi(ts_type = "'inotify' | 'fsevents' | 'windows' | 'kqueue' | 'polling' | 'mock'")]
+  pub backend: String,
+  /// Why the subscription polls instead of using the native watcher, if it does
+  pub backend_reason: Option<String>,
+  /// Number of events delivered to the subscription so far
+  pub event_count: u32,
+  /// When the subscription was created, in milliseconds since the Unix epoch
+  pub created_at: f64,
+}
+
+/// Which event `next` and `once` wait for
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct NextOptions {
+  /// Only settle for events of these types (any type by default)
+  #[napi(ts_type = "Array<'create' | 'update' | 'delete' | 'metadata'>")]
+  pub types: Option<Vec<String>>,
+  /// Reject with an `ETIMEDOUT` error if no matching event arrives within this many milliseconds
+  pub timeout_ms: Option<u32>,
+}
+
+/// What `waitForFile` waits for
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct WaitForFileOptions {
+  /// `create` waits for the file to exist, `update` for its next change and `any` (the default) for either
+  #[napi(ts_type = "'create' | 'update' | 'any'")]
+  pub event: Option<String>,
+  /// Reject with an `ETIMEDOUT` error if the file does not appear or change within this many milliseconds
+  pub timeout_ms: Option<u32>,
+  /// With `update`, resolve right away if the file was modified after this time, in milliseconds since the epoch
+  pub newer_than: Option<f64>,
+}
+
+/// What the file system watcher backend of this platform can do
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+  /// Where events come from
+  #[napi(ts_type = "'inotify' | 'fsevents' | 'windows' | 'kqueue' | 'polling'")]
+  pub backend: String,
+  /// Whether the OS watches whole trees itself, instead of one watch per directory
+  pub native_recursive: bool,
+  /// Whether the two halves of a rename carry a cookie that pairs them
+  pub supports_cookies: bool,
+  /// Whether files have an id that survives renames, used by `getEventsSince` to notice files replaced since the snapshot
+  pub supports_file_ids: bool,
+  /// Number of directories one user may watch at a time, where the OS sets such a limit
+  pub watch_limit: Option<u32>,
+  /// Version of the `notify` crate the watcher is built with
+  pub notify_version: String,
+}
+
+/// A callback of `subscribeRouted` and the events it is called with
+#[napi(object, object_to_js = false)]
+pub struct Route {
+  /// Pattern the paths of the events for the callback match, relative to the directory or as a whole. The one
+  /// route without a pattern is called with the events no other route takes.
+  pub pattern: Option<String>,
+  #[napi(ts_type = "(result: WatchCallbackResult | WatchCallbackJson) => void")]
+  pub callback: Callback,
+}
+
+/// A problem with a directory or options found by `validateOptions`
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct OptionsProblem {
+  /// What is wrong
+  #[napi(
+    ts_type = "'EMPTY_DIRECTORY' | 'DIRECTORY_NOT_FOUND' | 'NOT_A_DIRECTORY' | 'DIRECTORY_UNREADABLE' | 'LARGE_ROOT' | 'INVALID_PATTERN' | 'INVALID_RETRY' | 'INVALID_LOG_LEVEL' | 'INVALID_SERIALIZATION' | 'INVALID_JOURNAL' | 'INVALID_RING' | 'INVALID_MAX_WATCHED_ENTRIES' | 'INVALID_UNICODE_NORMALIZATION' | 'INVALID_BACKEND' | 'INVALID_RECONCILE_INTERVAL' | 'INVALID_HEARTBEAT_INTERVAL' | 'INVALID_DEBOUNCE' | 'INVALID_SINCE_EVENT_ID' | 'INVALID_ROUTE_MATCH' | 'INVALID_SKIP_UNCHANGED' | 'INVALID_POLL_COMPARE'"
+  )]
+  pub code: String,
+  /// The directory, pattern or option at fault
+  pub input: String,
+  /// The error `subscribe` would throw for it
+  pub message: String,
+}
+
+/// Result of `validateOptions`
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct OptionsReport {
+  /// Whether no problems were found
+  pub valid: bool,
+  /// The directory as it would be watched, with symlinks resolved, if it could be resolved
+  pub directory: Option<String>,
+  pub problems: Vec<OptionsProblem>,
+}
+
+/// Which events `waitForQuiescence` counts
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct QuiescenceOptions {
+  /// Count events for paths matched by the ignore patterns as well, instead of only the events that are delivered
+  pub include_ignored: Option<bool>,
+}
+
+/// Number of synthetic events delivered by `triggerRescan`
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct RescanResult {
+  pub created: u32,
+  pub updated: u32,
+  pub deleted: u32,
+}
+
+/// Counters shared between a subscription and its watcher thread
+#[derive(Default)]
+struct StatsCounters {
+  suppressed_permission_errors: AtomicU32,
+  suppressed_expected_changes: AtomicU32,
+  muted_events: AtomicU32,
+  suppressed_unchanged: AtomicU32,
+  delivered_batches: AtomicU32,
+  /// Events delivered to the callback or buffered for `nextBatch`, for `listSubscriptions`
+  delivered_events: AtomicU32,
+  /// Batches queued for the JS callback that have not been handled yet
+  in_flight_batches: AtomicU32,
+  /// Estimated memory of those batches
+  queued_bytes: AtomicUsize,
+  /// Most batches waiting for the callback or in the pull buffer at once since the stats were reset
+  max_queued_batches: AtomicU32,
+  /// Batches the callback could not be called with
+  dropped_batches: AtomicU32,
+  created_events: AtomicU32,
+  updated_events: AtomicU32,
+  deleted_events: AtomicU32,
+  metadata_events: AtomicU32,
+  /// Reads of the metadata of a locked file that were attempted again
+  locked_retries: AtomicU32,
+  /// Locked files whose metadata could not be read before the retries ran out
+  locked_failures: AtomicU32,
+  storm_trips: AtomicU32,
+  storm_events: AtomicU32,
+  latency: Latency,
 }
 
 /// An active subscription that can be unsubscribed
 #[napi]
 pub struct Subscription {
-  running: Arc<AtomicBool>,
-  #[allow(dead_code)]
-  watcher: Option<notify_debouncer_full::Debouncer<RecommendedWatcher, notify_debouncer_full::RecommendedCache>>,
+  hub: Arc<Hub>,
+  subscriber: Arc<Subscriber>,
+  warn_if_leaked: bool,
+  id: u32,
+  /// When the subscription's directories had all been registered
+  registered_at: Instant,
+  /// Keeps the buffer events are written to alive
+  _ring: Option<RingReference>,
+  /// The functions added with `on()`, by the id of their listener, to find them again in `off()`
+  listeners: Vec<(u64, listeners::Kind, FunctionRef<Emitted, Unknown<'static>>)>,
 }
 
 #[napi]
@@ -51,152 +791,1770 @@ impl Subscription {
   /// Stop watching for file system changes
   #[napi]
   pub fn unsubscribe(&mut self) -> Result<()> {
-    self.running.store(false, Ordering::SeqCst);
-    // Drop the watcher to stop receiving events
-    self.watcher.take();
+    self.close();
     Ok(())
   }
-}
-
-/// Build a GlobSet from ignore patterns
-fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
-  let mut builder = GlobSetBuilder::new();
 
-  for pattern in patterns {
-    let glob = Glob::new(pattern).map_err(|e| Error::new(Status::InvalidArg, format!("Invalid glob pattern '{}': {}", pattern, e)))?;
-    builder.add(glob);
+  /// Stop watching after delivering events that are still pending in the debouncer.
+  /// Resolves once the watcher has fully stopped and the callback has been released.
+  #[napi]
+  pub fn unsubscribe_async(&self) -> AsyncTask<UnsubscribeTask> {
+    AsyncTask::new(UnsubscribeTask { hub: Arc::clone(&self.hub), subscriber: Arc::clone(&self.subscriber), id: self.id })
   }
 
-  builder.build().map_err(|e| Error::new(Status::GenericFailure, format!("Failed to build glob set: {}", e)))
-}
+  /// Whether events are taken from a watcher registered for a parent directory instead of a watch of its own
+  #[napi(getter)]
+  pub fn attached(&self) -> bool {
+    self.subscriber.running.load(Ordering::SeqCst) && self.hub.is_attached(&self.subscriber.root)
+  }
 
-/// Convert a path to a clean string, stripping the Windows extended-length path prefix if present
-fn path_to_clean_string(path: &Path) -> String {
-  let path_str = path.to_string_lossy();
+  /// Number of directories registered with the OS watcher for this subscription's tree
+  #[napi(getter)]
+  pub fn watch_count(&self) -> u32 {
+    self.hub.watch_count(&self.subscriber.root)
+  }
 
-  // Strip the \\?\ prefix on Windows
-  #[cfg(windows)]
-  if let Some(stripped) = path_str.strip_prefix(r"\\?\") {
-    return stripped.to_string();
+  /// Identifies the subscription in `listSubscriptions`, unique within the process
+  #[napi(getter)]
+  pub fn id(&self) -> u32 {
+    self.id
   }
 
-  path_str.to_string()
-}
+  /// Watcher implementation this subscription's events come from
+  #[napi(getter, ts_return_type = "'inotify' | 'fsevents' | 'windows' | 'kqueue' | 'polling' | 'mock'")]
+  pub fn backend(&self) -> &'static str {
+    self.subscriber.backend_name()
+  }
 
-/// Check if a path should be ignored
-fn should_ignore(path: &PathBuf, glob_set: &GlobSet, base_path: &PathBuf) -> bool {
-  // Try matching against relative path first
-  if let Ok(relative) = path.strip_prefix(base_path)
-    && glob_set.is_match(relative)
-  {
-    return true;
+  /// Why the subscription polls instead of using the native watcher, if it does
+  #[napi(getter)]
+  pub fn backend_reason(&self) -> Option<String> {
+    self.subscriber.polling.get().map(|polling| polling.reason.clone())
   }
 
-  // Also try matching against full path
-  glob_set.is_match(path)
-}
+  /// How the subscription tells that a file changed, if it polls
+  #[napi(getter, ts_return_type = "'mtime' | 'contents' | null")]
+  pub fn poll_compare(&self) -> Option<&'static str> {
+    self.subscriber.polling.get().map(|_| if self.subscriber.poll_contents { "contents" } else { "mtime" })
+  }
 
-/// Convert notify event kind to our event type
-fn event_kind_to_type(kind: &EventKind) -> Option<&'static str> {
-  match kind {
-    EventKind::Create(CreateKind::File | CreateKind::Folder | CreateKind::Any) => Some("create"),
-    EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any | ModifyKind::Metadata(_)) => Some("update"),
-    EventKind::Remove(RemoveKind::File | RemoveKind::Folder | RemoveKind::Any) => Some("delete"),
-    _ => None,
+  /// FSEvents event id to pass as `sinceEventId` to resume from now, on macOS with the native watcher
+  #[napi(getter)]
+  pub fn event_id(&self) -> Option<i64> {
+    self.hub.event_id(&self.subscriber)
   }
-}
 
-/// Subscribe to file system changes in a directory
-///
-/// # Arguments
-/// * `directory` - The directory path to watch
-/// * `callback` - Function called with (error, events) when changes occur
-/// * `options` - Optional configuration including ignore patterns
-///
-/// # Returns
-/// A subscription that can be used to stop watching
-#[napi(strict, ts_args_type = "directory: string, callback: (result: WatchCallbackResult) => void, options?: WatchOptions")]
-pub fn subscribe(env: Env, directory: String, callback: Unknown, options: Option<WatchOptions>) -> Result<Subscription> {
-  if directory.is_empty() {
-    unsafe { JsTypeError::from(Error::new(Status::InvalidArg, "Directory path cannot be empty")).throw_into(env.raw()) };
-    return Err(Error::new(Status::PendingException, ""));
+  /// Resolves once the OS watcher reports changes for the whole tree and the initial scan has been delivered,
+  /// or rejects if the watcher failed
+  #[napi(getter)]
+  pub fn ready(&self) -> AsyncTask<ReadyTask> {
+    AsyncTask::new(ReadyTask { subscriber: Arc::clone(&self.subscriber), registered_at: self.registered_at })
   }
 
-  // Validate callback is a function
-  if callback.get_type()? != ValueType::Function {
-    unsafe { JsTypeError::from(Error::new(Status::InvalidArg, "Callback must be a function")).throw_into(env.raw()) };
-    return Err(Error::new(Status::PendingException, ""));
+  /// Get statistics about this subscription
+  #[napi]
+  pub fn stats(&self) -> SubscriptionStats {
+    let counters = &self.subscriber.counters;
+    let latency = counters.latency.summary();
+    SubscriptionStats {
+      suppressed_permission_errors: counters.suppressed_permission_errors.load(Ordering::Relaxed),
+      suppressed_expected_changes: counters.suppressed_expected_changes.load(Ordering::Relaxed),
+      muted_events: counters.muted_events.load(Ordering::Relaxed),
+      suppressed_unchanged: counters.suppressed_unchanged.load(Ordering::Relaxed),
+      latency_min_ms: latency.map(|(min, _, _)| min),
+      latency_avg_ms: latency.map(|(_, avg, _)| avg),
+      latency_max_ms: latency.map(|(_, _, max)| max),
+      latency_histogram: counters.latency.histogram(),
+      queued_batches: self.subscriber.queued_batches(),
+      max_queued_batches: counters.max_queued_batches.load(Ordering::Relaxed),
+      dropped_batches: counters.dropped_batches.load(Ordering::Relaxed),
+      created_events: counters.created_events.load(Ordering::Relaxed),
+      updated_events: counters.updated_events.load(Ordering::Relaxed),
+      deleted_events: counters.deleted_events.load(Ordering::Relaxed),
+      metadata_events: counters.metadata_events.load(Ordering::Relaxed),
+      cache_entries: u32::try_from(self.hub.cache_entries()).unwrap_or(u32::MAX),
+      locked_retries: counters.locked_retries.load(Ordering::Relaxed),
+      locked_failures: counters.locked_failures.load(Ordering::Relaxed),
+      storm_trips: counters.storm_trips.load(Ordering::Relaxed),
+      storm_events: counters.storm_events.load(Ordering::Relaxed),
+      memory_bytes: memory::usage(&self.subscriber) as f64,
+    }
   }
 
-  // Convert to Function
-  let callback: Function<WatchCallbackResult, ()> = unsafe { Function::from_napi_value(env.raw(), callback.raw())? };
+  /// Start the statistics over from zero. The number of queued batches is kept, as it describes the present.
+  #[napi]
+  pub fn reset_stats(&self) {
+    let counters = &self.subscriber.counters;
+    for counter in [
+      &counters.suppressed_permission_errors,
+      &counters.suppressed_expected_changes,
+      &counters.muted_events,
+      &counters.suppressed_unchanged,
+      &counters.dropped_batches,
+      &counters.created_events,
+      &counters.updated_events,
+      &counters.deleted_events,
+      &counters.metadata_events,
+      &counters.locked_retries,
+      &counters.locked_failures,
+      &counters.storm_trips,
+      &counters.storm_events,
+    ] {
+      counter.store(0, Ordering::Relaxed);
+    }
+    counters.max_queued_batches.store(self.subscriber.queued_batches(), Ordering::Relaxed);
+    counters.latency.reset();
+  }
 
-  let path = PathBuf::from(&directory);
+  /// Swallow the next create or update event for each of `paths` (relative to the watched directory or absolute)
+  /// that arrives within `window_ms`, for changes the calling process is about to make itself. Deletes are
+  /// still delivered, and announcing a path several times swallows as many events.
+  #[napi]
+  pub fn expect_change(&self, paths: Vec<String>, window_ms: Option<u32>) {
+    let window = window_ms.map_or(EXPECT_CHANGE_WINDOW, |window_ms| Duration::from_millis(window_ms.into()));
+    let until = Instant::now() + window;
 
-  if !path.exists() {
-    unsafe { JsTypeError::from(Error::new(Status::InvalidArg, format!("Directory does not exist: {}", directory))).throw_into(env.raw()) };
-    return Err(Error::new(Status::PendingException, ""));
+    let mut expectations = hub::lock(&self.subscriber.expectations);
+    for path in paths {
+      expectations.expect(path_to_clean_string(&resolve_in(&self.subscriber.root, &path)), until);
+    }
   }
 
-  if !path.is_dir() {
-    unsafe { JsTypeError::from(Error::new(Status::InvalidArg, format!("Path is not a directory: {}", directory))).throw_into(env.raw()) };
-    return Err(Error::new(Status::PendingException, ""));
+  /// Drop events for paths matching `patterns` until the returned handle is unmuted, or for `duration_ms`.
+  /// The watches stay in place, so events are heard again as soon as the mute ends.
+  #[napi]
+  pub fn mute(&self, patterns: Vec<String>, duration_ms: Option<u32>) -> Result<MuteHandle> {
+    let id = NEXT_MUTE_ID.fetch_add(1, Ordering::Relaxed);
+    let until = duration_ms.map(|duration_ms| Instant::now() + Duration::from_millis(duration_ms.into()));
+    hub::lock(&self.subscriber.mutes).add(id, patterns, until)?;
+    Ok(MuteHandle { subscriber: Arc::clone(&self.subscriber), id })
   }
 
-  let base_path = dunce::canonicalize(&path).map_err(|e| Error::new(Status::GenericFailure, format!("Failed to canonicalize path: {}", e)))?;
+  /// Call `listener` with each delivered event of type `event`, or with each error or warning delivered for
+  /// `'error'` and `'warning'`. Listeners are called besides the callback, in the order they were added.
+  #[napi(
+    ts_generic_types = "E extends 'create' | 'update' | 'delete' | 'metadata' | 'error' | 'warning'",
+    ts_args_type = "event: E, listener: (value: E extends 'error' ? Error : E extends 'warning' ? WatchWarning : WatchEvent) => void"
+  )]
+  pub fn on(&mut self, event: String, listener: Function<Emitted, Unknown<'static>>) -> Result<()> {
+    let kind = listeners::Kind::parse(&event)
+      .ok_or_else(|| Error::new(Status::InvalidArg, format!("Invalid event '{}', expected create, update, delete, metadata, error or warning", event)))?;
+    if !self.subscriber.running.load(Ordering::SeqCst) {
+      return Err(Error::new(Status::GenericFailure, "Cannot add a listener to a subscription that has stopped"));
+    }
+
+    let id = NEXT_LISTENER_ID.fetch_add(1, Ordering::Relaxed);
+    let callback = listener.build_threadsafe_function().build()?;
+    self.listeners.push((id, kind, listener.create_ref()?));
+    hub::lock(&self.subscriber.listeners).push(Listener { id, kind, callback });
+    Ok(())
+  }
 
-  // Build glob set for ignore patterns
-  let ignore_patterns = options.as_ref().and_then(|o| o.ignore.as_ref()).cloned().unwrap_or_default();
-  let glob_set = Arc::new(build_glob_set(&ignore_patterns)?);
+  /// Remove `listener` from the listeners of `event`, the one added last if it was added more than once
+  #[napi(ts_args_type = "event: 'create' | 'update' | 'delete' | 'metadata' | 'error' | 'warning', listener: (value: any) => void")]
+  pub fn off(&mut self, env: Env, event: String, listener: Unknown) -> Result<()> {
+    let Some(kind) = listeners::Kind::parse(&event) else {
+      return Ok(());
+    };
 
-  // Create threadsafe function for calling back to JS
-  let tsfn = callback.build_threadsafe_function().build()?;
-  let running = Arc::new(AtomicBool::new(true));
-  let running_clone = Arc::clone(&running);
-  let base_path_clone = base_path.clone();
+    let mut found = None;
+    for (index, (_, listening, reference)) in self.listeners.iter().enumerate().rev() {
+      if *listening == kind && env.strict_equals(reference.borrow_back(&env)?, listener)? {
+        found = Some(index);
+        break;
+      }
+    }
+    let Some(index) = found else {
+      return Ok(());
+    };
 
-  // Create debounced watcher with 100ms debounce time
-  let mut debouncer = new_debouncer(Duration::from_millis(100), None, move |result: DebounceEventResult| {
-    if !running_clone.load(Ordering::SeqCst) {
-      return;
+    let (id, _, _) = self.listeners.remove(index);
+    let mut listeners = hub::lock(&self.subscriber.listeners);
+    if let Some(position) = listeners.iter().position(|listener| listener.id == id) {
+      // Dropping the threadsafe function releases it, after the calls already queued
+      listeners.remove(position);
     }
+    Ok(())
+  }
+
+  /// Resolves with the next delivered event matching `options`, or rejects once the subscription stops
+  #[napi(ts_args_type = "options?: NextOptions", ts_return_type = "Promise<WatchEvent>")]
+  pub fn next<'env>(&self, env: &'env Env, options: Option<NextOptions>) -> Result<Object<'env>> {
+    waiters::wait(env, &self.subscriber, &options.unwrap_or_default(), None, None)
+  }
 
-    match result {
-      Ok(debounced_events) => {
-        let mut events = Vec::new();
+  /// Take every batch buffered for a subscription created without a callback, without waiting for more.
+  /// Returns an empty array when nothing was delivered since the last call.
+  #[napi]
+  pub fn poll(&self) -> Result<Vec<WatchCallbackResult>> {
+    pull::drain(&self.subscriber)
+  }
 
-        for debounced_event in debounced_events {
-          let event = debounced_event.event;
+  /// Resolves with the next batch of a subscription created without a callback, or with `null` when
+  /// `timeout_ms` passes first. Rejects once the subscription stops with nothing left to read.
+  #[napi(ts_args_type = "timeoutMs?: number", ts_return_type = "Promise<WatchCallbackResult | null>")]
+  pub fn next_batch<'env>(&self, env: &'env Env, timeout_ms: Option<u32>) -> Result<Object<'env>> {
+    pull::next_batch(env, &self.subscriber, timeout_ms)
+  }
 
-          if let Some(event_type) = event_kind_to_type(&event.kind) {
-            for path in &event.paths {
-              if !should_ignore(path, &glob_set, &base_path_clone) {
-                events.push(WatchEvent { path: path_to_clean_string(path), event_type: event_type.to_string() });
-              }
-            }
-          }
-        }
+  /// Resolves once no events have arrived for the tree for `quiet_ms`, or rejects with an `ETIMEDOUT` error
+  /// when that does not happen within `timeout_ms`. Rejects once the subscription stops.
+  #[napi(ts_args_type = "quietMs: number, timeoutMs?: number, options?: QuiescenceOptions", ts_return_type = "Promise<void>")]
+  pub fn wait_for_quiescence<'env>(&self, env: &'env Env, quiet_ms: u32, timeout_ms: Option<u32>, options: Option<QuiescenceOptions>) -> Result<Object<'env>> {
+    let include_ignored = options.and_then(|options| options.include_ignored).unwrap_or(false);
+    quiescence::wait(env, &self.subscriber, quiet_ms, timeout_ms, include_ignored)
+  }
+
+  /// Walk `subpath` (the whole tree by default) again and deliver synthetic events for everything that
+  /// differs from what was delivered so far. Resolves with the number of events of each type.
+  #[napi(ts_args_type = "subpath?: string")]
+  pub fn trigger_rescan(&self, subpath: Option<String>) -> Result<AsyncTask<RescanTask>> {
+    let directory = self.subtree(subpath)?;
+    Ok(AsyncTask::new(RescanTask { subscriber: Arc::clone(&self.subscriber), directory }))
+  }
+
+  /// The entries at or below `prefix` (the whole tree by default) as the subscription knows them from the events
+  /// it delivered, without ignored paths. Needs the state `emitInitial`, `reconcileIntervalMs` or
+  /// `triggerRescan()` records. With `onChunk`, entries are passed to it in chunks instead and an empty array is returned.
+  #[napi(ts_args_type = "prefix?: string, onChunk?: (entries: Array<ScanEntry>) => void")]
+  pub fn get_tree(&self, prefix: Option<String>, on_chunk: Option<Function<Vec<ScanEntry>, Unknown>>) -> Result<Vec<ScanEntry>> {
+    let directory = self.subtree(prefix)?;
+    // Copied out so events keep being recorded while the callback runs
+    let entries: Vec<ScanEntry> = {
+      let baseline = hub::lock(&self.subscriber.baseline);
+      let Some(baseline) = baseline.as_ref() else {
+        return Err(Error::new(
+          Status::GenericFailure,
+          "getTree needs the state of the tree, recorded with emitInitial, reconcileIntervalMs or after triggerRescan()",
+        ));
+      };
+      scan::keys_below(baseline, &directory).iter().filter_map(|key| baseline.get(key)).map(|scanned| scanned.entry.clone()).collect()
+    };
 
-        if !events.is_empty() {
-          tsfn.call(WatchCallbackResult { error: None, events }, napi::threadsafe_function::ThreadsafeFunctionCallMode::NonBlocking);
+    match on_chunk {
+      Some(on_chunk) => {
+        for chunk in entries.chunks(DIFF_CHUNK_SIZE) {
+          on_chunk.call(chunk.to_vec())?;
         }
+        Ok(Vec::new())
+      }
+      None => Ok(entries),
+    }
+  }
+
+  /// The events the debouncer holds for the subscription and has not delivered yet, after its `ignore` patterns
+  /// and mutes, left in place for delivery. The snapshot can be stale as soon as it is taken.
+  #[napi]
+  pub fn get_pending_events(&self) -> Vec<WatchEvent> {
+    if !self.subscriber.running.load(Ordering::SeqCst) {
+      return Vec::new();
+    }
+    self.hub.pending_events(&self.subscriber)
+  }
+
+  /// Deliver events again after `circuitBreaker` stopped delivery during an event storm, announcing it with a
+  /// batch carrying `storm`. Returns whether delivery was stopped.
+  #[napi]
+  pub fn resume(&self) -> bool {
+    self.subscriber.close_circuit(true)
+  }
+
+  /// Change how long the debouncer waits for further events of a path, for a subscription with a watcher of its
+  /// own. Events received before the call are delivered under the interval they were received with.
+  #[napi]
+  pub fn set_debounce(&self, ms: u32) -> Result<()> {
+    let timeout = validation::check_debounce(Some(ms)).map_err(Problem::into_error)?.unwrap_or(DEBOUNCE_TIMEOUT);
+    if self.hub.is_shared() {
+      return Err(Error::new(Status::InvalidArg, "setDebounce needs a watcher of the subscription's own, subscribe with debounceMs or isolated: true"));
+    }
+    self.hub.set_debounce(timeout)
+  }
+
+  /// Hand raw events to a subscription with `backend: 'mock'` as if the OS watcher had reported them, to be
+  /// debounced, filtered and delivered like any other
+  #[napi]
+  pub fn inject_raw(&self, events: Vec<RawEvent>) -> Result<()> {
+    match &self.subscriber.mock {
+      Some(mock) => mock.inject(events),
+      None => Err(Error::new(Status::InvalidArg, "injectRaw is only available on subscriptions with backend: 'mock'")),
+    }
+  }
+}
+
+/// A mute started with `Subscription.mute`
+#[napi]
+pub struct MuteHandle {
+  subscriber: Arc<Subscriber>,
+  id: u64,
+}
+
+#[napi]
+impl MuteHandle {
+  /// End the mute, unless it already ended. Patterns muted by other calls as well stay muted.
+  #[napi]
+  pub fn unmute(&self) {
+    hub::lock(&self.subscriber.mutes).remove(self.id);
+  }
+}
+
+impl Subscription {
+  /// The directory at `subpath` of the watched one, or the watched one itself, refusing paths outside it
+  fn subtree(&self, subpath: Option<String>) -> Result<PathBuf> {
+    let root = &self.subscriber.root;
+    let directory = subpath.map_or_else(|| root.clone(), |subpath| root.join(subpath));
+
+    if !directory.starts_with(root) || directory.components().any(|component| component == Component::ParentDir) {
+      return Err(Error::new(Status::InvalidArg, format!("Path is not inside the watched directory: {}", path_to_clean_string(&directory))));
+    }
+    Ok(directory)
+  }
+
+  /// Stop the watcher and release the JS callback. Safe to call more than once.
+  pub(crate) fn close(&mut self) {
+    if self.subscriber.running.swap(false, Ordering::SeqCst) {
+      self.subscriber.log(Level::Info, || "unsubscribed".to_string());
+    }
+    // Drop the watcher first so no further calls race the release
+    drop(self.hub.detach(self.id));
+    self.subscriber.stop_waiting();
+    self.subscriber.release_callbacks();
+    self.listeners.clear();
+    registry::unregister(self.id);
+  }
+}
+
+/// Runs when the JS object is garbage collected
+impl Drop for Subscription {
+  fn drop(&mut self) {
+    if self.subscriber.running.swap(false, Ordering::SeqCst) && self.warn_if_leaked {
+      let message = format!("Subscription for {} was garbage collected without unsubscribe", path_to_clean_string(&self.subscriber.root));
+      // Without `onLog` there is nothing to capture the warning, so it goes to stderr
+      if self.subscriber.logger.is_some() {
+        self.subscriber.log(Level::Warn, || message);
+      } else {
+        eprintln!("[watcher] {}", message);
       }
-      Err(errors) => {
-        let error_msg = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; ");
-        tsfn.call(
-          WatchCallbackResult { error: Some(Error::new(Status::GenericFailure, error_msg)), events: vec![] },
-          napi::threadsafe_function::ThreadsafeFunctionCallMode::NonBlocking,
-        );
+    }
+
+    if let Some(debouncer) = self.hub.detach(self.id) {
+      // Stopping the backend may block on its threads, keep that off the JS thread during GC
+      thread::spawn(move || drop(debouncer));
+    }
+
+    self.subscriber.stop_waiting();
+    self.subscriber.release_callbacks();
+    registry::unregister(self.id);
+  }
+}
+
+/// Background teardown for `Subscription::unsubscribe_async`
+pub struct UnsubscribeTask {
+  hub: Arc<Hub>,
+  subscriber: Arc<Subscriber>,
+  id: u32,
+}
+
+impl Task for UnsubscribeTask {
+  type Output = ();
+  type JsValue = ();
+
+  fn compute(&mut self) -> Result<()> {
+    if self.subscriber.running.load(Ordering::SeqCst) {
+      // Let pending events mature so the debouncer thread hands them to the callback
+      thread::sleep(self.hub.flush_delay());
+    }
+    stop_flushed(&self.hub, &self.subscriber, self.id);
+    Ok(())
+  }
+
+  fn resolve(&mut self, _env: Env, _output: ()) -> Result<()> {
+    Ok(())
+  }
+}
+
+/// Background teardown for `unsubscribeAll`
+pub struct UnsubscribeAllTask {
+  entries: Vec<(u32, registry::Entry)>,
+}
+
+impl Task for UnsubscribeAllTask {
+  type Output = u32;
+  type JsValue = u32;
+
+  fn compute(&mut self) -> Result<u32> {
+    // One wait lets the pending events of every subscription mature
+    let running = self.entries.iter().filter(|(_, entry)| entry.subscriber.running.load(Ordering::SeqCst));
+    if let Some(delay) = running.map(|(_, entry)| entry.hub.flush_delay()).max() {
+      thread::sleep(delay);
+    }
+
+    let mut closed = 0;
+    for (id, entry) in &self.entries {
+      // Subscriptions unsubscribed meanwhile are already stopped and not counted
+      if entry.subscriber.running.load(Ordering::SeqCst) {
+        closed += 1;
       }
+      stop_flushed(&entry.hub, &entry.subscriber, *id);
     }
-  })
-  .map_err(|e| Error::new(Status::GenericFailure, format!("Failed to create watcher: {}", e)))?;
+    Ok(closed)
+  }
+
+  fn resolve(&mut self, _env: Env, output: u32) -> Result<u32> {
+    Ok(output)
+  }
+}
+
+/// Stop a subscription once its pending events have matured, after the JS callback has handled every batch
+/// queued for it. Runs off the JS thread, so callbacks keep running meanwhile.
+fn stop_flushed(hub: &Hub, subscriber: &Subscriber, id: u32) {
+  if subscriber.running.swap(false, Ordering::SeqCst) {
+    subscriber.log(Level::Info, || "unsubscribed".to_string());
+  }
+
+  if let Some(debouncer) = hub.detach(id) {
+    // Joins the debouncer thread, after which no new batches can be queued
+    debouncer.stop();
+  }
+
+  // Wait until the JS callback has handled everything that was queued
+  let Subscriber { counters, callback, .. } = subscriber;
+  while counters.in_flight_batches.load(Ordering::SeqCst) > 0 && !callback.aborted() {
+    thread::sleep(Duration::from_millis(5));
+  }
+
+  subscriber.stop_waiting();
+  subscriber.release_callbacks();
+  registry::unregister(id);
+}
+
+pub struct ReadyTask {
+  subscriber: Arc<Subscriber>,
+  registered_at: Instant,
+}
 
-  // Configure watcher for high performance
-  let _config = Config::default().with_poll_interval(Duration::from_millis(100)).with_compare_contents(false);
+impl Task for ReadyTask {
+  type Output = ();
+  type JsValue = ();
 
-  // Start watching the directory
-  debouncer.watch(&base_path, RecursiveMode::Recursive).map_err(|e| Error::new(Status::GenericFailure, format!("Failed to watch directory: {}", e)))?;
+  fn compute(&mut self) -> Result<()> {
+    // Every directory is registered before `subscribe` returns, only the OS watcher may need a moment to start reporting
+    thread::sleep(READY_DELAY.saturating_sub(self.registered_at.elapsed()));
 
-  Ok(Subscription { running, watcher: Some(debouncer) })
+    // The initial scan has been delivered once live events are no longer held back
+    while hub::lock(&self.subscriber.held).is_some() && self.subscriber.running.load(Ordering::SeqCst) {
+      thread::sleep(Duration::from_millis(5));
+    }
+
+    match hub::lock(&self.subscriber.failure).as_ref() {
+      Some(message) => Err(Error::new(Status::GenericFailure, message.clone())),
+      None => Ok(()),
+    }
+  }
+
+  fn resolve(&mut self, _env: Env, _output: ()) -> Result<()> {
+    Ok(())
+  }
+}
+
+pub struct RescanTask {
+  subscriber: Arc<Subscriber>,
+  directory: PathBuf,
+}
+
+impl Task for RescanTask {
+  type Output = RescanResult;
+  type JsValue = RescanResult;
+
+  fn compute(&mut self) -> Result<RescanResult> {
+    if !self.subscriber.running.load(Ordering::SeqCst) {
+      return Ok(RescanResult { created: 0, updated: 0, deleted: 0 });
+    }
+
+    scan::rescan(&self.subscriber, &self.directory).map_err(|e| Error::new(Status::GenericFailure, e.to_string()))
+  }
+
+  fn resolve(&mut self, _env: Env, output: RescanResult) -> Result<RescanResult> {
+    Ok(output)
+  }
+}
+
+/// Release the threadsafe function so the JS callback and everything it closes over can be collected.
+/// Calls that are already queued are still delivered, later calls report `Status::Closing`.
+fn release_callback(callback: &ThreadsafeFunctionHandle) {
+  close_callback(callback, napi::sys::ThreadsafeFunctionReleaseMode::release);
+}
+
+/// Abort the threadsafe function, discarding queued calls. Used when the environment is going away.
+fn abort_callback(callback: &ThreadsafeFunctionHandle) {
+  close_callback(callback, napi::sys::ThreadsafeFunctionReleaseMode::abort);
+}
+
+fn close_callback(callback: &ThreadsafeFunctionHandle, mode: napi::sys::napi_threadsafe_function_release_mode) {
+  // The flag is also set by napi-rs when Node finalizes the function during environment teardown
+  callback.with_write_aborted(|mut closed| {
+    if !*closed {
+      unsafe { napi::sys::napi_release_threadsafe_function(callback.get_raw(), mode) };
+      *closed = true;
+    }
+  });
+}
+
+/// Build a GlobSet from glob patterns, matched as they are
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
+  validation::check_patterns(patterns).map_err(|mut problems| problems.swap_remove(0).into_error())
+}
+
+/// Build an IgnoreSet from ignore patterns
+fn build_ignore_set(patterns: &[String]) -> Result<IgnoreSet> {
+  validation::check_ignore_patterns(patterns).map_err(|mut problems| problems.swap_remove(0).into_error())
+}
+
+/// The ignore patterns to match, which with a `unicodeNormalization` form match names in either form
+fn ignore_patterns(patterns: &[String], normalization: Option<Form>) -> Vec<String> {
+  match normalization {
+    Some(_) => normalization::both_forms(patterns),
+    None => patterns.to_vec(),
+  }
+}
+
+/// Longest a single wait between attempts lasts, however large `backoff` makes it
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Longest the waits between attempts of `retry` add up to, after which the last error is returned
+const MAX_RETRY_TOTAL: Duration = Duration::from_secs(30);
+
+/// Resolved retry policy
+struct RetryPolicy {
+  attempts: u32,
+  delay: Duration,
+  backoff: f64,
+}
+
+impl RetryPolicy {
+  /// A policy that makes a single attempt
+  fn single() -> Self {
+    Self { attempts: 1, delay: Duration::ZERO, backoff: 1.0 }
+  }
+
+  /// Build a policy from the user options, defaulting to a single attempt
+  fn from_options(options: Option<&RetryOptions>) -> Result<Self> {
+    let Some(options) = options else {
+      return Ok(Self::single());
+    };
+
+    if options.attempts == 0 {
+      return Err(Error::new(Status::InvalidArg, "retry.attempts must be at least 1"));
+    }
+
+    let backoff = options.backoff.unwrap_or(1.0);
+    if !backoff.is_finite() || backoff < 1.0 {
+      return Err(Error::new(Status::InvalidArg, format!("retry.backoff must be a finite number >= 1, got {}", backoff)));
+    }
+
+    Ok(Self { attempts: options.attempts, delay: Duration::from_millis(u64::from(options.delay_ms)).min(MAX_RETRY_DELAY), backoff })
+  }
+
+  /// The wait after one of `delay`, multiplied by `backoff` up to `MAX_RETRY_DELAY`
+  fn next_delay(&self, delay: Duration) -> Duration {
+    Duration::try_from_secs_f64(delay.as_secs_f64() * self.backoff).map_or(MAX_RETRY_DELAY, |next| next.min(MAX_RETRY_DELAY))
+  }
+
+  /// Run `op` until it succeeds, fails with a non-transient error, or the attempts or `MAX_RETRY_TOTAL` are
+  /// exhausted. On failure the last error is returned together with the number of attempts made.
+  fn run<T, E>(&self, is_transient: impl Fn(&E) -> bool, mut op: impl FnMut() -> std::result::Result<T, E>) -> std::result::Result<T, (E, u32)> {
+    let mut delay = self.delay;
+    let mut waited = Duration::ZERO;
+    let mut attempt = 1;
+
+    loop {
+      match op() {
+        Ok(value) => return Ok(value),
+        Err(e) if attempt < self.attempts && waited + delay <= MAX_RETRY_TOTAL && is_transient(&e) => {
+          thread::sleep(delay);
+          waited += delay;
+          delay = self.next_delay(delay);
+          attempt += 1;
+        }
+        Err(e) => return Err((e, attempt)),
+      }
+    }
+  }
+}
+
+/// Format the attempt count for error messages, empty when only a single attempt was made
+fn attempts_suffix(attempts: u32) -> String {
+  if attempts > 1 { format!(" (after {} attempts)", attempts) } else { String::new() }
+}
+
+/// Reason a watch target could not be resolved
+enum DirectoryError {
+  NotFound,
+  NotADirectory,
+  Io(io::Error),
+}
+
+/// Validate that `path` is an existing directory and return its canonical form
+fn resolve_directory(path: &Path) -> std::result::Result<PathBuf, DirectoryError> {
+  match path.metadata() {
+    Ok(metadata) if !metadata.is_dir() => return Err(DirectoryError::NotADirectory),
+    Ok(_) => {}
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(DirectoryError::NotFound),
+    Err(e) => return Err(DirectoryError::Io(e)),
+  }
+
+  let canonical = dunce::canonicalize(path).map_err(|e| if e.kind() == io::ErrorKind::NotFound { DirectoryError::NotFound } else { DirectoryError::Io(e) })?;
+  // Network paths canonicalize to the verbatim `\\?\UNC\server\share` form, keep the caller's spelling instead
+  if network::is_verbatim_unc(&canonical) {
+    return std::path::absolute(path).map_err(DirectoryError::Io);
+  }
+  // Paths longer than `MAX_PATH` keep the verbatim prefix, which only the OS watcher needs
+  Ok(long_paths::simplified(&canonical).into_owned())
+}
+
+/// Resolve `path` against `root` the way events report it, with symlinks in its parent directories resolved
+fn resolve_in(root: &Path, path: &str) -> PathBuf {
+  let path = root.join(path);
+  match (path.parent().and_then(|parent| dunce::canonicalize(parent).ok()), path.file_name()) {
+    (Some(parent), Some(name)) => long_paths::simplified(&parent).join(name),
+    _ => path,
+  }
+}
+
+/// Check if an I/O error is likely to go away when retried shortly after
+fn is_transient_io_error(error: &io::Error) -> bool {
+  matches!(error.kind(), io::ErrorKind::NotFound | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted | io::ErrorKind::ResourceBusy)
+}
+
+/// Check if a directory resolution failure is worth retrying
+fn is_transient_directory_error(error: &DirectoryError) -> bool {
+  match error {
+    DirectoryError::NotFound => true,
+    DirectoryError::NotADirectory => false,
+    DirectoryError::Io(e) => is_transient_io_error(e),
+  }
+}
+
+/// Check if a watch registration failure is worth retrying
+fn is_transient_watch_error(error: &notify::Error) -> bool {
+  match &error.kind {
+    notify::ErrorKind::PathNotFound => true,
+    notify::ErrorKind::Io(e) => is_transient_io_error(e),
+    _ => false,
+  }
+}
+
+/// Check if a watch registration failed because the native watcher could not start rather than because of the
+/// directory. FSEvents reports a stream it cannot start, as in some sandboxed processes, as a generic error.
+fn is_backend_failure(error: &notify::Error) -> bool {
+  matches!(&error.kind, notify::ErrorKind::Generic(message) if cfg!(target_os = "macos") || capture::is_injected_failure(message))
+}
+
+/// Subscribe to file system changes in a directory
+///
+/// # Arguments
+/// * `directory` - The directory path to watch
+/// * `callback` - Function called with (error, events) when changes occur, or nothing to read batches with `nextBatch`
+/// * `options` - Optional configuration including ignore patterns and retry policy
+///
+/// # Returns
+/// A subscription that can be used to stop watching
+#[napi(
+  strict,
+  ts_generic_types = "O extends WatchOptions = WatchOptions",
+  ts_args_type = "directory: string, callback?: (O extends { callbackStyle: 'node' } ? (error: Error | null, events: ParcelEvent[]) => void : (result: O extends { serialization: 'json' } ? WatchCallbackJson : WatchCallbackResult) => void) | null, options?: O"
+)]
+pub fn subscribe(env: Env, directory: String, callback: Unknown, options: Option<WatchOptions>) -> Result<Subscription> {
+  start_subscription(env, directory, callback, options, None, Overflow::Drop)
+}
+
+/// Subscribe to file system changes in a directory without blocking the JS thread, resolving the directory and
+/// registering it with the OS watcher on a background thread. Takes the same arguments as `subscribe`.
+///
+/// # Returns
+/// A promise for the subscription, resolved once the directory is watched, or rejected with the error
+/// `subscribe` would throw
+#[napi(
+  strict,
+  ts_generic_types = "O extends WatchOptions = WatchOptions",
+  ts_args_type = "directory: string, callback?: (O extends { callbackStyle: 'node' } ? (error: Error | null, events: ParcelEvent[]) => void : (result: O extends { serialization: 'json' } ? WatchCallbackJson : WatchCallbackResult) => void) | null, options?: O",
+  ts_return_type = "Promise<Subscription>"
+)]
+pub fn subscribe_async(env: Env, directory: String, callback: Unknown, options: Option<WatchOptions>) -> Result<AsyncTask<SubscribeTask>> {
+  let task = match Setup::new(&env, directory, callback, options, None, Overflow::Drop) {
+    Ok((setup, ring)) => SubscribeTask { setup: Some(setup), ring, failure: None },
+    // Rejected rather than thrown, like everything else that can go wrong
+    Err(error) => SubscribeTask { setup: None, ring: None, failure: Some(Failure::Error(take_exception(&env, error))) },
+  };
+  Ok(AsyncTask::new(task))
+}
+
+/// Subscribe to file system changes in a directory, splitting each batch between several callbacks by the
+/// patterns the paths of its events match. One watcher serves every route.
+///
+/// # Arguments
+/// * `directory` - The directory path to watch
+/// * `routes` - Patterns and the callbacks called with the events whose paths match them, with at most one
+///   route leaving out its pattern to take the events no other route takes
+/// * `options` - The options of `subscribe`, with `routeMatch` choosing between the first and every matching route
+///
+/// # Returns
+/// A subscription that stops every route when unsubscribed
+#[napi(strict, ts_args_type = "directory: string, routes: Route[], options?: WatchOptions")]
+pub fn subscribe_routed(env: Env, directory: String, routes: Vec<Route>, options: Option<WatchOptions>) -> Result<Subscription> {
+  let all = validation::check_route_match(options.as_ref().and_then(|options| options.route_match.as_deref())).map_err(Problem::into_error)?;
+  if routes.is_empty() {
+    return Err(Error::new(Status::InvalidArg, "A routed subscription needs at least one route"));
+  }
+  if options.as_ref().is_some_and(|options| options.ring.is_some()) {
+    return Err(Error::new(Status::InvalidArg, "A routed subscription cannot write to a ring"));
+  }
+  let router = Router::new(routes, all)?;
+
+  // Events are split between the routes, the callback only has to exist
+  let callback = env.create_function_from_closure::<(), (), _>("routed", |_| Ok(()))?;
+  let (mut setup, _) = Setup::new(&env, directory, callback.to_unknown(), options, None, Overflow::Drop)?;
+  setup.router = Some(router);
+  setup.register().map_err(|failure| failure.into_error(&env))?.start(&env)
+}
+
+/// The exception thrown into `env` for `error` if it stands for one, cleared so a promise can be rejected with it
+fn take_exception(env: &Env, error: Error) -> Error {
+  if error.status != Status::PendingException {
+    return error;
+  }
+
+  let mut exception = std::ptr::null_mut();
+  if unsafe { napi::sys::napi_get_and_clear_last_exception(env.raw(), &mut exception) } != napi::sys::Status::napi_ok {
+    return error;
+  }
+  unsafe { Unknown::from_napi_value(env.raw(), exception) }.map_or_else(|e| e, Error::from)
+}
+
+/// Unsubscribe every subscription created in this thread, delivering their pending events first as
+/// `unsubscribeAsync` does. Resolves with the number of subscriptions closed.
+#[napi(ts_return_type = "Promise<number>")]
+pub fn unsubscribe_all(env: Env) -> AsyncTask<UnsubscribeAllTask> {
+  AsyncTask::new(UnsubscribeAllTask { entries: registry::take_env(&env) })
+}
+
+/// List the live subscriptions of the process, in the order they were created
+#[napi]
+pub fn list_subscriptions() -> Vec<SubscriptionInfo> {
+  registry::list()
+}
+
+/// Describe the file system watcher backend used on this platform
+#[napi]
+pub fn capabilities() -> Capabilities {
+  Capabilities {
+    backend: platform::backend_name().to_string(),
+    native_recursive: platform::BACKEND.native_recursive,
+    supports_cookies: platform::BACKEND.cookies,
+    supports_file_ids: platform::FILE_IDS,
+    watch_limit: platform::watch_limit(),
+    notify_version: env!("NOTIFY_VERSION").to_string(),
+  }
+}
+
+/// Check a directory and options the way `subscribe` does, reporting every problem found instead of throwing
+/// the first. Nothing is watched, and the directory is resolved once, without retries.
+#[napi(ts_args_type = "directory: string, options?: WatchOptions")]
+pub fn validate_options(env: Env, directory: String, options: Option<WatchOptions>) -> OptionsReport {
+  let options = options.unwrap_or_default();
+  let mut problems = Vec::new();
+
+  if let Err(invalid) = validation::check_ignore_patterns(options.ignore.as_deref().unwrap_or_default()) {
+    problems.extend(invalid);
+  }
+  if let Err(problem) = validation::check_retry(options.retry.as_ref()) {
+    problems.push(problem);
+  }
+  if let Err(problem) = validation::check_log_level(options.log_level.as_deref()) {
+    problems.push(problem);
+  }
+  if let Err(problem) = validation::check_serialization(options.serialization.as_deref(), options.callback_style.as_deref()) {
+    problems.push(problem);
+  }
+  if let Some(buffer) = &options.ring
+    && let Err(problem) = validation::check_ring(&env, buffer, options.ring_overflow.as_deref())
+  {
+    problems.push(problem);
+  }
+  if let Err(problem) = validation::check_entry_limit(options.max_watched_entries, options.max_watched_entries_action.as_deref()) {
+    problems.push(problem);
+  }
+  if let Err(problem) = validation::check_normalization(options.unicode_normalization.as_deref()) {
+    problems.push(problem);
+  }
+  match validation::check_backend(options.backend.as_deref(), options.poll_interval_ms) {
+    Ok((choice, _)) => {
+      if let Err(problem) = validation::check_since_event_id(options.since_event_id, choice) {
+        problems.push(problem);
+      }
+    }
+    Err(problem) => problems.push(problem),
+  }
+  if let Err(problem) = validation::check_reconcile_interval(options.reconcile_interval_ms) {
+    problems.push(problem);
+  }
+  if let Err(problem) = validation::check_heartbeat_interval(options.heartbeat_ms) {
+    problems.push(problem);
+  }
+  if let Err(problem) = validation::check_debounce(options.debounce_ms) {
+    problems.push(problem);
+  }
+  if let Err(problem) = validation::check_locked_file_retry(options.locked_file_retry.as_ref()) {
+    problems.push(problem);
+  }
+  if let Err(problem) = validation::check_circuit_breaker(options.circuit_breaker.as_ref()) {
+    problems.push(problem);
+  }
+  if let Err(problem) = validation::check_memory_cap(options.max_memory_bytes, options.max_memory_action.as_deref()) {
+    problems.push(problem);
+  }
+  if let Err(problem) = validation::check_route_match(options.route_match.as_deref()) {
+    problems.push(problem);
+  }
+  if let Err(problem) = validation::check_skip_unchanged(options.skip_unchanged.as_deref()) {
+    problems.push(problem);
+  }
+  if let Err(problem) = validation::check_poll_compare(options.poll_compare.as_deref()) {
+    problems.push(problem);
+  }
+
+  let root = validation::check_directory(&directory, &RetryPolicy::single())
+    .and_then(|root| validation::check_large_root(&root, options.allow_large_roots).map(|()| path_to_clean_string(&root)));
+  let root = match root {
+    Ok(root) => Some(root),
+    Err(problem) => {
+      problems.push(problem);
+      None
+    }
+  };
+
+  if let (Some(root), Some(journal_path)) = (&root, &options.journal_path)
+    && let Err(problem) = validation::check_journal(journal_path, root)
+  {
+    problems.push(problem);
+  }
+
+  OptionsReport { valid: problems.is_empty(), directory: root, problems: problems.into_iter().map(Problem::report).collect() }
+}
+
+/// Check if the watcher would leave out `path` when watching `base_dir` with `options`, using the same
+/// pattern matching as subscriptions. A relative `path` is taken as relative to `base_dir`; neither is resolved
+/// against the file system.
+#[napi(ts_args_type = "path: string, baseDir: string, options?: WatchOptions")]
+pub fn is_ignored(path: String, base_dir: String, options: Option<WatchOptions>) -> Result<bool> {
+  let options = options.unwrap_or_default();
+  let normalization = validation::check_normalization(options.unicode_normalization.as_deref()).map_err(Problem::into_error)?;
+  let ignore_set = build_ignore_set(&ignore_patterns(options.ignore.as_deref().unwrap_or_default(), normalization))?;
+  let base_path = PathBuf::from(base_dir);
+  Ok(ignore_set.is_ignored(&base_path.join(path), &base_path))
+}
+
+/// Resolve with the first event delivered for a directory that matches `options`, unsubscribing right after
+/// the promise settles
+#[napi(ts_args_type = "directory: string, options?: WatchOptions & NextOptions", ts_return_type = "Promise<WatchEvent>")]
+pub fn once<'env>(env: &'env Env, directory: String, options: Option<Object>) -> Result<Object<'env>> {
+  let watch_options = options.as_ref().map(|options| unsafe { WatchOptions::from_napi_value(env.raw(), options.raw()) }).transpose()?;
+  let next_options = options.as_ref().map(|options| unsafe { NextOptions::from_napi_value(env.raw(), options.raw()) }).transpose()?;
+
+  // Events are taken from the subscriber, the callback only has to exist
+  let callback = env.create_function_from_closure::<(), (), _>("once", |_| Ok(()))?;
+  let subscription = start_subscription(*env, directory, callback.to_unknown(), watch_options, None, Overflow::Drop)?;
+  let subscriber = Arc::clone(&subscription.subscriber);
+  waiters::wait(env, &subscriber, &next_options.unwrap_or_default(), None, Some(subscription))
+}
+
+/// Resolve once a file exists or changes, watching its parent directory until then. A file that is already
+/// there, or was modified after `newer_than`, resolves right away with an event whose `is_initial` is set.
+#[napi(ts_args_type = "path: string, options?: WaitForFileOptions", ts_return_type = "Promise<WatchEvent>")]
+pub fn wait_for_file<'env>(env: &'env Env, path: String, options: Option<WaitForFileOptions>) -> Result<Object<'env>> {
+  let options = options.unwrap_or_default();
+  let event = options.event.as_deref().unwrap_or("any");
+  let types = match event {
+    "create" => vec!["create"],
+    // Files replaced by renaming a new one over them are reported as created
+    "update" | "any" => vec!["create", "update"],
+    _ => {
+      unsafe { JsTypeError::from(Error::new(Status::InvalidArg, format!("Unknown event: {}", event))).throw_into(env.raw()) };
+      return Err(Error::new(Status::PendingException, ""));
+    }
+  };
+
+  let file = std::path::absolute(&path).map_err(|e| Error::new(Status::InvalidArg, format!("Invalid path {}: {}", path, e)))?;
+  let (Some(parent), Some(name)) = (file.parent(), file.file_name()) else {
+    unsafe { JsTypeError::from(Error::new(Status::InvalidArg, format!("Path has no parent directory: {}", path))).throw_into(env.raw()) };
+    return Err(Error::new(Status::PendingException, ""));
+  };
+
+  let callback = env.create_function_from_closure::<(), (), _>("waitForFile", |_| Ok(()))?;
+  let subscription = start_subscription(*env, path_to_clean_string(parent), callback.to_unknown(), None, None, Overflow::Drop)?;
+  let subscriber = Arc::clone(&subscription.subscriber);
+  let target = subscriber.root.join(name);
+  let next_options = NextOptions { types: Some(types.into_iter().map(String::from).collect()), timeout_ms: options.timeout_ms };
+  let promise = waiters::wait(env, &subscriber, &next_options, Some(path_to_clean_string(&target)), Some(subscription))?;
+
+  // Checked once the watch is in place, so a file appearing meanwhile is reported either way
+  let existing = fs::metadata(&target).ok().and_then(|metadata| match event {
+    "update" => options.newer_than.filter(|&newer_than| scan::scanned(String::new(), &metadata).entry.mtime_ms > newer_than).map(|_| "update"),
+    _ => Some("create"),
+  });
+  if let Some(event_type) = existing {
+    waiters::notify(&subscriber, &[WatchEvent::at(&target, event_type, true)]);
+  }
+
+  Ok(promise)
+}
+
+/// Iterate over the batches delivered for a directory, closing the subscription when the iteration ends
+#[napi(ts_args_type = "directory: string, options?: WatchOptions", ts_return_type = "WatchIterator")]
+pub fn watch(env: &Env, directory: String, options: Option<WatchOptions>) -> Result<ClassInstance<'_, WatchIterator>> {
+  let undefined = ().into_unknown(env)?;
+  let subscription = start_subscription(*env, directory, undefined, options, None, Overflow::Merge)?;
+  let mut iterator = WatchIterator { subscription: Arc::new(Mutex::new(Some(subscription))) }.into_instance(env)?;
+
+  // The iterator is its own async iterable, so whatever iterates it also keeps it alive
+  let symbol: Unknown = env.get_global()?.get_named_property::<Function>("Symbol")?.get_named_property("asyncIterator")?;
+  let iterable = env.create_function_from_closure::<(), Unknown<'static>, _>("[Symbol.asyncIterator]", |ctx| ctx.this())?;
+  iterator.set_property(symbol, iterable)?;
+  Ok(iterator)
+}
+
+/// Batches of a subscription as an async iterator. Breaking out of the loop closes the subscription,
+/// and an error delivered by the watcher is thrown from `next()` after closing it.
+#[napi]
+pub struct WatchIterator {
+  subscription: Arc<Mutex<Option<Subscription>>>,
+}
+
+#[napi]
+impl WatchIterator {
+  /// Resolve with the next batch, waiting for one unless some are buffered
+  #[napi(ts_return_type = "Promise<IteratorResult<WatchCallbackResult, undefined>>")]
+  pub fn next<'env>(&self, env: &'env Env) -> Result<PromiseRaw<'env, Object<'env>>> {
+    env.spawn_future_with_callback(next_iteration(Arc::clone(&self.subscription)), iteration_result)
+  }
+
+  /// Close the subscription and finish the iteration
+  #[napi(js_name = "return", ts_return_type = "Promise<IteratorResult<WatchCallbackResult, undefined>>")]
+  pub fn finish<'env>(&self, env: &'env Env) -> Result<PromiseRaw<'env, Object<'env>>> {
+    if let Some(mut subscription) = hub::lock(&self.subscription).take() {
+      subscription.close();
+    }
+    env.spawn_future_with_callback(async { Ok(None) }, iteration_result)
+  }
+}
+
+/// Wait for the next batch of an iterated subscription. The subscription is closed once the iteration
+/// ends, which an error delivered by the watcher does as well.
+async fn next_iteration(subscription: Arc<Mutex<Option<Subscription>>>) -> Result<Option<WatchCallbackResult>> {
+  let Some(subscriber) = hub::lock(&subscription).as_ref().map(|subscription| Arc::clone(&subscription.subscriber)) else {
+    return Ok(None);
+  };
+
+  let (sender, receiver) = oneshot::channel();
+  pull::read(
+    &subscriber,
+    Box::new(move |result| {
+      let _ = sender.send(result);
+    }),
+  )?;
+
+  let error = match receiver.await {
+    Ok(Ok(Some(batch))) if batch.error.is_none() => return Ok(Some(batch)),
+    Ok(Ok(Some(batch))) => batch.error,
+    Ok(Ok(None)) | Err(_) => None,
+    Ok(Err(error)) => Some(error),
+  };
+
+  // Closed by return() meanwhile, which ends the iteration without an error
+  match hub::lock(&subscription).take() {
+    Some(mut subscription) => {
+      subscription.close();
+      error.map_or(Ok(None), Err)
+    }
+    None => Ok(None),
+  }
+}
+
+fn iteration_result<'env>(env: &'env Env, batch: Option<WatchCallbackResult>) -> Result<Object<'env>> {
+  let mut result = Object::new(env)?;
+  result.set("done", batch.is_none())?;
+  match batch {
+    Some(batch) => result.set("value", batch)?,
+    None => result.set("value", ())?,
+  }
+  Ok(result)
+}
+
+/// Resume a subscription whose batches were journaled, first delivering the batches recorded after `cursor`
+///
+/// # Arguments
+/// * `journal_path` - The journal the subscription was writing to
+/// * `cursor` - The `cursor` of the last batch that was handled, or 0 to replay everything the journal kept
+/// * `callback` - Function called with (error, events) when changes occur
+/// * `options` - Optional configuration, `journalPath` is replaced by `journal_path`
+///
+/// # Returns
+/// A subscription to the directory the journal was written for
+#[napi(
+  strict,
+  ts_generic_types = "O extends WatchOptions = WatchOptions",
+  ts_args_type = "journalPath: string, cursor: number, callback: O extends { callbackStyle: 'node' } ? (error: Error | null, events: ParcelEvent[]) => void : (result: O extends { serialization: 'json' } ? WatchCallbackJson : WatchCallbackResult) => void, options?: O"
+)]
+pub fn subscribe_since(env: Env, journal_path: String, cursor: i64, callback: Unknown, options: Option<WatchOptions>) -> Result<Subscription> {
+  let Ok(cursor) = u64::try_from(cursor) else {
+    return Err(Error::new(Status::InvalidArg, format!("Cursor must not be negative: {}", cursor)));
+  };
+
+  let directory = journal::root(Path::new(&journal_path)).map_err(|e| journal_error(&journal_path, e))?;
+  let options = WatchOptions { journal_path: Some(journal_path), ..options.unwrap_or_default() };
+  start_subscription(env, directory, callback, Some(options), Some(cursor), Overflow::Drop)
+}
+
+/// Subscribe to a directory, replaying the journaled batches after `since` before any others. Without a
+/// callback, batches are buffered subject to `overflow`.
+fn start_subscription(
+  env: Env,
+  directory: String,
+  callback: Unknown,
+  options: Option<WatchOptions>,
+  since: Option<u64>,
+  overflow: Overflow,
+) -> Result<Subscription> {
+  let (setup, ring_reference) = Setup::new(&env, directory, callback, options, since, overflow)?;
+  let mut registered = setup.register().map_err(|failure| failure.into_error(&env))?;
+  registered.subscription._ring = ring_reference;
+  registered.start(&env)
+}
+
+/// A subscription whose callback and options have been checked on the JS thread, to have its directory
+/// resolved and registered on any thread
+struct Setup {
+  directory: String,
+  options: WatchOptions,
+  since: Option<u64>,
+  callback: Arc<Callback>,
+  ignore_set: IgnoreSet,
+  ignore_filter: IgnoreFilter,
+  prunes: bool,
+  retry: RetryPolicy,
+  serialization: Serialization,
+  entry_limit: Option<EntryLimit>,
+  normalization: Option<Form>,
+  /// Watcher asked for and the interval to poll at
+  backend: (Choice, Duration),
+  reconcile: Option<Duration>,
+  heartbeat: Option<Duration>,
+  debounce: Option<Duration>,
+  /// FSEvents event id to replay the history since
+  since_event_id: Option<u64>,
+  skip_unchanged: Option<Compare>,
+  poll_contents: bool,
+  locked_file_retry: RetryPolicy,
+  logger: Option<Logger>,
+  ring: Option<Arc<Ring>>,
+  pull: Option<Mutex<PullBuffer>>,
+  breaker: Option<Breaker>,
+  memory_cap: Option<MemoryCap>,
+  router: Option<Router>,
+}
+
+/// Why registering a subscription failed, thrown or rejected once back on the JS thread
+pub(crate) enum Failure {
+  Directory(Problem),
+  /// The tree has more entries than `maxWatchedEntries`, counted until the walk stopped
+  TooManyEntries {
+    root: PathBuf,
+    entries: usize,
+    limit: usize,
+  },
+  /// The directory is a filesystem root or the home directory, and `allowLargeRoots` is not set
+  LargeRoot(PathBuf),
+  /// The native watcher could not start, which a subscription may fall back to polling from
+  Backend(Error),
+  Error(Error),
+}
+
+impl From<Error> for Failure {
+  fn from(error: Error) -> Failure {
+    Failure::Error(error)
+  }
+}
+
+impl Failure {
+  /// The error `subscribe` throws and `subscribeAsync` rejects with
+  fn into_error(self, env: &Env) -> Error {
+    match self {
+      Failure::Directory(problem) => problem.into_js_error(env),
+      Failure::TooManyEntries { root, entries, limit } => {
+        let root = path_to_clean_string(&root);
+        let message = format!("Directory has more than maxWatchedEntries ({}) entries, stopped counting at {}: {}", limit, entries, root);
+        let error = env.create_error(Error::new(Status::GenericFailure, message)).and_then(|mut error| {
+          error.set_named_property("code", TOO_MANY_ENTRIES_CODE)?;
+          error.set_named_property("path", root)?;
+          error.set_named_property("entries", entries as f64)?;
+          error.set_named_property("limit", limit as f64)?;
+          Ok(error)
+        });
+        error.map_or_else(|e| e, |error| Error::from(error.to_unknown()))
+      }
+      Failure::LargeRoot(root) => {
+        let root = path_to_clean_string(&root);
+        let error = env.create_error(Error::new(Status::InvalidArg, validation::large_root_message(&root))).and_then(|mut error| {
+          error.set_named_property("code", LARGE_ROOT_CODE)?;
+          error.set_named_property("path", root)?;
+          Ok(error)
+        });
+        error.map_or_else(|e| e, |error| Error::from(error.to_unknown()))
+      }
+      Failure::Backend(error) | Failure::Error(error) => error,
+    }
+  }
+}
+
+/// A subscription whose directory is registered, to be tracked and started on the JS thread
+pub struct Registered {
+  subscription: Subscription,
+  label: Option<String>,
+  replay: Vec<WatchCallbackResult>,
+  emit_initial: bool,
+  reconcile: Option<Duration>,
+  heartbeat: Option<Duration>,
+}
+
+impl Setup {
+  /// Check the callback and options, leaving the file system alone. The ring is referenced from here on,
+  /// by the returned `RingReference`.
+  fn new(
+    env: &Env,
+    directory: String,
+    callback: Unknown,
+    options: Option<WatchOptions>,
+    since: Option<u64>,
+    overflow: Overflow,
+  ) -> Result<(Setup, Option<RingReference>)> {
+    let mut options = options.unwrap_or_default();
+
+    // Without a callback, batches are buffered for `nextBatch`
+    let pull = match (callback.get_type()?, options.pull) {
+      (ValueType::Function, Some(true)) => {
+        unsafe { JsTypeError::from(Error::new(Status::InvalidArg, "A subscription with a callback cannot also be pulled")).throw_into(env.raw()) };
+        return Err(Error::new(Status::PendingException, ""));
+      }
+      (ValueType::Function, _) => false,
+      (ValueType::Undefined | ValueType::Null, _) if options.ring.is_some() => {
+        unsafe { JsTypeError::from(Error::new(Status::InvalidArg, "A subscription with a ring needs a callback to signal events")).throw_into(env.raw()) };
+        return Err(Error::new(Status::PendingException, ""));
+      }
+      // Events only reach the listeners added with `on()`
+      (ValueType::Undefined | ValueType::Null, Some(false)) => false,
+      (ValueType::Undefined | ValueType::Null, _) => true,
+      _ => {
+        unsafe { JsTypeError::from(Error::new(Status::InvalidArg, "Callback must be a function")).throw_into(env.raw()) };
+        return Err(Error::new(Status::PendingException, ""));
+      }
+    };
+
+    // Convert to Function, the buffer or the listeners take the place of a callback without one
+    let callback: Function<Payload, Unknown<'static>> = if callback.get_type()? != ValueType::Function {
+      let placeholder = env.create_function_from_closure::<(), (), _>("pull", |_| Ok(()))?;
+      unsafe { Function::from_napi_value(env.raw(), placeholder.raw())? }
+    } else {
+      unsafe { Function::from_napi_value(env.raw(), callback.raw())? }
+    };
+
+    // Build glob set for ignore patterns (before touching the file system so invalid patterns are never retried)
+    let normalization = validation::check_normalization(options.unicode_normalization.as_deref()).map_err(Problem::into_error)?;
+    let backend = validation::check_backend(options.backend.as_deref(), options.poll_interval_ms).map_err(Problem::into_error)?;
+    let reconcile = validation::check_reconcile_interval(options.reconcile_interval_ms).map_err(Problem::into_error)?;
+    let heartbeat = validation::check_heartbeat_interval(options.heartbeat_ms).map_err(Problem::into_error)?;
+    let debounce = validation::check_debounce(options.debounce_ms).map_err(Problem::into_error)?;
+    let since_event_id = validation::check_since_event_id(options.since_event_id, backend.0).map_err(Problem::into_error)?;
+    let skip_unchanged = validation::check_skip_unchanged(options.skip_unchanged.as_deref()).map_err(Problem::into_error)?;
+    let poll_contents = validation::check_poll_compare(options.poll_compare.as_deref()).map_err(Problem::into_error)?;
+    let ignore_patterns = ignore_patterns(options.ignore.as_deref().unwrap_or_default(), normalization);
+    let ignore_set = build_ignore_set(&ignore_patterns)?;
+    let ignore_filter = IgnoreFilter::new(&ignore_patterns).map_err(|e| Error::new(Status::GenericFailure, format!("Failed to build glob set: {}", e)))?;
+    // Plain names ignore the trees of the directories they match as well, so the expanded patterns tell
+    let prunes = ignore_set.may_ignore_trees() || options.one_file_system.unwrap_or(false);
+
+    let retry = RetryPolicy::from_options(options.retry.as_ref())?;
+    let locked_file_retry = validation::check_locked_file_retry(options.locked_file_retry.as_ref()).map_err(Problem::into_error)?;
+    let log_level = validation::check_log_level(options.log_level.as_deref()).map_err(Problem::into_error)?;
+    let serialization = validation::check_serialization(options.serialization.as_deref(), options.callback_style.as_deref()).map_err(Problem::into_error)?;
+    let entry_limit = validation::check_entry_limit(options.max_watched_entries, options.max_watched_entries_action.as_deref()).map_err(Problem::into_error)?;
+    let breaker = validation::check_circuit_breaker(options.circuit_breaker.as_ref()).map_err(Problem::into_error)?;
+    let memory_cap = validation::check_memory_cap(options.max_memory_bytes, options.max_memory_action.as_deref()).map_err(Problem::into_error)?;
+
+    let (ring, ring_reference) = match options.ring.as_ref() {
+      Some(buffer) => {
+        let overflow = validation::check_ring(env, buffer, options.ring_overflow.as_deref()).map_err(Problem::into_error)?;
+        let (ring, reference) = ring::attach(env, buffer, overflow).map_err(|message| Error::new(Status::InvalidArg, message))?;
+        (Some(ring), Some(reference))
+      }
+      None => (None, None),
+    };
+
+    // Create threadsafe function for calling back to JS
+    let callback = Arc::new(callback.build_threadsafe_function().build()?);
+    let setup = Setup {
+      directory,
+      since,
+      callback,
+      ignore_set,
+      ignore_filter,
+      prunes,
+      retry,
+      serialization,
+      entry_limit,
+      normalization,
+      backend,
+      reconcile,
+      heartbeat,
+      debounce,
+      since_event_id,
+      skip_unchanged,
+      poll_contents,
+      locked_file_retry,
+      logger: options.on_log.take().map(|hook| Logger::new(hook, log_level)),
+      ring,
+      pull: pull.then(|| Mutex::new(PullBuffer::new(overflow))),
+      breaker,
+      memory_cap,
+      router: None,
+      options,
+    };
+    Ok((setup, ring_reference))
+  }
+
+  /// Resolve the directory, open the journal and capture, and register the directory with the OS watcher.
+  /// Blocks for as long as the file system takes, events are delivered from the moment the directory is registered.
+  fn register(self) -> std::result::Result<Registered, Failure> {
+    let Setup {
+      directory,
+      options,
+      since,
+      callback,
+      ignore_set,
+      ignore_filter,
+      prunes,
+      retry,
+      serialization,
+      entry_limit,
+      normalization,
+      backend,
+      reconcile,
+      heartbeat,
+      debounce,
+      since_event_id,
+      skip_unchanged,
+      poll_contents,
+      locked_file_retry,
+      logger,
+      ring,
+      pull,
+      breaker,
+      memory_cap,
+      router,
+    } = self;
+
+    let base_path = validation::check_directory(&directory, &retry).map_err(Failure::Directory)?;
+    if !options.allow_large_roots.unwrap_or(false) && validation::is_large_root(&base_path) {
+      return Err(Failure::LargeRoot(base_path));
+    }
+    let (polling, network_warning) = network::choose(backend.0, options.auto_fallback.unwrap_or(true), backend.1, &base_path);
+    if polling.is_some() && since_event_id.is_some() {
+      let message = format!("sinceEventId is unsupported on the polling backend this directory is watched with: {}", path_to_clean_string(&base_path));
+      return Err(Failure::Error(Error::new(Status::InvalidArg, message)));
+    }
+    let emit_initial = options.emit_initial.unwrap_or(false);
+
+    let (journal, mut replay) = match options.journal_path.as_ref() {
+      Some(journal_path) => {
+        let max_bytes = options.journal_max_bytes.map_or(journal::DEFAULT_MAX_BYTES, u64::from);
+        let (journal, records) = open_journal(journal_path, &path_to_clean_string(&base_path), max_bytes)?;
+        let replay = match since {
+          Some(cursor) => replay_since(journal_path, records, cursor)?,
+          None => Vec::new(),
+        };
+        (Some(Mutex::new(journal)), replay)
+      }
+      None => (None, Vec::new()),
+    };
+
+    // A capture that cannot be opened is reported with the warnings of the registration instead of failing
+    let mut capture_warning = None;
+    let capture =
+      options.debug_event_log.as_deref().and_then(|path| Capture::open(path).inspect_err(|e| capture_warning = Some(Capture::warning(path, e))).ok());
+
+    let containment = Containment::new(&base_path, options.flag_outside_root.unwrap_or(false));
+    let mounts = if options.one_file_system.unwrap_or(false) { Mounts::new(&base_path) } else { None };
+    let case_insensitive = case::is_case_insensitive(&base_path);
+    let fsevents_device = if polling.is_none() && backend.0 != Choice::Mock { history::device(&base_path) } else { None };
+    let subscriber = Arc::new(Subscriber {
+      root: base_path,
+      ignore_set,
+      ignore_filter,
+      callback,
+      running: AtomicBool::new(true),
+      counters: Arc::new(StatsCounters::default()),
+      delivered_at: Mutex::new(Instant::now()),
+      strict: options.strict.unwrap_or(false),
+      prunes,
+      ignore_permission_errors: options.ignore_permission_errors.unwrap_or(false),
+      failure: Mutex::new(None),
+      held: Mutex::new((emit_initial || !replay.is_empty() || since_event_id.is_some()).then(Vec::new)),
+      baseline: Mutex::new(None),
+      journal,
+      waiters: Mutex::new(Vec::new()),
+      activity: Mutex::new(Activity::new()),
+      expectations: Mutex::new(Expectations::default()),
+      mutes: Mutex::new(Mutes::default()),
+      logger,
+      capture,
+      serialization,
+      ring,
+      cache_limit: options.cache_limit.map(|limit| limit as usize),
+      entry_limit,
+      normalization,
+      polling: polling.map_or_else(OnceLock::new, OnceLock::from),
+      fallback: (backend.0 == Choice::Auto).then_some(backend.1),
+      poll_contents,
+      mock: (backend.0 == Choice::Mock).then(|| Arc::new(MockSource::default())),
+      locked_file_retry,
+      accessible: AtomicBool::new(true),
+      rewatch_on_access: options.rewatch_on_access.unwrap_or(true),
+      emit_metadata: options.emit_metadata.unwrap_or(false),
+      emit_xattr: options.emit_xattr.unwrap_or(false),
+      coalesce: options.coalesce.unwrap_or(false),
+      normalize_events: options.normalize.unwrap_or(false),
+      drop_parent_updates: options.drop_parent_updates.unwrap_or(options.normalize.unwrap_or(false)),
+      detect_type_changes: options.detect_type_changes.unwrap_or(false),
+      dedupe_hardlinks: options.dedupe_hardlinks.unwrap_or(false),
+      include_root: options.include_root.unwrap_or(true),
+      containment,
+      mounts,
+      case_insensitive,
+      special_files: options.skip_special_files.unwrap_or(false).then(|| Mutex::new(SpecialFiles::default())),
+      raw_kinds: options.raw_kinds.unwrap_or(false),
+      track_coalescing: options.track_coalescing.unwrap_or(false),
+      unchanged: skip_unchanged.map(|compare| Mutex::new(Unchanged::new(compare))),
+      pull,
+      breaker,
+      memory_cap,
+      debounce,
+      repeats: match options.repeated_error_window_ms {
+        Some(0) => None,
+        window => Some(Mutex::new(Repeats::new(window.map_or(repeats::DEFAULT_WINDOW, |ms| Duration::from_millis(u64::from(ms)))))),
+      },
+      fsevents_device,
+      cache_budget: Mutex::new(None),
+      router,
+      listeners: Mutex::new(Vec::new()),
+    });
+
+    // Join the shared watcher, registering the directory unless a parent is already watched
+    let id = registry::next_id();
+    let isolated = options.isolated.unwrap_or(false) || debounce.is_some();
+    let (hub, mut warnings) = Hub::attach(id, Arc::clone(&subscriber), !isolated, &retry)?;
+    warnings.extend(capture_warning);
+    warnings.extend(network_warning);
+    warnings.extend(subscriber.mounts.iter().flat_map(Mounts::warnings));
+
+    subscriber.log(Level::Info, || {
+      let watcher = if isolated { "an isolated watcher" } else { "the shared watcher" };
+      let registration = if hub.is_attached(&subscriber.root) {
+        "attached to the watch of a parent directory".to_string()
+      } else {
+        format!("directories registered: {}", hub.watch_count(&subscriber.root))
+      };
+      format!("watching {} with {} ({}, {})", path_to_clean_string(&subscriber.root), watcher, subscriber.backend_name(), registration)
+    });
+    if let Some(polling) = subscriber.polling.get() {
+      subscriber.log(Level::Info, || format!("polling every {}ms: {}", polling.interval.as_millis(), polling.reason));
+    }
+    for warning in &warnings {
+      subscriber.log(Level::Warn, || warning.message.clone());
+    }
+
+    // Report skipped directories once
+    if !warnings.is_empty() {
+      hub::deliver(&subscriber, WatchCallbackResult { warnings: Some(warnings), ..Default::default() });
+    }
+
+    // Live events are held from the moment the directory is registered, so the history reaches up to them
+    if let Some(since) = since_event_id {
+      replay.push(history::batch(&subscriber, since));
+    }
+
+    let warn_if_leaked = options.warn_if_leaked.unwrap_or(false);
+    let subscription = Subscription { hub, subscriber, warn_if_leaked, id, registered_at: Instant::now(), _ring: None, listeners: Vec::new() };
+    Ok(Registered { subscription, label: options.label, replay, emit_initial, reconcile, heartbeat })
+  }
+}
+
+impl Registered {
+  /// Track the subscription in its environment and start delivering what happened before it was registered
+  fn start(self, env: &Env) -> Result<Subscription> {
+    let Registered { mut subscription, label, replay, emit_initial, reconcile, heartbeat } = self;
+
+    // Tear the subscription down when the environment exits
+    let entry = registry::Entry {
+      hub: Arc::clone(&subscription.hub),
+      subscriber: Arc::clone(&subscription.subscriber),
+      label,
+      created_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |duration| duration.as_secs_f64() * 1000.0),
+    };
+    registry::register(env, subscription.id, entry)?;
+
+    // Deliver the journaled batches the consumer has not handled, then what is already in the directory,
+    // before any live events
+    if emit_initial {
+      for batch in replay {
+        subscription.subscriber.send(batch);
+      }
+    } else if !replay.is_empty() {
+      subscription.subscriber.deliver_walked(replay, None);
+    }
+
+    if emit_initial && let Err(e) = scan::spawn(Arc::clone(&subscription.subscriber)) {
+      subscription.close();
+      return Err(Error::new(Status::GenericFailure, format!("Failed to start initial scan: {}", e)));
+    }
+
+    if let Some(interval) = reconcile
+      && let Err(e) = reconcile::spawn(&subscription.subscriber, interval, emit_initial)
+    {
+      subscription.close();
+      return Err(Error::new(Status::GenericFailure, format!("Failed to start reconciling: {}", e)));
+    }
+
+    if let Some(interval) = heartbeat
+      && let Err(e) = heartbeat::spawn(&subscription.subscriber, interval)
+    {
+      subscription.close();
+      return Err(Error::new(Status::GenericFailure, format!("Failed to start heartbeats: {}", e)));
+    }
+
+    if subscription.subscriber.breaker.as_ref().is_some_and(Breaker::cools_down)
+      && let Err(e) = storm::spawn(&subscription.subscriber)
+    {
+      subscription.close();
+      return Err(Error::new(Status::GenericFailure, format!("Failed to start the circuit breaker: {}", e)));
+    }
+
+    Ok(subscription)
+  }
+}
+
+/// Background registration for `subscribeAsync`
+pub struct SubscribeTask {
+  /// Nothing when the arguments were rejected on the JS thread already
+  setup: Option<Setup>,
+  ring: Option<RingReference>,
+  /// Why the subscription failed, rejected with as `subscribe` would throw it
+  failure: Option<Failure>,
+}
+
+impl Task for SubscribeTask {
+  type Output = Registered;
+  type JsValue = Subscription;
+
+  fn compute(&mut self) -> Result<Registered> {
+    let Some(setup) = self.setup.take() else {
+      return Err(Error::from_status(Status::InvalidArg));
+    };
+
+    setup.register().map_err(|failure| {
+      self.failure = Some(failure);
+      Error::from_status(Status::GenericFailure)
+    })
+  }
+
+  fn resolve(&mut self, env: Env, mut registered: Registered) -> Result<Subscription> {
+    registered.subscription._ring = self.ring.take();
+    registered.start(&env)
+  }
+
+  fn reject(&mut self, env: Env, error: Error) -> Result<Subscription> {
+    Err(self.failure.take().map_or(error, |failure| failure.into_error(&env)))
+  }
+}
+
+/// Open a journal for appending, returning it together with the records it already contains
+fn open_journal(journal_path: &str, root: &str, max_bytes: u64) -> Result<(Journal, Vec<journal::Record>)> {
+  let records = validation::check_journal(journal_path, root).map_err(Problem::into_error)?;
+
+  let cursor = records.last().map_or(0, |record| record.cursor);
+  let journal = Journal::open(Path::new(journal_path), root, cursor, max_bytes)
+    .map_err(|e| Error::new(Status::GenericFailure, format!("Failed to open journal {}: {}", journal_path, e)))?;
+  Ok((journal, records))
+}
+
+/// Turn the journaled records after `cursor` into batches, failing if the journal no longer contains all of them
+fn replay_since(journal_path: &str, records: Vec<journal::Record>, cursor: u64) -> Result<Vec<WatchCallbackResult>> {
+  let last = records.last().map_or(0, |record| record.cursor);
+  if cursor > last {
+    return Err(Error::new(Status::InvalidArg, format!("Cursor {} is ahead of the journal, which ends at {}: {}", cursor, last, journal_path)));
+  }
+
+  // Records before the oldest one kept were rotated away
+  if let Some(first) = records.first()
+    && cursor + 1 < first.cursor
+  {
+    return Err(Error::new(
+      Status::InvalidArg,
+      format!("Journal no longer contains the batches after cursor {}, it starts at {}: {}", cursor, first.cursor, journal_path),
+    ));
+  }
+
+  Ok(
+    records
+      .into_iter()
+      .filter(|record| record.cursor > cursor)
+      .map(|record| WatchCallbackResult { events: record.events, cursor: Some(record.cursor as i64), ..Default::default() })
+      .collect(),
+  )
+}
+
+/// Describe why a journal cannot be used
+fn journal_error(journal_path: &str, error: FormatError) -> Error {
+  let message = match error {
+    FormatError::NotFound => "Journal does not exist".to_string(),
+    FormatError::Corrupt(reason) => format!("Journal is corrupt ({})", reason),
+    FormatError::UnsupportedVersion(version) => format!("Journal format version {} is not supported", version),
+    FormatError::Io(e) => return Error::new(Status::GenericFailure, format!("Failed to read journal {}: {}", journal_path, e)),
+  };
+  Error::new(Status::InvalidArg, format!("{}: {}", message, journal_path))
+}
+
+/// Number of background threads dispatching events to subscriptions.
+/// Subscriptions share one thread unless they are isolated. Only available in builds with the `faults` feature.
+#[cfg(feature = "faults")]
+#[napi]
+pub fn dispatch_thread_count() -> u32 {
+  hub::dispatch_threads()
+}
+
+/// Inject `faults`, adding to the counts of those still pending and replacing the patterns of `statFailures`,
+/// `lockedPaths` and `missedPaths` if given. Only available in builds with the `faults` feature.
+#[cfg(feature = "faults")]
+#[napi]
+pub fn inject_faults(faults: faults::Faults) -> Result<()> {
+  faults::inject(faults)
+}
+
+/// Drop every fault still pending. Only available in builds with the `faults` feature.
+#[cfg(feature = "faults")]
+#[napi]
+pub fn clear_faults() {
+  faults::clear()
+}
+
+/// List the entries of a directory tree that a subscription with the same options would report,
+/// without registering any watches
+#[napi(ts_args_type = "directory: string, options?: WatchOptions", ts_return_type = "Promise<Array<ScanEntry>>")]
+pub fn scan(directory: String, options: Option<WatchOptions>) -> AsyncTask<ScanTask> {
+  AsyncTask::new(ScanTask { directory, options: options.unwrap_or_default() })
+}
+
+pub struct ScanTask {
+  directory: String,
+  options: WatchOptions,
+}
+
+impl Task for ScanTask {
+  type Output = Vec<ScanEntry>;
+  type JsValue = Vec<ScanEntry>;
+
+  fn compute(&mut self) -> Result<Vec<ScanEntry>> {
+    Ok(scan_tree(&self.directory, &self.options)?.1.into_iter().map(|scanned| scanned.entry).collect())
+  }
+
+  fn resolve(&mut self, _env: Env, output: Vec<ScanEntry>) -> Result<Vec<ScanEntry>> {
+    Ok(output)
+  }
+}
+
+/// Walk a directory with the ignore rules of `options`, returning its canonical path and the entries found
+fn scan_tree(directory: &str, options: &WatchOptions) -> Result<(PathBuf, Vec<Scanned>)> {
+  let (base_path, ignore_set) = open_tree(directory, options)?;
+  let entries = walk_tree(&base_path, &ignore_set, options)?;
+  Ok((base_path, entries))
+}
+
+/// Resolve a directory to walk and the ignore rules to walk it with
+fn open_tree(directory: &str, options: &WatchOptions) -> Result<(PathBuf, IgnoreSet)> {
+  let ignore_set = build_ignore_set(options.ignore.as_deref().unwrap_or_default())?;
+  let retry = RetryPolicy::from_options(options.retry.as_ref())?;
+  let base_path = validation::check_directory(directory, &retry).map_err(Problem::into_error)?;
+  Ok((base_path, ignore_set))
+}
+
+fn walk_tree(base_path: &PathBuf, ignore_set: &IgnoreSet, options: &WatchOptions) -> Result<Vec<Scanned>> {
+  let mounts = if options.one_file_system.unwrap_or(false) { Mounts::new(base_path) } else { None };
+  let mut entries = Vec::new();
+  scan::walk(base_path, base_path, ignore_set, mounts.as_ref(), options.strict.unwrap_or(false), &mut |entry| {
+    // Entries removed while the tree is being walked are left out
+    if let Ok(metadata) = entry.metadata()
+      && let Some(scanned) = tree_entry(&entry.path(), &metadata, options)
+    {
+      entries.push(scanned);
+    }
+    true
+  })
+  .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))?;
+
+  Ok(entries)
+}
+
+/// Describe an entry of a tree walked with `options`, unless they leave it out
+fn tree_entry(path: &Path, metadata: &fs::Metadata, options: &WatchOptions) -> Option<Scanned> {
+  if options.skip_special_files.unwrap_or(false) && special::is_special(metadata.file_type()) {
+    return None;
+  }
+  Some(scan::scanned(path_to_clean_string(path), metadata))
+}
+
+/// Record the state of a directory tree for `writeSnapshot` and `getEventsSince`. With `changeJournal`, the
+/// changes the change journal recorded since `previous`, an earlier snapshot of the tree, are applied to its
+/// entries, and the tree is only walked where the journal cannot tell them. Returns the canonical path of the
+/// directory and the entries, with where the journal stood where it keeps one.
+fn snapshot_tree(directory: &str, options: &WatchOptions, previous: Option<&Snapshot>) -> Result<(PathBuf, Vec<Scanned>, Option<usn::Cursor>)> {
+  let (base_path, ignore_set) = open_tree(directory, options)?;
+  if !options.change_journal.unwrap_or(false) {
+    let entries = walk_tree(&base_path, &ignore_set, options)?;
+    return Ok((base_path, entries, None));
+  }
+
+  if let Some(previous) = previous.filter(|previous| previous.root == path_to_clean_string(&base_path))
+    && let Some(cursor) = previous.cursor
+    && let Ok((changes, cursor)) = usn::changes_since(&base_path, cursor)
+  {
+    let record = |path: &Path, metadata: &fs::Metadata| tree_entry(path, metadata, options);
+    let mounts = if options.one_file_system.unwrap_or(false) { Mounts::new(&base_path) } else { None };
+    let entries = usn::apply(&base_path, &previous.entries, &changes, &ignore_set, mounts.as_ref(), options.strict.unwrap_or(false), &record)
+      .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))?;
+    return Ok((base_path, entries, Some(cursor)));
+  }
+
+  // Taken before the walk, so that changes made during it are read again next time rather than missed
+  let cursor = usn::cursor(&base_path).ok();
+  let entries = walk_tree(&base_path, &ignore_set, options)?;
+  Ok((base_path, entries, cursor))
+}
+
+/// Compare two results of `scan`, returning the events that turn the first into the second.
+/// With `onChunk`, events are passed to it in chunks instead and an empty array is returned.
+#[napi(ts_args_type = "before: Array<ScanEntry>, after: Array<ScanEntry>, onChunk?: (events: Array<WatchEvent>) => void")]
+pub fn diff_trees(before: Vec<ScanEntry>, after: Vec<ScanEntry>, on_chunk: Option<Function<Vec<WatchEvent>, Unknown>>) -> Result<Vec<WatchEvent>> {
+  let mut events = Vec::new();
+  let mut result = Ok(());
+
+  scan::diff(&before, &after, |entry| entry, scan::entry_changed, &mut |event| {
+    // Once the chunk callback has thrown, the remaining events are dropped
+    if result.is_err() {
+      return;
+    }
+    events.push(event);
+
+    if let Some(on_chunk) = &on_chunk
+      && events.len() >= DIFF_CHUNK_SIZE
+    {
+      result = on_chunk.call(std::mem::take(&mut events)).map(|_| ());
+    }
+  });
+  result?;
+
+  match on_chunk {
+    Some(on_chunk) if !events.is_empty() => {
+      on_chunk.call(events)?;
+      Ok(Vec::new())
+    }
+    _ => Ok(events),
+  }
+}
+
+/// Record the current state of a directory tree in `snapshotPath`, for `getEventsSince` to compare against later
+#[napi(ts_args_type = "directory: string, snapshotPath: string, options?: WatchOptions", ts_return_type = "Promise<void>")]
+pub fn write_snapshot(directory: String, snapshot_path: String, options: Option<WatchOptions>) -> AsyncTask<WriteSnapshotTask> {
+  AsyncTask::new(WriteSnapshotTask { directory, snapshot_path, options: options.unwrap_or_default() })
+}
+
+pub struct WriteSnapshotTask {
+  directory: String,
+  snapshot_path: String,
+  options: WatchOptions,
+}
+
+impl Task for WriteSnapshotTask {
+  type Output = ();
+  type JsValue = ();
+
+  fn compute(&mut self) -> Result<()> {
+    // The snapshot written last time, brought up to date instead of walking the tree where the journal can tell
+    let previous = match self.options.change_journal {
+      Some(true) => snapshot::read(Path::new(&self.snapshot_path)).and_then(|bytes| snapshot::decode(&bytes)).ok(),
+      _ => None,
+    };
+    let (base_path, entries, cursor) = snapshot_tree(&self.directory, &self.options, previous.as_ref())?;
+    let bytes = snapshot::encode(&path_to_clean_string(&base_path), cursor, &entries);
+
+    snapshot::write(Path::new(&self.snapshot_path), &bytes)
+      .map_err(|e| Error::new(Status::GenericFailure, format!("Failed to write snapshot {}: {}", self.snapshot_path, e)))
+  }
+
+  fn resolve(&mut self, _env: Env, _output: ()) -> Result<()> {
+    Ok(())
+  }
+}
+
+/// Get the events that turn the state recorded by `writeSnapshot` into the current state of the directory tree
+#[napi(ts_args_type = "directory: string, snapshotPath: string, options?: WatchOptions", ts_return_type = "Promise<Array<WatchEvent>>")]
+pub fn get_events_since(directory: String, snapshot_path: String, options: Option<WatchOptions>) -> AsyncTask<EventsSinceTask> {
+  AsyncTask::new(EventsSinceTask { directory, snapshot_path, options: options.unwrap_or_default() })
+}
+
+pub struct EventsSinceTask {
+  directory: String,
+  snapshot_path: String,
+  options: WatchOptions,
+}
+
+impl Task for EventsSinceTask {
+  type Output = Vec<WatchEvent>;
+  type JsValue = Vec<WatchEvent>;
+
+  fn compute(&mut self) -> Result<Vec<WatchEvent>> {
+    let snapshot_error = |message: String| Error::new(Status::InvalidArg, format!("{}: {}", message, self.snapshot_path));
+    let snapshot = match snapshot::read(Path::new(&self.snapshot_path)).and_then(|bytes| snapshot::decode(&bytes)) {
+      Ok(snapshot) => snapshot,
+      Err(FormatError::NotFound) => return Err(snapshot_error("Snapshot does not exist".to_string())),
+      Err(FormatError::Corrupt(reason)) => return Err(snapshot_error(format!("Snapshot is corrupt ({})", reason))),
+      Err(FormatError::UnsupportedVersion(version)) => return Err(snapshot_error(format!("Snapshot format version {} is not supported", version))),
+      Err(FormatError::Io(e)) => return Err(Error::new(Status::GenericFailure, format!("Failed to read snapshot {}: {}", self.snapshot_path, e))),
+    };
+
+    let (base_path, after, _) = snapshot_tree(&self.directory, &self.options, Some(&snapshot))?;
+    if snapshot.root != path_to_clean_string(&base_path) {
+      return Err(snapshot_error(format!("Snapshot was taken of {}, not {}", snapshot.root, self.directory)));
+    }
+
+    let mut events = Vec::new();
+    scan::diff(
+      &snapshot.entries,
+      &after,
+      |scanned| &scanned.entry,
+      |old, new| old.file_id != new.file_id || scan::entry_changed(&old.entry, &new.entry),
+      &mut |event| events.push(event),
+    );
+    Ok(events)
+  }
+
+  fn resolve(&mut self, _env: Env, output: Vec<WatchEvent>) -> Result<Vec<WatchEvent>> {
+    Ok(output)
+  }
 }
@@ -0,0 +1,98 @@
+//! Listeners added to a subscription with `on()`, each called with the delivered events of one type, or with
+//! the errors or warnings delivered
+
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
+
+use crate::hub::{Subscriber, lock};
+use crate::{WatchCallbackResult, WatchEvent, WatchWarning};
+
+pub(crate) type ListenerCallback = ThreadsafeFunction<Emitted, Unknown<'static>, Emitted, Status, false>;
+
+/// What a listener listens for
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Kind {
+  Create,
+  Update,
+  Delete,
+  Metadata,
+  Error,
+  Warning,
+}
+
+impl Kind {
+  pub fn parse(name: &str) -> Option<Kind> {
+    match name {
+      "create" => Some(Kind::Create),
+      "update" => Some(Kind::Update),
+      "delete" => Some(Kind::Delete),
+      "metadata" => Some(Kind::Metadata),
+      "error" => Some(Kind::Error),
+      "warning" => Some(Kind::Warning),
+      _ => None,
+    }
+  }
+
+  /// The kind of listener an event goes to
+  fn of(event: &WatchEvent) -> Kind {
+    match event.event_type.as_str() {
+      "create" => Kind::Create,
+      "update" => Kind::Update,
+      "metadata" => Kind::Metadata,
+      _ => Kind::Delete,
+    }
+  }
+}
+
+/// What a listener is called with
+pub enum Emitted {
+  Event(WatchEvent),
+  Error(Error),
+  Warning(WatchWarning),
+}
+
+impl ToNapiValue for Emitted {
+  unsafe fn to_napi_value(env: napi::sys::napi_env, emitted: Emitted) -> Result<napi::sys::napi_value> {
+    match emitted {
+      Emitted::Event(event) => unsafe { WatchEvent::to_napi_value(env, event) },
+      Emitted::Error(error) => unsafe { Error::to_napi_value(env, error) },
+      Emitted::Warning(warning) => unsafe { WatchWarning::to_napi_value(env, warning) },
+    }
+  }
+}
+
+/// A listener of a subscription, under the id its `Subscription` knows it by
+pub(crate) struct Listener {
+  pub id: u64,
+  pub kind: Kind,
+  pub callback: ListenerCallback,
+}
+
+/// Call the listeners of a subscription with what `batch` carries for them, each event in a call of its own
+pub(crate) fn emit(subscriber: &Subscriber, batch: &WatchCallbackResult) {
+  let listeners = lock(&subscriber.listeners);
+  if listeners.is_empty() {
+    return;
+  }
+
+  for listener in listeners.iter() {
+    let call = |emitted| listener.callback.call(emitted, ThreadsafeFunctionCallMode::NonBlocking);
+    match listener.kind {
+      Kind::Error => {
+        if let Some(error) = &batch.error {
+          call(Emitted::Error(Error::new(error.status, error.reason.clone())));
+        }
+      }
+      Kind::Warning => {
+        for warning in batch.warnings.iter().flatten() {
+          call(Emitted::Warning(warning.clone()));
+        }
+      }
+      kind => {
+        for event in batch.events.iter().filter(|event| Kind::of(event) == kind) {
+          call(Emitted::Event(event.clone()));
+        }
+      }
+    }
+  }
+}
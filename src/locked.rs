@@ -0,0 +1,88 @@
+//! Reading the metadata of changed files that antivirus and indexing services briefly lock on Windows right
+//! after they change, which fails with a sharing or lock violation until they let go
+
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use crate::RetryPolicy;
+use crate::hub::Subscriber;
+use crate::logging::Level;
+use crate::long_paths;
+
+/// `ERROR_SHARING_VIOLATION` and `ERROR_LOCK_VIOLATION`
+const LOCK_ERRORS: [i32; 2] = [32, 33];
+
+/// Retries of a locked file unless `lockedFileRetry` says otherwise: 3 attempts over 50ms
+pub(crate) fn default_retry() -> RetryPolicy {
+  RetryPolicy { attempts: 3, delay: Duration::from_millis(25), backoff: 1.0 }
+}
+
+/// Outcome of reading the metadata of a path, without following symlinks
+pub(crate) enum Access {
+  Found(Metadata),
+  /// The path is gone, or cannot be read for a reason waiting will not fix
+  Missing,
+  Locked,
+}
+
+pub(crate) fn symlink_metadata(path: &Path) -> Access {
+  #[cfg(feature = "faults")]
+  if crate::faults::locked(path) {
+    return Access::Locked;
+  }
+  #[cfg(feature = "faults")]
+  if crate::faults::stat_fails(path) {
+    return Access::Missing;
+  }
+
+  match fs::symlink_metadata(long_paths::verbatim(path)) {
+    Ok(metadata) => Access::Found(metadata),
+    Err(e) if is_locked(&e) => Access::Locked,
+    Err(_) => Access::Missing,
+  }
+}
+
+fn is_locked(error: &io::Error) -> bool {
+  cfg!(windows) && error.raw_os_error().is_some_and(|code| LOCK_ERRORS.contains(&code))
+}
+
+/// Read the metadata of `paths` found locked again, sleeping between attempts as the subscriber's
+/// `lockedFileRetry` says. Called once the batch the paths came with has gone out, so that waiting for them
+/// delays no events. Returns the metadata of the paths that could be read, or None for those that are gone;
+/// the paths that stayed locked are counted as failures.
+pub(crate) fn retry(subscriber: &Subscriber, mut paths: Vec<PathBuf>) -> Vec<(PathBuf, Option<Metadata>)> {
+  let policy = &subscriber.locked_file_retry;
+  let mut delay = policy.delay;
+  let mut found = Vec::new();
+
+  for _ in 1..policy.attempts {
+    if paths.is_empty() {
+      break;
+    }
+    thread::sleep(delay);
+    delay = policy.next_delay(delay);
+
+    subscriber.counters.locked_retries.fetch_add(u32::try_from(paths.len()).unwrap_or(u32::MAX), Ordering::Relaxed);
+    paths.retain(|path| match symlink_metadata(path) {
+      Access::Found(metadata) => {
+        found.push((path.clone(), Some(metadata)));
+        false
+      }
+      Access::Missing => {
+        found.push((path.clone(), None));
+        false
+      }
+      Access::Locked => true,
+    });
+  }
+
+  if !paths.is_empty() {
+    subscriber.counters.locked_failures.fetch_add(u32::try_from(paths.len()).unwrap_or(u32::MAX), Ordering::Relaxed);
+    subscriber.log(Level::Debug, || format!("gave up reading {} locked files", paths.len()));
+  }
+  found
+}
@@ -0,0 +1,130 @@
+//! Diagnostics passed to the `onLog` hook of a subscription, telling what the native side observed, filtered and delivered
+
+use std::borrow::Cow;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use notify::EventKind;
+
+use crate::hub::lock;
+use crate::path_to_clean_string;
+
+/// The `onLog` hook. Weak, as the hook alone must not keep the process alive.
+pub(crate) type LogHook = ThreadsafeFunction<FnArgs<(&'static str, String)>, Unknown<'static>, FnArgs<(&'static str, String)>, Status, false, true>;
+
+/// Raw events logged per subscription and window, before further ones are only counted
+const RAW_EVENT_LIMIT: u32 = 100;
+const RAW_EVENT_WINDOW: Duration = Duration::from_secs(1);
+
+/// Severity of a log line, from the most to the least severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Level {
+  Error,
+  Warn,
+  Info,
+  Debug,
+  Trace,
+}
+
+impl Level {
+  pub const DEFAULT: Level = Level::Info;
+
+  pub fn parse(name: &str) -> Option<Level> {
+    match name {
+      "error" => Some(Level::Error),
+      "warn" => Some(Level::Warn),
+      "info" => Some(Level::Info),
+      "debug" => Some(Level::Debug),
+      "trace" => Some(Level::Trace),
+      _ => None,
+    }
+  }
+
+  fn name(self) -> &'static str {
+    match self {
+      Level::Error => "error",
+      Level::Warn => "warn",
+      Level::Info => "info",
+      Level::Debug => "debug",
+      Level::Trace => "trace",
+    }
+  }
+}
+
+/// Raw events logged in the current window, and how many were left out
+struct RawEvents {
+  window_start: Instant,
+  logged: u32,
+  dropped: u32,
+}
+
+/// Sends the log lines of a subscription at or above its level to its hook
+pub(crate) struct Logger {
+  hook: LogHook,
+  level: Level,
+  raw_events: Mutex<RawEvents>,
+}
+
+impl Logger {
+  pub fn new(hook: LogHook, level: Level) -> Logger {
+    Logger { hook, level, raw_events: Mutex::new(RawEvents { window_start: Instant::now(), logged: 0, dropped: 0 }) }
+  }
+
+  pub fn enabled(&self, level: Level) -> bool {
+    level <= self.level
+  }
+
+  pub fn write(&self, level: Level, message: String) {
+    self.hook.call((level.name(), message).into(), ThreadsafeFunctionCallMode::NonBlocking);
+  }
+
+  /// Log an event as the OS watcher reported it, at most `RAW_EVENT_LIMIT` per window so a burst of events
+  /// cannot flood the JS thread. The events left out are counted in a line once the window has passed.
+  pub fn raw_event(&self, path: &Path, kind: &EventKind) {
+    if !self.enabled(Level::Trace) {
+      return;
+    }
+
+    let dropped = {
+      let mut raw_events = lock(&self.raw_events);
+      let now = Instant::now();
+      let mut dropped = 0;
+      if now.duration_since(raw_events.window_start) >= RAW_EVENT_WINDOW {
+        dropped = raw_events.dropped;
+        *raw_events = RawEvents { window_start: now, logged: 0, dropped: 0 };
+      }
+      if raw_events.logged >= RAW_EVENT_LIMIT {
+        raw_events.dropped += 1;
+        return;
+      }
+      raw_events.logged += 1;
+      dropped
+    };
+
+    if dropped > 0 {
+      self.write(Level::Trace, format!("{} raw events were not logged, over {} per second", dropped, RAW_EVENT_LIMIT));
+    }
+    self.write(Level::Trace, format!("received {:?} for {}", kind, printable(&path_to_clean_string(path))));
+  }
+}
+
+/// `text` with control characters and the characters changing the direction of text escaped, so that a file
+/// name cannot break a log line in two or make it read differently from what it says
+pub(crate) fn printable(text: &str) -> Cow<'_, str> {
+  let escaped = |c: char| c.is_control() || matches!(c, '\u{061c}' | '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}');
+  if !text.chars().any(escaped) {
+    return Cow::Borrowed(text);
+  }
+  let mut printable = String::with_capacity(text.len() + 8);
+  for c in text.chars() {
+    if escaped(c) {
+      printable.extend(c.escape_default());
+    } else {
+      printable.push(c);
+    }
+  }
+  Cow::Owned(printable)
+}
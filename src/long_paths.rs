@@ -0,0 +1,120 @@
+//! Paths longer than `MAX_PATH` on Windows. The standard library adds the verbatim `\\?\` prefix such paths
+//! need by itself, but the OS watcher and the few Win32 functions called directly take paths as they are.
+//! Paths are kept without the prefix everywhere else, so that they compare equal however they were obtained,
+//! and the prefix is only added on the way to those functions and removed from what they report.
+//!
+//! Names Win32 would change also need the prefix, whatever the length of the path: it drops trailing dots and
+//! spaces, and takes reserved names such as `CON` or `NUL.txt` for devices. The OS watcher reports such files
+//! by their real names, so reading their metadata goes through `verbatim` as well.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+/// `path` in the verbatim form if it is too long for Win32 functions that do not add the prefix themselves,
+/// or has a name Win32 would change
+#[cfg(windows)]
+pub(crate) fn verbatim(path: &Path) -> Cow<'_, Path> {
+  use std::ffi::OsString;
+  use std::path::{Component, Prefix};
+
+  /// Longest path `CreateFileW` opens directories by without the prefix, leaving room for an 8.3 file name
+  const LONGEST_PLAIN: usize = 247;
+
+  if path.as_os_str().len() <= LONGEST_PLAIN && !path.components().any(|component| matches!(component, Component::Normal(name) if is_mangled(name))) {
+    return Cow::Borrowed(path);
+  }
+
+  let mut components = path.components();
+  let mut verbatim = match components.next() {
+    Some(Component::Prefix(prefix)) => match prefix.kind() {
+      Prefix::Disk(letter) => OsString::from(format!(r"\\?\{}:", letter as char)),
+      Prefix::UNC(server, share) => {
+        let mut unc = OsString::from(r"\\?\UNC\");
+        unc.push(server);
+        unc.push(r"\");
+        unc.push(share);
+        unc
+      }
+      _ => return Cow::Borrowed(path),
+    },
+    _ => return Cow::Borrowed(path),
+  };
+
+  // The OS leaves verbatim paths alone, so separators and `.` are normalized here, and `..` is not resolved
+  for component in components {
+    match component {
+      Component::RootDir | Component::CurDir => {}
+      Component::Normal(name) => {
+        verbatim.push(r"\");
+        verbatim.push(name);
+      }
+      Component::ParentDir | Component::Prefix(_) => return Cow::Borrowed(path),
+    }
+  }
+  Cow::Owned(verbatim.into())
+}
+
+#[cfg(not(windows))]
+pub(crate) fn verbatim(path: &Path) -> Cow<'_, Path> {
+  Cow::Borrowed(path)
+}
+
+/// Whether Win32 changes `name` when a path containing it is taken as it is: trailing dots and spaces are
+/// dropped, and reserved device names are taken for the device, with any extension
+#[cfg(windows)]
+fn is_mangled(name: &std::ffi::OsStr) -> bool {
+  const DEVICES: [&str; 4] = ["CON", "PRN", "AUX", "NUL"];
+
+  let name = name.to_string_lossy();
+  if name.ends_with(['.', ' ']) {
+    return true;
+  }
+  let stem = name.split('.').next().unwrap_or_default().trim_end_matches(' ');
+  let numbered = |prefix: &str| {
+    // Compared by bytes, a name of four bytes can start with a character of several
+    let bytes = stem.as_bytes();
+    bytes.len() == 4 && bytes[..3].eq_ignore_ascii_case(prefix.as_bytes()) && (b'1'..=b'9').contains(&bytes[3])
+  };
+  DEVICES.iter().any(|device| stem.eq_ignore_ascii_case(device)) || numbered("COM") || numbered("LPT")
+}
+
+/// `path` without the verbatim prefix, turning `\\?\UNC\server\share` back into `\\server\share`
+#[cfg(windows)]
+pub(crate) fn simplified(path: &Path) -> Cow<'_, Path> {
+  use std::ffi::OsString;
+  use std::path::{Component, Prefix};
+
+  let mut components = path.components();
+  let mut simplified = match components.next() {
+    Some(Component::Prefix(prefix)) => match prefix.kind() {
+      Prefix::VerbatimDisk(letter) => OsString::from(format!("{}:", letter as char)),
+      Prefix::VerbatimUNC(server, share) => {
+        let mut unc = OsString::from(r"\\");
+        unc.push(server);
+        unc.push(r"\");
+        unc.push(share);
+        unc
+      }
+      _ => return Cow::Borrowed(path),
+    },
+    _ => return Cow::Borrowed(path),
+  };
+
+  let mut named = false;
+  for component in components {
+    if let Component::Normal(name) = component {
+      simplified.push(r"\");
+      simplified.push(name);
+      named = true;
+    }
+  }
+  if !named {
+    simplified.push(r"\");
+  }
+  Cow::Owned(simplified.into())
+}
+
+#[cfg(not(windows))]
+pub(crate) fn simplified(path: &Path) -> Cow<'_, Path> {
+  Cow::Borrowed(path)
+}
@@ -0,0 +1,107 @@
+//! The memory cap of `maxMemoryBytes`. What a subscription holds natively, its batches waiting for the callback
+//! or to be read and the file id cache of its watcher, is estimated before each batch is delivered. A batch
+//! that would take it over the cap first makes room by dropping the oldest batches waiting to be read, then
+//! entries of the file id cache, and is dropped itself if that is not enough, as batches already handed to the
+//! callback cannot be taken back. With `maxMemoryAction: 'close'` the subscription is stopped instead.
+
+use std::mem::size_of;
+use std::sync::atomic::Ordering;
+
+use napi_derive::napi;
+
+use crate::hub::{Subscriber, lock};
+use crate::{WatchCallbackResult, WatchEvent};
+
+/// Most memory a subscription may hold, from `maxMemoryBytes`
+#[derive(Clone, Copy)]
+pub(crate) struct MemoryCap {
+  pub max_bytes: usize,
+  /// Whether going over the cap stops the subscription, rather than shedding load
+  pub close: bool,
+}
+
+/// What was dropped to stay within `maxMemoryBytes`, on the batch reporting it
+#[napi(object, object_from_js = false)]
+#[derive(Debug, Clone, Default)]
+pub struct MemoryShed {
+  /// Batches that were waiting to be read, oldest first
+  pub batches: u32,
+  /// Events of those batches, and of the batch that could not be delivered within the cap
+  pub events: u32,
+  /// Entries of the file id cache
+  pub cache_entries: u32,
+  /// Estimated memory freed, in bytes
+  pub bytes: f64,
+}
+
+/// What delivering a batch that would go over the cap comes to
+pub(crate) enum Excess {
+  /// Load was shed, and the batch may have lost its events
+  Shed(MemoryShed),
+  /// The subscription has to stop, for the reason given
+  Close(String),
+}
+
+/// Memory an event takes, roughly
+pub(crate) fn event_bytes(event: &WatchEvent) -> usize {
+  let strings = [Some(&event.path), event.root.as_ref(), event.raw_kind.as_ref()].into_iter().flatten().map(String::len).sum::<usize>();
+  let aliases = event.aliases.iter().flatten().map(|alias| size_of::<String>() + alias.len()).sum::<usize>();
+  size_of::<WatchEvent>() + strings + aliases + event.path_bytes.as_ref().map_or(0, |bytes| bytes.0.len())
+}
+
+/// Memory a batch takes, roughly
+pub(crate) fn batch_bytes(batch: &WatchCallbackResult) -> usize {
+  size_of::<WatchCallbackResult>() + batch.events.iter().map(event_bytes).sum::<usize>()
+}
+
+/// Estimated memory the subscriber holds natively
+pub(crate) fn usage(subscriber: &Subscriber) -> usize {
+  let buffered = subscriber.pull.as_ref().map_or(0, |buffer| lock(buffer).bytes());
+  let cache = lock(&subscriber.cache_budget).as_ref().map_or(0, |budget| budget.bytes());
+  buffered + subscriber.counters.queued_bytes.load(Ordering::SeqCst) + cache
+}
+
+/// Make room for `batch` if delivering it would take the subscriber over `cap`, dropping its events as the
+/// last resort. Batches without events always fit.
+pub(crate) fn make_room(subscriber: &Subscriber, cap: MemoryCap, batch: &mut WatchCallbackResult) -> Option<Excess> {
+  if batch.events.is_empty() {
+    return None;
+  }
+  let incoming = batch_bytes(batch);
+  let used = usage(subscriber);
+  if used + incoming <= cap.max_bytes {
+    return None;
+  }
+  if cap.close {
+    return Some(Excess::Close(format!(
+      "Subscription stopped because it would hold an estimated {} bytes, more than maxMemoryBytes ({})",
+      used + incoming,
+      cap.max_bytes
+    )));
+  }
+
+  let mut excess = used + incoming - cap.max_bytes;
+  let mut shed = MemoryShed::default();
+  if let Some(buffer) = &subscriber.pull {
+    let (batches, events, bytes) = lock(buffer).shed_oldest(excess);
+    shed.batches = u32::try_from(batches).unwrap_or(u32::MAX);
+    shed.events = u32::try_from(events).unwrap_or(u32::MAX);
+    shed.bytes += bytes as f64;
+    excess = excess.saturating_sub(bytes);
+  }
+  let budget = lock(&subscriber.cache_budget).clone();
+  if excess > 0
+    && let Some(budget) = budget
+  {
+    let (entries, bytes) = budget.shed(excess);
+    shed.cache_entries = u32::try_from(entries).unwrap_or(u32::MAX);
+    shed.bytes += bytes as f64;
+    excess = excess.saturating_sub(bytes);
+  }
+  if excess > 0 {
+    shed.events = shed.events.saturating_add(u32::try_from(batch.events.len()).unwrap_or(u32::MAX));
+    shed.bytes += incoming as f64;
+    batch.events.clear();
+  }
+  Some(Excess::Shed(shed))
+}
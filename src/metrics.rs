@@ -0,0 +1,74 @@
+//! How long events take from the OS watcher to the callback, measured with the monotonic clock
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Number of histogram buckets. Bucket 0 counts latencies below 1ms, bucket `i` those from `2^(i-1)` up to
+/// `2^i` milliseconds, and the last one everything slower.
+pub(crate) const LATENCY_BUCKETS: usize = 16;
+
+/// Latencies of delivered events, in microseconds
+pub(crate) struct Latency {
+  count: AtomicU64,
+  total: AtomicU64,
+  min: AtomicU64,
+  max: AtomicU64,
+  histogram: [AtomicU32; LATENCY_BUCKETS],
+}
+
+impl Default for Latency {
+  fn default() -> Latency {
+    Latency {
+      count: AtomicU64::new(0),
+      total: AtomicU64::new(0),
+      min: AtomicU64::new(u64::MAX),
+      max: AtomicU64::new(0),
+      histogram: std::array::from_fn(|_| AtomicU32::new(0)),
+    }
+  }
+}
+
+impl Latency {
+  pub fn record(&self, latency: Duration) {
+    let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+    self.count.fetch_add(1, Ordering::Relaxed);
+    self.total.fetch_add(micros, Ordering::Relaxed);
+    self.min.fetch_min(micros, Ordering::Relaxed);
+    self.max.fetch_max(micros, Ordering::Relaxed);
+    self.histogram[bucket(micros / 1000)].fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub fn reset(&self) {
+    self.count.store(0, Ordering::Relaxed);
+    self.total.store(0, Ordering::Relaxed);
+    self.min.store(u64::MAX, Ordering::Relaxed);
+    self.max.store(0, Ordering::Relaxed);
+    for bucket in &self.histogram {
+      bucket.store(0, Ordering::Relaxed);
+    }
+  }
+
+  /// Shortest, average and longest latency in milliseconds, or `None` before any event was measured
+  pub fn summary(&self) -> Option<(f64, f64, f64)> {
+    let count = self.count.load(Ordering::Relaxed);
+    if count == 0 {
+      return None;
+    }
+
+    let millis = |micros: u64| micros as f64 / 1000.0;
+    let min = self.min.load(Ordering::Relaxed);
+    let max = self.max.load(Ordering::Relaxed);
+    Some((millis(min), millis(self.total.load(Ordering::Relaxed)) / count as f64, millis(max)))
+  }
+
+  pub fn histogram(&self) -> Vec<u32> {
+    self.histogram.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect()
+  }
+}
+
+fn bucket(millis: u64) -> usize {
+  match millis.checked_ilog2() {
+    Some(log) => (log as usize + 1).min(LATENCY_BUCKETS - 1),
+    None => 0,
+  }
+}
@@ -0,0 +1,99 @@
+//! The `'mock'` backend: an OS watcher that reports nothing by itself, whose raw events are handed in with
+//! `injectRaw` instead and then go through the debouncer, filtering and delivery like any other. Only builds
+//! with the `mock` feature accept the backend.
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use notify::event::{AccessKind, AccessMode, CreateKind, DataChange, EventKind, MetadataKind, ModifyKind, RemoveKind, RenameMode};
+use notify::{Event, EventHandler};
+
+use crate::hub::lock;
+
+/// A raw event as the OS watcher would report it, for `injectRaw`
+#[napi(object, object_to_js = false)]
+pub struct RawEvent {
+  /// Kind of event the way `rawKind` and `debugEventLog` render it, such as `Create(File)` or `Modify(Name(From))`
+  pub kind: String,
+  pub paths: Vec<String>,
+  /// Pairs the halves of a rename, as the cookies of inotify and Windows do
+  pub tracker: Option<u32>,
+}
+
+/// Where the events of a mock watcher come from, shared between its subscriber and the watcher the debouncer
+/// creates for it
+#[derive(Default)]
+pub(crate) struct MockSource {
+  /// Handler of the debouncer's watcher, set once the watcher has been created
+  handler: Mutex<Option<Box<dyn EventHandler>>>,
+}
+
+impl MockSource {
+  /// Take over the handler the watcher would pass its events to
+  pub fn attach(&self, handler: Box<dyn EventHandler>) {
+    *lock(&self.handler) = Some(handler);
+  }
+
+  /// Hand `events` to the debouncer as if the OS watcher had reported them, in order. None is handed over when
+  /// one of them has a kind that does not exist.
+  pub fn inject(&self, events: Vec<RawEvent>) -> Result<()> {
+    let events = events
+      .into_iter()
+      .map(|raw| {
+        let kind = parse_kind(&raw.kind).ok_or_else(|| Error::new(Status::InvalidArg, format!("Unknown event kind '{}'", raw.kind)))?;
+        let event = raw.paths.into_iter().fold(Event::new(kind), |event, path| event.add_path(PathBuf::from(path)));
+        Ok(match raw.tracker {
+          Some(tracker) => event.set_tracker(tracker as usize),
+          None => event,
+        })
+      })
+      .collect::<Result<Vec<Event>>>()?;
+
+    let mut handler = lock(&self.handler);
+    let Some(handler) = handler.as_mut() else {
+      return Err(Error::new(Status::GenericFailure, "The mock watcher has stopped"));
+    };
+    for event in events {
+      handler.handle_event(Ok(event));
+    }
+    Ok(())
+  }
+}
+
+/// The event kind `name` renders, with `Debug`, as notify's kinds do in `rawKind`
+fn parse_kind(name: &str) -> Option<EventKind> {
+  kinds().into_iter().find(|kind| format!("{:?}", kind) == name)
+}
+
+/// Every kind of event notify has
+fn kinds() -> Vec<EventKind> {
+  let modes = [AccessMode::Any, AccessMode::Execute, AccessMode::Read, AccessMode::Write, AccessMode::Other];
+  let accesses = [AccessKind::Any, AccessKind::Read, AccessKind::Other]
+    .into_iter()
+    .chain(modes.into_iter().flat_map(|mode| [AccessKind::Open(mode), AccessKind::Close(mode)]));
+  let creates = [CreateKind::Any, CreateKind::File, CreateKind::Folder, CreateKind::Other];
+  let data = [DataChange::Any, DataChange::Size, DataChange::Content, DataChange::Other].map(ModifyKind::Data);
+  let metadata = [
+    MetadataKind::Any,
+    MetadataKind::AccessTime,
+    MetadataKind::WriteTime,
+    MetadataKind::Permissions,
+    MetadataKind::Ownership,
+    MetadataKind::Extended,
+    MetadataKind::Other,
+  ]
+  .map(ModifyKind::Metadata);
+  let names = [RenameMode::Any, RenameMode::To, RenameMode::From, RenameMode::Both, RenameMode::Other].map(ModifyKind::Name);
+  let modifies = [ModifyKind::Any, ModifyKind::Other].into_iter().chain(data).chain(metadata).chain(names);
+  let removes = [RemoveKind::Any, RemoveKind::File, RemoveKind::Folder, RemoveKind::Other];
+
+  [EventKind::Any, EventKind::Other]
+    .into_iter()
+    .chain(accesses.map(EventKind::Access))
+    .chain(creates.map(EventKind::Create))
+    .chain(modifies.map(EventKind::Modify))
+    .chain(removes.map(EventKind::Remove))
+    .collect()
+}
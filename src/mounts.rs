@@ -0,0 +1,172 @@
+//! Staying on the file system of the watched directory for `oneFileSystem`. A FUSE mount of a remote directory
+//! or a tmpfs below the root can hang the walks of registration and scans, or flood a subscription with changes
+//! nobody meant to watch, so directories on another device than the root are left out with everything below
+//! them. Each mount point is reported once with an `other-file-system` warning, including those mounted after
+//! the subscription started once events or a walk come across them.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::hub::lock;
+use crate::{WatchEvent, WatchWarning, path_to_clean_string};
+
+/// The device of a subscription's root and the mount points found below it
+pub(crate) struct Mounts {
+  root: PathBuf,
+  device: u64,
+  /// Mount points found so far, and whether they have been reported
+  found: Mutex<HashMap<PathBuf, bool>>,
+}
+
+impl Mounts {
+  /// Track the mount points below `root`, or None if its device cannot be read
+  pub fn new(root: &Path) -> Option<Mounts> {
+    Some(Mounts { root: root.to_path_buf(), device: device(root)?, found: Mutex::default() })
+  }
+
+  /// Check if `directory` below the root is on another file system, recording it as a mount point when it is
+  pub fn crosses(&self, directory: &Path) -> bool {
+    if directory == self.root || !directory.starts_with(&self.root) || device(directory).is_none_or(|device| device == self.device) {
+      return false;
+    }
+    lock(&self.found).entry(directory.to_path_buf()).or_insert(false);
+    true
+  }
+
+  /// Drop the events for paths below a mount point, returning how many were dropped. Directories are looked up
+  /// once per batch, as file systems may be mounted and unmounted between batches.
+  pub fn confine(&self, events: &mut Vec<WatchEvent>) -> u32 {
+    let mut directories = HashMap::new();
+    let before = events.len();
+    events.retain(|event| !self.is_mounted(&mut directories, Path::new(&event.path)));
+    u32::try_from(before - events.len()).unwrap_or(u32::MAX)
+  }
+
+  /// Warnings for the mount points found since the last call
+  pub fn warnings(&self) -> Vec<WatchWarning> {
+    let mut found = lock(&self.found);
+    let mut warnings = Vec::new();
+    for (path, reported) in found.iter_mut().filter(|(_, reported)| !**reported) {
+      *reported = true;
+      warnings.push(WatchWarning {
+        path: path_to_clean_string(path),
+        kind: "other-file-system".to_string(),
+        message: format!("Skipped directory on another file system: {}", path_to_clean_string(path)),
+        file_system: None,
+      });
+    }
+    warnings
+  }
+
+  /// Check if `path` is below a mount point, by the mount points found so far or the device of its directory
+  fn is_mounted(&self, directories: &mut HashMap<PathBuf, bool>, path: &Path) -> bool {
+    let known: Vec<PathBuf> = lock(&self.found).keys().filter(|mount| path.starts_with(mount) && path != *mount).cloned().collect();
+    for mount in known {
+      if self.is_other_device(directories, &mount) {
+        return true;
+      }
+      // Unmounted since, which leaves the directory of the root's file system
+      lock(&self.found).remove(&mount);
+    }
+
+    let Some(directory) = path.parent().filter(|directory| *directory != self.root && directory.starts_with(&self.root)) else {
+      return false;
+    };
+    if !self.is_other_device(directories, directory) {
+      return false;
+    }
+
+    // The mount point is the outermost directory on another device
+    let mut below_root: Vec<&Path> = directory.ancestors().take_while(|ancestor| *ancestor != self.root).collect();
+    below_root.reverse();
+    if let Some(mount) = below_root.into_iter().find(|ancestor| self.is_other_device(directories, ancestor)) {
+      lock(&self.found).entry(mount.to_path_buf()).or_insert(false);
+    }
+    true
+  }
+
+  fn is_other_device(&self, directories: &mut HashMap<PathBuf, bool>, directory: &Path) -> bool {
+    if let Some(&other) = directories.get(directory) {
+      return other;
+    }
+    let other = device(directory).is_some_and(|device| device != self.device);
+    directories.insert(directory.to_path_buf(), other);
+    other
+  }
+}
+
+/// Device a directory is on: its `dev_t` on Unix, the serial number of its volume on Windows
+#[cfg(unix)]
+fn device(path: &Path) -> Option<u64> {
+  use std::os::unix::fs::MetadataExt;
+
+  std::fs::symlink_metadata(path).ok().map(|metadata| metadata.dev())
+}
+
+#[cfg(windows)]
+fn device(path: &Path) -> Option<u64> {
+  windows::volume_serial(path)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn device(_path: &Path) -> Option<u64> {
+  None
+}
+
+#[cfg(windows)]
+mod windows {
+  use std::ffi::c_void;
+  use std::iter;
+  use std::os::windows::ffi::OsStrExt;
+  use std::path::Path;
+  use std::ptr;
+
+  use crate::long_paths;
+
+  const FILE_SHARE_READ_WRITE_DELETE: u32 = 0x7;
+  const OPEN_EXISTING: u32 = 3;
+  const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+
+  type Handle = *mut c_void;
+
+  /// `BY_HANDLE_FILE_INFORMATION`
+  #[repr(C)]
+  #[derive(Default)]
+  struct FileInformation {
+    attributes: u32,
+    creation_time: [u32; 2],
+    last_access_time: [u32; 2],
+    last_write_time: [u32; 2],
+    volume_serial_number: u32,
+    size_high: u32,
+    size_low: u32,
+    links: u32,
+    index_high: u32,
+    index_low: u32,
+  }
+
+  #[link(name = "kernel32")]
+  unsafe extern "system" {
+    fn CreateFileW(name: *const u16, access: u32, share: u32, security: *mut c_void, disposition: u32, flags: u32, template: Handle) -> Handle;
+    fn GetFileInformationByHandle(file: Handle, information: *mut FileInformation) -> i32;
+    fn CloseHandle(handle: Handle) -> i32;
+  }
+
+  /// Serial number of the volume a directory is on. A folder a volume is mounted on opens as the root of that
+  /// volume.
+  pub fn volume_serial(path: &Path) -> Option<u64> {
+    let wide: Vec<u16> = long_paths::verbatim(path).as_os_str().encode_wide().chain(iter::once(0)).collect();
+    let file =
+      unsafe { CreateFileW(wide.as_ptr(), 0, FILE_SHARE_READ_WRITE_DELETE, ptr::null_mut(), OPEN_EXISTING, FILE_FLAG_BACKUP_SEMANTICS, ptr::null_mut()) };
+    // INVALID_HANDLE_VALUE
+    if file as isize == -1 {
+      return None;
+    }
+
+    let mut information = FileInformation::default();
+    let read = unsafe { GetFileInformationByHandle(file, &mut information) };
+    unsafe { CloseHandle(file) };
+    (read != 0).then_some(u64::from(information.volume_serial_number))
+  }
+}
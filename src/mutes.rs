@@ -0,0 +1,108 @@
+//! Patterns muted for a while, whose live events are dropped as if they were ignored
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use napi::bindgen_prelude::*;
+use watcher_core::IgnoreSet;
+
+use crate::{WatchEvent, build_ignore_set};
+
+/// A call to `mute`, undone by its handle or once `until` passes
+struct Mute {
+  patterns: Vec<String>,
+  until: Option<Instant>,
+}
+
+/// The mutes of a subscription. Patterns muted more than once are counted, so the set of patterns, and
+/// with it the compiled ignore set, only changes when the first mute of a pattern starts or the last one ends.
+#[derive(Default)]
+pub(crate) struct Mutes {
+  mutes: HashMap<u64, Mute>,
+  counts: BTreeMap<String, u32>,
+  ignore_set: IgnoreSet,
+}
+
+impl Mutes {
+  /// Mute `patterns` under `id` until it is removed, or until `until` passes
+  pub fn add(&mut self, id: u64, patterns: Vec<String>, until: Option<Instant>) -> Result<()> {
+    // Compiled on their own first, so an invalid pattern leaves the current mutes untouched
+    build_ignore_set(&patterns)?;
+
+    let mut changed = false;
+    for pattern in &patterns {
+      let count = self.counts.entry(pattern.clone()).or_default();
+      changed |= *count == 0;
+      *count += 1;
+    }
+    self.mutes.insert(id, Mute { patterns, until });
+
+    if changed { self.rebuild() } else { Ok(()) }
+  }
+
+  /// Undo the mute with `id`, unless it already ended
+  pub fn remove(&mut self, id: u64) {
+    if let Some(mute) = self.mutes.remove(&id)
+      && self.release(mute)
+    {
+      // The remaining patterns compiled before, so they compile again
+      let _ = self.rebuild();
+    }
+  }
+
+  /// Drop the events for muted paths, returning how many were dropped
+  pub fn drop_muted(&mut self, events: &mut Vec<WatchEvent>, root: &Path) -> u32 {
+    if self.mutes.is_empty() {
+      return 0;
+    }
+
+    self.expire(Instant::now());
+    if self.counts.is_empty() {
+      return 0;
+    }
+
+    let root = root.to_path_buf();
+    let before = events.len();
+    events.retain(|event| !self.ignore_set.is_ignored(&PathBuf::from(&event.path), &root));
+    u32::try_from(before - events.len()).unwrap_or(u32::MAX)
+  }
+
+  /// End the timed mutes whose duration has passed
+  fn expire(&mut self, now: Instant) {
+    let expired: Vec<u64> = self.mutes.iter().filter(|(_, mute)| mute.until.is_some_and(|until| until <= now)).map(|(id, _)| *id).collect();
+    if expired.is_empty() {
+      return;
+    }
+
+    let mut changed = false;
+    for id in expired {
+      if let Some(mute) = self.mutes.remove(&id) {
+        changed |= self.release(mute);
+      }
+    }
+    if changed {
+      let _ = self.rebuild();
+    }
+  }
+
+  /// Count down the patterns of an ended mute, returning whether any of them is no longer muted
+  fn release(&mut self, mute: Mute) -> bool {
+    let mut changed = false;
+    for pattern in mute.patterns {
+      if let Some(count) = self.counts.get_mut(&pattern) {
+        *count -= 1;
+        if *count == 0 {
+          self.counts.remove(&pattern);
+          changed = true;
+        }
+      }
+    }
+    changed
+  }
+
+  fn rebuild(&mut self) -> Result<()> {
+    self.ignore_set = build_ignore_set(&self.counts.keys().cloned().collect::<Vec<_>>())?;
+    Ok(())
+  }
+}
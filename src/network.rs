@@ -0,0 +1,219 @@
+//! Detecting directories on file systems where native watching is unreliable: network shares, and the mounts
+//! WSL, Docker and VMs share with their host. The OS learns about changes made on the other side late or not
+//! at all, so native watching misses them and subscriptions poll instead. Detection takes a single call to the
+//! OS, so it costs nothing noticeable for local disks.
+
+use std::path::{Component, Path, Prefix};
+use std::time::Duration;
+
+use crate::{WatchWarning, path_to_clean_string, platform};
+
+/// Interval a polling subscription scans its tree at, unless configured otherwise
+pub(crate) const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// A file system native watching misses changes on
+pub(crate) struct Unreliable {
+  /// Name of the file system, such as `nfs` or `9p`
+  pub file_system: String,
+  /// Where the directory is, to complete "... is on"
+  pub location: String,
+}
+
+/// Find out whether `path` is on a file system native watching misses changes on
+pub(crate) fn unreliable(path: &Path) -> Option<Unreliable> {
+  if let Some(Component::Prefix(prefix)) = path.components().next() {
+    return match prefix.kind() {
+      Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => {
+        let root = format!(r"\\{}\{}", server.to_string_lossy(), share.to_string_lossy());
+        let file_system = volume_file_system(&root).unwrap_or_else(|| "network".to_string());
+        Some(Unreliable { file_system, location: format!("the network share {}", root) })
+      }
+      Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => mapped_drive(letter as char),
+      _ => None,
+    };
+  }
+
+  mount(path)
+}
+
+/// Whether `path` is in the verbatim `\\?\UNC\server\share` form canonicalizing a network path produces
+pub(crate) fn is_verbatim_unc(path: &Path) -> bool {
+  matches!(path.components().next(), Some(Component::Prefix(prefix)) if matches!(prefix.kind(), Prefix::VerbatimUNC(..)))
+}
+
+#[cfg(windows)]
+fn mapped_drive(letter: char) -> Option<Unreliable> {
+  /// `DRIVE_REMOTE`
+  const DRIVE_REMOTE: u32 = 4;
+
+  #[link(name = "kernel32")]
+  unsafe extern "system" {
+    fn GetDriveTypeW(root: *const u16) -> u32;
+  }
+
+  let root = format!("{}:", letter);
+  let wide: Vec<u16> = format!(r"{}\", root).encode_utf16().chain(std::iter::once(0)).collect();
+  if unsafe { GetDriveTypeW(wide.as_ptr()) } != DRIVE_REMOTE {
+    return None;
+  }
+  let file_system = volume_file_system(&root).unwrap_or_else(|| "network".to_string());
+  Some(Unreliable { file_system, location: format!("the network drive {}", root) })
+}
+
+#[cfg(not(windows))]
+fn mapped_drive(_letter: char) -> Option<Unreliable> {
+  None
+}
+
+/// Name of the file system of the volume at `root`, such as `NTFS` for a share of a Windows disk or `9P` for WSL
+#[cfg(windows)]
+fn volume_file_system(root: &str) -> Option<String> {
+  use std::ptr;
+
+  #[link(name = "kernel32")]
+  unsafe extern "system" {
+    fn GetVolumeInformationW(
+      root: *const u16,
+      name: *mut u16,
+      name_len: u32,
+      serial: *mut u32,
+      max_component_len: *mut u32,
+      flags: *mut u32,
+      file_system: *mut u16,
+      file_system_len: u32,
+    ) -> i32;
+  }
+
+  let wide: Vec<u16> = format!(r"{}\", root).encode_utf16().chain(std::iter::once(0)).collect();
+  let mut name = [0u16; 32];
+  let found = unsafe {
+    GetVolumeInformationW(wide.as_ptr(), ptr::null_mut(), 0, ptr::null_mut(), ptr::null_mut(), ptr::null_mut(), name.as_mut_ptr(), name.len() as u32)
+  };
+  let len = name.iter().position(|&unit| unit == 0).unwrap_or(name.len());
+  (found != 0 && len > 0).then(|| String::from_utf16_lossy(&name[..len]))
+}
+
+#[cfg(not(windows))]
+fn volume_file_system(_root: &str) -> Option<String> {
+  None
+}
+
+/// Look up the file system `path` is on by the magic number `statfs` reports for it
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn mount(path: &Path) -> Option<Unreliable> {
+  use std::ffi::CString;
+  use std::mem::MaybeUninit;
+  use std::os::unix::ffi::OsStrExt;
+
+  /// Magic numbers of file systems native watching misses changes on, from `linux/magic.h`
+  const UNRELIABLE: &[(u32, &str)] = &[
+    (0x6969, "nfs"),
+    (0x517b, "smb"),
+    (0xff53_4d42, "cifs"),
+    (0xfe53_4d42, "smb2"),
+    (0x5346_414f, "afs"),
+    (0x00c3_6400, "ceph"),
+    (0x7375_7245, "coda"),
+    // WSL 2 mounts Windows drives (drvfs) over 9p, as do VMs sharing folders with their host
+    (0x0102_1997, "9p"),
+    // Includes the file systems Docker Desktop shares bind mounts over, such as virtiofs and gRPC FUSE
+    (0x6573_5546, "fuse"),
+    (0x786f_4256, "vboxsf"),
+  ];
+
+  let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+  let mut stat = MaybeUninit::<libc::statfs>::uninit();
+  if unsafe { libc::statfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+    return None;
+  }
+  // The width and signedness of `f_type` differ between architectures, the magic numbers all fit 32 bits
+  let magic = unsafe { stat.assume_init() }.f_type as u32;
+  UNRELIABLE
+    .iter()
+    .find(|(known, _)| *known == magic)
+    .map(|(_, file_system)| Unreliable { file_system: file_system.to_string(), location: format!("a {} file system", file_system) })
+}
+
+/// Look up the file system `path` is on by the type name `statfs` reports for it
+#[cfg(target_os = "macos")]
+fn mount(path: &Path) -> Option<Unreliable> {
+  use std::ffi::{CStr, CString};
+  use std::mem::MaybeUninit;
+  use std::os::unix::ffi::OsStrExt;
+
+  /// Type names of file systems native watching misses changes on. FSEvents only sees changes made by this Mac.
+  const UNRELIABLE: &[&str] = &["nfs", "smbfs", "afpfs", "webdav", "cifs", "macfuse", "osxfuse"];
+
+  let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+  let mut stat = MaybeUninit::<libc::statfs>::uninit();
+  if unsafe { libc::statfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+    return None;
+  }
+  let stat = unsafe { stat.assume_init() };
+  let file_system = unsafe { CStr::from_ptr(stat.f_fstypename.as_ptr()) }.to_string_lossy().into_owned();
+  let unreliable = UNRELIABLE.contains(&file_system.as_str()) || file_system.starts_with("fuse");
+  unreliable.then(|| Unreliable { location: format!("a {} file system", file_system), file_system })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn mount(_path: &Path) -> Option<Unreliable> {
+  None
+}
+
+/// Which watcher a subscription asked for with `backend`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Choice {
+  /// The native watcher, unless the directory is on an unreliable file system and `autoFallback` is on
+  Auto,
+  Native,
+  Polling,
+  /// Events handed in with `injectRaw`, in builds with the `mock` feature
+  Mock,
+}
+
+impl Choice {
+  pub fn parse(name: &str) -> Option<Choice> {
+    match name {
+      "auto" => Some(Choice::Auto),
+      "native" => Some(Choice::Native),
+      "polling" => Some(Choice::Polling),
+      "mock" if cfg!(feature = "mock") => Some(Choice::Mock),
+      // The native watcher on the BSDs. notify only builds kqueue on macOS in place of FSEvents, not next to it.
+      "kqueue" if platform::backend_name() == "kqueue" => Some(Choice::Native),
+      _ => None,
+    }
+  }
+}
+
+/// A subscription scanning its tree at an interval instead of relying on OS notifications
+pub(crate) struct Polling {
+  pub interval: Duration,
+  /// Why the subscription polls, as reported by `backendReason`
+  pub reason: String,
+}
+
+/// Decide whether a subscription to `root` polls. A directory on an unreliable file system is polled with
+/// `autoFallback`, otherwise it is watched natively and the returned warning tells that changes may be missed.
+pub(crate) fn choose(choice: Choice, auto_fallback: bool, interval: Duration, root: &Path) -> (Option<Polling>, Option<WatchWarning>) {
+  match choice {
+    Choice::Polling => return (Some(Polling { interval, reason: "backend: 'polling' was requested".to_string() }), None),
+    Choice::Mock => return (None, None),
+    _ => {}
+  }
+
+  let root_str = path_to_clean_string(root);
+  match unreliable(root) {
+    Some(Unreliable { location, .. }) if choice == Choice::Auto && auto_fallback => {
+      let reason = format!("{} is on {}, where native watching misses changes made on the other side", root_str, location);
+      (Some(Polling { interval, reason }), None)
+    }
+    Some(Unreliable { file_system, location }) => {
+      let message = format!(
+        "Watching {} natively although it is on {}, changes made on the other side may be missed; pass backend: 'polling' to poll it",
+        root_str, location
+      );
+      (None, Some(WatchWarning { path: root_str, kind: "unreliable-file-system".to_string(), message, file_system: Some(file_system) }))
+    }
+    None => (None, None),
+  }
+}
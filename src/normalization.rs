@@ -0,0 +1,125 @@
+//! Unicode normalization of event paths and ignore patterns for `unicodeNormalization`. HFS+ stores names
+//! decomposed (NFD), while most input arrives composed (NFC), so the same name can reach a subscription in
+//! either form. Normalizing needs the Unicode tables of the OS, so it only happens on macOS.
+
+/// A Unicode normalization form
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Form {
+  Nfc,
+  Nfd,
+}
+
+impl Form {
+  /// Parse the `unicodeNormalization` option, with `none` as the absence of a form
+  pub fn parse(name: &str) -> Option<Option<Form>> {
+    match name {
+      "nfc" => Some(Some(Form::Nfc)),
+      "nfd" => Some(Some(Form::Nfd)),
+      "none" => Some(None),
+      _ => None,
+    }
+  }
+}
+
+/// `value` in `form`, or None if it already is
+pub(crate) fn normalize(value: &str, form: Form) -> Option<String> {
+  // Names are mostly ASCII, which every form leaves alone
+  if value.is_ascii() {
+    return None;
+  }
+
+  convert(value, form).filter(|normalized| normalized != value)
+}
+
+/// `patterns` with the composed and decomposed forms of every pattern added, so that they match names in either form
+pub(crate) fn both_forms(patterns: &[String]) -> Vec<String> {
+  let mut expanded = Vec::with_capacity(patterns.len());
+  for pattern in patterns {
+    expanded.push(pattern.clone());
+    for form in [Form::Nfc, Form::Nfd] {
+      if let Some(normalized) = normalize(pattern, form)
+        && !expanded.contains(&normalized)
+      {
+        expanded.push(normalized);
+      }
+    }
+  }
+  expanded
+}
+
+#[cfg(target_os = "macos")]
+fn convert(value: &str, form: Form) -> Option<String> {
+  use std::ptr;
+
+  use core_foundation::*;
+
+  unsafe {
+    let string = CFStringCreateWithBytes(ptr::null(), value.as_ptr(), value.len() as CFIndex, UTF8, 0);
+    if string.is_null() {
+      return None;
+    }
+    let mutable = CFStringCreateMutableCopy(ptr::null(), 0, string);
+    CFRelease(string);
+    if mutable.is_null() {
+      return None;
+    }
+
+    CFStringNormalize(mutable, if form == Form::Nfc { NORMALIZATION_FORM_C } else { NORMALIZATION_FORM_D });
+    let range = CFRange { location: 0, length: CFStringGetLength(mutable) };
+    // Measure the UTF-8 length first, then fill a buffer of that size
+    let mut len: CFIndex = 0;
+    CFStringGetBytes(mutable, range, UTF8, 0, 0, ptr::null_mut(), 0, &mut len);
+    let mut bytes = vec![0; len as usize];
+    CFStringGetBytes(mutable, range, UTF8, 0, 0, bytes.as_mut_ptr(), len, &mut len);
+    CFRelease(mutable);
+
+    bytes.truncate(len as usize);
+    String::from_utf8(bytes).ok()
+  }
+}
+
+#[cfg(not(target_os = "macos"))]
+fn convert(_value: &str, _form: Form) -> Option<String> {
+  None
+}
+
+/// The few CoreFoundation string functions needed, from the framework notify already links on macOS
+#[cfg(target_os = "macos")]
+mod core_foundation {
+  use std::ffi::c_void;
+
+  pub type CFIndex = isize;
+
+  #[repr(C)]
+  #[derive(Clone, Copy)]
+  pub struct CFRange {
+    pub location: CFIndex,
+    pub length: CFIndex,
+  }
+
+  /// `kCFStringEncodingUTF8`
+  pub const UTF8: u32 = 0x0800_0100;
+  /// `kCFStringNormalizationFormD`
+  pub const NORMALIZATION_FORM_D: CFIndex = 0;
+  /// `kCFStringNormalizationFormC`
+  pub const NORMALIZATION_FORM_C: CFIndex = 2;
+
+  #[link(name = "CoreFoundation", kind = "framework")]
+  unsafe extern "C" {
+    pub fn CFStringCreateWithBytes(allocator: *const c_void, bytes: *const u8, len: CFIndex, encoding: u32, external: u8) -> *const c_void;
+    pub fn CFStringCreateMutableCopy(allocator: *const c_void, max_length: CFIndex, string: *const c_void) -> *mut c_void;
+    pub fn CFStringNormalize(string: *mut c_void, form: CFIndex);
+    pub fn CFStringGetLength(string: *const c_void) -> CFIndex;
+    pub fn CFStringGetBytes(
+      string: *const c_void,
+      range: CFRange,
+      encoding: u32,
+      loss_byte: u8,
+      external: u8,
+      buffer: *mut u8,
+      max_len: CFIndex,
+      used: *mut CFIndex,
+    ) -> CFIndex;
+    pub fn CFRelease(cf: *const c_void);
+  }
+}
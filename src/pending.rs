@@ -0,0 +1,126 @@
+//! The raw events a debouncer holds, for `getPendingEvents`. notify's debouncer keeps its queue to itself, so
+//! the OS watcher's events are recorded here as they reach it and released once a batch with their paths
+//! comes out of it.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind};
+use notify_debouncer_full::DebouncedEvent;
+
+use crate::hub::lock;
+
+/// The raw events of a path since it was last part of a debounced batch
+struct Held {
+  /// Kind of the first and the last of them
+  first: EventKind,
+  last: EventKind,
+  /// When the first and the last of them were received
+  since: Instant,
+  latest: Instant,
+  /// Tracker of the last of them if it was half of a rename, pairing it with the other half
+  tracker: Option<usize>,
+}
+
+/// The paths a hub's debouncer holds events for, shared between the hub and its OS watcher
+#[derive(Default)]
+pub(crate) struct Pending {
+  held: Mutex<HashMap<PathBuf, Held>>,
+}
+
+impl Pending {
+  /// Hold `event` for its paths, the halves of a rename reported together separately
+  pub fn record(&self, event: &Event) {
+    let now = Instant::now();
+    let tracker = event.attrs.tracker();
+    let mut held = lock(&self.held);
+    for (index, path) in event.paths.iter().enumerate() {
+      let kind = match (event.kind, index) {
+        (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), 0) => EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+        (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), _) => EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+        (kind, _) => kind,
+      };
+
+      match held.get_mut(path) {
+        // The debouncer drops a path created and removed again before it delivered it
+        Some(entry) if entry.first.is_create() && kind.is_remove() => {
+          held.remove(path);
+        }
+        Some(entry) => {
+          entry.last = kind;
+          entry.latest = now;
+          entry.tracker = tracker;
+        }
+        None => {
+          held.insert(path.clone(), Held { first: kind, last: kind, since: now, latest: now, tracker });
+        }
+      }
+    }
+  }
+
+  /// Release the paths of a batch the debouncer delivered
+  pub fn release(&self, batch: &[DebouncedEvent]) {
+    let mut held = lock(&self.held);
+    if held.is_empty() {
+      return;
+    }
+    for path in batch.iter().flat_map(|debounced_event| &debounced_event.event.paths) {
+      held.remove(path);
+    }
+  }
+
+  /// When the oldest raw event held was received
+  pub fn oldest(&self) -> Option<Instant> {
+    lock(&self.held).values().map(|entry| entry.since).min()
+  }
+
+  /// One event for each path held, in the order they were first received, as the debouncer would deliver it,
+  /// with the halves of a rename it would pair as one. Paths without a raw event for longer than `stale` were
+  /// dropped by the debouncer, and are dropped here too.
+  pub fn snapshot(&self, stale: Duration) -> Vec<DebouncedEvent> {
+    let now = Instant::now();
+    let mut held = lock(&self.held);
+    held.retain(|_, entry| now.duration_since(entry.latest) < stale);
+
+    // The paths renamed away and to under each tracker
+    let mut renamed_from = HashSet::new();
+    let mut renamed_to = HashMap::new();
+    for (path, entry) in held.iter() {
+      match (entry.last, entry.tracker) {
+        (EventKind::Modify(ModifyKind::Name(RenameMode::From)), Some(tracker)) => {
+          renamed_from.insert(tracker);
+        }
+        (EventKind::Modify(ModifyKind::Name(RenameMode::To)), Some(tracker)) => {
+          renamed_to.insert(tracker, path);
+        }
+        _ => {}
+      }
+    }
+
+    let mut events: Vec<DebouncedEvent> = held
+      .iter()
+      .filter_map(|(path, entry)| {
+        let partner = entry.tracker.and_then(|tracker| renamed_to.get(&tracker));
+        match (entry.last, partner) {
+          (EventKind::Modify(ModifyKind::Name(RenameMode::From)), Some(&to)) => {
+            let event = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both))).add_path(path.clone()).add_path(to.clone());
+            return Some(DebouncedEvent::new(event, entry.since));
+          }
+          // The half the rename went to is part of the event of the half it came from
+          (EventKind::Modify(ModifyKind::Name(RenameMode::To)), _) if entry.tracker.is_some_and(|tracker| renamed_from.contains(&tracker)) => return None,
+          _ => {}
+        }
+
+        // A path created in the window is a creation, unless it was removed or renamed away since
+        let gone = entry.last.is_remove() || matches!(entry.last, EventKind::Modify(ModifyKind::Name(_)));
+        let kind = if entry.first.is_create() && !gone { entry.first } else { entry.last };
+        Some(DebouncedEvent::new(Event::new(kind).add_path(path.clone()), entry.since))
+      })
+      .collect();
+    events.sort_by_key(|debounced_event| debounced_event.time);
+    events
+  }
+}
@@ -0,0 +1,64 @@
+//! What the file system watcher backend chosen for this platform can do
+
+use std::fs;
+
+use notify::{RecommendedWatcher, Watcher, WatcherKind};
+
+/// What the backend `notify::RecommendedWatcher` wraps on this platform can do
+pub(crate) struct Backend {
+  /// Whether the OS watches whole trees itself, instead of one watch per directory
+  pub native_recursive: bool,
+  /// Whether the two halves of a rename carry a cookie that pairs them
+  pub cookies: bool,
+}
+
+// Mirrors the platform conditions notify uses to pick its recommended watcher
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) const BACKEND: Backend = Backend { native_recursive: false, cookies: true };
+
+#[cfg(target_os = "macos")]
+pub(crate) const BACKEND: Backend = Backend { native_recursive: true, cookies: false };
+
+#[cfg(target_os = "windows")]
+pub(crate) const BACKEND: Backend = Backend { native_recursive: true, cookies: false };
+
+#[cfg(any(target_os = "freebsd", target_os = "openbsd", target_os = "netbsd", target_os = "dragonfly", target_os = "ios"))]
+pub(crate) const BACKEND: Backend = Backend { native_recursive: false, cookies: false };
+
+#[cfg(not(any(
+  target_os = "linux",
+  target_os = "android",
+  target_os = "macos",
+  target_os = "windows",
+  target_os = "freebsd",
+  target_os = "openbsd",
+  target_os = "netbsd",
+  target_os = "dragonfly",
+  target_os = "ios"
+)))]
+pub(crate) const BACKEND: Backend = Backend { native_recursive: false, cookies: false };
+
+/// Name of the watcher implementation subscriptions get their events from. Subscriptions always use the
+/// recommended watcher, as notify does not fall back to polling at runtime.
+pub(crate) fn backend_name() -> &'static str {
+  match RecommendedWatcher::kind() {
+    WatcherKind::Inotify => "inotify",
+    WatcherKind::Fsevent => "fsevents",
+    WatcherKind::Kqueue => "kqueue",
+    WatcherKind::ReadDirectoryChangesWatcher => "windows",
+    _ => "polling",
+  }
+}
+
+/// Whether files can be told apart by an id that survives renames, as compared by `getEventsSince`
+pub(crate) const FILE_IDS: bool = cfg!(unix);
+
+/// Number of directories one user may watch at a time, where the OS sets such a limit
+pub(crate) fn watch_limit() -> Option<u32> {
+  if !cfg!(any(target_os = "linux", target_os = "android")) {
+    return None;
+  }
+
+  fs::read_to_string("/proc/sys/fs/inotify/max_user_watches").ok()?.trim().parse().ok()
+}
@@ -0,0 +1,241 @@
+//! Batches buffered natively for subscriptions without a callback, read with `nextBatch` or by iterating
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use napi::Env;
+use napi::bindgen_prelude::*;
+
+use crate::WatchCallbackResult;
+use crate::hub::{Subscriber, lock};
+use crate::memory;
+
+/// Number of events buffered before the overflow policy applies
+pub(crate) const MAX_BUFFERED_EVENTS: usize = 10_000;
+
+/// Called once with the next batch, with `None` when waiting timed out, or with the error that stopped the subscription
+pub(crate) type Reader = Box<dyn FnOnce(Result<Option<WatchCallbackResult>>) + Send>;
+
+type Resolver = Box<dyn FnOnce(Env) -> Result<Option<WatchCallbackResult>> + Send>;
+
+static NEXT_READER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// What happens to events once `MAX_BUFFERED_EVENTS` are waiting to be read
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Overflow {
+  /// Drop events until everything buffered has been read, leaving a batch with an error in their place
+  Drop,
+  /// Merge further batches into the last one, keeping the latest event for each path
+  Merge,
+}
+
+/// Batches waiting to be read, or readers waiting for a batch. Only one of the two is ever non-empty.
+pub(crate) struct PullBuffer {
+  overflow: Overflow,
+  batches: VecDeque<WatchCallbackResult>,
+  events: usize,
+  /// Estimated memory the buffered batches take
+  bytes: usize,
+  /// Whether events have been dropped since the buffer was last read empty
+  overflowing: bool,
+  /// Position of each path among the events of the last batch, while batches are merged into it
+  merged: Option<HashMap<String, usize>>,
+  readers: Vec<(u64, Reader)>,
+}
+
+impl PullBuffer {
+  pub fn new(overflow: Overflow) -> PullBuffer {
+    PullBuffer { overflow, batches: VecDeque::new(), events: 0, bytes: 0, overflowing: false, merged: None, readers: Vec::new() }
+  }
+
+  /// Number of batches waiting to be read
+  pub fn len(&self) -> usize {
+    self.batches.len()
+  }
+
+  /// Estimated memory the batches waiting to be read take
+  pub fn bytes(&self) -> usize {
+    self.bytes
+  }
+
+  /// Drop the batches waiting longest until at least `bytes` are freed or none are left. Returns the number of
+  /// batches, events and bytes dropped.
+  pub fn shed_oldest(&mut self, bytes: usize) -> (usize, usize, usize) {
+    let (mut batches, mut events, before) = (0, 0, self.bytes);
+    while before - self.bytes < bytes
+      && let Some(batch) = self.pop()
+    {
+      batches += 1;
+      events += batch.events.len();
+    }
+    (batches, events, before - self.bytes)
+  }
+
+  /// Hand a batch to the longest waiting reader, or buffer it subject to the overflow policy
+  pub fn push(&mut self, mut batch: WatchCallbackResult) {
+    if !self.readers.is_empty() {
+      let (_, reader) = self.readers.remove(0);
+      reader(Ok(Some(batch)));
+      return;
+    }
+
+    let full = self.overflowing || self.events + batch.events.len() > MAX_BUFFERED_EVENTS;
+    if full && self.overflow == Overflow::Merge && batch.error.is_none() && batch.warnings.is_none() && batch.storm.is_none() && self.merge(&mut batch) {
+      return;
+    }
+
+    if full && self.overflow == Overflow::Drop {
+      batch.events.clear();
+      if !self.overflowing {
+        self.overflowing = true;
+        self.batches.push_back(WatchCallbackResult {
+          seq: batch.seq,
+          overflowed: Some(true),
+          error: Some(Error::new(
+            Status::GenericFailure,
+            format!("Events were dropped because {} events were waiting to be read; call triggerRescan() to catch up", MAX_BUFFERED_EVENTS),
+          )),
+          ..Default::default()
+        });
+      }
+      if batch.error.is_none() && batch.warnings.is_none() && batch.storm.is_none() {
+        return;
+      }
+    }
+
+    self.events += batch.events.len();
+    self.bytes += memory::batch_bytes(&batch);
+    self.merged = None;
+    self.batches.push_back(batch);
+  }
+
+  /// Merge a batch into the last buffered one, returning false if there is none
+  fn merge(&mut self, batch: &mut WatchCallbackResult) -> bool {
+    let Some(last) = self.batches.back_mut() else {
+      return false;
+    };
+    let merged = self.merged.get_or_insert_with(|| last.events.iter().enumerate().map(|(index, event)| (event.path.clone(), index)).collect());
+
+    last.seq = batch.seq;
+    for mut event in batch.events.drain(..) {
+      match merged.get(&event.path) {
+        Some(&index) => {
+          let kept = &mut last.events[index];
+          kept.absorb(&mut event);
+          // A file created and then changed is still new to the reader, and changed contents outweigh changed metadata
+          if !matches!((kept.event_type.as_str(), event.event_type.as_str()), ("create", "update" | "metadata") | ("update", "metadata")) {
+            event.raw_kind = kept.raw_kind.take();
+            *kept = event;
+          }
+        }
+        None => {
+          merged.insert(event.path.clone(), last.events.len());
+          self.bytes += memory::event_bytes(&event);
+          last.events.push(event);
+          self.events += 1;
+        }
+      }
+    }
+    true
+  }
+
+  fn pop(&mut self) -> Option<WatchCallbackResult> {
+    let batch = self.batches.pop_front()?;
+    self.events -= batch.events.len();
+    self.bytes = self.bytes.saturating_sub(memory::batch_bytes(&batch));
+    if self.batches.is_empty() {
+      self.bytes = 0;
+      self.overflowing = false;
+      self.merged = None;
+    }
+    Some(batch)
+  }
+}
+
+/// Pass the next batch of a subscription to `reader`, right away if one is buffered. Returns the id of the
+/// reader while it waits.
+pub(crate) fn read(subscriber: &Subscriber, reader: Reader) -> Result<Option<u64>> {
+  let mut buffer = lock(buffer(subscriber, "nextBatch()")?);
+  if let Some(batch) = buffer.pop() {
+    drop(buffer);
+    reader(Ok(Some(batch)));
+    return Ok(None);
+  }
+
+  // Checked under the lock, so a subscription stopping meanwhile cannot miss the reader
+  if !subscriber.running.load(Ordering::SeqCst) {
+    drop(buffer);
+    reader(Err(Error::new(Status::GenericFailure, stopped_message(subscriber))));
+    return Ok(None);
+  }
+
+  let id = NEXT_READER_ID.fetch_add(1, Ordering::Relaxed);
+  buffer.readers.push((id, reader));
+  Ok(Some(id))
+}
+
+/// Read the next batch of a subscription. Resolves with `null` when `timeout_ms` passes without one.
+pub(crate) fn next_batch<'env>(env: &'env Env, subscriber: &Arc<Subscriber>, timeout_ms: Option<u32>) -> Result<Object<'env>> {
+  // Checked before creating the promise, which would keep the process alive if it never settled
+  buffer(subscriber, "nextBatch()")?;
+  let (deferred, promise) = env.create_deferred::<Option<WatchCallbackResult>, Resolver>()?;
+  let reader: Reader = Box::new(move |result| match result {
+    Ok(batch) => deferred.resolve(Box::new(move |_| Ok(batch))),
+    Err(error) => deferred.reject(error),
+  });
+
+  if let (Some(id), Some(timeout_ms)) = (read(subscriber, reader)?, timeout_ms) {
+    let subscriber = Arc::downgrade(subscriber);
+    thread::Builder::new()
+      .name("watcher-next-batch-timeout".to_string())
+      .spawn(move || {
+        thread::sleep(Duration::from_millis(timeout_ms.into()));
+        if let Some(reader) = take(&subscriber, id) {
+          reader(Ok(None));
+        }
+      })
+      .map_err(|e| Error::new(Status::GenericFailure, format!("Failed to start timeout: {}", e)))?;
+  }
+
+  Ok(promise)
+}
+
+/// Take every batch buffered for a subscription, without waiting
+pub(crate) fn drain(subscriber: &Subscriber) -> Result<Vec<WatchCallbackResult>> {
+  let mut buffer = lock(buffer(subscriber, "poll()")?);
+  Ok(std::iter::from_fn(|| buffer.pop()).collect())
+}
+
+/// Fail every waiting reader, after the subscription stopped
+pub(crate) fn stop(subscriber: &Subscriber) {
+  let Some(buffer) = subscriber.pull.as_ref() else {
+    return;
+  };
+
+  let readers = std::mem::take(&mut lock(buffer).readers);
+  for (_, reader) in readers {
+    reader(Err(Error::new(Status::GenericFailure, stopped_message(subscriber))));
+  }
+}
+
+/// The buffer of a subscription, or the error `method` throws for subscriptions with a callback
+fn buffer<'a>(subscriber: &'a Subscriber, method: &str) -> Result<&'a Mutex<PullBuffer>> {
+  subscriber.pull.as_ref().ok_or_else(|| Error::new(Status::InvalidArg, format!("{} is only available on subscriptions created without a callback", method)))
+}
+
+fn take(subscriber: &Weak<Subscriber>, id: u64) -> Option<Reader> {
+  let subscriber = subscriber.upgrade()?;
+  let mut buffer = lock(subscriber.pull.as_ref()?);
+  let index = buffer.readers.iter().position(|(reader, _)| *reader == id)?;
+  Some(buffer.readers.remove(index).1)
+}
+
+fn stopped_message(subscriber: &Subscriber) -> String {
+  match lock(&subscriber.failure).as_ref() {
+    Some(failure) => failure.clone(),
+    None => "Subscription was closed before a batch was delivered".to_string(),
+  }
+}
@@ -0,0 +1,164 @@
+//! Promises settled once no events have arrived for a subscription's tree for a while
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use napi::bindgen_prelude::*;
+use napi::{Env, JsDeferred};
+
+use crate::hub::{Subscriber, lock};
+use crate::waiters::TIMEOUT_CODE;
+
+type Resolver = Box<dyn FnOnce(Env) -> Result<()> + Send>;
+
+static NEXT_WAITER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// When events last arrived for a subscription's tree, and the promises waiting for it to stay quiet
+pub(crate) struct Activity {
+  /// Last event anywhere in the tree, including paths matched by the ignore patterns
+  any: Instant,
+  /// Last event that passed the ignore patterns
+  delivered: Instant,
+  waiters: Vec<QuietWaiter>,
+}
+
+/// A promise waiting for the tree to stay quiet
+pub(crate) struct QuietWaiter {
+  id: u64,
+  deferred: JsDeferred<(), Resolver>,
+}
+
+/// How a waiter settles
+enum Outcome {
+  Quiet,
+  TimedOut { quiet_ms: u32, timeout_ms: u32 },
+  Stopped(String),
+}
+
+impl Activity {
+  pub fn new() -> Activity {
+    let now = Instant::now();
+    Activity { any: now, delivered: now, waiters: Vec::new() }
+  }
+}
+
+impl QuietWaiter {
+  fn settle(self, outcome: Outcome) {
+    self.deferred.resolve(Box::new(move |env| match outcome {
+      Outcome::Quiet => Ok(()),
+      Outcome::TimedOut { quiet_ms, timeout_ms } => {
+        let mut error = env.create_error(Error::new(Status::GenericFailure, format!("No quiet period of {}ms within {}ms", quiet_ms, timeout_ms)))?;
+        error.set_named_property("code", TIMEOUT_CODE)?;
+        Err(Error::from(error.to_unknown()))
+      }
+      Outcome::Stopped(message) => Err(Error::new(Status::GenericFailure, message)),
+    }));
+  }
+}
+
+/// Note that events arrived for the tree, `delivered` telling whether any of them passed the ignore patterns
+pub(crate) fn observe(subscriber: &Subscriber, delivered: bool) {
+  let now = Instant::now();
+  let mut activity = lock(&subscriber.activity);
+  activity.any = now;
+  if delivered {
+    activity.delivered = now;
+  }
+}
+
+/// How long ago events last arrived for the tree, including paths matched by the ignore patterns
+pub(crate) fn idle_for(subscriber: &Subscriber) -> Duration {
+  lock(&subscriber.activity).any.elapsed()
+}
+
+/// Wait until no events have arrived for `quiet_ms`, counting events for ignored paths as well with
+/// `include_ignored`. The quiet period starts no earlier than the call.
+pub(crate) fn wait<'env>(env: &'env Env, subscriber: &Arc<Subscriber>, quiet_ms: u32, timeout_ms: Option<u32>, include_ignored: bool) -> Result<Object<'env>> {
+  let (deferred, promise) = env.create_deferred()?;
+  let waiter = QuietWaiter { id: NEXT_WAITER_ID.fetch_add(1, Ordering::Relaxed), deferred };
+  let id = waiter.id;
+
+  {
+    // Checked under the lock, so a subscription stopping meanwhile cannot miss the waiter
+    let mut activity = lock(&subscriber.activity);
+    if !subscriber.running.load(Ordering::SeqCst) {
+      drop(activity);
+      waiter.settle(Outcome::Stopped(stopped_message(subscriber)));
+      return Ok(promise);
+    }
+    activity.waiters.push(waiter);
+  }
+
+  let started = Instant::now();
+  let quiet = Duration::from_millis(quiet_ms.into());
+  let deadline = timeout_ms.map(|timeout_ms| started + Duration::from_millis(timeout_ms.into()));
+  let subscriber = Arc::downgrade(subscriber);
+
+  thread::Builder::new()
+    .name("watcher-quiescence".to_string())
+    .spawn(move || {
+      loop {
+        let Some(last) = last_event(&subscriber, include_ignored) else {
+          return;
+        };
+        let quiet_at = last.max(started) + quiet;
+        let now = Instant::now();
+
+        let outcome = if now >= quiet_at {
+          Outcome::Quiet
+        } else if let (Some(deadline), Some(timeout_ms)) = (deadline, timeout_ms)
+          && now >= deadline
+        {
+          Outcome::TimedOut { quiet_ms, timeout_ms }
+        } else {
+          // Woken at the end of the quiet period, where a later event moves it further out
+          thread::sleep(deadline.map_or(quiet_at, |deadline| quiet_at.min(deadline)) - now);
+          continue;
+        };
+
+        if let Some(waiter) = take(&subscriber, id) {
+          waiter.settle(outcome);
+        }
+        return;
+      }
+    })
+    .map_err(|e| Error::new(Status::GenericFailure, format!("Failed to start quiescence timer: {}", e)))?;
+
+  Ok(promise)
+}
+
+/// Reject every waiter, after the subscription stopped
+pub(crate) fn stop(subscriber: &Subscriber) {
+  let waiters = std::mem::take(&mut lock(&subscriber.activity).waiters);
+  if waiters.is_empty() {
+    return;
+  }
+
+  let message = stopped_message(subscriber);
+  for waiter in waiters {
+    waiter.settle(Outcome::Stopped(message.clone()));
+  }
+}
+
+/// When the last counted event arrived, or `None` once the waiter is no longer needed
+fn last_event(subscriber: &Weak<Subscriber>, include_ignored: bool) -> Option<Instant> {
+  let subscriber = subscriber.upgrade()?;
+  let activity = lock(&subscriber.activity);
+  Some(if include_ignored { activity.any } else { activity.delivered })
+}
+
+fn take(subscriber: &Weak<Subscriber>, id: u64) -> Option<QuietWaiter> {
+  let subscriber = subscriber.upgrade()?;
+  let mut activity = lock(&subscriber.activity);
+  let index = activity.waiters.iter().position(|waiter| waiter.id == id)?;
+  Some(activity.waiters.swap_remove(index))
+}
+
+fn stopped_message(subscriber: &Subscriber) -> String {
+  match lock(&subscriber.failure).as_ref() {
+    Some(failure) => failure.clone(),
+    None => "Subscription was closed before the tree became quiet".to_string(),
+  }
+}
@@ -0,0 +1,66 @@
+//! Periodic rescans for `reconcileIntervalMs`, catching changes the OS watcher missed on file systems that
+//! mostly report them, such as folders synced by OneDrive or Dropbox. Each pass walks the tree like
+//! `triggerRescan` and delivers what differs from the baseline of delivered events as synthetic events.
+
+use std::io;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::hub::Subscriber;
+use crate::logging::Level;
+use crate::{quiescence, scan};
+
+/// Longest the thread sleeps before checking whether the subscription is still running
+const STEP: Duration = Duration::from_millis(100);
+
+/// How long the tree has to be quiet, and the callback caught up, before a pass runs. Passes are postponed
+/// until then, so the walk neither competes with a burst of changes nor duplicates events still being debounced.
+const QUIET: Duration = Duration::from_secs(1);
+
+/// Reconcile the subscriber's tree every `interval` on a background thread, until the subscription stops.
+/// Unless the initial scan `seeds` the baseline, the first pass records it as soon as the tree is quiet.
+pub(crate) fn spawn(subscriber: &Arc<Subscriber>, interval: Duration, seeds: bool) -> io::Result<()> {
+  let subscriber = Arc::downgrade(subscriber);
+  thread::Builder::new().name("watcher-reconcile".to_string()).spawn(move || {
+    let mut due = if seeds { Instant::now() + interval } else { Instant::now() };
+    loop {
+      let now = Instant::now();
+      if now < due {
+        thread::sleep(STEP.min(due - now));
+        if !running(&subscriber) {
+          return;
+        }
+        continue;
+      }
+
+      let Some(subscriber) = subscriber.upgrade() else {
+        return;
+      };
+      if !subscriber.running.load(Ordering::SeqCst) {
+        return;
+      }
+      if subscriber.queued_batches() > 0 || quiescence::idle_for(&subscriber) < QUIET {
+        subscriber.log(Level::Trace, || "reconcile postponed, the tree is busy".to_string());
+        due = now + QUIET;
+        continue;
+      }
+
+      match scan::rescan(&subscriber, &subscriber.root) {
+        Ok(result) if result.created + result.updated + result.deleted > 0 => subscriber.log(Level::Info, || {
+          format!("reconcile found changes the watcher missed: {} created, {} updated, {} deleted", result.created, result.updated, result.deleted)
+        }),
+        Ok(_) => {}
+        Err(e) => subscriber.log(Level::Warn, || format!("reconcile failed: {}", e)),
+      }
+      due = Instant::now() + interval;
+    }
+  })?;
+
+  Ok(())
+}
+
+fn running(subscriber: &Weak<Subscriber>) -> bool {
+  subscriber.upgrade().is_some_and(|subscriber| subscriber.running.load(Ordering::SeqCst))
+}
@@ -0,0 +1,454 @@
+//! Registration of watched directories with the OS watcher
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::{fs, io, thread};
+
+use notify::RecursiveMode;
+
+use crate::hub::Subscriber;
+use crate::{Debouncer, WatchWarning, path_to_clean_string};
+
+/// Whether the OS watcher needs a watch per directory, so leaving directories out saves watches.
+/// FSEvents could leave out pruned directories with `FSEventStreamSetExclusionPaths`, but notify creates its
+/// stream internally without a way to pass exclusions, so on macOS ignored events are dropped after the fact.
+const PER_DIRECTORY_WATCHES: bool = cfg!(any(target_os = "linux", target_os = "android"));
+
+/// Upper bound for the number of threads walking a tree before it is registered
+const WALK_THREADS: usize = 8;
+
+/// Directories registered with the OS watcher
+#[derive(Default)]
+pub(crate) struct Registration {
+  /// Directories the registration was started from
+  pub roots: HashSet<PathBuf>,
+  /// Directories passed to the OS watcher and how they were watched
+  watched: HashMap<PathBuf, RecursiveMode>,
+  /// Directories watched non-recursively, whose subdirectories are registered one by one
+  pub partial: HashSet<PathBuf>,
+  /// Directories skipped because they could not be watched
+  pub skipped: HashSet<PathBuf>,
+  /// Directories left out because every subscription covering them ignores their contents
+  pub pruned: HashSet<PathBuf>,
+  /// Symlinks to a directory that contains them, which the OS watcher does not follow into the loop
+  cycles: HashSet<PathBuf>,
+}
+
+impl Registration {
+  /// Build a warning for every skipped directory and symlink cycle below `root`
+  pub fn warnings(&self, root: &Path) -> Vec<WatchWarning> {
+    let skipped = self.skipped.iter().filter(|path| path.starts_with(root)).map(|path| permission_warning(path));
+    skipped.chain(self.cycles.iter().filter(|path| path.starts_with(root)).map(|path| cycle_warning(path))).collect()
+  }
+
+  /// Unwatch everything that was registered for `root`
+  pub fn remove_root(&mut self, debouncer: &mut Debouncer, root: &Path) {
+    for (path, _) in self.watched.extract_if(|path, _| path.starts_with(root)) {
+      // Descendants of a recursive watch are already gone once their ancestor is unwatched
+      let _ = debouncer.unwatch(&path);
+    }
+
+    self.partial.retain(|path| !path.starts_with(root));
+    self.skipped.retain(|path| !path.starts_with(root));
+    self.pruned.retain(|path| !path.starts_with(root));
+    self.cycles.retain(|path| !path.starts_with(root));
+    self.roots.remove(root);
+  }
+
+  /// Register directories below `root` that were pruned but are wanted by the current subscriptions
+  pub fn restore_pruned(&mut self, debouncer: &mut Debouncer, root: &Path, pruning: &Pruning) {
+    let wanted: Vec<PathBuf> = self.pruned.iter().filter(|path| (path.starts_with(root) || root.starts_with(path)) && !pruning.prunes(path)).cloned().collect();
+
+    for path in wanted {
+      self.pruned.remove(&path);
+
+      if let Err(e) = watch_tree(debouncer, &path, false, self, pruning)
+        && is_permission_error(&e)
+      {
+        self.skipped.insert(path);
+      }
+    }
+  }
+
+  /// Number of directories watched below `root`
+  pub fn watch_count(&self, root: &Path) -> u32 {
+    self
+      .watched
+      .iter()
+      .map(|(path, mode)| match mode {
+        RecursiveMode::NonRecursive => u32::from(path.starts_with(root)),
+        RecursiveMode::Recursive if path.starts_with(root) => self.count_directories(path),
+        RecursiveMode::Recursive if root.starts_with(path) => self.count_directories(root),
+        RecursiveMode::Recursive => 0,
+      })
+      .fold(0, u32::saturating_add)
+  }
+
+  /// Count `directory` and the directories below it that are not left out
+  fn count_directories(&self, directory: &Path) -> u32 {
+    let Ok(read_dir) = fs::read_dir(directory) else {
+      return 1;
+    };
+
+    read_dir
+      .flatten()
+      .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+      .map(|entry| entry.path())
+      .filter(|path| !self.pruned.contains(path) && !self.skipped.contains(path))
+      .map(|path| self.count_directories(&path))
+      .fold(1, u32::saturating_add)
+  }
+
+  /// List everything below a newly registered directory, so entries created before its watch was in place are not missed
+  pub fn entries_below(&self, directory: &Path) -> Vec<PathBuf> {
+    let mut entries = Vec::new();
+    let Ok(read_dir) = fs::read_dir(directory) else {
+      return entries;
+    };
+
+    for entry in read_dir.flatten() {
+      let path = entry.path();
+      let below = if entry.file_type().is_ok_and(|t| t.is_dir()) && !self.pruned.contains(&path) && !self.skipped.contains(&path) {
+        self.entries_below(&path)
+      } else {
+        vec![]
+      };
+
+      entries.push(path);
+      entries.extend(below);
+    }
+
+    entries
+  }
+}
+
+/// Decides which directories can be left out of the registration because no subscription wants their contents
+pub(crate) struct Pruning<'a> {
+  subscribers: Vec<&'a Subscriber>,
+}
+
+impl<'a> Pruning<'a> {
+  pub fn new(subscribers: impl IntoIterator<Item = &'a Subscriber>) -> Self {
+    Self { subscribers: subscribers.into_iter().collect() }
+  }
+
+  /// Check if every subscription covering `directory` ignores everything below it
+  pub fn prunes(&self, directory: &Path) -> bool {
+    if !PER_DIRECTORY_WATCHES || self.subscribers.iter().any(|subscriber| subscriber.root.starts_with(directory)) {
+      return false;
+    }
+
+    let mut covering = self.subscribers.iter().filter(|subscriber| directory.starts_with(&subscriber.root)).peekable();
+    covering.peek().is_some() && covering.all(|subscriber| subscriber.ignores_tree(directory))
+  }
+}
+
+/// Most entries a subscription lets the registration of its tree find, from the `maxWatchedEntries` option
+#[derive(Clone, Copy)]
+pub(crate) struct EntryLimit {
+  pub max: usize,
+  /// Whether registration fails once there are more, rather than going on with a warning
+  pub fail: bool,
+}
+
+/// What a walk of a tree found out before the tree is registered
+#[derive(Default)]
+pub(crate) struct Survey {
+  /// Directories left out because every subscription covering them ignores their contents
+  pub pruned: Vec<PathBuf>,
+  /// Directories with a pruned or unreadable directory below them, which cannot be watched recursively
+  dirty: HashSet<PathBuf>,
+  /// Entries found below the root outside pruned directories, when there was a limit to count them for
+  pub entries: usize,
+  /// Symlinks to a directory that contains them
+  cycles: Vec<PathBuf>,
+}
+
+impl Survey {
+  /// Walk the tree below `root` on several threads. Only needed where the OS watcher registers every
+  /// directory on its own, or to hold the tree to `limit`; the walk also leaves the directories cached for
+  /// the OS watcher's serial walk. A walk that will fail its limit stops as soon as the limit is passed.
+  pub fn walk(root: &Path, pruning: &Pruning, limit: Option<EntryLimit>) -> Self {
+    if !PER_DIRECTORY_WATCHES && limit.is_none() {
+      return Self::default();
+    }
+
+    let helpers = thread::available_parallelism().map_or(1, usize::from).min(WALK_THREADS) - 1;
+    let queue = WalkQueue {
+      state: Mutex::new(WalkState { pending: vec![root.to_path_buf()], busy: 0 }),
+      changed: Condvar::new(),
+      entries: AtomicUsize::new(0),
+      stop_after: limit.filter(|limit| limit.fail).map(|limit| limit.max),
+      stopped: AtomicBool::new(false),
+    };
+
+    let (pruned, blocked, cycles) = thread::scope(|scope| {
+      // The calling thread walks as well, so the walk completes even if no helper can be spawned
+      let walkers: Vec<_> = (0..helpers).filter_map(|_| thread::Builder::new().spawn_scoped(scope, || queue.walk(pruning)).ok()).collect();
+      let (mut pruned, mut blocked, mut cycles) = queue.walk(pruning);
+
+      for walker in walkers {
+        if let Ok(found) = walker.join() {
+          pruned.extend(found.0);
+          blocked.extend(found.1);
+          cycles.extend(found.2);
+        }
+      }
+
+      (pruned, blocked, cycles)
+    });
+
+    let entries = queue.entries.load(Ordering::Relaxed);
+    if !PER_DIRECTORY_WATCHES {
+      return Self { entries, ..Self::default() };
+    }
+
+    let mut dirty = HashSet::new();
+    for path in pruned.iter().chain(&blocked) {
+      for ancestor in path.ancestors().skip(1) {
+        if !ancestor.starts_with(root) || !dirty.insert(ancestor.to_path_buf()) {
+          break;
+        }
+      }
+    }
+
+    Self { pruned, dirty, entries, cycles }
+  }
+}
+
+/// Directories waiting to be read by the threads walking a tree
+struct WalkQueue {
+  state: Mutex<WalkState>,
+  changed: Condvar,
+  /// Entries read so far, counted per directory
+  entries: AtomicUsize,
+  /// Number of entries past which the walk is abandoned
+  stop_after: Option<usize>,
+  stopped: AtomicBool,
+}
+
+struct WalkState {
+  pending: Vec<PathBuf>,
+  /// Number of directories being read, whose subdirectories are not pending yet
+  busy: usize,
+}
+
+impl WalkQueue {
+  /// Read directories until the whole tree has been walked. Returns the pruned directories, the ones that
+  /// could not be read and the symlinks to a directory containing them.
+  fn walk(&self, pruning: &Pruning) -> (Vec<PathBuf>, Vec<PathBuf>, Vec<PathBuf>) {
+    let mut pruned = Vec::new();
+    let mut blocked = Vec::new();
+    let mut cycles = Vec::new();
+
+    while let Some(directory) = self.next() {
+      let mut children = Vec::new();
+      let mut entries = 0;
+
+      match fs::read_dir(&directory) {
+        Ok(read_dir) => {
+          for entry in read_dir.flatten() {
+            entries += 1;
+            let Ok(file_type) = entry.file_type() else {
+              continue;
+            };
+            // The walk does not follow symlinks, but the OS watcher does, and a link to a directory containing it
+            // would lead it in circles. Directories are walked by their resolved paths, so a link's target
+            // containing it is its ancestor.
+            if file_type.is_symlink() && fs::canonicalize(entry.path()).is_ok_and(|target| directory.starts_with(target)) {
+              cycles.push(entry.path());
+            }
+            if !file_type.is_dir() {
+              continue;
+            }
+
+            let child = entry.path();
+            if pruning.prunes(&child) {
+              pruned.push(child);
+            } else {
+              children.push(child);
+            }
+          }
+        }
+        Err(_) => blocked.push(directory),
+      }
+
+      let counted = self.entries.fetch_add(entries, Ordering::Relaxed) + entries;
+      if self.stop_after.is_some_and(|max| counted > max) {
+        self.stopped.store(true, Ordering::Relaxed);
+      }
+      self.finish(children);
+    }
+
+    (pruned, blocked, cycles)
+  }
+
+  /// Take the next pending directory, waiting while other threads may still add some
+  fn next(&self) -> Option<PathBuf> {
+    let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+    loop {
+      if self.stopped.load(Ordering::Relaxed) {
+        // Let the other threads see the walk is over once their directory is done
+        state.pending.clear();
+      }
+
+      if let Some(directory) = state.pending.pop() {
+        state.busy += 1;
+        return Some(directory);
+      }
+
+      if state.busy == 0 {
+        return None;
+      }
+
+      state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+    }
+  }
+
+  /// Queue the subdirectories of a directory that has been read
+  fn finish(&self, children: Vec<PathBuf>) {
+    let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+    state.pending.extend(children);
+    state.busy -= 1;
+    self.changed.notify_all();
+  }
+}
+
+/// Reduce a set of directories to the ones not contained in another
+pub(crate) fn outermost<'a>(paths: impl Iterator<Item = &'a Path>) -> HashSet<PathBuf> {
+  let mut paths: Vec<&Path> = paths.collect();
+  paths.sort_by_key(|path| path.components().count());
+
+  let mut roots: HashSet<PathBuf> = HashSet::new();
+  for path in paths {
+    if !roots.iter().any(|root| path.starts_with(root)) {
+      roots.insert(path.to_path_buf());
+    }
+  }
+
+  roots
+}
+
+/// Check if a watcher error was caused by missing permissions
+pub(crate) fn is_permission_error(error: &notify::Error) -> bool {
+  matches!(&error.kind, notify::ErrorKind::Io(e) if e.kind() == io::ErrorKind::PermissionDenied)
+}
+
+/// Check if a watcher error was caused by the path disappearing
+fn is_not_found_error(error: &notify::Error) -> bool {
+  matches!(&error.kind, notify::ErrorKind::PathNotFound) || matches!(&error.kind, notify::ErrorKind::Io(e) if e.kind() == io::ErrorKind::NotFound)
+}
+
+/// Build the warning reported for a directory that was skipped
+pub(crate) fn permission_warning(path: &Path) -> WatchWarning {
+  WatchWarning {
+    path: path_to_clean_string(path),
+    kind: "permission-denied".to_string(),
+    message: format!("Skipped directory that cannot be watched: {}", path_to_clean_string(path)),
+    file_system: None,
+  }
+}
+
+/// Build the warning reported for a symlink to a directory that contains it
+fn cycle_warning(path: &Path) -> WatchWarning {
+  WatchWarning {
+    path: path_to_clean_string(path),
+    kind: "symlink-cycle".to_string(),
+    message: format!("Not following symlink to a directory that contains it: {}", path_to_clean_string(path)),
+    file_system: None,
+  }
+}
+
+/// Build the warning reported for a tree registered although it has more entries than its subscription allows
+pub(crate) fn too_many_entries_warning(root: &Path, entries: usize, limit: usize) -> WatchWarning {
+  WatchWarning {
+    path: path_to_clean_string(root),
+    kind: "too-many-entries".to_string(),
+    message: format!("Watching {} entries, more than maxWatchedEntries ({}): {}", entries, limit, path_to_clean_string(root)),
+    file_system: None,
+  }
+}
+
+/// Watch `directory` recursively. Unless `strict` is set, subdirectories that cannot be watched due to
+/// permissions are skipped and recorded in `registration` while the rest of the tree is still watched.
+/// Directories that `pruning` rules out are not watched at all.
+pub(crate) fn watch_tree(debouncer: &mut Debouncer, directory: &Path, strict: bool, registration: &mut Registration, pruning: &Pruning) -> notify::Result<()> {
+  let survey = Survey::walk(directory, pruning, None);
+  register_tree(debouncer, directory, strict, registration, pruning, &survey)
+}
+
+/// Register a surveyed tree, watching every subtree without pruned or unreadable directories in one go
+pub(crate) fn register_tree(
+  debouncer: &mut Debouncer,
+  directory: &Path,
+  strict: bool,
+  registration: &mut Registration,
+  pruning: &Pruning,
+  survey: &Survey,
+) -> notify::Result<()> {
+  registration.cycles.extend(survey.cycles.iter().filter(|path| path.starts_with(directory)).cloned());
+  if survey.dirty.contains(directory) {
+    watch_directory(debouncer, directory, registration)?;
+    return watch_children(debouncer, directory, strict, registration, pruning, survey);
+  }
+
+  match debouncer.watch(directory, RecursiveMode::Recursive) {
+    Err(e) if !strict && is_permission_error(&e) => {
+      // A recursive registration may have partially succeeded before hitting the unreadable directory
+      let _ = debouncer.unwatch(directory);
+      watch_directory(debouncer, directory, registration)?;
+      watch_children(debouncer, directory, strict, registration, pruning, survey)
+    }
+    Ok(()) => {
+      registration.watched.insert(directory.to_path_buf(), RecursiveMode::Recursive);
+      Ok(())
+    }
+    result => result,
+  }
+}
+
+/// Watch a single directory, leaving its subdirectories to be registered one by one
+fn watch_directory(debouncer: &mut Debouncer, directory: &Path, registration: &mut Registration) -> notify::Result<()> {
+  debouncer.watch(directory, RecursiveMode::NonRecursive)?;
+  registration.watched.insert(directory.to_path_buf(), RecursiveMode::NonRecursive);
+  registration.partial.insert(directory.to_path_buf());
+  Ok(())
+}
+
+/// Watch the subdirectories of a non-recursively watched directory
+fn watch_children(
+  debouncer: &mut Debouncer,
+  directory: &Path,
+  strict: bool,
+  registration: &mut Registration,
+  pruning: &Pruning,
+  survey: &Survey,
+) -> notify::Result<()> {
+  for entry in fs::read_dir(directory).map_err(notify::Error::io)? {
+    let entry = entry.map_err(notify::Error::io)?;
+
+    if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+      continue;
+    }
+
+    let child = entry.path();
+    if pruning.prunes(&child) {
+      registration.pruned.insert(child);
+      continue;
+    }
+
+    match register_tree(debouncer, &child, strict, registration, pruning, survey) {
+      Err(e) if !strict && is_permission_error(&e) => {
+        registration.skipped.insert(child);
+      }
+      // The directory was removed while the tree was being walked
+      Err(e) if is_not_found_error(&e) => {}
+      result => result?,
+    }
+  }
+
+  Ok(())
+}
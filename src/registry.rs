@@ -0,0 +1,130 @@
+//! Bookkeeping of live subscriptions so they can be torn down when their environment exits
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+
+use napi::bindgen_prelude::{FnArgs, Function, JsObjectValue, Object, Unknown};
+use napi::{Env, Result};
+
+use crate::hub::{Hub, Subscriber};
+use crate::{SubscriptionInfo, path_to_clean_string};
+
+/// Handles needed to stop a subscription without going through its JS object
+pub(crate) struct Entry {
+  pub hub: Arc<Hub>,
+  pub subscriber: Arc<Subscriber>,
+  pub label: Option<String>,
+  /// Milliseconds since the Unix epoch
+  pub created_at: f64,
+}
+
+#[derive(Default)]
+struct Registry {
+  /// Live subscriptions by id, together with the environment they belong to
+  entries: HashMap<u32, (usize, Entry)>,
+  /// Environments that have the cleanup hook installed
+  hooked_envs: HashSet<usize>,
+}
+
+/// Signature of `EventEmitter.prototype.on` for a listener without arguments
+type EventListenerRegistration<'env> = Function<'env, FnArgs<(&'static str, Function<'env, (), ()>)>, Unknown<'env>>;
+
+static REGISTRY: LazyLock<Mutex<Registry>> = LazyLock::new(Mutex::default);
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+fn lock() -> MutexGuard<'static, Registry> {
+  REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Allocate the id of a new subscription
+pub(crate) fn next_id() -> u32 {
+  NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Track a subscription created in `env`, installing the environment's cleanup hook on first use
+pub(crate) fn register(env: &Env, id: u32, entry: Entry) -> Result<()> {
+  let env_key = env.raw() as usize;
+  let mut registry = lock();
+
+  if !registry.hooked_envs.contains(&env_key) {
+    install_hooks(env, env_key)?;
+    registry.hooked_envs.insert(env_key);
+  }
+
+  registry.entries.insert(id, (env_key, entry));
+  Ok(())
+}
+
+/// Run `shutdown_env` when the environment is torn down (e.g. a worker terminating) or the process exits.
+/// Cleanup hooks do not run on `process.exit()`, so the main thread also needs an `exit` listener.
+fn install_hooks(env: &Env, env_key: usize) -> Result<()> {
+  env.add_env_cleanup_hook(env_key, shutdown_env)?;
+
+  let process: Object = env.get_global()?.get_named_property("process")?;
+  let on: EventListenerRegistration = process.get_named_property("on")?;
+  let listener = env.create_function_from_closure("watcherShutdown", move |_| {
+    shutdown_env(env_key);
+    Ok(())
+  })?;
+  on.apply(process, ("exit", listener).into())?;
+
+  Ok(())
+}
+
+/// Stop tracking a subscription that has been closed
+pub(crate) fn unregister(id: u32) {
+  lock().entries.remove(&id);
+}
+
+/// Stop tracking the subscriptions of `env`, handing them over to be closed
+pub(crate) fn take_env(env: &Env) -> Vec<(u32, Entry)> {
+  let env_key = env.raw() as usize;
+  lock().entries.extract_if(|_, (env, _)| *env == env_key).map(|(id, (_, entry))| (id, entry)).collect()
+}
+
+/// Describe every live subscription, across all environments
+pub(crate) fn list() -> Vec<SubscriptionInfo> {
+  let registry = lock();
+  let mut list: Vec<SubscriptionInfo> = registry
+    .entries
+    .iter()
+    .map(|(id, (_, entry))| SubscriptionInfo {
+      id: *id,
+      path: path_to_clean_string(&entry.subscriber.root),
+      label: entry.label.clone(),
+      backend: entry.subscriber.backend_name().to_string(),
+      backend_reason: entry.subscriber.polling.get().map(|polling| polling.reason.clone()),
+      event_count: entry.subscriber.counters.delivered_events.load(Ordering::Relaxed),
+      created_at: entry.created_at,
+    })
+    .collect();
+  list.sort_by_key(|info| info.id);
+  list
+}
+
+/// Stop every subscription of an environment that is being torn down
+fn shutdown_env(env_key: usize) {
+  let entries: Vec<(u32, Entry)> = {
+    let mut registry = lock();
+    registry.hooked_envs.remove(&env_key);
+    registry.entries.extract_if(|_, (env, _)| *env == env_key).map(|(id, (_, entry))| (id, entry)).collect()
+  };
+
+  for (id, entry) in entries {
+    // Mark the subscription closed first so its watcher thread stops calling into the environment
+    entry.subscriber.running.store(false, Ordering::SeqCst);
+    if let Some(ring) = &entry.subscriber.ring {
+      ring.close();
+    }
+
+    // Join the debouncer thread if this was its last subscription, shared watchers keep serving other environments
+    if let Some(debouncer) = entry.hub.detach(id) {
+      debouncer.stop();
+    }
+
+    // Queued calls can no longer be delivered to an environment that is being destroyed.
+    // Aborting also guards against a shared watcher thread that is about to call it.
+    entry.subscriber.abort_callbacks();
+  }
+}
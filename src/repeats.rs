@@ -0,0 +1,122 @@
+//! Repeats of the same error of the OS watcher, which it can report dozens of times a second once a network
+//! mount goes away. The first is delivered, repeats within `repeatedErrorWindowMs` of it are only counted, and
+//! a summary with the count follows when a different error comes or the window closes. Different errors are
+//! always delivered.
+
+use std::io;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use napi::bindgen_prelude::*;
+
+use crate::WatchCallbackResult;
+use crate::hub::{Subscriber, lock};
+
+/// Window repeats are counted in unless `repeatedErrorWindowMs` says otherwise
+pub(crate) const DEFAULT_WINDOW: Duration = Duration::from_secs(30);
+
+/// Longest the thread waiting for a window to close sleeps before checking whether the subscription is still running
+const STEP: Duration = Duration::from_millis(100);
+
+/// The last error delivered to a subscription and how often it came again since
+pub(crate) struct Repeats {
+  window: Duration,
+  last: Option<Repeat>,
+}
+
+struct Repeat {
+  message: String,
+  /// When the error was delivered, which opened the window
+  since: Instant,
+  count: u32,
+}
+
+/// What to do with an error
+pub(crate) enum Seen {
+  /// Deliver it, after the summary of the repeats of the error before it if there were any
+  New(Option<Summary>),
+  /// Count it. `first` is set for the first repeat in the window, which closes at `closes`.
+  Repeat { first: bool, closes: Instant },
+}
+
+/// How often an error came again after it was delivered
+pub(crate) struct Summary {
+  message: String,
+  count: u32,
+  elapsed: Duration,
+}
+
+impl Repeats {
+  pub fn new(window: Duration) -> Repeats {
+    Repeats { window, last: None }
+  }
+
+  /// Tell a repeat of the last error within the window from an error to deliver
+  pub fn see(&mut self, message: &str, now: Instant) -> Seen {
+    if let Some(last) = &mut self.last
+      && last.message == message
+      && now < last.since + self.window
+    {
+      last.count += 1;
+      return Seen::Repeat { first: last.count == 1, closes: last.since + self.window };
+    }
+
+    let summary = self.take_summary(now);
+    self.last = Some(Repeat { message: message.to_string(), since: now, count: 0 });
+    Seen::New(summary)
+  }
+
+  /// The summary of the repeats of the last error once its window closed, after which the error is delivered
+  /// again when it comes back
+  pub fn close(&mut self, now: Instant) -> Option<Summary> {
+    if self.last.as_ref().is_some_and(|last| now >= last.since + self.window) { self.take_summary(now) } else { None }
+  }
+
+  fn take_summary(&mut self, now: Instant) -> Option<Summary> {
+    let last = self.last.take().filter(|last| last.count > 0)?;
+    Some(Summary { message: last.message, count: last.count, elapsed: now.duration_since(last.since).min(self.window) })
+  }
+}
+
+impl Summary {
+  /// The batch reporting the repeats, with their count in `repeated`
+  pub fn into_batch(self) -> WatchCallbackResult {
+    // Whole seconds, rounded up so that repeats within the first second are not reported as in the last 0s
+    let seconds = self.elapsed.as_secs() + u64::from(self.elapsed.subsec_nanos() > 0);
+    let message = format!("{} (repeated {} times in the last {}s)", self.message, self.count, seconds);
+    WatchCallbackResult { error: Some(Error::new(Status::GenericFailure, message)), repeated: Some(self.count), ..Default::default() }
+  }
+}
+
+/// Deliver the summary of the repeats once the window closes at `closes`, unless a different error delivered it
+/// first or the subscription stopped
+pub(crate) fn summarize_at(subscriber: &Arc<Subscriber>, closes: Instant) -> io::Result<()> {
+  let subscriber = Arc::downgrade(subscriber);
+  thread::Builder::new().name("watcher-repeats".to_string()).spawn(move || {
+    while wait(&subscriber, closes) {}
+    let Some(subscriber) = subscriber.upgrade() else {
+      return;
+    };
+    let summary = subscriber.repeats.as_ref().and_then(|repeats| lock(repeats).close(Instant::now()));
+    if let Some(summary) = summary {
+      subscriber.deliver(summary.into_batch());
+    }
+  })?;
+
+  Ok(())
+}
+
+/// Sleep towards `closes`, returning whether to keep waiting
+fn wait(subscriber: &Weak<Subscriber>, closes: Instant) -> bool {
+  let Some(running) = subscriber.upgrade().map(|subscriber| subscriber.running.load(Ordering::SeqCst)) else {
+    return false;
+  };
+  let now = Instant::now();
+  if !running || now >= closes {
+    return false;
+  }
+  thread::sleep(STEP.min(closes - now));
+  true
+}
@@ -0,0 +1,305 @@
+//! Events written to a `SharedArrayBuffer` provided by JS, read there with the helper in `ring.js` without an
+//! object being created per event.
+//!
+//! The buffer starts with a header of 16 `u32` words, followed by `capacity` records of 4 `u32` words and an arena
+//! of `arenaBytes` bytes holding the UTF-8 paths of the records. Both sizes are powers of two, so the positions
+//! below can be counted as wrapping `u32`s that never reset. A record holds the event type (0 create, 1 update,
+//! 2 delete, 3 metadata) with bit 8 set for initial events and bit 9 set for paths that are not valid UTF-8,
+//! written as the bytes the OS reported, the `seq` of the callback invocation signaling it, and the start
+//! position and length of its path in the arena. A path never wraps around the end of the arena: a path that
+//! does not fit before the end starts at the beginning, and the bytes skipped count as written.
+//!
+//! There is a single writer. It announces the record and arena bytes it is about to overwrite in `CLAIMED` and
+//! `ARENA_CLAIMED` before writing them, and publishes the record in `COMMITTED` afterwards. The reader advances
+//! `READ`, and `ARENA_READ` to the end of the last path it read. When the ring overwrites the oldest records,
+//! a reader copies a record and its path and then checks against the claimed positions that they were not
+//! overwritten meanwhile. When the ring blocks, the writer waits until the reader made room instead.
+
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use napi::bindgen_prelude::*;
+use napi::{Env, UnknownRef, sys};
+
+use crate::hub::lock;
+use crate::{PathBytes, WatchEvent};
+
+/// Identifies a buffer initialized by `createEventRing`, "WRNG" in little endian
+const MAGIC: u32 = 0x474e_5257;
+/// Version of the layout, bumped whenever it changes
+const VERSION: u32 = 1;
+
+const HEADER_WORDS: usize = 16;
+const RECORD_WORDS: usize = 4;
+
+// Header words
+const MAGIC_WORD: usize = 0;
+const VERSION_WORD: usize = 1;
+const CAPACITY: usize = 2;
+const ARENA_BYTES: usize = 3;
+const CLAIMED: usize = 4;
+const COMMITTED: usize = 5;
+const READ: usize = 6;
+const ARENA_CLAIMED: usize = 7;
+const ARENA_READ: usize = 8;
+/// Records left out because their path is larger than the arena
+const DROPPED: usize = 9;
+/// 1 while a subscription writes to the ring
+const WRITER: usize = 10;
+
+/// Flag of initial events in the type word of a record
+const INITIAL: u32 = 1 << 8;
+/// Flag of events whose path is written as the bytes the OS reported, as it is not valid UTF-8
+const LOSSY: u32 = 1 << 9;
+
+/// How often a blocked writer checks whether the reader made room
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// What happens when the reader falls behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Overflow {
+  /// Overwrite the oldest records, which the reader then counts as lost
+  Overwrite,
+  /// Wait for the reader, holding up the dispatch thread
+  Block,
+}
+
+impl Overflow {
+  pub fn parse(name: &str) -> Option<Overflow> {
+    match name {
+      "overwrite" => Some(Overflow::Overwrite),
+      "block" => Some(Overflow::Block),
+      _ => None,
+    }
+  }
+}
+
+/// The `ring` option as passed in, only looked at during the call that received it
+pub struct RingBuffer(sys::napi_value);
+
+// Options are moved to background tasks, which leave the ring alone
+unsafe impl Send for RingBuffer {}
+
+impl FromNapiValue for RingBuffer {
+  unsafe fn from_napi_value(_env: sys::napi_env, value: sys::napi_value) -> Result<RingBuffer> {
+    Ok(RingBuffer(value))
+  }
+}
+
+/// The memory of the `SharedArrayBuffer`, kept alive by the subscription's `RingReference`
+struct Memory {
+  base: *mut u8,
+  capacity: u32,
+  arena_bytes: u32,
+}
+
+// The buffer is shared memory by design, only accessed through atomics and the writer's mutex on this side
+unsafe impl Send for Memory {}
+
+impl Memory {
+  fn word(&self, index: usize) -> &AtomicU32 {
+    unsafe { &*(self.base.add(index * 4) as *const AtomicU32) }
+  }
+
+  fn record(&self, index: u32) -> *mut u32 {
+    let slot = (index & (self.capacity - 1)) as usize;
+    unsafe { self.base.add((HEADER_WORDS + slot * RECORD_WORDS) * 4) as *mut u32 }
+  }
+
+  fn arena(&self, position: u32) -> *mut u8 {
+    let offset = (HEADER_WORDS + self.capacity as usize * RECORD_WORDS) * 4;
+    unsafe { self.base.add(offset + (position & (self.arena_bytes - 1)) as usize) }
+  }
+
+  /// Where a path of `len` bytes goes in the arena, moved to its start if it would wrap around the end
+  fn placement(&self, len: u32) -> u32 {
+    let claimed = self.word(ARENA_CLAIMED).load(Ordering::SeqCst);
+    let offset = claimed & (self.arena_bytes - 1);
+    if offset + len > self.arena_bytes { claimed.wrapping_add(self.arena_bytes - offset) } else { claimed }
+  }
+
+  /// Whether a record with a path of `len` bytes can be written without overwriting anything unread
+  fn has_room(&self, len: u32) -> bool {
+    let records = self.word(COMMITTED).load(Ordering::SeqCst).wrapping_sub(self.word(READ).load(Ordering::SeqCst));
+    let end = self.placement(len).wrapping_add(len);
+    records < self.capacity && end.wrapping_sub(self.word(ARENA_READ).load(Ordering::SeqCst)) <= self.arena_bytes
+  }
+
+  fn write(&self, event: &WatchEvent, seq: u32) {
+    let path = path_bytes(event);
+    let len = path.len() as u32;
+    let start = self.placement(len);
+    let index = self.word(COMMITTED).load(Ordering::SeqCst);
+
+    self.word(CLAIMED).store(index.wrapping_add(1), Ordering::SeqCst);
+    self.word(ARENA_CLAIMED).store(start.wrapping_add(len), Ordering::SeqCst);
+
+    let kind = match event.event_type.as_str() {
+      "create" => 0,
+      "update" => 1,
+      "metadata" => 3,
+      _ => 2,
+    };
+    unsafe {
+      ptr::copy_nonoverlapping(path.as_ptr(), self.arena(start), path.len());
+      let record = self.record(index);
+      let flags = if event.is_initial { INITIAL } else { 0 } | if event.path_bytes.is_some() { LOSSY } else { 0 };
+      record.write_volatile(kind | flags);
+      record.add(1).write_volatile(seq);
+      record.add(2).write_volatile(start);
+      record.add(3).write_volatile(len);
+    }
+
+    self.word(COMMITTED).store(index.wrapping_add(1), Ordering::SeqCst);
+  }
+}
+
+/// The bytes written for the path of `event`
+fn path_bytes(event: &WatchEvent) -> &[u8] {
+  match &event.path_bytes {
+    Some(PathBytes(bytes)) => bytes,
+    None => event.path.as_bytes(),
+  }
+}
+
+/// The writing end of a ring
+pub(crate) struct Ring {
+  memory: Mutex<Option<Memory>>,
+  overflow: Overflow,
+}
+
+impl Ring {
+  /// Write as many of `events` as fit, all of them unless the ring blocks, returning how many were consumed.
+  /// Events whose path is larger than the arena are counted as dropped.
+  pub fn write(&self, events: &[WatchEvent], seq: u32) -> usize {
+    let memory = lock(&self.memory);
+    let Some(memory) = memory.as_ref() else {
+      return events.len();
+    };
+
+    for (written, event) in events.iter().enumerate() {
+      let len = path_bytes(event).len();
+      if len > memory.arena_bytes as usize {
+        memory.word(DROPPED).fetch_add(1, Ordering::SeqCst);
+        continue;
+      }
+      if self.overflow == Overflow::Block && !memory.has_room(len as u32) {
+        return written;
+      }
+      memory.write(event, seq);
+    }
+
+    events.len()
+  }
+
+  /// Wait until the reader made room for a record with a path of `len` bytes, or the subscription stopped.
+  /// Returns whether there is room.
+  pub fn wait_for_room(&self, len: usize, running: &AtomicBool) -> bool {
+    while running.load(Ordering::SeqCst) {
+      match lock(&self.memory).as_ref() {
+        Some(memory) if memory.has_room(len as u32) => return true,
+        Some(_) => {}
+        None => return false,
+      }
+      thread::sleep(POLL_INTERVAL);
+    }
+    false
+  }
+
+  /// Stop writing, leaving the buffer to another subscription
+  pub fn close(&self) {
+    if let Some(memory) = lock(&self.memory).take() {
+      memory.word(WRITER).store(0, Ordering::SeqCst);
+    }
+  }
+}
+
+/// Keeps the `SharedArrayBuffer` of a ring alive for as long as the subscription writing to it
+pub(crate) struct RingReference {
+  ring: Arc<Ring>,
+  env: sys::napi_env,
+  reference: Option<UnknownRef>,
+}
+
+// Subscriptions with a ring are only owned by their JS object, so they are dropped on the JS thread
+unsafe impl Send for RingReference {}
+
+impl Drop for RingReference {
+  fn drop(&mut self) {
+    // A write still in progress finishes before the memory can go away
+    self.ring.close();
+    if let Some(reference) = self.reference.take() {
+      let _ = reference.unref(&Env::from_raw(self.env));
+    }
+  }
+}
+
+/// Start writing to the ring initialized by `createEventRing` in `buffer`
+pub(crate) fn attach(env: &Env, buffer: &RingBuffer, overflow: Overflow) -> std::result::Result<(Arc<Ring>, RingReference), String> {
+  let memory = check(env, buffer)?;
+
+  if memory.word(WRITER).compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+    return Err("Ring buffer is already written by another subscription".to_string());
+  }
+
+  let reference = match unsafe { Unknown::from_napi_value(env.raw(), buffer.0) }.and_then(|buffer| buffer.create_ref()) {
+    Ok(reference) => reference,
+    Err(e) => {
+      memory.word(WRITER).store(0, Ordering::SeqCst);
+      return Err(format!("Failed to reference the ring buffer: {}", e));
+    }
+  };
+
+  let ring = Arc::new(Ring { memory: Mutex::new(Some(memory)), overflow });
+  Ok((Arc::clone(&ring), RingReference { ring, env: env.raw(), reference: Some(reference) }))
+}
+
+/// Check that `buffer` could be written to, for `validateOptions`
+pub(crate) fn validate(env: &Env, buffer: &RingBuffer) -> std::result::Result<(), String> {
+  let memory = check(env, buffer)?;
+  if memory.word(WRITER).load(Ordering::SeqCst) != 0 {
+    return Err("Ring buffer is already written by another subscription".to_string());
+  }
+  Ok(())
+}
+
+/// Check that `buffer` is a `SharedArrayBuffer` initialized by `createEventRing` and locate its memory
+fn check(env: &Env, buffer: &RingBuffer) -> std::result::Result<Memory, String> {
+  let invalid = |e: Error| format!("Invalid ring buffer: {}", e);
+  let global = env.get_global().map_err(invalid)?;
+  let buffer = unsafe { Unknown::from_napi_value(env.raw(), buffer.0) }.map_err(invalid)?;
+
+  let shared: Function<Unknown, Unknown> = global.get_named_property("SharedArrayBuffer").map_err(invalid)?;
+  let mut is_shared = false;
+  unsafe { sys::napi_instanceof(env.raw(), buffer.raw(), shared.raw(), &mut is_shared) };
+  if !is_shared {
+    return Err("Ring buffer must be a SharedArrayBuffer created by createEventRing".to_string());
+  }
+
+  // N-API only exposes the memory of shared buffers through a view
+  let view_constructor: Function<Unknown, Unknown> = global.get_named_property("Uint8Array").map_err(invalid)?;
+  let view = view_constructor.new_instance(buffer).map_err(invalid)?;
+  let mut data = ptr::null_mut();
+  let mut len = 0;
+  let status = unsafe { sys::napi_get_typedarray_info(env.raw(), view.raw(), ptr::null_mut(), &mut len, &mut data, ptr::null_mut(), ptr::null_mut()) };
+  if status != sys::Status::napi_ok || data.is_null() || !(data as usize).is_multiple_of(4) || len < HEADER_WORDS * 4 {
+    return Err("Ring buffer is too small".to_string());
+  }
+
+  let memory = Memory { base: data as *mut u8, capacity: 0, arena_bytes: 0 };
+  if memory.word(MAGIC_WORD).load(Ordering::SeqCst) != MAGIC || memory.word(VERSION_WORD).load(Ordering::SeqCst) != VERSION {
+    return Err("Ring buffer was not initialized by createEventRing of this version".to_string());
+  }
+
+  let capacity = memory.word(CAPACITY).load(Ordering::SeqCst);
+  let arena_bytes = memory.word(ARENA_BYTES).load(Ordering::SeqCst);
+  let expected = (HEADER_WORDS + capacity as usize * RECORD_WORDS) * 4 + arena_bytes as usize;
+  if !capacity.is_power_of_two() || !arena_bytes.is_power_of_two() || len != expected {
+    return Err(format!("Ring buffer header does not match its size of {} bytes", len));
+  }
+
+  Ok(Memory { capacity, arena_bytes, ..memory })
+}
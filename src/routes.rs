@@ -0,0 +1,113 @@
+//! Batches split between several callbacks by the patterns their paths match, for `subscribeRouted`
+
+use std::path::Path;
+
+use globset::GlobSet;
+use napi::bindgen_prelude::*;
+
+use crate::hub::{self, Subscriber};
+use crate::{Callback, Route, WatchCallbackResult, WatchEvent, build_glob_set};
+
+/// The callbacks of a routed subscription, in place of its one callback
+pub(crate) struct Router {
+  /// Patterns of the routes that have one, in the order the routes were given
+  glob_set: GlobSet,
+  /// Callback of each pattern
+  callbacks: Vec<Callback>,
+  /// Callback of the events no pattern matched, which are dropped without one
+  fallback: Option<Callback>,
+  /// Whether an event goes to every route whose pattern it matches rather than the first
+  all: bool,
+}
+
+impl Router {
+  pub fn new(routes: Vec<Route>, all: bool) -> Result<Router> {
+    let mut patterns = Vec::new();
+    let mut callbacks = Vec::new();
+    let mut fallback = None;
+    for route in routes {
+      match route.pattern {
+        Some(pattern) => {
+          patterns.push(pattern);
+          callbacks.push(route.callback);
+        }
+        None if fallback.is_none() => fallback = Some(route.callback),
+        None => return Err(Error::new(Status::InvalidArg, "Only one route can leave out its pattern")),
+      }
+    }
+
+    Ok(Router { glob_set: build_glob_set(&patterns)?, callbacks, fallback, all })
+  }
+
+  /// Every callback of the routes
+  pub fn callbacks(&self) -> impl Iterator<Item = &Callback> {
+    self.callbacks.iter().chain(&self.fallback)
+  }
+
+  /// Call each route with the events of `batch` it takes, all as number `seq`. Batches that carry an error or
+  /// warnings, or no events at all like heartbeats, reach every route.
+  pub fn dispatch(&self, subscriber: &Subscriber, seq: u32, mut batch: WatchCallbackResult) -> Status {
+    let broadcast = batch.events.is_empty() || batch.error.is_some() || batch.warnings.is_some();
+    let mut routed: Vec<Vec<WatchEvent>> = (0..=self.callbacks.len()).map(|_| Vec::new()).collect();
+    for event in batch.events.drain(..) {
+      let matches = self.matches(Path::new(&event.path), &subscriber.root);
+      match matches.split_last() {
+        None => routed[self.callbacks.len()].push(event),
+        Some((&last, rest)) if self.all => {
+          for &index in rest {
+            routed[index].push(event.clone());
+          }
+          routed[last].push(event);
+        }
+        Some(_) => routed[matches[0]].push(event),
+      }
+    }
+
+    let mut status = Status::Ok;
+    for (callback, events) in self.callbacks.iter().map(Some).chain([self.fallback.as_ref()]).zip(routed) {
+      let Some(callback) = callback else {
+        continue;
+      };
+      if events.is_empty() && !broadcast {
+        continue;
+      }
+      let routed_status = hub::call(subscriber, callback, seq, share(&batch, events));
+      // A released callback means the subscription was closed, which outweighs any other failure
+      if routed_status == Status::Closing || status == Status::Ok {
+        status = routed_status;
+      }
+    }
+    status
+  }
+
+  /// Indices of the patterns `path` matches, relative to the root or as a whole, in the order of the routes
+  fn matches(&self, path: &Path, root: &Path) -> Vec<usize> {
+    let mut matches = self.glob_set.matches(path);
+    if let Ok(relative) = path.strip_prefix(root) {
+      matches.extend(self.glob_set.matches(relative));
+      matches.sort_unstable();
+      matches.dedup();
+    }
+    matches
+  }
+}
+
+/// A copy of `batch` with `events` in place of its own
+fn share(batch: &WatchCallbackResult, events: Vec<WatchEvent>) -> WatchCallbackResult {
+  WatchCallbackResult {
+    seq: batch.seq,
+    error: batch.error.as_ref().map(|error| Error::new(error.status, error.reason.clone())),
+    events,
+    warnings: batch.warnings.clone(),
+    initial: batch.initial.clone(),
+    synthetic: batch.synthetic,
+    cursor: batch.cursor,
+    heartbeat: batch.heartbeat,
+    overflowed: batch.overflowed,
+    storm: batch.storm.clone(),
+    memory_shed: batch.memory_shed.clone(),
+    repeated: batch.repeated,
+    historical: batch.historical,
+    event_id: batch.event_id,
+  }
+}
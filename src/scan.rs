@@ -0,0 +1,286 @@
+//! Walking a tree with the ignore rules of a subscription, and comparing the results of two walks
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, DirEntry, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::UNIX_EPOCH;
+
+use napi::{Error, Status};
+use watcher_core::IgnoreSet;
+
+use crate::hub::{Subscriber, lock};
+use crate::long_paths;
+use crate::mounts::Mounts;
+use crate::{RescanResult, ScanEntry, WatchCallbackResult, WatchEvent, path_to_clean_string};
+
+/// Number of create events delivered per batch of the initial scan
+const BATCH_SIZE: usize = 1000;
+
+/// An entry found by walking a tree, together with the id identifying its file
+#[derive(Clone)]
+pub(crate) struct Scanned {
+  pub entry: ScanEntry,
+  /// Inode number where available, so a file replaced by another of the same size and time is noticed
+  pub file_id: u64,
+}
+
+/// State of a tree keyed by path, as last delivered to a subscription
+pub(crate) type Baseline = BTreeMap<String, Scanned>;
+
+/// Describe an entry found while walking a tree
+pub(crate) fn scanned(path: String, metadata: &Metadata) -> Scanned {
+  let file_type = file_type(metadata);
+  let mtime_ms = metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map_or(0.0, |duration| duration.as_secs_f64() * 1000.0);
+
+  Scanned { entry: ScanEntry { path, file_type: file_type.to_string(), mtime_ms, size: metadata.len() as f64 }, file_id: file_id(metadata) }
+}
+
+/// Type of the entry `metadata` describes, as `ScanEntry.fileType` and `WatchEvent.fileType` name it
+pub(crate) fn file_type(metadata: &Metadata) -> &'static str {
+  if metadata.is_symlink() {
+    "symlink"
+  } else if metadata.is_dir() {
+    "directory"
+  } else {
+    "file"
+  }
+}
+
+/// Identity of the file behind an entry, where the platform exposes one
+fn file_id(metadata: &Metadata) -> u64 {
+  #[cfg(unix)]
+  {
+    std::os::unix::fs::MetadataExt::ino(metadata)
+  }
+  #[cfg(not(unix))]
+  {
+    let _ = metadata;
+    0
+  }
+}
+
+/// Keys of the entries at or below `directory`
+pub(crate) fn keys_below(baseline: &Baseline, directory: &Path) -> Vec<String> {
+  let directory_key = path_to_clean_string(directory);
+  let prefix = format!("{}{}", directory_key, std::path::MAIN_SEPARATOR);
+
+  baseline
+    .contains_key(&directory_key)
+    .then(|| directory_key.clone())
+    .into_iter()
+    .chain(baseline.range(prefix.clone()..).map(|(key, _)| key).take_while(|key| key.starts_with(&prefix)).cloned())
+    .collect()
+}
+
+/// Check if the baseline already matches the current state of the path an event is about
+pub(crate) fn reflects(baseline: &Baseline, event: &WatchEvent) -> bool {
+  match (baseline.get(&event.path), fs::symlink_metadata(long_paths::verbatim(Path::new(&event.path)))) {
+    (None, Err(_)) => true,
+    (Some(old), Ok(metadata)) => {
+      let new = scanned(event.path.clone(), &metadata);
+      old.file_id == new.file_id && !entry_changed(&old.entry, &new.entry)
+    }
+    _ => false,
+  }
+}
+
+/// Visit every entry below `directory` that the ignore patterns of a subscription on `root` do not exclude,
+/// without descending into directories whose contents are all ignored, nor with `mounts` into directories on
+/// another file system. Subdirectories that cannot be read due to permissions are skipped unless `strict` is
+/// set. Stops early when `visit` returns false.
+pub(crate) fn walk(
+  directory: &PathBuf,
+  root: &PathBuf,
+  ignore_set: &IgnoreSet,
+  mounts: Option<&Mounts>,
+  strict: bool,
+  visit: &mut dyn FnMut(&DirEntry) -> bool,
+) -> io::Result<()> {
+  walk_directory(directory, true, root, ignore_set, mounts, strict, visit).map(|_| ())
+}
+
+/// Walk one directory, returning whether the walk should go on
+fn walk_directory(
+  directory: &PathBuf,
+  top: bool,
+  root: &PathBuf,
+  ignore_set: &IgnoreSet,
+  mounts: Option<&Mounts>,
+  strict: bool,
+  visit: &mut dyn FnMut(&DirEntry) -> bool,
+) -> io::Result<bool> {
+  let read_dir = match fs::read_dir(directory) {
+    Ok(read_dir) => read_dir,
+    // The directory was removed while the tree was being walked
+    Err(e) if !top && e.kind() == io::ErrorKind::NotFound => return Ok(true),
+    Err(e) if !top && !strict && e.kind() == io::ErrorKind::PermissionDenied => return Ok(true),
+    Err(e) => return Err(io::Error::new(e.kind(), format!("Failed to read directory {}: {}", path_to_clean_string(directory), e))),
+  };
+
+  for entry in read_dir.flatten() {
+    let path = entry.path();
+    if !ignore_set.is_ignored(&path, root) && !visit(&entry) {
+      return Ok(false);
+    }
+
+    if entry.file_type().is_ok_and(|t| t.is_dir())
+      && !ignore_set.ignores_tree(&path, root)
+      && !mounts.is_some_and(|mounts| mounts.crosses(&path))
+      && !walk_directory(&path, false, root, ignore_set, mounts, strict, visit)?
+    {
+      return Ok(false);
+    }
+  }
+
+  Ok(true)
+}
+
+/// Walk the subscriber's tree on a background thread, delivering everything that is not ignored as create
+/// events. Live events are held back until the scan has been delivered, so they always follow its entries.
+/// A tree that grew past the subscriber's entry limit since it was registered ends the scan with an error if
+/// the limit is meant to fail; otherwise registration has already warned about it.
+pub(crate) fn spawn(subscriber: Arc<Subscriber>) -> io::Result<()> {
+  thread::Builder::new().name("watcher-initial-scan".to_string()).spawn(move || {
+    let mut events = Vec::new();
+    let mut baseline = Baseline::new();
+    let mut entries = 0;
+    let mut exceeded = false;
+
+    // Unreadable directories have already been reported as warnings
+    let _ = walk(&subscriber.root, &subscriber.root, &subscriber.ignore_set, subscriber.mounts.as_ref(), false, &mut |entry| {
+      if subscriber.skips_special(entry) {
+        return true;
+      }
+      entries += 1;
+      if subscriber.entry_limit.is_some_and(|limit| limit.fail && entries > limit.max) {
+        exceeded = true;
+        return false;
+      }
+
+      let event = WatchEvent::at(&entry.path(), "create", true);
+      if let Ok(metadata) = entry.metadata() {
+        baseline.insert(event.path.clone(), scanned(event.path.clone(), &metadata));
+      }
+      events.push(event);
+
+      if events.len() >= BATCH_SIZE {
+        subscriber.send(WatchCallbackResult { events: std::mem::take(&mut events), initial: Some("partial".to_string()), ..Default::default() });
+      }
+
+      subscriber.running.load(Ordering::SeqCst)
+    });
+
+    let warnings = subscriber.mounts.iter().flat_map(Mounts::warnings).collect::<Vec<_>>();
+    let warnings = (!warnings.is_empty()).then_some(warnings);
+    let mut last = WatchCallbackResult { events, initial: Some("complete".to_string()), warnings, ..Default::default() };
+    if let Some(limit) = subscriber.entry_limit.filter(|_| exceeded) {
+      let message = format!("Stopped the initial scan at more than maxWatchedEntries ({}) entries: {}", limit.max, path_to_clean_string(&subscriber.root));
+      last.error = Some(Error::new(Status::GenericFailure, message));
+    }
+
+    // Rescans compare against what the scan delivered
+    *lock(&subscriber.baseline) = Some(baseline);
+    subscriber.deliver_walked(vec![last], None);
+  })?;
+
+  Ok(())
+}
+
+/// Compare two scans, emitting creates for new paths, updates for entries `changed` reports as different,
+/// and deletes for paths that are gone. Paths are compared byte for byte.
+pub(crate) fn diff<T>(before: &[T], after: &[T], entry: impl Fn(&T) -> &ScanEntry, changed: impl Fn(&T, &T) -> bool, emit: &mut dyn FnMut(WatchEvent)) {
+  let mut previous: HashMap<&str, &T> = before.iter().map(|item| (entry(item).path.as_str(), item)).collect();
+
+  for item in after {
+    let path = &entry(item).path;
+    let event_type = match previous.remove(path.as_str()) {
+      None => "create",
+      Some(old) if changed(old, item) => "update",
+      Some(_) => continue,
+    };
+    emit(WatchEvent::new(path.clone(), event_type, false));
+  }
+
+  // Keep the order of the earlier scan for deletes
+  for item in before {
+    let path = &entry(item).path;
+    if previous.remove(path.as_str()).is_some() {
+      emit(WatchEvent::new(path.clone(), "delete", false));
+    }
+  }
+}
+
+/// Check if the type, size or modification time of an entry changed
+pub(crate) fn entry_changed(old: &ScanEntry, new: &ScanEntry) -> bool {
+  old.file_type != new.file_type || old.size != new.size || old.mtime_ms != new.mtime_ms
+}
+
+/// Walk `directory` again and deliver synthetic events for everything that differs from what the subscription
+/// last delivered. Live events are held back meanwhile and delivered after the synthetic ones. Without a
+/// baseline yet, the whole tree is walked to record one and nothing is delivered.
+pub(crate) fn rescan(subscriber: &Subscriber, directory: &Path) -> io::Result<RescanResult> {
+  subscriber.hold();
+
+  let seeding = lock(&subscriber.baseline).is_none();
+  let directory = if seeding { subscriber.root.clone() } else { directory.to_path_buf() };
+
+  let mut after = Vec::new();
+  if directory != subscriber.root
+    && !subscriber.ignore_set.is_ignored(&directory, &subscriber.root)
+    && let Ok(metadata) = fs::symlink_metadata(&directory)
+  {
+    after.push(scanned(path_to_clean_string(&directory), &metadata));
+  }
+
+  // A directory that is gone turns everything recorded below it into deletes
+  if directory.is_dir() {
+    let walked = walk(&directory, &subscriber.root, &subscriber.ignore_set, subscriber.mounts.as_ref(), false, &mut |entry| {
+      if subscriber.skips_special(entry) {
+        return true;
+      }
+      if let Ok(metadata) = entry.metadata() {
+        after.push(scanned(path_to_clean_string(&entry.path()), &metadata));
+      }
+      subscriber.running.load(Ordering::SeqCst)
+    });
+
+    if let Err(e) = walked {
+      subscriber.deliver_walked(vec![], None);
+      return Err(e);
+    }
+  }
+
+  let mut result = RescanResult { created: 0, updated: 0, deleted: 0 };
+  let mut events = Vec::new();
+  {
+    let mut baseline = lock(&subscriber.baseline);
+    let baseline = baseline.get_or_insert_default();
+    let removed: Vec<Scanned> = keys_below(baseline, &directory).iter().filter_map(|key| baseline.remove(key)).collect();
+
+    if !seeding {
+      diff(&removed, &after, |scanned| &scanned.entry, |old, new| old.file_id != new.file_id || entry_changed(&old.entry, &new.entry), &mut |event| {
+        match event.event_type.as_str() {
+          "create" => result.created += 1,
+          "update" => result.updated += 1,
+          _ => result.deleted += 1,
+        }
+        events.push(event);
+      });
+    }
+
+    baseline.extend(after.into_iter().map(|scanned| (scanned.entry.path.clone(), scanned)));
+  }
+
+  let mut batches: Vec<WatchCallbackResult> =
+    events.chunks(BATCH_SIZE).map(|events| WatchCallbackResult { events: events.to_vec(), synthetic: Some(true), ..Default::default() }).collect();
+  let warnings = subscriber.mounts.iter().flat_map(Mounts::warnings).collect::<Vec<_>>();
+  if !warnings.is_empty() {
+    batches.push(WatchCallbackResult { warnings: Some(warnings), ..Default::default() });
+  }
+  subscriber.deliver_walked(batches, Some(&directory));
+  Ok(result)
+}
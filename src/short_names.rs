@@ -0,0 +1,115 @@
+//! Resolving Windows 8.3 short names such as `PROGRA~1` in event paths, which tools touching files through
+//! short paths cause the OS to report, to the long names the rest of an application knows them by.
+
+use crate::WatchEvent;
+
+/// Replace the short names in the paths of `events` by long ones. Paths that cannot be resolved, for example
+/// because the file was deleted meanwhile, are left as they are and flagged with `shortName`.
+#[cfg(windows)]
+pub(crate) fn expand(events: &mut [WatchEvent]) {
+  windows::expand(events)
+}
+
+#[cfg(not(windows))]
+pub(crate) fn expand(_events: &mut [WatchEvent]) {}
+
+#[cfg(windows)]
+mod windows {
+  use std::collections::HashMap;
+  use std::iter;
+  use std::os::windows::ffi::OsStrExt;
+  use std::path::Path;
+  use std::ptr;
+  use std::sync::{LazyLock, Mutex};
+
+  use crate::hub::lock;
+  use crate::{WatchEvent, long_paths, path_to_clean_string};
+
+  /// Most prefixes remembered before starting over
+  const REMEMBERED_PREFIXES: usize = 4096;
+
+  /// Long form of paths ending in a short name, by short path, shared by all subscriptions
+  static LONG_NAMES: LazyLock<Mutex<HashMap<String, String>>> = LazyLock::new(Mutex::default);
+
+  #[link(name = "kernel32")]
+  unsafe extern "system" {
+    fn GetLongPathNameW(short: *const u16, long: *mut u16, len: u32) -> u32;
+  }
+
+  pub fn expand(events: &mut [WatchEvent]) {
+    if !events.iter().any(|event| short_prefix_len(&event.path).is_some()) {
+      return;
+    }
+
+    let mut long_names = lock(&LONG_NAMES);
+    for event in events.iter_mut().filter(|event| event.path_bytes.is_none()) {
+      let Some(end) = short_prefix_len(&event.path) else {
+        continue;
+      };
+
+      // Resolving the part up to the last short name still works when only something below it was deleted
+      let prefix = &event.path[..end];
+      let long = match long_names.get(prefix) {
+        Some(long) => Some(long.clone()),
+        None => long_path(prefix).inspect(|long| {
+          if long_names.len() >= REMEMBERED_PREFIXES {
+            long_names.clear();
+          }
+          long_names.insert(prefix.to_string(), long.clone());
+        }),
+      };
+
+      match long {
+        Some(long) => {
+          // A new file may take over the short name of a deleted one
+          if event.event_type == "delete" && end == event.path.len() {
+            long_names.remove(prefix);
+          }
+          event.path.replace_range(..end, &long);
+        }
+        None => event.short_name = Some(true),
+      }
+    }
+  }
+
+  /// Length of the part of `path` up to and including its last component that looks like a short name
+  fn short_prefix_len(path: &str) -> Option<usize> {
+    let mut end = None;
+    let mut start = 0;
+    for component in path.split(['\\', '/']) {
+      if is_short_name(component) {
+        end = Some(start + component.len());
+      }
+      start += component.len() + 1;
+    }
+    end
+  }
+
+  /// Whether a name has the shape of a generated 8.3 name: at most six characters, a tilde and a number making
+  /// up at most eight, and an extension of at most three
+  fn is_short_name(name: &str) -> bool {
+    let (base, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+    let Some((stem, number)) = base.rsplit_once('~') else {
+      return false;
+    };
+    !stem.is_empty() && base.len() <= 8 && !number.is_empty() && number.bytes().all(|byte| byte.is_ascii_digit()) && extension.len() <= 3
+  }
+
+  /// The long form of an existing path
+  fn long_path(path: &str) -> Option<String> {
+    let path = long_paths::verbatim(Path::new(path));
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(iter::once(0)).collect();
+    let len = unsafe { GetLongPathNameW(wide.as_ptr(), ptr::null_mut(), 0) };
+    if len == 0 {
+      return None;
+    }
+
+    let mut buffer = vec![0; len as usize];
+    let written = unsafe { GetLongPathNameW(wide.as_ptr(), buffer.as_mut_ptr(), len) };
+    if written == 0 || written >= len {
+      return None;
+    }
+    buffer.truncate(written as usize);
+    String::from_utf16(&buffer).ok().map(|long| path_to_clean_string(Path::new(&long)))
+  }
+}
@@ -0,0 +1,168 @@
+//! Snapshots of a tree persisted to disk, to find out what changed while nothing was watching
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::ScanEntry;
+use crate::scan::Scanned;
+use crate::usn::Cursor;
+
+/// Leading bytes identifying a snapshot file
+const MAGIC: &[u8; 8] = b"WTCHSNAP";
+
+/// Version of the snapshot format, bumped whenever the layout changes
+const VERSION: u32 = 2;
+
+/// A snapshot read back
+pub(crate) struct Snapshot {
+  /// Directory the snapshot was taken of
+  pub root: String,
+  /// Where the change journal of its volume stood, for snapshots taken with `changeJournal` where it keeps one
+  pub cursor: Option<Cursor>,
+  pub entries: Vec<Scanned>,
+}
+
+/// Reasons a snapshot or journal cannot be used
+pub(crate) enum FormatError {
+  NotFound,
+  Corrupt(&'static str),
+  UnsupportedVersion(u32),
+  Io(io::Error),
+}
+
+/// Serialize the entries of a tree. The layout is the magic bytes, the format version, the root the
+/// snapshot was taken of, a byte telling whether the journal id and USN of a change journal cursor follow,
+/// the number of entries and then every entry's path, type, modification time, size and file id, with all
+/// numbers little-endian. Version 1 had no cursor.
+pub(crate) fn encode(root: &str, cursor: Option<Cursor>, entries: &[Scanned]) -> Vec<u8> {
+  let mut bytes = Vec::with_capacity(49 + root.len() + entries.iter().map(|scanned| scanned.entry.path.len() + 29).sum::<usize>());
+  bytes.extend_from_slice(MAGIC);
+  bytes.extend_from_slice(&VERSION.to_le_bytes());
+  write_str(&mut bytes, root);
+  match cursor {
+    Some(cursor) => {
+      bytes.push(1);
+      bytes.extend_from_slice(&cursor.journal_id.to_le_bytes());
+      bytes.extend_from_slice(&cursor.usn.to_le_bytes());
+    }
+    None => bytes.push(0),
+  }
+  bytes.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+
+  for Scanned { entry, file_id } in entries {
+    write_str(&mut bytes, &entry.path);
+    bytes.push(match entry.file_type.as_str() {
+      "directory" => 1,
+      "symlink" => 2,
+      _ => 0,
+    });
+    bytes.extend_from_slice(&entry.mtime_ms.to_le_bytes());
+    bytes.extend_from_slice(&(entry.size as u64).to_le_bytes());
+    bytes.extend_from_slice(&file_id.to_le_bytes());
+  }
+
+  bytes
+}
+
+/// Parse a snapshot
+pub(crate) fn decode(bytes: &[u8]) -> Result<Snapshot, FormatError> {
+  let mut reader = Reader { bytes };
+
+  if reader.take(MAGIC.len())? != MAGIC {
+    return Err(FormatError::Corrupt("not a snapshot file"));
+  }
+
+  let version = u32::from_le_bytes(reader.array()?);
+  if version != 1 && version != VERSION {
+    return Err(FormatError::UnsupportedVersion(version));
+  }
+
+  let root = reader.string()?;
+  let cursor = match version {
+    1 => None,
+    _ => match reader.array::<1>()?[0] {
+      0 => None,
+      1 => Some(Cursor { journal_id: u64::from_le_bytes(reader.array()?), usn: i64::from_le_bytes(reader.array()?) }),
+      _ => return Err(FormatError::Corrupt("unknown change journal cursor")),
+    },
+  };
+  let count = u64::from_le_bytes(reader.array()?);
+  let mut entries = Vec::new();
+
+  for _ in 0..count {
+    let path = reader.string()?;
+    let file_type = match reader.array::<1>()?[0] {
+      0 => "file",
+      1 => "directory",
+      2 => "symlink",
+      _ => return Err(FormatError::Corrupt("unknown file type")),
+    };
+    let mtime_ms = f64::from_le_bytes(reader.array()?);
+    let size = u64::from_le_bytes(reader.array()?) as f64;
+    let file_id = u64::from_le_bytes(reader.array()?);
+
+    entries.push(Scanned { entry: ScanEntry { path, file_type: file_type.to_string(), mtime_ms, size }, file_id });
+  }
+
+  if !reader.bytes.is_empty() {
+    return Err(FormatError::Corrupt("unexpected data after the last entry"));
+  }
+
+  Ok(Snapshot { root, cursor, entries })
+}
+
+/// Write a snapshot next to its destination and move it into place, so readers never see a partial file
+pub(crate) fn write(path: &Path, bytes: &[u8]) -> io::Result<()> {
+  let mut temporary = path.as_os_str().to_owned();
+  temporary.push(format!(".{}.tmp", std::process::id()));
+
+  let result = File::create(&temporary).and_then(|mut file| {
+    file.write_all(bytes)?;
+    file.sync_all()
+  });
+
+  match result.and_then(|()| fs::rename(&temporary, path)) {
+    Ok(()) => Ok(()),
+    Err(e) => {
+      let _ = fs::remove_file(&temporary);
+      Err(e)
+    }
+  }
+}
+
+/// Read a snapshot file
+pub(crate) fn read(path: &Path) -> Result<Vec<u8>, FormatError> {
+  fs::read(path).map_err(|e| if e.kind() == io::ErrorKind::NotFound { FormatError::NotFound } else { FormatError::Io(e) })
+}
+
+pub(crate) fn write_str(bytes: &mut Vec<u8>, value: &str) {
+  bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+  bytes.extend_from_slice(value.as_bytes());
+}
+
+/// Cursor over the bytes of a snapshot or journal that reports truncation as corruption
+pub(crate) struct Reader<'a> {
+  pub bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+  pub fn take(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+    if self.bytes.len() < len {
+      return Err(FormatError::Corrupt("unexpected end of file"));
+    }
+
+    let (taken, rest) = self.bytes.split_at(len);
+    self.bytes = rest;
+    Ok(taken)
+  }
+
+  pub fn array<const N: usize>(&mut self) -> Result<[u8; N], FormatError> {
+    Ok(self.take(N)?.try_into().unwrap_or([0; N]))
+  }
+
+  pub fn string(&mut self) -> Result<String, FormatError> {
+    let len = u32::from_le_bytes(self.array()?) as usize;
+    String::from_utf8(self.take(len)?.to_vec()).map_err(|_| FormatError::Corrupt("path is not valid UTF-8"))
+  }
+}
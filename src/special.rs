@@ -0,0 +1,51 @@
+//! Skipping FIFOs, sockets and device nodes for `skipSpecialFiles`, which consumers reading every changed file
+//! would block on or fail to read
+
+use std::collections::HashSet;
+use std::fs::{self, FileType};
+use std::path::Path;
+
+use crate::WatchEvent;
+use crate::long_paths;
+
+/// Check if a file is neither a regular file, a directory nor a symlink
+pub(crate) fn is_special(file_type: FileType) -> bool {
+  !file_type.is_file() && !file_type.is_dir() && !file_type.is_symlink()
+}
+
+/// The special files of a subscription's tree that events were dropped for, so their deletes can be dropped
+/// as well once their type can no longer be read
+#[derive(Default)]
+pub(crate) struct SpecialFiles {
+  paths: HashSet<String>,
+}
+
+impl SpecialFiles {
+  /// Drop the events for special files, returning how many were dropped. Deletes of paths not known to have
+  /// been special files are delivered.
+  pub fn drop_special(&mut self, events: &mut Vec<WatchEvent>) -> u32 {
+    let before = events.len();
+    events.retain(|event| {
+      if event.event_type == "delete" {
+        return !self.paths.remove(&event.path);
+      }
+      #[cfg(feature = "faults")]
+      if crate::faults::stat_fails(Path::new(&event.path)) {
+        return !self.paths.contains(&event.path);
+      }
+      match fs::symlink_metadata(long_paths::verbatim(Path::new(&event.path))) {
+        Ok(metadata) if is_special(metadata.file_type()) => {
+          self.paths.insert(event.path.clone());
+          false
+        }
+        Ok(_) => {
+          self.paths.remove(&event.path);
+          true
+        }
+        // Gone again already, a delete is on its way
+        Err(_) => !self.paths.contains(&event.path),
+      }
+    });
+    u32::try_from(before - events.len()).unwrap_or(u32::MAX)
+  }
+}
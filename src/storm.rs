@@ -0,0 +1,171 @@
+//! The circuit breaker of `circuitBreaker`: once more events are delivered within a second than it allows, a
+//! subscription stops delivering them and only counts them by directory, so that a runaway process cannot keep
+//! the JS thread busy with batches, until the rate stays low for the cool-down or `resume()` is called. Opening
+//! and closing the circuit are each announced with a batch carrying `storm`.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use napi_derive::napi;
+
+use crate::WatchEvent;
+use crate::hub::{Subscriber, lock};
+use crate::path_to_clean_string;
+
+/// Span the events are counted over to compare them with the limit
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Most directories a report names
+const HOTTEST: usize = 10;
+
+/// Most directories counted at once, the events of any others are only counted in total
+const MAX_DIRECTORIES: usize = 10_000;
+
+/// Longest the cool-down thread sleeps before checking whether the subscription is still running
+const STEP: Duration = Duration::from_millis(100);
+
+/// Number of events in a directory, for `StormReport`
+#[napi(object, object_from_js = false)]
+#[derive(Debug, Clone)]
+pub struct DirectoryCount {
+  pub path: String,
+  pub count: u32,
+}
+
+/// The circuit breaker opening or closing, on the batch announcing it
+#[napi(object, object_from_js = false)]
+#[derive(Debug, Clone)]
+pub struct StormReport {
+  /// `open` when delivery stopped, `closed` when it resumed
+  #[napi(ts_type = "'open' | 'closed'")]
+  pub state: String,
+  /// Events within the second that tripped the breaker, or events left undelivered while it was open
+  pub event_count: u32,
+  /// The directories with the most of those events, most first
+  pub directories: Vec<DirectoryCount>,
+}
+
+/// Events counted by the directory they are in
+#[derive(Default)]
+struct Tally {
+  total: u32,
+  directories: HashMap<String, u32>,
+}
+
+impl Tally {
+  fn add(&mut self, events: &[WatchEvent]) {
+    self.total = self.total.saturating_add(u32::try_from(events.len()).unwrap_or(u32::MAX));
+    for event in events {
+      let directory = Path::new(&event.path).parent().map(path_to_clean_string).unwrap_or_default();
+      if let Some(count) = self.directories.get_mut(&directory) {
+        *count = count.saturating_add(1);
+      } else if self.directories.len() < MAX_DIRECTORIES {
+        self.directories.insert(directory, 1);
+      }
+    }
+  }
+
+  fn report(self, state: &str) -> StormReport {
+    let mut directories: Vec<DirectoryCount> = self.directories.into_iter().map(|(path, count)| DirectoryCount { path, count }).collect();
+    directories.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.path.cmp(&b.path)));
+    directories.truncate(HOTTEST);
+    StormReport { state: state.to_string(), event_count: self.total, directories }
+  }
+}
+
+struct State {
+  window_started: Instant,
+  /// Events of the current window
+  window: Tally,
+  /// Events left undelivered and when the rate last went over the limit, while the circuit is open
+  open: Option<(Tally, Instant)>,
+}
+
+/// The circuit breaker of a subscription
+pub(crate) struct Breaker {
+  max_events: u32,
+  cooldown: Option<Duration>,
+  state: Mutex<State>,
+}
+
+impl Breaker {
+  pub fn new(max_events: u32, cooldown: Option<Duration>) -> Breaker {
+    Breaker { max_events, cooldown, state: Mutex::new(State { window_started: Instant::now(), window: Tally::default(), open: None }) }
+  }
+
+  /// Whether the circuit closes by itself once the rate stays low
+  pub fn cools_down(&self) -> bool {
+    self.cooldown.is_some()
+  }
+
+  /// Take the events of a batch out of `events` while the circuit is open, counting them instead. Returns the
+  /// report to announce when these events opened it.
+  pub fn admit(&self, events: &mut Vec<WatchEvent>) -> Option<StormReport> {
+    let now = Instant::now();
+    let mut state = lock(&self.state);
+    if now.saturating_duration_since(state.window_started) >= WINDOW {
+      state.window_started = now;
+      state.window = Tally::default();
+    }
+    state.window.add(events);
+    let exceeded = state.window.total > self.max_events;
+
+    if let Some((tally, last_exceeded)) = &mut state.open {
+      tally.add(events);
+      if exceeded {
+        *last_exceeded = now;
+      }
+      events.clear();
+      return None;
+    }
+    if !exceeded {
+      return None;
+    }
+
+    let mut tally = Tally::default();
+    tally.add(events);
+    events.clear();
+    state.open = Some((tally, now));
+    Some(std::mem::take(&mut state.window).report("open"))
+  }
+
+  /// Close the circuit if it is open and, unless `force`d, the rate has stayed low for the cool-down. Returns
+  /// the report of the events left undelivered meanwhile.
+  pub fn close(&self, force: bool) -> Option<StormReport> {
+    let mut state = lock(&self.state);
+    let (_, last_exceeded) = state.open.as_ref()?;
+    let cooled = self.cooldown.is_some_and(|cooldown| last_exceeded.elapsed() >= cooldown);
+    if !force && !cooled {
+      return None;
+    }
+
+    let (tally, _) = state.open.take()?;
+    state.window_started = Instant::now();
+    state.window = Tally::default();
+    Some(tally.report("closed"))
+  }
+}
+
+/// Close the circuit of the subscriber once it has cooled down, until the subscription stops
+pub(crate) fn spawn(subscriber: &Arc<Subscriber>) -> io::Result<()> {
+  let subscriber: Weak<Subscriber> = Arc::downgrade(subscriber);
+  thread::Builder::new().name("watcher-storm".to_string()).spawn(move || {
+    loop {
+      thread::sleep(STEP);
+      let Some(subscriber) = subscriber.upgrade() else {
+        return;
+      };
+      if !subscriber.running.load(Ordering::SeqCst) {
+        return;
+      }
+      subscriber.close_circuit(false);
+    }
+  })?;
+
+  Ok(())
+}
@@ -0,0 +1,62 @@
+//! Paths that turned from a file into a directory or back, told apart for `detectTypeChanges` by comparing the
+//! type the baseline recorded for them with the one on disk, so they are reported as the old entry going away
+//! and a new one appearing instead of as an update
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::WatchEvent;
+use crate::long_paths;
+use crate::scan::{self, Baseline};
+
+/// Give every create and delete of `events` the type of its entry, from disk for creates and from `baseline` for
+/// deletes, and turn the events of a path whose type differs from the one in `baseline` into a delete of the old
+/// entry followed by a create of the new one. Returns how many paths changed their type.
+pub(crate) fn split(baseline: &Baseline, events: &mut Vec<WatchEvent>) -> u32 {
+  let mut changed: HashMap<String, (String, &str)> = HashMap::new();
+  for event in events.iter_mut() {
+    let recorded = baseline.get(&event.path).map(|scanned| scanned.entry.file_type.as_str());
+    let current = fs::symlink_metadata(long_paths::verbatim(Path::new(&event.path))).ok().map(|metadata| scan::file_type(&metadata));
+
+    match (recorded, current) {
+      (Some(recorded), Some(current)) if recorded != current => {
+        changed.insert(event.path.clone(), (recorded.to_string(), current));
+      }
+      _ if event.event_type == "delete" => event.file_type = recorded.map(str::to_string),
+      _ if event.event_type == "create" => event.file_type = current.map(str::to_string),
+      _ => {}
+    }
+  }
+  if changed.is_empty() {
+    return 0;
+  }
+
+  let mut kept: Vec<WatchEvent> = Vec::with_capacity(events.len() + changed.len());
+  let mut merged: HashMap<String, usize> = HashMap::new();
+  for mut event in events.drain(..) {
+    let Some((recorded, current)) = changed.get(&event.path) else {
+      kept.push(event);
+      continue;
+    };
+    // The first event of the path stands for the others, which are counted with the create
+    if let Some(&index) = merged.get(&event.path) {
+      kept[index].absorb(&mut event);
+      continue;
+    }
+
+    let mut delete = WatchEvent { event_type: "delete".to_string(), file_type: Some(recorded.clone()), ..event.clone() };
+    delete.raw_kind = None;
+    delete.coalesced_count = None;
+    delete.first_seen_ms = None;
+    delete.last_seen_ms = None;
+    kept.push(delete);
+
+    event.event_type = "create".to_string();
+    event.file_type = Some(current.to_string());
+    merged.insert(event.path.clone(), kept.len());
+    kept.push(event);
+  }
+  *events = kept;
+  u32::try_from(changed.len()).unwrap_or(u32::MAX)
+}
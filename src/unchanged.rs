@@ -0,0 +1,156 @@
+//! Updates that leave a file as it was, such as a `touch` or a rewrite with the same contents, dropped by
+//! comparing the file with how it was last seen, for `skipUnchanged`
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hasher};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::WatchEvent;
+use crate::long_paths;
+
+/// Most files whose state is kept, the ones seen longest ago are forgotten first
+const MAX_ENTRIES: usize = 10_000;
+
+/// What has to stay the same for an update to be dropped
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Compare {
+  Mtime,
+  SizeAndMtime,
+  /// Size and contents, whatever the modification time
+  Hash,
+}
+
+impl Compare {
+  pub fn parse(name: &str) -> Option<Compare> {
+    match name {
+      "mtime" => Some(Compare::Mtime),
+      "size+mtime" => Some(Compare::SizeAndMtime),
+      "hash" => Some(Compare::Hash),
+      _ => None,
+    }
+  }
+}
+
+#[derive(PartialEq)]
+struct State {
+  size: u64,
+  mtime: Option<SystemTime>,
+  /// Hash of the contents, only taken to compare by them
+  hash: Option<u64>,
+}
+
+struct Entry {
+  state: State,
+  /// When the file was last seen, counted in sightings
+  seen: u64,
+}
+
+/// The files of a subscription as they were last seen, by path so the entries below a deleted directory are
+/// next to each other
+pub(crate) struct Unchanged {
+  compare: Compare,
+  entries: BTreeMap<PathBuf, Entry>,
+  sightings: u64,
+}
+
+impl Unchanged {
+  pub fn new(compare: Compare) -> Unchanged {
+    Unchanged { compare, entries: BTreeMap::new(), sightings: 0 }
+  }
+
+  /// Drop the updates of files that are the same as when they were last seen, returning how many were
+  /// dropped. The first update of a file the subscription has not seen yet is kept. Creates and deletes are
+  /// always kept, with creates recording the state of the file and deletes forgetting everything below the path.
+  pub fn drop_unchanged(&mut self, events: &mut Vec<WatchEvent>) -> u32 {
+    let before = events.len();
+    events.retain(|event| match event.event_type.as_str() {
+      "delete" => {
+        self.forget(Path::new(&event.path));
+        true
+      }
+      "create" => {
+        self.see(Path::new(&event.path));
+        true
+      }
+      "update" => !self.see(Path::new(&event.path)),
+      _ => true,
+    });
+    u32::try_from(before - events.len()).unwrap_or(u32::MAX)
+  }
+
+  /// Record the state of the file at `path`, returning whether it is the same as when it was last seen
+  fn see(&mut self, path: &Path) -> bool {
+    let Ok(state) = self.read(path) else {
+      self.forget(path);
+      return false;
+    };
+
+    self.sightings += 1;
+    let entry = Entry { state, seen: self.sightings };
+    let unchanged = match self.entries.insert(path.to_path_buf(), entry) {
+      Some(last) => self.entries.get(path).is_some_and(|entry| entry.state == last.state),
+      None => false,
+    };
+    self.prune();
+    unchanged
+  }
+
+  fn read(&self, path: &Path) -> io::Result<State> {
+    #[cfg(feature = "faults")]
+    if crate::faults::stat_fails(path) {
+      return Err(io::Error::other("metadata lookup failure injected by injectFaults"));
+    }
+    // A reserved name such as `CON` would otherwise open the console and wait for input
+    let path = &long_paths::verbatim(path);
+    let metadata = fs::metadata(path)?;
+    // Directories change with their entries, which their own events report
+    if metadata.is_dir() {
+      return Err(io::ErrorKind::IsADirectory.into());
+    }
+
+    let state = match self.compare {
+      Compare::Mtime => State { size: 0, mtime: metadata.modified().ok(), hash: None },
+      Compare::SizeAndMtime => State { size: metadata.len(), mtime: metadata.modified().ok(), hash: None },
+      Compare::Hash => State { size: metadata.len(), mtime: None, hash: Some(hash(path)?) },
+    };
+    Ok(state)
+  }
+
+  /// Forget `path` and everything below it
+  fn forget(&mut self, path: &Path) {
+    let below: Vec<PathBuf> = self.entries.range(path.to_path_buf()..).map(|(entry, _)| entry).take_while(|entry| entry.starts_with(path)).cloned().collect();
+    for entry in below {
+      self.entries.remove(&entry);
+    }
+  }
+
+  /// Forget the files seen longest ago once there are more than `MAX_ENTRIES`, making room for an eighth more
+  /// so pruning does not run on every sighting
+  fn prune(&mut self) {
+    if self.entries.len() <= MAX_ENTRIES {
+      return;
+    }
+
+    let keep = MAX_ENTRIES - MAX_ENTRIES / 8;
+    let mut seen: Vec<u64> = self.entries.values().map(|entry| entry.seen).collect();
+    let forgotten = seen.len() - keep - 1;
+    let cutoff = *seen.select_nth_unstable(forgotten).1;
+    self.entries.retain(|_, entry| entry.seen > cutoff);
+  }
+}
+
+/// Hash of the contents of the file at `path`
+fn hash(path: &Path) -> io::Result<u64> {
+  let mut file = File::open(path)?;
+  let mut hasher = DefaultHasher::new();
+  let mut buffer = [0; 64 * 1024];
+  loop {
+    match file.read(&mut buffer)? {
+      0 => return Ok(hasher.finish()),
+      read => hasher.write(&buffer[..read]),
+    }
+  }
+}
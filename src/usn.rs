@@ -0,0 +1,387 @@
+//! The NTFS change journal, in which a volume records every change made to it under an ever growing number, the
+//! USN. With `changeJournal`, snapshots record where the journal of their volume stood, and the next
+//! `writeSnapshot` or `getEventsSince` reads the changes recorded since then instead of walking the tree, which
+//! on a large tree takes minutes where the journal answers in milliseconds. Reading it takes administrator
+//! rights and a volume that keeps one; without either, or once the journal no longer holds every change since
+//! the snapshot, the tree is walked as without the option.
+
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use watcher_core::IgnoreSet;
+
+use crate::mounts::Mounts;
+use crate::scan::{self, Scanned};
+use crate::{long_paths, path_to_clean_string};
+
+/// Where the change journal of a volume stood
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Cursor {
+  /// Identity of the journal, which changes when the journal is deleted and created again
+  pub journal_id: u64,
+  /// USN the next change is recorded under
+  pub usn: i64,
+}
+
+/// A path the journal recorded changes of
+pub(crate) struct Change {
+  pub path: PathBuf,
+  /// Whether the path was created or renamed to, so that what is there now may be another file or directory
+  /// than the one the snapshot has
+  pub replaced: bool,
+}
+
+/// Where the change journal of the volume `root` is on stands now
+#[cfg(windows)]
+pub(crate) fn cursor(root: &Path) -> io::Result<Cursor> {
+  windows::cursor(root)
+}
+
+#[cfg(not(windows))]
+pub(crate) fn cursor(_root: &Path) -> io::Result<Cursor> {
+  Err(unsupported())
+}
+
+/// The paths below `root` the change journal recorded changes of since `since`, with where it stands now.
+/// Fails when the journal no longer holds every change since then.
+#[cfg(windows)]
+pub(crate) fn changes_since(root: &Path, since: Cursor) -> io::Result<(Vec<Change>, Cursor)> {
+  windows::changes_since(root, since)
+}
+
+#[cfg(not(windows))]
+pub(crate) fn changes_since(_root: &Path, _since: Cursor) -> io::Result<(Vec<Change>, Cursor)> {
+  Err(unsupported())
+}
+
+#[cfg(not(windows))]
+fn unsupported() -> io::Error {
+  io::Error::new(io::ErrorKind::Unsupported, "Only NTFS volumes on Windows keep a change journal")
+}
+
+/// Bring the entries of a snapshot of `root` up to date with `changes`. A changed path gets the entry it has
+/// now, or none once it is gone, and a directory gone or replaced takes the entries below it along. Directories
+/// created or renamed to are walked, as their entries came with them without changes of their own. `record`
+/// describes an entry the way the walk of the snapshot did, or leaves it out.
+pub(crate) fn apply(
+  root: &PathBuf,
+  before: &[Scanned],
+  changes: &[Change],
+  ignore_set: &IgnoreSet,
+  mounts: Option<&Mounts>,
+  strict: bool,
+  record: &dyn Fn(&Path, &Metadata) -> Option<Scanned>,
+) -> io::Result<Vec<Scanned>> {
+  let mut entries: scan::Baseline = before.iter().map(|scanned| (scanned.entry.path.clone(), scanned.clone())).collect();
+  let mut walk = Vec::new();
+
+  for change in changes {
+    let key = path_to_clean_string(&change.path);
+    let was_directory = entries.get(&key).is_some_and(|scanned| scanned.entry.file_type == "directory");
+    let metadata = fs::symlink_metadata(long_paths::verbatim(&change.path)).ok();
+    let is_directory = metadata.as_ref().is_some_and(Metadata::is_dir);
+    let new_directory = is_directory && (change.replaced || !was_directory);
+
+    if was_directory && (!is_directory || new_directory) {
+      for below in scan::keys_below(&entries, &change.path) {
+        entries.remove(&below);
+      }
+    } else {
+      entries.remove(&key);
+    }
+
+    if is_ignored(ignore_set, &change.path, root) {
+      continue;
+    }
+    if let Some(scanned) = metadata.and_then(|metadata| record(&change.path, &metadata)) {
+      entries.insert(key, scanned);
+      if new_directory && !ignore_set.ignores_tree(&change.path, root) {
+        walk.push(change.path.clone());
+      }
+    }
+  }
+
+  for directory in walk {
+    scan::walk(&directory, root, ignore_set, mounts, strict, &mut |entry| {
+      if let Ok(metadata) = entry.metadata()
+        && let Some(scanned) = record(&entry.path(), &metadata)
+      {
+        entries.insert(scanned.entry.path.clone(), scanned);
+      }
+      true
+    })?;
+  }
+
+  Ok(entries.into_values().collect())
+}
+
+/// Check if the walk of a snapshot of `root` leaves `path` out, itself or as part of a directory it does not
+/// descend into
+fn is_ignored(ignore_set: &IgnoreSet, path: &Path, root: &Path) -> bool {
+  ignore_set.is_ignored(path, root) || path.ancestors().skip(1).take_while(|ancestor| *ancestor != root).any(|ancestor| ignore_set.ignores_tree(ancestor, root))
+}
+
+#[cfg(windows)]
+mod windows {
+  use std::collections::HashMap;
+  use std::ffi::{OsString, c_void};
+  use std::io;
+  use std::iter;
+  use std::mem::size_of;
+  use std::os::windows::ffi::{OsStrExt, OsStringExt};
+  use std::path::{Path, PathBuf};
+  use std::ptr;
+
+  use super::{Change, Cursor};
+  use crate::long_paths;
+
+  const GENERIC_READ: u32 = 0x8000_0000;
+  const FILE_READ_ATTRIBUTES: u32 = 0x80;
+  const FILE_SHARE_READ_WRITE_DELETE: u32 = 0x7;
+  const OPEN_EXISTING: u32 = 3;
+  const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+  const FSCTL_QUERY_USN_JOURNAL: u32 = 0x0009_00f4;
+  const FSCTL_READ_USN_JOURNAL: u32 = 0x0009_00bb;
+  const USN_REASON_FILE_CREATE: u32 = 0x0000_0100;
+  const USN_REASON_RENAME_NEW_NAME: u32 = 0x0000_2000;
+  const ERROR_HANDLE_EOF: i32 = 38;
+
+  /// Size of the buffer records are read into
+  const BUFFER_BYTES: usize = 64 * 1024;
+
+  /// Offsets of the fields of a `USN_RECORD_V2`
+  const RECORD_PARENT_ID: usize = 16;
+  const RECORD_REASON: usize = 40;
+  const RECORD_NAME_LEN: usize = 56;
+  const RECORD_NAME_OFFSET: usize = 58;
+
+  type Handle = *mut c_void;
+
+  /// `USN_JOURNAL_DATA_V0`
+  #[repr(C)]
+  #[derive(Default)]
+  struct JournalData {
+    journal_id: u64,
+    first_usn: i64,
+    next_usn: i64,
+    lowest_valid_usn: i64,
+    max_usn: i64,
+    maximum_size: u64,
+    allocation_delta: u64,
+  }
+
+  /// `READ_USN_JOURNAL_DATA_V0`
+  #[repr(C)]
+  struct ReadJournalData {
+    start_usn: i64,
+    reason_mask: u32,
+    return_only_on_close: u32,
+    timeout: u64,
+    bytes_to_wait_for: u64,
+    journal_id: u64,
+  }
+
+  /// `FILE_ID_DESCRIPTOR` with a 64-bit file id, the second half of the union unused
+  #[repr(C)]
+  struct FileIdDescriptor {
+    size: u32,
+    kind: u32,
+    id: [u64; 2],
+  }
+
+  #[link(name = "kernel32")]
+  unsafe extern "system" {
+    fn CreateFileW(name: *const u16, access: u32, share: u32, security: *mut c_void, disposition: u32, flags: u32, template: Handle) -> Handle;
+    fn DeviceIoControl(
+      device: Handle,
+      code: u32,
+      input: *const c_void,
+      input_len: u32,
+      output: *mut c_void,
+      output_len: u32,
+      returned: *mut u32,
+      overlapped: *mut c_void,
+    ) -> i32;
+    fn OpenFileById(volume: Handle, id: *const FileIdDescriptor, access: u32, share: u32, security: *mut c_void, flags: u32) -> Handle;
+    fn GetFinalPathNameByHandleW(file: Handle, path: *mut u16, len: u32, flags: u32) -> u32;
+    fn GetVolumePathNameW(path: *const u16, mount_point: *mut u16, len: u32) -> i32;
+    fn GetVolumeNameForVolumeMountPointW(mount_point: *const u16, name: *mut u16, len: u32) -> i32;
+    fn CloseHandle(handle: Handle) -> i32;
+  }
+
+  /// A handle closed when dropped
+  struct Owned(Handle);
+
+  impl Owned {
+    fn new(handle: Handle) -> io::Result<Owned> {
+      // INVALID_HANDLE_VALUE
+      if handle as isize == -1 { Err(io::Error::last_os_error()) } else { Ok(Owned(handle)) }
+    }
+  }
+
+  impl Drop for Owned {
+    fn drop(&mut self) {
+      unsafe { CloseHandle(self.0) };
+    }
+  }
+
+  pub fn cursor(root: &Path) -> io::Result<Cursor> {
+    let journal = query(&open_volume(root)?)?;
+    Ok(Cursor { journal_id: journal.journal_id, usn: journal.next_usn })
+  }
+
+  pub fn changes_since(root: &Path, since: Cursor) -> io::Result<(Vec<Change>, Cursor)> {
+    let volume = open_volume(root)?;
+    let journal = query(&volume)?;
+    if journal.journal_id != since.journal_id || since.usn < journal.lowest_valid_usn || since.usn > journal.next_usn {
+      return Err(io::Error::other("The change journal no longer holds every change since the snapshot"));
+    }
+
+    // Whether each changed path was replaced, and the paths of the directories the changes were in by file id
+    let mut changes: HashMap<PathBuf, bool> = HashMap::new();
+    let mut directories: HashMap<u64, Option<PathBuf>> = HashMap::new();
+    let mut buffer = vec![0u64; BUFFER_BYTES / size_of::<u64>()];
+    let mut start = since.usn;
+
+    // Changes recorded while reading are left to the next read, which starts where this one did not go past
+    while start < journal.next_usn {
+      let request =
+        ReadJournalData { start_usn: start, reason_mask: u32::MAX, return_only_on_close: 0, timeout: 0, bytes_to_wait_for: 0, journal_id: since.journal_id };
+      let mut returned = 0;
+      let read = unsafe {
+        DeviceIoControl(
+          volume.0,
+          FSCTL_READ_USN_JOURNAL,
+          (&raw const request).cast(),
+          size_of::<ReadJournalData>() as u32,
+          buffer.as_mut_ptr().cast(),
+          BUFFER_BYTES as u32,
+          &mut returned,
+          ptr::null_mut(),
+        )
+      };
+      if read == 0 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() == Some(ERROR_HANDLE_EOF) {
+          break;
+        }
+        return Err(e);
+      }
+
+      let bytes = unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), returned as usize) };
+      let Some(next) = field::<8>(bytes, 0).map(i64::from_le_bytes) else {
+        break;
+      };
+
+      let mut offset = size_of::<i64>();
+      while let Some(len) = field::<4>(bytes, offset).map(|len| u32::from_le_bytes(len) as usize) {
+        let Some(record) = bytes.get(offset..offset + len).filter(|_| len > 0) else {
+          break;
+        };
+        if let Some((parent, name, reason)) = parse(record) {
+          let directory = directories.entry(parent).or_insert_with(|| path_of(&volume, parent)).clone();
+          if let Some(path) = directory.map(|directory| directory.join(name)).filter(|path| path.starts_with(root) && path != root) {
+            *changes.entry(path).or_default() |= reason & (USN_REASON_FILE_CREATE | USN_REASON_RENAME_NEW_NAME) != 0;
+          }
+        }
+        offset += len;
+      }
+
+      if next <= start {
+        break;
+      }
+      start = next;
+    }
+
+    let changes = changes.into_iter().map(|(path, replaced)| Change { path, replaced }).collect();
+    Ok((changes, Cursor { journal_id: journal.journal_id, usn: start.min(journal.next_usn) }))
+  }
+
+  /// The parent directory id, name and reasons of a `USN_RECORD_V2`, the version NTFS reports, or None for a
+  /// record of another version
+  fn parse(record: &[u8]) -> Option<(u64, OsString, u32)> {
+    if field::<2>(record, 4).map(u16::from_le_bytes) != Some(2) {
+      return None;
+    }
+    let parent = u64::from_le_bytes(field(record, RECORD_PARENT_ID)?);
+    let reason = u32::from_le_bytes(field(record, RECORD_REASON)?);
+    let name_len = usize::from(u16::from_le_bytes(field(record, RECORD_NAME_LEN)?));
+    let name_offset = usize::from(u16::from_le_bytes(field(record, RECORD_NAME_OFFSET)?));
+    let name: Vec<u16> = record.get(name_offset..name_offset + name_len)?.chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).collect();
+    Some((parent, OsString::from_wide(&name), reason))
+  }
+
+  fn field<const N: usize>(bytes: &[u8], offset: usize) -> Option<[u8; N]> {
+    bytes.get(offset..offset + N)?.try_into().ok()
+  }
+
+  /// The current path of the file or directory with id `id`, or None once it is gone
+  fn path_of(volume: &Owned, id: u64) -> Option<PathBuf> {
+    let descriptor = FileIdDescriptor { size: size_of::<FileIdDescriptor>() as u32, kind: 0, id: [id, 0] };
+    let file = unsafe { OpenFileById(volume.0, &descriptor, FILE_READ_ATTRIBUTES, FILE_SHARE_READ_WRITE_DELETE, ptr::null_mut(), FILE_FLAG_BACKUP_SEMANTICS) };
+    let file = Owned::new(file).ok()?;
+
+    let len = unsafe { GetFinalPathNameByHandleW(file.0, ptr::null_mut(), 0, 0) };
+    if len == 0 {
+      return None;
+    }
+    let mut buffer = vec![0; len as usize];
+    let written = unsafe { GetFinalPathNameByHandleW(file.0, buffer.as_mut_ptr(), len, 0) };
+    if written == 0 || written >= len {
+      return None;
+    }
+    buffer.truncate(written as usize);
+    Some(long_paths::simplified(Path::new(&OsString::from_wide(&buffer))).into_owned())
+  }
+
+  /// Open the volume `root` is on, which takes administrator rights
+  fn open_volume(root: &Path) -> io::Result<Owned> {
+    let root = wide(&long_paths::verbatim(root));
+    let mut mount_point = vec![0; root.len() + 1];
+    if unsafe { GetVolumePathNameW(root.as_ptr(), mount_point.as_mut_ptr(), mount_point.len() as u32) } == 0 {
+      return Err(io::Error::last_os_error());
+    }
+    let mount_point = wide(&long_paths::simplified(Path::new(&OsString::from_wide(until_nul(&mount_point)))));
+
+    // `\\?\Volume{GUID}\`, which opens the volume once the trailing backslash is dropped
+    let mut name = [0; 64];
+    if unsafe { GetVolumeNameForVolumeMountPointW(mount_point.as_ptr(), name.as_mut_ptr(), name.len() as u32) } == 0 {
+      return Err(io::Error::last_os_error());
+    }
+    let mut device = until_nul(&name).to_vec();
+    if device.last() == Some(&u16::from(b'\\')) {
+      device.pop();
+    }
+    device.push(0);
+
+    let volume = unsafe { CreateFileW(device.as_ptr(), GENERIC_READ, FILE_SHARE_READ_WRITE_DELETE, ptr::null_mut(), OPEN_EXISTING, 0, ptr::null_mut()) };
+    Owned::new(volume)
+  }
+
+  fn query(volume: &Owned) -> io::Result<JournalData> {
+    let mut journal = JournalData::default();
+    let mut returned = 0;
+    let queried = unsafe {
+      DeviceIoControl(
+        volume.0,
+        FSCTL_QUERY_USN_JOURNAL,
+        ptr::null(),
+        0,
+        (&raw mut journal).cast(),
+        size_of::<JournalData>() as u32,
+        &mut returned,
+        ptr::null_mut(),
+      )
+    };
+    if queried == 0 { Err(io::Error::last_os_error()) } else { Ok(journal) }
+  }
+
+  fn wide(path: &Path) -> Vec<u16> {
+    path.as_os_str().encode_wide().chain(iter::once(0)).collect()
+  }
+
+  fn until_nul(units: &[u16]) -> &[u16] {
+    &units[..units.iter().position(|&unit| unit == 0).unwrap_or(units.len())]
+  }
+}
@@ -0,0 +1,362 @@
+//! Checks of a subscription's directory and options, shared by `subscribe`, `scan` and `validateOptions`
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use napi::bindgen_prelude::*;
+use napi::{Env, JsTypeError};
+use watcher_core::IgnoreSet;
+
+use crate::history;
+use crate::logging::Level;
+use crate::memory::MemoryCap;
+use crate::network::{self, Choice};
+use crate::normalization::Form;
+use crate::registration::EntryLimit;
+use crate::ring::{self, Overflow, RingBuffer};
+use crate::snapshot::FormatError;
+use crate::storm::Breaker;
+use crate::unchanged::Compare;
+use crate::{
+  CircuitBreakerOptions, DirectoryError, LARGE_ROOT_CODE, OptionsProblem, RetryOptions, RetryPolicy, Serialization, attempts_suffix,
+  is_transient_directory_error, journal, journal_error, locked, path_to_clean_string, resolve_directory,
+};
+
+/// Something wrong with the directory or options of a subscription
+pub(crate) struct Problem {
+  code: &'static str,
+  /// The directory, pattern or option at fault
+  input: String,
+  status: Status,
+  message: String,
+  /// Whether `subscribe` throws it as a `TypeError`, as it does for problems with the directory
+  type_error: bool,
+}
+
+impl Problem {
+  fn new(code: &'static str, input: &str, status: Status, message: String) -> Problem {
+    Problem { code, input: input.to_string(), status, message, type_error: false }
+  }
+
+  fn of_directory(code: &'static str, directory: &str, message: String) -> Problem {
+    Problem { type_error: true, ..Problem::new(code, directory, Status::InvalidArg, message) }
+  }
+
+  /// The error `subscribe` throws for the problem, a `TypeError` for problems with the directory
+  pub fn into_js_error(self, env: &Env) -> Error {
+    if !self.type_error {
+      return self.into_error();
+    }
+
+    let error = unsafe { JsTypeError::from(Error::new(self.status, self.message)).into_value(env.raw()) };
+    unsafe { Unknown::from_napi_value(env.raw(), error) }.map_or_else(|e| e, Error::from)
+  }
+
+  pub fn into_error(self) -> Error {
+    Error::new(self.status, self.message)
+  }
+
+  pub fn report(self) -> OptionsProblem {
+    OptionsProblem { code: self.code.to_string(), input: self.input, message: self.message }
+  }
+}
+
+/// Resolve the directory to watch to its canonical path, retrying transient failures as `retry` allows
+pub(crate) fn check_directory(directory: &str, retry: &RetryPolicy) -> std::result::Result<PathBuf, Problem> {
+  if directory.is_empty() {
+    return Err(Problem::of_directory("EMPTY_DIRECTORY", directory, "Directory path cannot be empty".to_string()));
+  }
+
+  retry.run(is_transient_directory_error, || resolve_directory(Path::new(directory))).map_err(|error| match error {
+    (DirectoryError::NotFound, attempts) => {
+      Problem::of_directory("DIRECTORY_NOT_FOUND", directory, format!("Directory does not exist: {}{}", directory, attempts_suffix(attempts)))
+    }
+    (DirectoryError::NotADirectory, _) => Problem::of_directory("NOT_A_DIRECTORY", directory, format!("Path is not a directory: {}", directory)),
+    (DirectoryError::Io(e), attempts) => {
+      Problem::new("DIRECTORY_UNREADABLE", directory, Status::GenericFailure, format!("Failed to canonicalize path: {}{}", e, attempts_suffix(attempts)))
+    }
+  })
+}
+
+/// Check if `root`, resolved by `check_directory`, is a filesystem root such as `/` or `C:\\`, or the home
+/// directory, whose trees are too large to register watches for
+pub(crate) fn is_large_root(root: &Path) -> bool {
+  root.parent().is_none() || std::env::home_dir().and_then(|home| dunce::canonicalize(home).ok()).is_some_and(|home| home == root)
+}
+
+/// Why a subscription of `root` is refused without `allowLargeRoots`
+pub(crate) fn large_root_message(root: &str) -> String {
+  format!(
+    "Refusing to watch {}, a filesystem root or the home directory, which would take watches over most of the disk. Pass allowLargeRoots: true to watch it anyway",
+    root
+  )
+}
+
+/// Refuse a filesystem root or the home directory unless `allowLargeRoots` is set
+pub(crate) fn check_large_root(root: &Path, allow: Option<bool>) -> std::result::Result<(), Problem> {
+  if allow.unwrap_or(false) || !is_large_root(root) {
+    return Ok(());
+  }
+  let root = path_to_clean_string(root);
+  Err(Problem::of_directory(LARGE_ROOT_CODE, &root, large_root_message(&root)))
+}
+
+/// Compile glob patterns, reporting every pattern that is invalid rather than only the first
+pub(crate) fn check_patterns(patterns: &[String]) -> std::result::Result<GlobSet, Vec<Problem>> {
+  let mut builder = GlobSetBuilder::new();
+  let mut problems = Vec::new();
+
+  for pattern in patterns {
+    match Glob::new(pattern) {
+      Ok(glob) => {
+        builder.add(glob);
+      }
+      Err(e) => problems.push(Problem::new("INVALID_PATTERN", pattern, Status::InvalidArg, format!("Invalid glob pattern '{}': {}", pattern, e))),
+    }
+  }
+
+  if !problems.is_empty() {
+    return Err(problems);
+  }
+
+  builder.build().map_err(|e| vec![Problem::new("INVALID_PATTERN", &patterns.join(", "), Status::GenericFailure, format!("Failed to build glob set: {}", e))])
+}
+
+/// Compile ignore patterns the way `IgnoreSet` matches them, reporting every pattern that is invalid rather
+/// than only the first
+pub(crate) fn check_ignore_patterns(patterns: &[String]) -> std::result::Result<IgnoreSet, Vec<Problem>> {
+  let problems: Vec<Problem> = patterns
+    .iter()
+    .filter_map(|pattern| {
+      let error = Glob::new(pattern.strip_prefix('!').unwrap_or(pattern)).err()?;
+      Some(Problem::new("INVALID_PATTERN", pattern, Status::InvalidArg, format!("Invalid glob pattern '{}': {}", pattern, error)))
+    })
+    .collect();
+  if !problems.is_empty() {
+    return Err(problems);
+  }
+
+  IgnoreSet::new(patterns)
+    .map_err(|e| vec![Problem::new("INVALID_PATTERN", &patterns.join(", "), Status::GenericFailure, format!("Failed to build glob set: {}", e))])
+}
+
+pub(crate) fn check_retry(options: Option<&RetryOptions>) -> std::result::Result<RetryPolicy, Problem> {
+  RetryPolicy::from_options(options).map_err(|e| Problem::new("INVALID_RETRY", "retry", e.status, e.reason.clone()))
+}
+
+pub(crate) fn check_locked_file_retry(options: Option<&RetryOptions>) -> std::result::Result<RetryPolicy, Problem> {
+  match options {
+    Some(options) => RetryPolicy::from_options(Some(options))
+      .map_err(|e| Problem::new("INVALID_RETRY", "lockedFileRetry", e.status, e.reason.replacen("retry.", "lockedFileRetry.", 1))),
+    None => Ok(locked::default_retry()),
+  }
+}
+
+pub(crate) fn check_log_level(log_level: Option<&str>) -> std::result::Result<Level, Problem> {
+  let Some(name) = log_level else {
+    return Ok(Level::DEFAULT);
+  };
+
+  Level::parse(name).ok_or_else(|| {
+    Problem::new("INVALID_LOG_LEVEL", name, Status::InvalidArg, format!("Invalid log level '{}', expected error, warn, info, debug or trace", name))
+  })
+}
+
+pub(crate) fn check_serialization(serialization: Option<&str>, callback_style: Option<&str>) -> std::result::Result<Serialization, Problem> {
+  let parsed = match serialization {
+    Some(name) => Serialization::parse(name)
+      .ok_or_else(|| Problem::new("INVALID_SERIALIZATION", name, Status::InvalidArg, format!("Invalid serialization '{}', expected object or json", name)))?,
+    None => Serialization::Object,
+  };
+
+  match callback_style {
+    None | Some("object") => Ok(parsed),
+    Some("node") if parsed == Serialization::Object => Ok(Serialization::Node),
+    Some("node") => {
+      Err(Problem::new("INVALID_CALLBACK_STYLE", "node", Status::InvalidArg, "callbackStyle 'node' cannot be combined with serialization 'json'".to_string()))
+    }
+    Some(name) => Err(Problem::new("INVALID_CALLBACK_STYLE", name, Status::InvalidArg, format!("Invalid callbackStyle '{}', expected object or node", name))),
+  }
+}
+
+/// Check that `buffer` can take the events of a subscription, and how it handles a reader that falls behind
+pub(crate) fn check_ring(env: &Env, buffer: &RingBuffer, overflow: Option<&str>) -> std::result::Result<Overflow, Problem> {
+  let overflow = match overflow {
+    Some(name) => Overflow::parse(name)
+      .ok_or_else(|| Problem::new("INVALID_RING", name, Status::InvalidArg, format!("Invalid ring overflow policy '{}', expected overwrite or block", name)))?,
+    None => Overflow::Overwrite,
+  };
+
+  ring::validate(env, buffer).map_err(|message| Problem::new("INVALID_RING", "ring", Status::InvalidArg, message))?;
+  Ok(overflow)
+}
+
+/// Check what happens to a tree with more than `max` entries
+pub(crate) fn check_entry_limit(max: Option<u32>, action: Option<&str>) -> std::result::Result<Option<EntryLimit>, Problem> {
+  let fail = match action {
+    None | Some("fail") => true,
+    Some("warn") => false,
+    Some(name) => {
+      return Err(Problem::new(
+        "INVALID_MAX_WATCHED_ENTRIES",
+        name,
+        Status::InvalidArg,
+        format!("Invalid maxWatchedEntriesAction '{}', expected fail or warn", name),
+      ));
+    }
+  };
+
+  Ok(max.map(|max| EntryLimit { max: max as usize, fail }))
+}
+
+/// Check the most memory a subscription may hold and what happens once it would hold more, if there is a cap
+pub(crate) fn check_memory_cap(max_bytes: Option<f64>, action: Option<&str>) -> std::result::Result<Option<MemoryCap>, Problem> {
+  let close = match action {
+    None | Some("shed") => false,
+    Some("close") => true,
+    Some(name) => {
+      return Err(Problem::new("INVALID_MAX_MEMORY", name, Status::InvalidArg, format!("Invalid maxMemoryAction '{}', expected shed or close", name)));
+    }
+  };
+
+  match max_bytes {
+    None => Ok(None),
+    Some(max_bytes) if max_bytes.is_finite() && max_bytes >= 1.0 => Ok(Some(MemoryCap { max_bytes: max_bytes as usize, close })),
+    Some(max_bytes) => {
+      Err(Problem::new("INVALID_MAX_MEMORY", &max_bytes.to_string(), Status::InvalidArg, "maxMemoryBytes must be a positive number".to_string()))
+    }
+  }
+}
+
+pub(crate) fn check_normalization(normalization: Option<&str>) -> std::result::Result<Option<Form>, Problem> {
+  let Some(name) = normalization else {
+    return Ok(None);
+  };
+
+  Form::parse(name).ok_or_else(|| {
+    Problem::new("INVALID_UNICODE_NORMALIZATION", name, Status::InvalidArg, format!("Invalid unicode normalization '{}', expected nfc, nfd or none", name))
+  })
+}
+
+/// Check the watcher asked for and the interval a polling subscription scans at
+pub(crate) fn check_backend(backend: Option<&str>, poll_interval_ms: Option<u32>) -> std::result::Result<(Choice, Duration), Problem> {
+  let choice = match backend {
+    Some(name) => Choice::parse(name).ok_or_else(|| {
+      let message = if name == "kqueue" {
+        "The kqueue backend is not available on this platform, expected auto, native or polling".to_string()
+      } else if name == "mock" {
+        "The mock backend is only available in builds with the mock feature, expected auto, native or polling".to_string()
+      } else {
+        format!("Invalid backend '{}', expected auto, native or polling", name)
+      };
+      Problem::new("INVALID_BACKEND", name, Status::InvalidArg, message)
+    })?,
+    None => Choice::Auto,
+  };
+
+  let interval = match poll_interval_ms {
+    Some(0) => return Err(Problem::new("INVALID_BACKEND", "pollIntervalMs", Status::InvalidArg, "pollIntervalMs must be greater than 0".to_string())),
+    Some(ms) => Duration::from_millis(u64::from(ms)),
+    None => network::DEFAULT_POLL_INTERVAL,
+  };
+  Ok((choice, interval))
+}
+
+/// Check the FSEvents event id to replay the history since, if given, which only the native watcher on macOS keeps
+pub(crate) fn check_since_event_id(since_event_id: Option<i64>, backend: Choice) -> std::result::Result<Option<u64>, Problem> {
+  let Some(since) = since_event_id else {
+    return Ok(None);
+  };
+  let unsupported = |message: &str| Problem::new("INVALID_SINCE_EVENT_ID", &since.to_string(), Status::InvalidArg, message.to_string());
+  if !history::is_supported() {
+    return Err(unsupported("sinceEventId is unsupported on this backend, only FSEvents on macOS keeps a history of events"));
+  }
+  if matches!(backend, Choice::Polling | Choice::Mock) {
+    return Err(unsupported("sinceEventId is unsupported on this backend, only the native watcher keeps a history of events"));
+  }
+  u64::try_from(since).map(Some).map_err(|_| unsupported("sinceEventId must not be negative"))
+}
+
+/// Check whether a polling subscription also compares the contents of files to tell that they changed
+pub(crate) fn check_poll_compare(poll_compare: Option<&str>) -> std::result::Result<bool, Problem> {
+  match poll_compare {
+    None | Some("mtime") => Ok(false),
+    Some("contents") => Ok(true),
+    Some(name) => Err(Problem::new("INVALID_POLL_COMPARE", name, Status::InvalidArg, format!("Invalid pollCompare '{}', expected mtime or contents", name))),
+  }
+}
+
+/// Check the interval the tree is walked at to catch missed changes, if it is
+pub(crate) fn check_reconcile_interval(interval_ms: Option<u32>) -> std::result::Result<Option<Duration>, Problem> {
+  match interval_ms {
+    Some(0) => {
+      Err(Problem::new("INVALID_RECONCILE_INTERVAL", "reconcileIntervalMs", Status::InvalidArg, "reconcileIntervalMs must be greater than 0".to_string()))
+    }
+    interval_ms => Ok(interval_ms.map(|ms| Duration::from_millis(u64::from(ms)))),
+  }
+}
+
+/// Check the interval after which a quiet subscription is sent a heartbeat, if it is
+pub(crate) fn check_heartbeat_interval(interval_ms: Option<u32>) -> std::result::Result<Option<Duration>, Problem> {
+  match interval_ms {
+    Some(0) => Err(Problem::new("INVALID_HEARTBEAT_INTERVAL", "heartbeatMs", Status::InvalidArg, "heartbeatMs must be greater than 0".to_string())),
+    interval_ms => Ok(interval_ms.map(|ms| Duration::from_millis(u64::from(ms)))),
+  }
+}
+
+/// Check how long the debouncer waits for further events, if given
+pub(crate) fn check_debounce(debounce_ms: Option<u32>) -> std::result::Result<Option<Duration>, Problem> {
+  match debounce_ms {
+    Some(0) => Err(Problem::new("INVALID_DEBOUNCE", "debounceMs", Status::InvalidArg, "debounceMs must be greater than 0".to_string())),
+    debounce_ms => Ok(debounce_ms.map(|ms| Duration::from_millis(u64::from(ms)))),
+  }
+}
+
+/// Check the limit of the circuit breaker for event storms, if there is one
+pub(crate) fn check_circuit_breaker(options: Option<&CircuitBreakerOptions>) -> std::result::Result<Option<Breaker>, Problem> {
+  match options {
+    None => Ok(None),
+    Some(CircuitBreakerOptions { max_events_per_second: 0, .. }) => {
+      Err(Problem::new("INVALID_CIRCUIT_BREAKER", "circuitBreaker", Status::InvalidArg, "circuitBreaker.maxEventsPerSecond must be greater than 0".to_string()))
+    }
+    Some(options) => Ok(Some(Breaker::new(options.max_events_per_second, options.cooldown_ms.map(|ms| Duration::from_millis(u64::from(ms)))))),
+  }
+}
+
+/// Check whether an event goes to every route of `subscribeRouted` whose pattern it matches rather than the first
+pub(crate) fn check_route_match(route_match: Option<&str>) -> std::result::Result<bool, Problem> {
+  match route_match {
+    None | Some("first") => Ok(false),
+    Some("all") => Ok(true),
+    Some(name) => Err(Problem::new("INVALID_ROUTE_MATCH", name, Status::InvalidArg, format!("Invalid routeMatch '{}', expected first or all", name))),
+  }
+}
+
+/// Check what has to stay the same for `skipUnchanged` to drop an update, if updates are dropped at all
+pub(crate) fn check_skip_unchanged(skip_unchanged: Option<&str>) -> std::result::Result<Option<Compare>, Problem> {
+  match skip_unchanged {
+    None => Ok(None),
+    Some(name) => Compare::parse(name).map(Some).ok_or_else(|| {
+      Problem::new("INVALID_SKIP_UNCHANGED", name, Status::InvalidArg, format!("Invalid skipUnchanged '{}', expected mtime, size+mtime or hash", name))
+    }),
+  }
+}
+
+/// Read the journal a subscription to `root` would append to. A journal that does not exist yet has no records.
+pub(crate) fn check_journal(journal_path: &str, root: &str) -> std::result::Result<Vec<journal::Record>, Problem> {
+  match journal::read(Path::new(journal_path)) {
+    Ok((journal_root, records)) if journal_root == root => Ok(records),
+    Ok((journal_root, _)) => Err(Problem::new(
+      "INVALID_JOURNAL",
+      journal_path,
+      Status::InvalidArg,
+      format!("Journal was written for {}, not {}: {}", journal_root, root, journal_path),
+    )),
+    Err(FormatError::NotFound) => Ok(Vec::new()),
+    Err(e) => {
+      let error = journal_error(journal_path, e);
+      Err(Problem::new("INVALID_JOURNAL", journal_path, error.status, error.reason.clone()))
+    }
+  }
+}
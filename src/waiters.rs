@@ -0,0 +1,147 @@
+//! Promises settled by the next matching event delivered to a subscription
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+use napi::bindgen_prelude::*;
+use napi::{Env, JsDeferred};
+
+use crate::hub::{Subscriber, lock};
+use crate::{NextOptions, Subscription, WatchEvent};
+
+/// Error code of the rejection when no matching event arrived in time
+pub(crate) const TIMEOUT_CODE: &str = "ETIMEDOUT";
+
+type Resolver = Box<dyn FnOnce(Env) -> Result<WatchEvent> + Send>;
+
+static NEXT_WAITER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A promise waiting for an event
+pub(crate) struct Waiter {
+  id: u64,
+  types: Option<Vec<String>>,
+  /// Only settle for events for this path
+  path: Option<String>,
+  deferred: JsDeferred<WatchEvent, Resolver>,
+  /// Subscription created for this waiter alone, closed once it settles
+  owned: Option<Subscription>,
+}
+
+/// How a waiter settles
+enum Outcome {
+  Event(WatchEvent),
+  TimedOut(u32),
+  Stopped(String),
+}
+
+impl Waiter {
+  fn matches(&self, event: &WatchEvent) -> bool {
+    self.types.as_ref().is_none_or(|types| types.contains(&event.event_type)) && self.path.as_ref().is_none_or(|path| *path == event.path)
+  }
+
+  /// Settle the promise on the JS thread, closing the owned subscription first on every path
+  fn settle(self, outcome: Outcome) {
+    let Waiter { deferred, owned, .. } = self;
+
+    deferred.resolve(Box::new(move |env| {
+      if let Some(mut subscription) = owned {
+        subscription.close();
+      }
+
+      match outcome {
+        Outcome::Event(event) => Ok(event),
+        Outcome::TimedOut(timeout_ms) => {
+          let mut error = env.create_error(Error::new(Status::GenericFailure, format!("No matching event within {}ms", timeout_ms)))?;
+          error.set_named_property("code", TIMEOUT_CODE)?;
+          Err(Error::from(error.to_unknown()))
+        }
+        Outcome::Stopped(message) => Err(Error::new(Status::GenericFailure, message)),
+      }
+    }));
+  }
+}
+
+/// Wait for the next event delivered to `subscriber` that matches `options`, and is for `path` if given.
+/// An `owned` subscription is closed as soon as the returned promise settles.
+pub(crate) fn wait<'env>(
+  env: &'env Env,
+  subscriber: &Arc<Subscriber>,
+  options: &NextOptions,
+  path: Option<String>,
+  owned: Option<Subscription>,
+) -> Result<Object<'env>> {
+  let (deferred, promise) = env.create_deferred()?;
+  let waiter = Waiter { id: NEXT_WAITER_ID.fetch_add(1, Ordering::Relaxed), types: options.types.clone(), path, deferred, owned };
+  let id = waiter.id;
+
+  {
+    // Checked under the lock, so a subscription stopping meanwhile cannot miss the waiter
+    let mut waiters = lock(&subscriber.waiters);
+    if !subscriber.running.load(Ordering::SeqCst) {
+      drop(waiters);
+      waiter.settle(Outcome::Stopped(stopped_message(subscriber)));
+      return Ok(promise);
+    }
+    waiters.push(waiter);
+  }
+
+  if let Some(timeout_ms) = options.timeout_ms {
+    let subscriber = Arc::downgrade(subscriber);
+    thread::Builder::new()
+      .name("watcher-next-timeout".to_string())
+      .spawn(move || {
+        thread::sleep(Duration::from_millis(timeout_ms.into()));
+        if let Some(waiter) = take(&subscriber, id) {
+          waiter.settle(Outcome::TimedOut(timeout_ms));
+        }
+      })
+      .map_err(|e| Error::new(Status::GenericFailure, format!("Failed to start timeout: {}", e)))?;
+  }
+
+  Ok(promise)
+}
+
+/// Settle the waiters that one of the delivered events matches
+pub(crate) fn notify(subscriber: &Subscriber, events: &[WatchEvent]) {
+  let mut waiters = lock(&subscriber.waiters);
+  if waiters.is_empty() || events.is_empty() {
+    return;
+  }
+
+  let mut index = 0;
+  while index < waiters.len() {
+    match events.iter().find(|event| waiters[index].matches(event)) {
+      Some(event) => waiters.swap_remove(index).settle(Outcome::Event(event.clone())),
+      None => index += 1,
+    }
+  }
+}
+
+/// Reject every waiter, after the subscription stopped
+pub(crate) fn stop(subscriber: &Subscriber) {
+  let waiters = std::mem::take(&mut *lock(&subscriber.waiters));
+  if waiters.is_empty() {
+    return;
+  }
+
+  let message = stopped_message(subscriber);
+  for waiter in waiters {
+    waiter.settle(Outcome::Stopped(message.clone()));
+  }
+}
+
+fn take(subscriber: &Weak<Subscriber>, id: u64) -> Option<Waiter> {
+  let subscriber = subscriber.upgrade()?;
+  let mut waiters = lock(&subscriber.waiters);
+  let index = waiters.iter().position(|waiter| waiter.id == id)?;
+  Some(waiters.swap_remove(index))
+}
+
+fn stopped_message(subscriber: &Subscriber) -> String {
+  match lock(&subscriber.failure).as_ref() {
+    Some(failure) => failure.clone(),
+    None => "Subscription was closed before a matching event was delivered".to_string(),
+  }
+}